@@ -0,0 +1,129 @@
+//! Tool for replaying GPU captures recorded by `splst_core::Gpu::start_capture`.
+//!
+//! Usage:
+//!
+//! ```text
+//! splst_gpu_replay list <capture>
+//! splst_gpu_replay step <capture>
+//! splst_gpu_replay vram <capture> <step> <before.ppm> <after.ppm>
+//! ```
+//!
+//! `list` replays the whole capture and prints each command with its vertices and the part of
+//! VRAM it changed. `step` does the same a single step at a time, waiting for enter between each
+//! step. Entering `w <path>` writes the current VRAM to a PPM image and `q` quits. `vram` replays
+//! the capture up to step `step` and writes VRAM before and after it as PPM images.
+
+use splst_core::gpu::capture::{Capture, CaptureError, Replayer, VramDiff};
+use splst_core::Vram;
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::process::ExitCode;
+
+fn open(path: &str) -> Result<Capture, CaptureError> {
+    Capture::read(BufReader::new(File::open(path)?))
+}
+
+fn write_ppm(vram: &Vram, path: &str) -> io::Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    writeln!(out, "P6 1024 512 255")?;
+    for pixel in vram.to_rgba().chunks_exact(4) {
+        out.write_all(&pixel[..3])?;
+    }
+    out.flush()
+}
+
+/// Execute and print the next step along with the changes to VRAM. `before` is used to store VRAM
+/// before the step. Returns `false` when the whole capture has been replayed.
+fn print_step(replayer: &mut Replayer, before: &mut Vram) -> bool {
+    before.data.copy_from_slice(&replayer.vram().data);
+
+    let Some(step) = replayer.step() else {
+        return false;
+    };
+
+    println!("{step}");
+
+    if let Some(diff) = VramDiff::new(before, replayer.vram()) {
+        println!("    {diff}");
+    }
+
+    true
+}
+
+fn usage() -> ExitCode {
+    eprintln!("usage: splst_gpu_replay list <capture>");
+    eprintln!("       splst_gpu_replay step <capture>");
+    eprintln!("       splst_gpu_replay vram <capture> <step> <before.ppm> <after.ppm>");
+    ExitCode::from(2)
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(|arg| arg.as_str()).collect();
+
+    let result = match args.as_slice() {
+        ["list", path] => open(path).map(|capture| {
+            let mut replayer = Replayer::new(&capture);
+            let mut before = Box::new(Vram::new());
+            while print_step(&mut replayer, &mut before) {}
+            ExitCode::SUCCESS
+        }),
+        ["step", path] => open(path).and_then(|capture| {
+            let mut replayer = Replayer::new(&capture);
+            let mut before = Box::new(Vram::new());
+
+            println!("{} frames, {} records", capture.frames(), capture.events.len());
+
+            for line in io::stdin().lock().lines() {
+                let line = line?;
+                match line.trim().split_once(' ') {
+                    Some(("w", path)) => write_ppm(replayer.vram(), path.trim())?,
+                    _ if line.trim() == "q" => break,
+                    _ => {
+                        if !print_step(&mut replayer, &mut before) {
+                            println!("end of capture");
+                            break;
+                        }
+                    }
+                }
+            }
+
+            Ok(ExitCode::SUCCESS)
+        }),
+        ["vram", path, step, before, after] => {
+            let Ok(step) = step.parse::<usize>() else {
+                return usage();
+            };
+            open(path).and_then(|capture| {
+                let mut replayer = Replayer::new(&capture);
+                while replayer.steps() < step {
+                    if replayer.step().is_none() {
+                        eprintln!("the capture only has {} steps", replayer.steps());
+                        return Ok(ExitCode::FAILURE);
+                    }
+                }
+
+                write_ppm(replayer.vram(), before)?;
+
+                match replayer.step() {
+                    Some(step) => println!("{step}"),
+                    None => {
+                        eprintln!("the capture only has {} steps", replayer.steps());
+                        return Ok(ExitCode::FAILURE);
+                    }
+                }
+
+                write_ppm(replayer.vram(), after)?;
+
+                Ok(ExitCode::SUCCESS)
+            })
+        }
+        _ => return usage(),
+    };
+
+    result.unwrap_or_else(|err| {
+        eprintln!("{err}");
+        ExitCode::FAILURE
+    })
+}
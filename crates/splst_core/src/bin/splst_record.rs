@@ -0,0 +1,134 @@
+//! Tool for recording video and audio without a window.
+//!
+//! Usage:
+//!
+//! ```text
+//! splst_record <bios> <game> <seconds> <video.y4m> <audio.wav> [<width>x<height> [<filter>]]
+//! ```
+//!
+//! Runs `game` for `seconds` of emulated time as fast as possible and records it with
+//! `splst_core::record::Recorder`. `game` is either an executable or a cue file. The frames are
+//! 640x480 unless another size is given. `filter` is one of `nearest`, `scale2x`, `scale3x`,
+//! `xbr`, `scanlines` or `<n>x` for integer scaling, and is `nearest` by default.
+
+use splst_core::io_port::{memcard::MemCards, pad::GamePads};
+use splst_core::cheat::Cheats;
+use splst_core::record::Recorder;
+use splst_core::gpu::Filter;
+use splst_core::{Bios, Disc, System};
+use splst_util::Exe;
+
+use std::cell::RefCell;
+use std::error::Error;
+use std::path::Path;
+use std::process::ExitCode;
+use std::rc::Rc;
+use std::time::Duration;
+
+fn usage() -> ExitCode {
+    eprintln!(
+        "usage: splst_record <bios> <game> <seconds> <video.y4m> <audio.wav> \
+         [<width>x<height> [<filter>]]"
+    );
+    ExitCode::from(2)
+}
+
+fn parse_size(size: &str) -> Option<(u32, u32)> {
+    let (width, height) = size.split_once('x')?;
+    let (width, height) = (width.parse().ok()?, height.parse().ok()?);
+    (width > 0 && height > 0).then(|| (width, height))
+}
+
+fn record(
+    bios: &str,
+    game: &str,
+    seconds: u64,
+    video: &str,
+    audio: &str,
+    (width, height): (u32, u32),
+    filter: Filter,
+) -> Result<(), Box<dyn Error>> {
+    let bios = Bios::from_file(Path::new(bios))?;
+    let game = Path::new(game);
+
+    let is_cue = game
+        .extension()
+        .map_or(false, |ext| ext.eq_ignore_ascii_case("cue"));
+
+    let mut disc = Disc::default();
+    let exe = if is_cue {
+        disc.load(splst_cdimg::open_cd(game)?);
+        None
+    } else {
+        Some(Exe::load(game)?)
+    };
+
+    let mut recorder = Recorder::create(Path::new(video), Path::new(audio), width, height)?;
+    recorder.set_filter(filter);
+
+    let recorder = Rc::new(RefCell::new(recorder));
+
+    let mut system = System::new(
+        bios,
+        recorder.clone(),
+        recorder.clone(),
+        Rc::new(RefCell::new(disc)),
+        Rc::new(RefCell::new(GamePads::default())),
+        Rc::new(RefCell::new(MemCards::default())),
+        Rc::new(RefCell::new(Cheats::default())),
+    );
+
+    if let Some(exe) = exe {
+        system.load_exe(&exe);
+    }
+
+    for second in 0..seconds {
+        system.run(Duration::from_secs(1));
+        eprint!("\rrecorded {} of {seconds} seconds", second + 1);
+    }
+
+    eprintln!();
+
+    let mut recorder = recorder.borrow_mut();
+    recorder.finish()?;
+
+    println!("wrote {} frames and {} samples", recorder.frames(), recorder.samples());
+
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(|arg| arg.as_str()).collect();
+
+    let (bios, game, seconds, video, audio, size, filter) = match args.as_slice() {
+        [bios, game, seconds, video, audio, rest @ ..] => {
+            let (size, filter) = match rest {
+                [] => ((640, 480), Filter::Nearest),
+                [size] => match parse_size(size) {
+                    Some(size) => (size, Filter::Nearest),
+                    None => return usage(),
+                },
+                [size, filter] => match (parse_size(size), filter.parse()) {
+                    (Some(size), Ok(filter)) => (size, filter),
+                    _ => return usage(),
+                },
+                _ => return usage(),
+            };
+            let seconds = match seconds.parse() {
+                Ok(seconds) => seconds,
+                Err(_) => return usage(),
+            };
+            (*bios, *game, seconds, *video, *audio, size, filter)
+        }
+        _ => return usage(),
+    };
+
+    match record(bios, game, seconds, video, audio, size, filter) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("{err}");
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,72 @@
+//! Tool for working with execution traces recorded by `splst_core::trace::TraceRecorder`.
+//!
+//! Usage:
+//!
+//! ```text
+//! splst_trace diff <trace> <reference> [context]
+//! splst_trace text <trace>
+//! ```
+//!
+//! `diff` compares two traces and prints the first divergence along with `context` (default 16)
+//! instructions leading up to it. Either trace can be binary or text. `text` prints a trace in the
+//! text format.
+
+use splst_core::trace::{self, TraceEntry, TraceError};
+
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::process::ExitCode;
+
+type Trace = Box<dyn Iterator<Item = Result<TraceEntry, TraceError>>>;
+
+fn open(path: &str) -> Result<Trace, TraceError> {
+    trace::read_trace(BufReader::new(File::open(path)?))
+}
+
+fn usage() -> ExitCode {
+    eprintln!("usage: splst_trace diff <trace> <reference> [context]");
+    eprintln!("       splst_trace text <trace>");
+    ExitCode::from(2)
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(|arg| arg.as_str()).collect();
+
+    let result = match args.as_slice() {
+        ["diff", left, right, rest @ ..] => {
+            let context = match rest {
+                [] => 16,
+                [context] => match context.parse() {
+                    Ok(context) => context,
+                    Err(_) => return usage(),
+                },
+                _ => return usage(),
+            };
+            open(left).and_then(|left| {
+                let right = open(right)?;
+                trace::diff(left, right, context)
+            })
+            .map(|div| match div {
+                Some(div) => {
+                    print!("{div}");
+                    ExitCode::FAILURE
+                }
+                None => {
+                    println!("traces are equal");
+                    ExitCode::SUCCESS
+                }
+            })
+        }
+        ["text", path] => open(path).and_then(|trace| {
+            trace::write_text(trace, &mut io::stdout().lock())?;
+            Ok(ExitCode::SUCCESS)
+        }),
+        _ => return usage(),
+    };
+
+    result.unwrap_or_else(|err| {
+        eprintln!("{err}");
+        ExitCode::FAILURE
+    })
+}
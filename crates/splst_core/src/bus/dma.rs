@@ -1,9 +1,13 @@
 //! Emulating Direct Memory Access chip. Used to transfer data between devices. The CPU halts when
 //! this is running, but the CPU can be allowed to run in intervals called chopping.
 //!
-//! # TODO
+//! # Timing
 //!
-//! - Add proper timings for transfers.
+//! Each word takes the time given by [`Channel::dma_word_time`], which is a single cycle unless
+//! the port is slower, and reading the header of a linked list node takes a cycle as well. New
+//! blocks are only started when [`Channel::dma_ready`] and each word waits for
+//! [`Channel::dma_word_ready`]. If a port isn't ready, the CPU gets to run until the port runs
+//! the channel again.
 
 use splst_util::{Bit, BitSet};
 
@@ -29,6 +33,8 @@ pub struct Dma {
     /// Interrupt register.
     irq: IrqReg,
     channels: [ChanStat; 7],
+    /// The total amount of time the CPU has been halted by transfers.
+    stalled: SysTime,
 }
 
 impl Dma {
@@ -45,9 +51,15 @@ impl Dma {
                 ChanStat::new(Port::Pio),
                 ChanStat::new(Port::Otc),
             ],
+            stalled: SysTime::ZERO,
         }
     }
 
+    /// The total amount of time the CPU has been halted by transfers since startup.
+    pub fn stall_time(&self) -> SysTime {
+        self.stalled
+    }
+
     pub(super) fn load<T: AddrUnit>(&self, offset: u32) -> T {
         let chan = offset.bit_range(4, 6);
         let reg = offset.bit_range(0, 3);
@@ -111,37 +123,40 @@ impl Dma {
     ) {
         let ctrl = self[port].ctrl;
 
+        // The CPU is still running between chopped blocks.
+        if schedule.now() < self[port].chopped_until {
+            return;
+        }
+
         let done = if ctrl.chopping_enabled() {
             schedule.now() + ctrl.dma_chop_size()
         } else {
             Timestamp::NEVER
         };
 
-        let mut manual_done = false;
-
-        while schedule.now() < done
-            && self[port].ctrl.enabled()
-            && chan.dma_ready(self[port].ctrl.direction())
-        {
+        while schedule.now() < done && self[port].ctrl.enabled() {
             let stat = &mut self[port];
 
+            // A transfer stopped in the middle of a block only has to wait for the next word.
+            let ready = match stat.transfer {
+                Some(_) => chan.dma_word_ready(stat.ctrl.direction()),
+                None => chan.dma_ready(stat.ctrl.direction()),
+            };
+
+            if !ready {
+                return;
+            }
+
             let mut tran = match stat.transfer.take() {
                 Some(tran) => tran,
                 None => match stat.ctrl.sync_mode() {
                     SyncMode::Manual => {
-                        if manual_done {
-                            self.channel_done(port, schedule);
-                            return;
-                        }
-
                         // For manual transfers the start flag must be set as opposed to the other
                         // sync modes.
                         if !stat.ctrl.start() {
                             return;
                         }
 
-                        manual_done = true;
-
                         Transfer {
                             inc: stat.ctrl.step().step_amount(),
                             size: stat.block_ctrl.size as u32,
@@ -165,6 +180,7 @@ impl Dma {
                     SyncMode::LinkedList => {
                         if stat.base != 0x00ff_ffff {
                             let header: u32 = ram.load(stat.base & 0x001f_fffc);
+                            schedule.advance(SysTime::new(1));
 
                             let tran = Transfer {
                                 inc: stat.ctrl.step().step_amount(),
@@ -178,6 +194,8 @@ impl Dma {
                                 continue;
                             }
 
+                            chan.dma_linked_node(tran.cursor.wrapping_sub(4), tran.size);
+
                             tran
                         } else {
                             self.channel_done(port, schedule);
@@ -194,7 +212,8 @@ impl Dma {
             self[port].transfer = match stat.ctrl.direction() {
                 Direction::ToRam => {
                     loop {
-                        if schedule.now() > done {
+                        // A block that's all sent is done, even if it runs out of cycles.
+                        if schedule.now() > done && tran.size != 0 {
                             let stat = &mut self[port];
                            
                             // If the channel is in manual sync mode, then the base address will
@@ -204,6 +223,7 @@ impl Dma {
                                 stat.base = tran.cursor;
                             }
 
+                            stat.chopped_until = schedule.now() + stat.ctrl.cpu_chop_size();
                             schedule.schedule(
                                 stat.ctrl.cpu_chop_size(),
                                 Event::Dma(port, Bus::run_dma_chan)
@@ -213,13 +233,20 @@ impl Dma {
                         }
 
                         if let Some(size) = tran.size.checked_sub(1) {
+                            if !chan.dma_word_ready(Direction::ToRam) {
+                                break Some(tran);
+                            }
+
                             let addr = tran.cursor & 0x001f_fffc;
+                            let word_time = chan.dma_word_time();
                             let val = chan.dma_load(schedule, (tran.size as u16, tran.cursor));
 
                             ram.store(addr, val);
 
                             tran.cursor = tran.cursor.wrapping_add(tran.inc) & 0x00ff_ffff;
                             tran.size = size;
+
+                            schedule.advance(word_time);
                         } else {
                             let stat = &mut self[port];
 
@@ -235,19 +262,18 @@ impl Dma {
 
                             break None;
                         }
-
-                        schedule.advance(SysTime::new(1));
                     }
                 }
                 Direction::ToPort => {
                     loop {
-                        if schedule.now() > done {
+                        if schedule.now() > done && tran.size != 0 {
                             let stat = &mut self[port];
                            
                             if let SyncMode::Manual = stat.ctrl.sync_mode() {
                                 stat.base = tran.cursor;
                             }
 
+                            stat.chopped_until = schedule.now() + stat.ctrl.cpu_chop_size();
                             schedule.schedule(
                                 stat.ctrl.cpu_chop_size(),
                                 Event::Dma(port, Bus::run_dma_chan)
@@ -257,13 +283,21 @@ impl Dma {
                         }
 
                         if let Some(size) = tran.size.checked_sub(1) {
+                            // The port is run again when it's got room for more.
+                            if !chan.dma_word_ready(Direction::ToPort) {
+                                break Some(tran);
+                            }
+
                             let addr = tran.cursor & 0x001f_fffc;
                             let val: u32 = ram.load(addr);
 
-                            chan.dma_store(schedule, val);
+                            let word_time = chan.dma_word_time();
+                            chan.dma_store(schedule, val, addr);
 
                             tran.cursor = tran.cursor.wrapping_add(tran.inc) & 0x00ff_ffff;
                             tran.size = size;
+
+                            schedule.advance(word_time);
                         } else {
                             let stat = &mut self[port];
 
@@ -277,17 +311,23 @@ impl Dma {
 
                             break None;
                         }
-
-                        schedule.advance(SysTime::new(1));
                     }
                 }
             };
+
+            // Manual transfers are a single block.
+            if self[port].transfer.is_none() && self[port].ctrl.sync_mode() == SyncMode::Manual {
+                self.channel_done(port, schedule);
+                return;
+            }
         }
     }
 }
 
 impl Bus {
     pub(crate) fn run_dma(&mut self) {
+        let start = self.schedule.now();
+
         self.dma.run_chan(
             Port::Gpu,
             &mut self.gpu,
@@ -312,9 +352,13 @@ impl Bus {
             &mut self.schedule,
             &mut self.ram,
         );
+
+        self.dma.stalled = self.dma.stalled + self.schedule.now().time_since(&start);
     }
 
     pub(crate) fn run_dma_chan(&mut self, port: Port) {
+        let start = self.schedule.now();
+
         match port {
             Port::Gpu => {
                self.dma.run_chan(
@@ -350,6 +394,8 @@ impl Bus {
             }
             _ => todo!(),
         }
+
+        self.dma.stalled = self.dma.stalled + self.schedule.now().time_since(&start);
     }
 }
 
@@ -551,6 +597,8 @@ pub struct ChanStat {
     block_ctrl: BlockCtrl,
     ctrl: ChanCtrl,
     transfer: Option<Transfer>,
+    /// When the CPU is done running after a chopped block.
+    chopped_until: Timestamp,
 }
 
 impl ChanStat {
@@ -561,6 +609,7 @@ impl ChanStat {
             block_ctrl: BlockCtrl::new(0x0),
             ctrl: ChanCtrl(0x0),
             transfer: None,
+            chopped_until: Timestamp::STARTUP,
         }
     }
 
@@ -733,7 +782,7 @@ impl Channel for OrderingTable {
         }
     }
 
-    fn dma_store(&mut self, _: &mut Schedule, _: u32) {
+    fn dma_store(&mut self, _: &mut Schedule, _: u32, _: u32) {
         warn!("Ordering table DMA store");
     }
 
@@ -760,11 +809,187 @@ pub trait Channel {
     /// `stats` contains the number of words left in the transfer and the address where the value
     /// is going to be stored. It's only really used by the depth ordering table.
     fn dma_load(&mut self, schedule: &mut Schedule, stats: (u16, u32)) -> u32;
-    fn dma_store(&mut self, schedule: &mut Schedule, val: u32);
+    /// `addr` is the address in RAM `val` is loaded from.
+    fn dma_store(&mut self, schedule: &mut Schedule, val: u32, addr: u32);
+    /// If the channel is ready to start transferring a block.
     fn dma_ready(&self, dir: Direction) -> bool;
+    /// If the channel is ready for the next word in the middle of a block.
+    fn dma_word_ready(&self, _dir: Direction) -> bool {
+        true
+    }
+    /// The time it takes to transfer a single word.
+    fn dma_word_time(&self) -> SysTime {
+        SysTime::new(1)
+    }
+    /// Called with the address and size in words of each node of a linked list transfer before
+    /// it's transferred.
+    fn dma_linked_node(&mut self, _addr: u32, _size: u32) {}
 }
 
 impl BusMap for Dma {
     const BUS_BEGIN: u32 = 0x1f801080;
     const BUS_END: u32 = Self::BUS_BEGIN + 128 - 1;
 }
+
+/// Run the events of the GPU and the GPU DMA channel until the channel is done. Returns when
+/// each run of the channel that transferred anything started, and when the DMA interrupt was
+/// triggered.
+#[cfg(test)]
+fn run_gpu_dma(
+    dma: &mut Dma,
+    gpu: &mut crate::gpu::Gpu,
+    schedule: &mut Schedule,
+    ram: &mut Ram,
+) -> (Vec<Timestamp>, Option<Timestamp>) {
+    let mut timers = crate::timer::Timers::new();
+    let mut runs = Vec::new();
+    let mut irq = None;
+
+    while dma[Port::Gpu].ctrl.enabled() {
+        schedule.skip_to_next_event();
+        while let Some(event) = schedule.get_pending_event() {
+            match event {
+                Event::Gpu(callback) => callback(gpu, schedule, &mut timers),
+                Event::Dma(Port::Gpu, _) => {
+                    let before = schedule.now();
+                    dma.run_chan(Port::Gpu, gpu, schedule, ram);
+                    if schedule.now() > before {
+                        runs.push(before);
+                    }
+                }
+                Event::Irq(Irq::Dma) => irq = Some(schedule.now()),
+                _ => (),
+            }
+        }
+    }
+
+    (runs, irq)
+}
+
+#[test]
+fn gpu_linked_list_stalls() {
+    use crate::gpu::Gpu;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    let mut schedule = Schedule::new();
+    let mut gpu = Gpu::new(&mut schedule, Rc::new(RefCell::new(())));
+    let mut ram = Ram::new();
+    let mut dma = Dma::new();
+
+    // The first node sets the draw area and draws 6 white triangles, which is more than the FIFO
+    // can hold while the first one is drawing. The second node sets the draw mode.
+    let mut words = vec![0xe3000000, 0xe4000000 | 1023 | 511 << 10];
+    for _ in 0..6 {
+        words.extend([0x20ffffff, 0, 32, 32 << 16]);
+    }
+    ram.store::<u32>(0x100, (words.len() as u32) << 24 | 0x200);
+    for (i, word) in words.iter().enumerate() {
+        ram.store::<u32>(0x104 + i as u32 * 4, *word);
+    }
+    ram.store::<u32>(0x200, 1 << 24 | 0xffffff);
+    ram.store::<u32>(0x204, 0xe1000000);
+
+    dma.irq.store(&mut schedule, 1 << 23 | 1 << (16 + Port::Gpu as u32));
+    dma[Port::Gpu].store(0, 0x100);
+    dma[Port::Gpu].store(8, 1 << 24 | 2 << 9 | 1);
+
+    let start = schedule.now();
+    dma.run_chan(Port::Gpu, &mut gpu, &mut schedule, &mut ram);
+
+    // The header takes a cycle and each word a cycle. The FIFO is full after the 2 immediate
+    // commands, the first triangle and 4 more triangles, so it stops in the middle of the node.
+    assert_eq!(schedule.now(), start + SysTime::new(1 + 2 + 4 * 5));
+    assert!(gpu.fifo().is_full());
+    assert!(dma[Port::Gpu].transfer.is_some());
+    assert!(dma[Port::Gpu].ctrl.enabled());
+
+    // It shouldn't do anything while the GPU is still drawing.
+    dma.run_chan(Port::Gpu, &mut gpu, &mut schedule, &mut ram);
+    assert_eq!(schedule.now(), start + SysTime::new(1 + 2 + 4 * 5));
+
+    let (runs, irq) = run_gpu_dma(&mut dma, &mut gpu, &mut schedule, &mut ram);
+
+    // The header of the last node is only read when the GPU is done drawing. It then takes a
+    // cycle for the header and one for the word.
+    let done = *runs.last().unwrap() + SysTime::new(2);
+    assert!(gpu.is_idle());
+    assert_eq!(schedule.now(), done);
+    assert_eq!(irq, Some(done));
+    assert!(dma.irq.channel_irq_flag(Port::Gpu));
+    assert!(dma.irq.master_irq_flag());
+    assert_eq!(dma[Port::Gpu].base, 0xffffff);
+
+    assert_eq!(gpu.vram().load_16(4, 4), 0x7fff);
+}
+
+#[test]
+fn gpu_chopped_vram_store() {
+    use crate::gpu::Gpu;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    let mut schedule = Schedule::new();
+    let mut gpu = Gpu::new(&mut schedule, Rc::new(RefCell::new(())));
+    let mut ram = Ram::new();
+    let mut dma = Dma::new();
+
+    // Start a transfer of 16 x 4 halfwords to VRAM.
+    for val in [0xa0000000, 0, 16 | 4 << 16] {
+        gpu.store::<u32>(&mut schedule, 0, val);
+    }
+    while schedule.get_pending_event().is_some() {}
+
+    for i in 0..32 {
+        ram.store::<u32>(0x1000 + i * 4, (i * 2) | (i * 2 + 1) << 16);
+    }
+
+    // Manual mode with chopping. The DMA runs for 4 cycles and the CPU for 6.
+    dma.irq.store(&mut schedule, 1 << 23 | 1 << (16 + Port::Gpu as u32));
+    dma[Port::Gpu].store(0, 0x1000);
+    dma[Port::Gpu].store(4, 32);
+    dma[Port::Gpu].store(8, 1 << 28 | 1 << 24 | 3 << 20 | 2 << 16 | 1 << 8 | 1);
+
+    let word_time = Channel::dma_word_time(&gpu);
+    let (dma_chop, cpu_chop) = (SysTime::new(4), SysTime::new(6));
+    assert!(word_time > SysTime::new(1));
+
+    let start = schedule.now();
+    dma.run_chan(Port::Gpu, &mut gpu, &mut schedule, &mut ram);
+
+    // Words are sent as long as the chopping window isn't over.
+    let words = (dma[Port::Gpu].base - 0x1000) as u64 / 4;
+    assert!(words > 0 && words < 32);
+    assert_eq!(schedule.now(), start + word_time * words);
+    assert!(start + word_time * (words - 1) <= start + dma_chop);
+    assert!(schedule.now() > start + dma_chop);
+
+    let chopped_until = dma[Port::Gpu].chopped_until;
+    assert_eq!(chopped_until, schedule.now() + cpu_chop);
+    assert!(schedule.iter_event_entries().any(|entry| {
+        matches!(entry.event, Event::Dma(Port::Gpu, _)) && entry.ready == chopped_until
+    }));
+
+    // Running the channel while the CPU gets to run does nothing.
+    let now = schedule.now();
+    dma.run_chan(Port::Gpu, &mut gpu, &mut schedule, &mut ram);
+    assert_eq!(schedule.now(), now);
+    assert_eq!(dma[Port::Gpu].base, 0x1000 + words as u32 * 4);
+
+    let (runs, irq) = run_gpu_dma(&mut dma, &mut gpu, &mut schedule, &mut ram);
+
+    // Each window after the first starts when the CPU is done, so the whole transfer takes the
+    // time of the words and the time the CPU got to run.
+    assert_eq!(runs[0], chopped_until);
+    assert_eq!(schedule.now(), start + word_time * 32 + cpu_chop * runs.len() as u64);
+    assert_eq!(irq, Some(schedule.now()));
+
+    // Manual mode clears the start flag when done.
+    assert!(!dma[Port::Gpu].ctrl.start());
+    assert!(dma.irq.channel_irq_flag(Port::Gpu));
+    assert!(gpu.is_idle());
+
+    for i in 0..64 {
+        assert_eq!(gpu.vram().load_16(i % 16, i / 16), i as u16);
+    }
+}
@@ -11,6 +11,12 @@ impl Ram {
         Self(RawMem::new())
     }
 
+    /// The raw bytes of the memory.
+    #[inline]
+    pub fn data(&self) -> &[u8] {
+        self.0.data()
+    }
+
     #[inline]
     pub unsafe fn load_unchecked<T: AddrUnit>(&self, offset: u32) -> T {
         let offset = offset.bit_range(0, 20);
@@ -11,6 +11,11 @@ impl<const SIZE: usize> RawMem<SIZE> {
         }
     }
 
+    #[inline]
+    pub fn data(&self) -> &[u8; SIZE] {
+        &self.data
+    }
+
     #[inline]
     pub unsafe fn load_unchecked<T: AddrUnit>(&self, addr: u32) -> T {
         let val: u32 = (0..T::WIDTH as usize).fold(0, |val, byte| {
@@ -10,6 +10,12 @@ impl ScratchPad {
         Self(RawMem::new())
     }
 
+    /// The raw bytes of the memory.
+    #[inline]
+    pub fn data(&self) -> &[u8] {
+        self.0.data()
+    }
+
     #[inline]
     pub unsafe fn load_unchecked<T: AddrUnit>(&self, offset: u32) -> T {
         self.0.load_unchecked(offset)
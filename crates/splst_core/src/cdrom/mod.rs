@@ -803,7 +803,7 @@ impl dma::Channel for CdRom {
         v1 | (v2 << 8) | (v3 << 8) | (v4 << 8)
     }
 
-    fn dma_store(&mut self, _: &mut Schedule, _: u32) {
+    fn dma_store(&mut self, _: &mut Schedule, _: u32, _: u32) {
         unreachable!("DMA store to CDROM");
     }
 
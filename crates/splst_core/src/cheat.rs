@@ -0,0 +1,290 @@
+//! Cheat engine for GameShark codes.
+//!
+//! Each code is a line of the form `TTAAAAAA VVVV`, where `TT` is the code type, `AAAAAA` is
+//! an address in main RAM and `VVVV` is a value. The supported code types are:
+//!
+//! - 80 - Write 16-bit value `VVVV` to address.
+//! - 30 - Write 8-bit value `VV` to address.
+//! - D0 - Only run the next code if the 16-bit value at address is equal to `VVVV`.
+//! - D1 - Only run the next code if the 16-bit value at address is not equal to `VVVV`.
+//! - E0 - Only run the next code if the 8-bit value at address is equal to `VV`.
+//! - E1 - Only run the next code if the 8-bit value at address is not equal to `VV`.
+//! - 50 - Slide code on the form `5000CCSS VVVV`. The next code, which must be a 80 or 30 code,
+//!   is repeated `CC` times, where the address is incremented by `SS` and the value by `VVVV`
+//!   each time.
+//! - C0 - Only run the rest of the codes if the 16-bit value at address is equal to `VVVV`.
+//! - C1 - Only run the rest of the codes after `VVVV` vblanks have passed since the cheats were
+//!   loaded.
+//!
+//! Codes are applied each vblank.
+
+use crate::bus::{self, Bus};
+
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum CheatError {
+    #[error("invalid code `{0}`, codes must be of the form `XXXXXXXX YYYY`")]
+    InvalidCode(String),
+    #[error("unsupported code type `{0:02X}`")]
+    UnsupportedType(u8),
+    #[error("`{0}` must be followed by a code")]
+    MissingCode(String),
+    #[error("slide code must be followed by a 80 or 30 code")]
+    InvalidSlide,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Code {
+    Write16 { addr: u32, val: u16 },
+    Write8 { addr: u32, val: u8 },
+    If16 { addr: u32, val: u16, equal: bool },
+    If8 { addr: u32, val: u8, equal: bool },
+    Slide { count: u8, addr_step: u8, val_step: u16 },
+    Activate { addr: u32, val: u16 },
+    Delay { vblanks: u16 },
+}
+
+/// Get the bus address in main RAM from the address of a code.
+fn code_addr(addr: u32) -> u32 {
+    bus::regioned_addr(0x8000_0000 | addr) & 0x1f_ffff
+}
+
+impl Code {
+    fn parse(line: &str) -> Result<Self, CheatError> {
+        let invalid = || CheatError::InvalidCode(line.to_string());
+
+        let (code, val) = line.split_once(char::is_whitespace).ok_or_else(invalid)?;
+        let val = val.trim();
+
+        if code.len() != 8 || val.len() != 4 {
+            return Err(invalid());
+        }
+
+        let code = u32::from_str_radix(code, 16).map_err(|_| invalid())?;
+        let val = u16::from_str_radix(val, 16).map_err(|_| invalid())?;
+
+        let kind = (code >> 24) as u8;
+        let addr = code_addr(code & 0xff_ffff);
+
+        Ok(match kind {
+            0x80 => Code::Write16 { addr, val },
+            0x30 => Code::Write8 { addr, val: val as u8 },
+            0xd0 => Code::If16 { addr, val, equal: true },
+            0xd1 => Code::If16 { addr, val, equal: false },
+            0xe0 => Code::If8 { addr, val: val as u8, equal: true },
+            0xe1 => Code::If8 { addr, val: val as u8, equal: false },
+            0x50 => Code::Slide {
+                count: (code >> 8) as u8,
+                addr_step: code as u8,
+                val_step: val,
+            },
+            0xc0 => Code::Activate { addr, val },
+            0xc1 => Code::Delay { vblanks: val },
+            kind => return Err(CheatError::UnsupportedType(kind)),
+        })
+    }
+}
+
+/// A single cheat, which may consist of multiple codes.
+pub struct Cheat {
+    name: String,
+    source: String,
+    codes: Vec<Code>,
+    pub enabled: bool,
+}
+
+impl Cheat {
+    /// Parse cheat from `source`, which contains a code on each line. Empty lines are ignored.
+    pub fn new(name: String, source: String) -> Result<Self, CheatError> {
+        let lines: Vec<&str> = source
+            .lines()
+            .map(|line| line.trim())
+            .filter(|line| !line.is_empty())
+            .collect();
+
+        let codes = lines
+            .iter()
+            .map(|line| Code::parse(line))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // Validate that conditionals and slides are followed by a code.
+        for (i, code) in codes.iter().enumerate() {
+            let next = codes.get(i + 1);
+            match code {
+                Code::If16 { .. } | Code::If8 { .. } if next.is_none() => {
+                    return Err(CheatError::MissingCode(lines[i].to_string()));
+                }
+                Code::Slide { .. } => {
+                    if !matches!(next, Some(Code::Write16 { .. } | Code::Write8 { .. })) {
+                        return Err(CheatError::InvalidSlide);
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        Ok(Self { name, source, codes, enabled: true })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The source code of the cheat.
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    fn apply(&self, bus: &mut Bus, vblanks: u64) {
+        let mut codes = self.codes.iter().copied();
+
+        while let Some(code) = codes.next() {
+            match code {
+                Code::Write16 { addr, val } => {
+                    bus.store(addr, val);
+                }
+                Code::Write8 { addr, val } => {
+                    bus.store(addr, val);
+                }
+                Code::If16 { addr, val, equal } => {
+                    if (bus.peek::<u16>(addr) == Some(val)) != equal {
+                        codes.next();
+                    }
+                }
+                Code::If8 { addr, val, equal } => {
+                    if (bus.peek::<u8>(addr) == Some(val)) != equal {
+                        codes.next();
+                    }
+                }
+                Code::Slide { count, addr_step, val_step } => {
+                    let step_addr = |addr: u32, i: u8| {
+                        code_addr(addr.wrapping_add(i as u32 * addr_step as u32))
+                    };
+                    match codes.next() {
+                        Some(Code::Write16 { addr, val }) => {
+                            for i in 0..count {
+                                let val = val.wrapping_add((i as u16).wrapping_mul(val_step));
+                                bus.store(step_addr(addr, i), val);
+                            }
+                        }
+                        Some(Code::Write8 { addr, val }) => {
+                            for i in 0..count {
+                                let val = val.wrapping_add((i as u16).wrapping_mul(val_step) as u8);
+                                bus.store(step_addr(addr, i), val);
+                            }
+                        }
+                        _ => unreachable!("invalid slide code"),
+                    }
+                }
+                Code::Activate { addr, val } => {
+                    if bus.peek::<u16>(addr) != Some(val) {
+                        break;
+                    }
+                }
+                Code::Delay { vblanks: delay } => {
+                    if vblanks < delay as u64 {
+                        break;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// A list of cheats.
+#[derive(Default)]
+pub struct Cheats {
+    cheats: Vec<Cheat>,
+    /// The number of vblanks since the cheats were loaded.
+    vblanks: u64,
+}
+
+impl Cheats {
+    pub fn iter(&self) -> impl Iterator<Item = &Cheat> {
+        self.cheats.iter()
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut Cheat> {
+        self.cheats.iter_mut()
+    }
+
+    /// Replace all cheats with `cheats`.
+    pub fn load(&mut self, cheats: Vec<Cheat>) {
+        self.cheats = cheats;
+        self.vblanks = 0;
+    }
+
+    pub fn clear(&mut self) {
+        self.load(Vec::new());
+    }
+
+    /// Apply all enabled cheats. Should be called at the start of each vblank.
+    pub(crate) fn apply(&mut self, bus: &mut Bus) {
+        for cheat in self.cheats.iter().filter(|cheat| cheat.enabled) {
+            cheat.apply(bus, self.vblanks);
+        }
+        self.vblanks += 1;
+    }
+}
+
+#[test]
+fn parse_and_apply() {
+    use crate::bus::bios::Bios;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    let mut bus = Bus::new(
+        Bios::from_code(0xbfc00000, &[]),
+        Rc::new(RefCell::new(())),
+        Rc::new(RefCell::new(())),
+        Default::default(),
+        Default::default(),
+        Default::default(),
+    );
+
+    let source = "8009c6e4 03e7\n\
+                  \n\
+                  3009c6e8 0063\n\
+                  d009c6e4 0000\n\
+                  8009c6ec 1111\n\
+                  e109c6e8 0063\n\
+                  8009c6ee 2222\n\
+                  50000502 0001\n\
+                  80010000 0010";
+
+    let cheat = Cheat::new("test".to_string(), source.to_string()).unwrap();
+
+    let mut cheats = Cheats::default();
+    cheats.load(vec![cheat]);
+    cheats.apply(&mut bus);
+
+    assert_eq!(bus.peek::<u16>(0x9c6e4), Some(0x03e7));
+    assert_eq!(bus.peek::<u8>(0x9c6e8), Some(0x63));
+
+    // The conditions are false, so the codes after shouldn't be applied.
+    assert_ne!(bus.peek::<u16>(0x9c6ec), Some(0x1111));
+    assert_ne!(bus.peek::<u16>(0x9c6ee), Some(0x2222));
+
+    for i in 0..5 {
+        assert_eq!(bus.peek::<u16>(0x10000 + i * 2), Some(0x10 + i as u16));
+    }
+
+    let activator = "c009c6e4 0001\n\
+                     80000100 1234";
+
+    let cheat = Cheat::new("test".to_string(), activator.to_string()).unwrap();
+    cheats.load(vec![cheat]);
+    cheats.apply(&mut bus);
+
+    assert_ne!(bus.peek::<u16>(0x100), Some(0x1234));
+
+    bus.store::<u16>(0x9c6e4, 1);
+    cheats.apply(&mut bus);
+
+    assert_eq!(bus.peek::<u16>(0x100), Some(0x1234));
+
+    assert!(Cheat::new("test".to_string(), "d0001000 0000".to_string()).is_err());
+    assert!(Cheat::new("test".to_string(), "50000502 0001".to_string()).is_err());
+    assert!(Cheat::new("test".to_string(), "f0001000 0000".to_string()).is_err());
+}
@@ -3,6 +3,7 @@
 //! - SIMD optimize.
 
 use crate::{dump, dump::Dumper};
+use crate::gpu::pgxp::PreciseVertex;
 use splst_util::{Bit, BitSet};
 
 use std::fmt;
@@ -11,6 +12,9 @@ use std::fmt;
 pub struct Gte {
     data: DataRegs,
     control: ControlRegs,
+    /// The screen xy coordinate FIFO with sub-pixel precision. Each mirrors `sxy0`, `sxy1`,
+    /// `sxy2` and `sxyp`.
+    precise: [PreciseVertex; 4],
 }
 
 impl Gte {
@@ -53,6 +57,13 @@ impl Gte {
                 self.data.sxy1 = self.data.sxy2;
                 self.data.sxy2 = self.data.sxyp;
 
+                self.precise.rotate_left(1);
+                self.precise[3] = PreciseVertex::from_sxy(val);
+
+                self.data.store_unchecked(offset, val);
+            }
+            12..=14 => unsafe {
+                self.precise[offset as usize - 12] = PreciseVertex::from_sxy(val);
                 self.data.store_unchecked(offset, val);
             }
             7 | 16..=19 => unsafe {
@@ -140,6 +151,16 @@ impl Gte {
         self.control.flags.update_error_flag();        
     }
 
+    /// The precise vertex of data register `offset` if it's one of the screen xy coordinates.
+    pub(super) fn precise_vertex(&self, offset: u32) -> Option<PreciseVertex> {
+        match offset {
+            12..=14 => Some(self.precise[offset as usize - 12]),
+            // `sxyp` is a mirror of `sxy2`.
+            15 => Some(self.precise[2]),
+            _ => None,
+        }
+    }
+
     pub fn data_regs(&self) -> &DataRegs {
         &self.data
     }
@@ -298,6 +319,10 @@ impl Gte {
         check_mac0_overflow(&mut self.control.flags, sx);
         check_mac0_overflow(&mut self.control.flags, sy);
 
+        // Keep the fractional part for sub-pixel precision.
+        let precise = |val: i64| (val as f64 / 65536.0).clamp(-1024.0, 1023.0) as f32;
+        let (precise_x, precise_y) = (precise(sx), precise(sy));
+
         let sx = (sx >> 16) as i32;
         let sy = (sy >> 16) as i32;
 
@@ -318,6 +343,17 @@ impl Gte {
         self.data.sxy1 = self.data.sxy2;
         self.data.sxy2 = self.data.sxyp;
 
+        self.precise[3] = PreciseVertex {
+            x: precise_x,
+            y: precise_y,
+            z: f32::from(z_saturated),
+            sxy: xy[0] as u16 as u32 | (xy[1] as u16 as u32) << 16,
+        };
+
+        self.precise[0] = self.precise[1];
+        self.precise[1] = self.precise[2];
+        self.precise[2] = self.precise[3];
+
         pf
     }
 
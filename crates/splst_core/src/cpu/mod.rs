@@ -26,6 +26,7 @@ use crate::{SysTime, Timestamp, VideoOutput, AudioOutput};
 use crate::schedule::Event;
 use crate::debug::Debugger;
 use crate::dump::Dumper;
+use crate::cheat::Cheats;
 
 use cop0::{Cop0, Exception};
 
@@ -94,6 +95,11 @@ impl Registers {
         self.0[0] = 0;
     }
 
+    /// The value of all registers, indexed by register number.
+    pub fn values(&self) -> &[u32; 32] {
+        &self.0
+    }
+
     pub fn dump(&self, printer: &mut impl Dumper) {
         for (label, val) in REGISTER_NAMES.iter().zip(self.0.iter()) {
             printer.dump_addr_unit(*label, *val)
@@ -168,6 +174,8 @@ pub struct Cpu {
     pub(super) bus: Bus,
     gte: Gte,
     cop0: Cop0,
+    /// Cheats applied at the start of each vblank.
+    cheats: Rc<RefCell<Cheats>>,
 }
 
 const PC_START_ADDRESS: u32 = 0xbfc00000;
@@ -180,6 +188,7 @@ impl Cpu {
         disc: Rc<RefCell<Disc>>,
         gamepads: Rc<RefCell<pad::GamePads>>,
         memcards: Rc<RefCell<memcard::MemCards>>,
+        cheats: Rc<RefCell<Cheats>>,
     ) -> Box<Self> {
         let bus = Bus::new(
             bios,
@@ -206,6 +215,7 @@ impl Cpu {
             icache,
             icache_misses: 0,
             bus,
+            cheats,
         })
     }
 
@@ -557,6 +567,10 @@ impl Cpu {
                 Event::Irq(irq) => {
                     dbg.irq(self, irq);
 
+                    if let Irq::VBlank = irq {
+                        self.cheats.borrow_mut().apply(&mut self.bus);
+                    }
+
                     self.bus.irq_state.trigger(irq);
                     self.check_for_pending_irq();
                 }
@@ -595,6 +609,10 @@ impl Cpu {
                         callback(&mut self.bus.gpu, &mut self.bus.schedule, &mut self.bus.timers);
                     }
                     Event::Irq(irq) => {
+                        if let Irq::VBlank = irq {
+                            self.cheats.borrow_mut().apply(&mut self.bus);
+                        }
+
                         self.bus.irq_state.trigger(irq);
                         self.check_for_pending_irq();
                     }
@@ -1689,8 +1707,13 @@ impl Cpu {
 
         self.fetch_load_slot();
 
-        if let Err(ex) = self.store::<u32, Dbg>(dbg, addr, val) {
-            self.throw_exception(ex);
+        match self.store::<u32, Dbg>(dbg, addr, val) {
+            Ok(()) => {
+                if let Some(vertex) = self.gte.precise_vertex(op.rt().index().into()) {
+                    self.bus.gpu.store_precise_vertex(addr, vertex);
+                }
+            }
+            Err(ex) => self.throw_exception(ex),
         }
     }
 
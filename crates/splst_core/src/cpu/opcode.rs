@@ -39,6 +39,12 @@ impl Opcode {
         Opcode(opcode)
     }
 
+    /// The raw 32-bit opcode.
+    #[inline(always)]
+    pub fn raw(self) -> u32 {
+        self.0
+    }
+
     /// Operation.
     #[inline(always)]
     pub fn op(self) -> u32 {
@@ -117,6 +123,31 @@ impl Opcode {
     pub fn update_ra_on_branch(self) -> bool {
         self.0.bit_range(17, 20) == 0x8
     }
+
+    /// If the opcode is a branch or jump, meaning that it's followed by a branch delay slot.
+    pub fn is_branch(self) -> bool {
+        match self.op() {
+            0x0 => matches!(self.special(), 0x8 | 0x9),
+            0x1..=0x7 => true,
+            _ => false,
+        }
+    }
+
+    /// If the opcode is a function call, which is any branch or jump that stores the return
+    /// address, i.e. JAL, JALR, BLTZAL and BGEZAL.
+    pub fn is_call(self) -> bool {
+        match self.op() {
+            0x0 => self.special() == 0x9,
+            0x1 => self.update_ra_on_branch(),
+            0x3 => true,
+            _ => false,
+        }
+    }
+
+    /// If the opcode returns from a function, i.e. `jr $ra`.
+    pub fn is_return(self) -> bool {
+        self.op() == 0x0 && self.special() == 0x8 && self.rs() == Register::RA
+    }
 }
 
 impl fmt::Display for Opcode {
@@ -0,0 +1,883 @@
+//! GPU command captures.
+//!
+//! A [`Capture`] records every write to GP0 and GP1 along with each node of linked list DMA
+//! transfers to the GPU for one or more frames. Together with VRAM and the GPU settings at the
+//! start of the capture, this is enough to replay the frames without the rest of the system.
+//! [`Replayer`] does just that one command at a time, which makes it possible to see exactly what
+//! each command does to VRAM and to reduce rasterizer bugs to small test cases.
+//!
+//! Recording starts at the first vblank after [`Gpu::start_capture`] where the GPU isn't in the
+//! middle of a command, and stops after the given number of frames.
+//!
+//! # Binary format
+//!
+//! The file starts with the magic [`MAGIC`] followed by a single version byte. After that follows
+//! VRAM at the start of the capture as 1024 * 512 halfwords, the number of setup records as an u32
+//! and the setup records, which restore the GPU settings before replaying. The rest of the file
+//! is records. Each record starts with an u8 tag, all integers are little endian:
+//!
+//! - 0: GP0 write, followed by the u32 value.
+//! - 1: GP1 write, followed by the u32 value.
+//! - 2: Linked list DMA node, followed by the u32 address and the u8 size in words.
+//! - 3: End of frame.
+
+use splst_util::Bit;
+use crate::schedule::{Event, Schedule};
+use crate::timer::Timers;
+
+use super::{gp0, Gpu, State, Vram};
+
+use thiserror::Error;
+
+use std::io::{self, Read, Write};
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::{fmt, mem};
+
+/// The magic bytes at the start of every capture file.
+pub const MAGIC: &[u8; 8] = b"SPLGPUCP";
+
+const VERSION: u8 = 1;
+
+#[derive(Error, Debug)]
+pub enum CaptureError {
+    #[error("failed to access capture: {0}")]
+    IoError(#[from] io::Error),
+    #[error("invalid capture file: {0}")]
+    InvalidBinary(&'static str),
+}
+
+/// Something recorded in a [`Capture`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CaptureEvent {
+    Gp0(u32),
+    Gp1(u32),
+    /// A node of a linked list DMA transfer. `size` is the number of words after the header.
+    DmaNode { addr: u32, size: u8 },
+    /// The end of a frame, when the GPU enters vblank.
+    Frame,
+}
+
+impl CaptureEvent {
+    fn write(self, out: &mut impl Write) -> io::Result<()> {
+        match self {
+            CaptureEvent::Gp0(val) => {
+                out.write_all(&[0])?;
+                out.write_all(&val.to_le_bytes())
+            }
+            CaptureEvent::Gp1(val) => {
+                out.write_all(&[1])?;
+                out.write_all(&val.to_le_bytes())
+            }
+            CaptureEvent::DmaNode { addr, size } => {
+                out.write_all(&[2])?;
+                out.write_all(&addr.to_le_bytes())?;
+                out.write_all(&[size])
+            }
+            CaptureEvent::Frame => out.write_all(&[3]),
+        }
+    }
+
+    /// Read a single record. Returns `None` at the end of the input.
+    fn read(input: &mut impl Read) -> Result<Option<Self>, CaptureError> {
+        let mut tag = [0x0; 1];
+        if input.read(&mut tag)? == 0 {
+            return Ok(None);
+        }
+        let event = match tag[0] {
+            0 => CaptureEvent::Gp0(read_u32(input)?),
+            1 => CaptureEvent::Gp1(read_u32(input)?),
+            2 => {
+                let addr = read_u32(input)?;
+                let mut size = [0x0; 1];
+                input.read_exact(&mut size)?;
+                CaptureEvent::DmaNode { addr, size: size[0] }
+            }
+            3 => CaptureEvent::Frame,
+            _ => return Err(CaptureError::InvalidBinary("invalid record tag")),
+        };
+        Ok(Some(event))
+    }
+}
+
+fn read_u32(input: &mut impl Read) -> io::Result<u32> {
+    let mut buf = [0x0; 4];
+    input.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+/// GPU commands recorded for one or more frames.
+#[derive(Clone)]
+pub struct Capture {
+    /// VRAM at the start of the capture.
+    pub vram: Box<Vram>,
+    /// GP0 and GP1 writes which restore the GPU settings from the start of the capture.
+    pub setup: Vec<CaptureEvent>,
+    pub events: Vec<CaptureEvent>,
+}
+
+impl Capture {
+    /// The number of whole frames recorded.
+    pub fn frames(&self) -> usize {
+        self.events
+            .iter()
+            .filter(|event| **event == CaptureEvent::Frame)
+            .count()
+    }
+
+    pub fn write(&self, mut out: impl Write) -> Result<(), CaptureError> {
+        out.write_all(MAGIC)?;
+        out.write_all(&[VERSION])?;
+
+        let vram: Vec<u8> = self.vram.data
+            .iter()
+            .flat_map(|val| val.to_le_bytes())
+            .collect();
+        out.write_all(&vram)?;
+
+        out.write_all(&(self.setup.len() as u32).to_le_bytes())?;
+        for event in self.setup.iter().chain(self.events.iter()) {
+            event.write(&mut out)?;
+        }
+
+        out.flush()?;
+        Ok(())
+    }
+
+    pub fn read(mut input: impl Read) -> Result<Self, CaptureError> {
+        let mut header = [0x0; 9];
+        input.read_exact(&mut header)?;
+        if &header[..8] != MAGIC {
+            return Err(CaptureError::InvalidBinary("missing magic"));
+        }
+        if header[8] != VERSION {
+            return Err(CaptureError::InvalidBinary("unsupported version"));
+        }
+
+        let mut bytes = vec![0x0; Vram::SIZE * 2];
+        input.read_exact(&mut bytes)?;
+
+        let mut vram = Box::new(Vram::new());
+        for (val, bytes) in vram.data.iter_mut().zip(bytes.chunks_exact(2)) {
+            *val = u16::from_le_bytes([bytes[0], bytes[1]]);
+        }
+
+        let setup_len = read_u32(&mut input)?;
+        let mut setup = Vec::new();
+        for _ in 0..setup_len {
+            match CaptureEvent::read(&mut input)? {
+                Some(event) => setup.push(event),
+                None => return Err(CaptureError::InvalidBinary("missing setup records")),
+            }
+        }
+
+        let mut events = Vec::new();
+        while let Some(event) = CaptureEvent::read(&mut input)? {
+            events.push(event);
+        }
+
+        Ok(Self { vram, setup, events })
+    }
+}
+
+/// The state of the capture on the GPU.
+pub(super) enum CaptureState {
+    Off,
+    /// Waiting for the next vblank to start recording a number of frames.
+    Pending(u32),
+    Recording {
+        capture: Capture,
+        frames_left: u32,
+    },
+    /// Done recording, but the capture hasn't been taken yet.
+    Done(Capture),
+}
+
+impl Gpu {
+    /// Capture all commands for the next `frames` frames. It replaces any capture not yet taken
+    /// by [`Gpu::take_capture`].
+    pub fn start_capture(&mut self, frames: u32) {
+        self.capture = CaptureState::Pending(frames.max(1));
+    }
+
+    /// The number of frames left to capture, if a capture is pending or being recorded.
+    pub fn capture_frames_left(&self) -> Option<u32> {
+        match self.capture {
+            CaptureState::Pending(frames) => Some(frames),
+            CaptureState::Recording { frames_left, .. } => Some(frames_left),
+            _ => None,
+        }
+    }
+
+    /// Stop capturing early. The frames recorded so far can still be taken by
+    /// [`Gpu::take_capture`].
+    pub fn stop_capture(&mut self) {
+        self.capture = match mem::replace(&mut self.capture, CaptureState::Off) {
+            CaptureState::Recording { capture, .. } => CaptureState::Done(capture),
+            CaptureState::Pending(_) => CaptureState::Off,
+            state => state,
+        };
+    }
+
+    /// Take the capture once it's done recording.
+    pub fn take_capture(&mut self) -> Option<Capture> {
+        match mem::replace(&mut self.capture, CaptureState::Off) {
+            CaptureState::Done(capture) => Some(capture),
+            state => {
+                self.capture = state;
+                None
+            }
+        }
+    }
+
+    pub(super) fn capture_event(&mut self, event: CaptureEvent) {
+        if let CaptureState::Recording { capture, .. } = &mut self.capture {
+            capture.events.push(event);
+        }
+    }
+
+    /// Start or end the capture at the end of a frame.
+    pub(super) fn capture_frame_end(&mut self) {
+        match self.capture {
+            CaptureState::Pending(frames) if self.is_between_commands() => {
+                self.sync_raster();
+                self.capture = CaptureState::Recording {
+                    capture: Capture {
+                        vram: self.vram.clone(),
+                        setup: self.capture_setup(),
+                        events: Vec::new(),
+                    },
+                    frames_left: frames,
+                };
+            }
+            CaptureState::Recording { ref mut capture, ref mut frames_left } => {
+                capture.events.push(CaptureEvent::Frame);
+                *frames_left -= 1;
+                if *frames_left == 0 {
+                    self.stop_capture();
+                }
+            }
+            _ => (),
+        }
+    }
+
+    /// If the GPU isn't in the middle of receiving or executing a command.
+    fn is_between_commands(&self) -> bool {
+        self.state.is_idle() && self.fifo.is_empty() && self.poly_line.is_none()
+    }
+
+    /// GP1 and GP0 commands to restore the current settings.
+    fn capture_setup(&self) -> Vec<CaptureEvent> {
+        let status = self.status.0;
+
+        let gp0 = |cmd: u32, val: u32| CaptureEvent::Gp0(cmd << 24 | val);
+        let gp1 = |cmd: u32, val: u32| CaptureEvent::Gp1(cmd << 24 | val);
+
+        vec![
+            gp1(0x09, self.allow_tex_disable as u32),
+            gp1(0x03, status.bit(23) as u32),
+            gp1(0x04, status.bit_range(29, 30)),
+            gp1(0x05, self.vram_x_start as u32 | (self.vram_y_start as u32) << 10),
+            gp1(0x06, self.dis_x_start as u32 | (self.dis_x_end as u32) << 12),
+            gp1(0x07, self.dis_y_start as u32 | (self.dis_y_end as u32) << 10),
+            gp1(0x08, status.bit_range(17, 22)
+                | (status.bit(16) as u32) << 6
+                | (status.bit(14) as u32) << 7
+            ),
+            gp0(0xe1, status.bit_range(0, 10)
+                | (status.bit(15) as u32) << 11
+                | (self.tex_x_flip as u32) << 12
+                | (self.tex_y_flip as u32) << 13
+            ),
+            gp0(0xe2, (self.tex_win_w as u32)
+                | (self.tex_win_h as u32) << 5
+                | (self.tex_win_x as u32) << 10
+                | (self.tex_win_y as u32) << 15
+            ),
+            gp0(0xe3, self.da_x_min as u32 | (self.da_y_min as u32) << 10),
+            gp0(0xe4, self.da_x_max as u32 | (self.da_y_max as u32) << 10),
+            gp0(0xe5, (self.x_offset as u32 & 0x7ff) | (self.y_offset as u32 & 0x7ff) << 11),
+            gp0(0xe6, status.bit_range(11, 12)),
+        ]
+    }
+}
+
+/// What a single [`Step`] of a replay did.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum StepKind {
+    /// A GP0 command and all its arguments and data. `continued` is set if the start of the
+    /// command was in the previous step, which happens if the command is split by for instance a
+    /// GP1 write.
+    Gp0 { words: Vec<u32>, continued: bool },
+    Gp1(u32),
+    DmaNode { addr: u32, size: u8 },
+    /// The end of a frame, counting from 1.
+    Frame(u32),
+}
+
+/// A single step of a replay.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Step {
+    pub index: usize,
+    pub kind: StepKind,
+    /// The draw offset when the step was executed.
+    pub draw_offset: (i16, i16),
+    /// The top left and bottom right corners of the draw area when the step was executed.
+    pub draw_area: ((i32, i32), (i32, i32)),
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "#{} ", self.index)?;
+        match &self.kind {
+            StepKind::Gp0 { words, continued: true } => {
+                write!(f, "GP0 continued, {} words", words.len())
+            }
+            StepKind::Gp0 { words, continued: false } => {
+                describe_gp0(f, words)?;
+                if matches!(words[0].bit_range(24, 31), 0x20..=0x7f) {
+                    let ((left, top), (right, bottom)) = self.draw_area;
+                    let (x, y) = self.draw_offset;
+                    write!(
+                        f,
+                        "\n    draw offset ({x}, {y}) draw area ({left}, {top}) - ({right}, {bottom})",
+                    )?;
+                }
+                Ok(())
+            }
+            StepKind::Gp1(val) => write!(f, "GP1({:02x}) {val:08x}", val.bit_range(24, 29)),
+            StepKind::DmaNode { addr, size } => {
+                write!(f, "DMA node at {addr:06x} with {size} words")
+            }
+            StepKind::Frame(frame) => write!(f, "end of frame {frame}"),
+        }
+    }
+}
+
+fn word(words: &[u32], idx: usize) -> u32 {
+    words.get(idx).copied().unwrap_or(0)
+}
+
+/// Sign extend the 11 bit coordinates of a vertex.
+fn fmt_point(val: u32) -> String {
+    let x = ((val << 21) as i32) >> 21;
+    let y = ((val.bit_range(16, 26) << 21) as i32) >> 21;
+    format!("({x}, {y})")
+}
+
+fn fmt_color(val: u32) -> String {
+    format!("#{:06x}", val & 0xff_ffff)
+}
+
+fn fmt_uv(val: u32) -> String {
+    format!("({}, {})", val.bit_range(0, 7), val.bit_range(8, 15))
+}
+
+fn fmt_coord(val: u32) -> String {
+    format!("({}, {})", val.bit_range(0, 15), val.bit_range(16, 31))
+}
+
+/// Format the CLUT position found in the upper halfword of the first texture coordinate.
+fn fmt_clut(val: u32) -> String {
+    format!("({}, {})", val.bit_range(16, 21) * 16, val.bit_range(22, 30))
+}
+
+/// Format texture page settings. `val` uses the same layout as GP0(e1).
+fn fmt_tex_page(val: u32) -> String {
+    let depth = match val.bit_range(7, 8) {
+        0 => "4-bit",
+        1 => "8-bit",
+        _ => "15-bit",
+    };
+    let blend = match val.bit_range(5, 6) {
+        0 => "B/2+F/2",
+        1 => "B+F",
+        2 => "B-F",
+        _ => "B+F/4",
+    };
+    format!("({}, {}) {depth} blend {blend}", val.bit_range(0, 3) * 64, val.bit(4) as u32 * 256)
+}
+
+fn texture_mode(cmd: u32) -> &'static str {
+    match (cmd.bit(2), cmd.bit(0)) {
+        (false, _) => "",
+        (true, false) => ", textured",
+        (true, true) => ", raw textured",
+    }
+}
+
+fn transparency(cmd: u32) -> &'static str {
+    if cmd.bit(1) { ", semi-transparent" } else { "" }
+}
+
+/// Describe the GP0 command in `words` along with its vertices.
+fn describe_gp0(f: &mut fmt::Formatter, words: &[u32]) -> fmt::Result {
+    let cmd = word(words, 0).bit_range(24, 31);
+
+    write!(f, "GP0({cmd:02x}) ")?;
+
+    match cmd {
+        0x01 => f.write_str("clear texture cache"),
+        0x02 => write!(
+            f,
+            "fill rectangle at {} size {} color {}",
+            fmt_coord(word(words, 1)),
+            fmt_coord(word(words, 2)),
+            fmt_color(word(words, 0)),
+        ),
+        0x20..=0x3f => {
+            let (shaded, textured) = (cmd.bit(4), cmd.bit(2));
+            let verts = if cmd.bit(3) { 4 } else { 3 };
+
+            write!(
+                f,
+                "{}{}{}{}",
+                if verts == 4 { "quad" } else { "triangle" },
+                if shaded { ", shaded" } else { "" },
+                texture_mode(cmd),
+                transparency(cmd),
+            )?;
+
+            if !shaded {
+                write!(f, " color {}", fmt_color(word(words, 0)))?;
+            }
+
+            let mut idx = 1;
+            let (mut clut, mut page) = (0, 0);
+
+            for vert in 0..verts {
+                let color = match (shaded, vert) {
+                    (true, 0) => Some(word(words, 0)),
+                    (true, _) => {
+                        idx += 1;
+                        Some(word(words, idx - 1))
+                    }
+                    (false, _) => None,
+                };
+
+                write!(f, "\n    v{vert} {}", fmt_point(word(words, idx)))?;
+                idx += 1;
+
+                if let Some(color) = color {
+                    write!(f, " color {}", fmt_color(color))?;
+                }
+
+                if textured {
+                    let uv = word(words, idx);
+                    idx += 1;
+                    match vert {
+                        0 => clut = uv,
+                        1 => page = uv.bit_range(16, 31),
+                        _ => (),
+                    }
+                    write!(f, " uv {}", fmt_uv(uv))?;
+                }
+            }
+
+            if textured {
+                write!(f, "\n    clut {} texture page {}", fmt_clut(clut), fmt_tex_page(page))?;
+            }
+
+            Ok(())
+        }
+        0x40..=0x5f => {
+            let (shaded, poly) = (cmd.bit(4), gp0::cmd_is_poly_line(cmd));
+
+            write!(
+                f,
+                "{}{}{}",
+                if poly { "poly-line" } else { "line" },
+                if shaded { ", shaded" } else { "" },
+                transparency(cmd),
+            )?;
+
+            write!(
+                f,
+                "\n    v0 {} color {}",
+                fmt_point(word(words, 1)),
+                fmt_color(word(words, 0)),
+            )?;
+
+            let mut idx = 2;
+            let mut vert = 1;
+
+            while idx < words.len() && (poly || vert < 2) {
+                if poly && gp0::is_poly_line_terminator(words[idx]) {
+                    break;
+                }
+
+                let color = if shaded {
+                    idx += 1;
+                    word(words, idx - 1)
+                } else {
+                    word(words, 0)
+                };
+
+                write!(
+                    f,
+                    "\n    v{vert} {} color {}",
+                    fmt_point(word(words, idx)),
+                    fmt_color(color),
+                )?;
+
+                idx += 1;
+                vert += 1;
+            }
+
+            Ok(())
+        }
+        0x60..=0x7f => {
+            let size = match cmd.bit_range(3, 4) {
+                0 => "variable size",
+                1 => "1x1",
+                2 => "8x8",
+                _ => "16x16",
+            };
+
+            write!(f, "rectangle {size}{}{}", texture_mode(cmd), transparency(cmd))?;
+            write!(
+                f,
+                "\n    v0 {} color {}",
+                fmt_point(word(words, 1)),
+                fmt_color(word(words, 0)),
+            )?;
+
+            let mut idx = 2;
+
+            if cmd.bit(2) {
+                let uv = word(words, idx);
+                write!(f, " uv {} clut {}", fmt_uv(uv), fmt_clut(uv))?;
+                idx += 1;
+            }
+
+            if cmd.bit_range(3, 4) == 0 {
+                write!(f, " size {}", fmt_coord(word(words, idx)))?;
+            }
+
+            Ok(())
+        }
+        0x80..=0x9f => write!(
+            f,
+            "copy rectangle from {} to {} size {}",
+            fmt_coord(word(words, 1)),
+            fmt_coord(word(words, 2)),
+            fmt_coord(word(words, 3)),
+        ),
+        0xa0..=0xbf => write!(
+            f,
+            "copy rectangle to VRAM at {} size {}, {} data words",
+            fmt_coord(word(words, 1)),
+            fmt_coord(word(words, 2)),
+            words.len().saturating_sub(3),
+        ),
+        0xc0..=0xdf => write!(
+            f,
+            "copy rectangle from VRAM at {} size {}",
+            fmt_coord(word(words, 1)),
+            fmt_coord(word(words, 2)),
+        ),
+        0xe1 => {
+            let val = word(words, 0);
+            write!(
+                f,
+                "draw mode texture page {}{}{}{}{}{}",
+                fmt_tex_page(val),
+                if val.bit(9) { ", dithering" } else { "" },
+                if val.bit(10) { ", draw to display" } else { "" },
+                if val.bit(11) { ", texture disable" } else { "" },
+                if val.bit(12) { ", x-flip" } else { "" },
+                if val.bit(13) { ", y-flip" } else { "" },
+            )
+        }
+        0xe2 => {
+            let val = word(words, 0);
+            write!(
+                f,
+                "texture window mask ({}, {}) offset ({}, {})",
+                val.bit_range(0, 4),
+                val.bit_range(5, 9),
+                val.bit_range(10, 14),
+                val.bit_range(15, 19),
+            )
+        }
+        0xe3 | 0xe4 => {
+            let val = word(words, 0);
+            write!(
+                f,
+                "draw area {} ({}, {})",
+                if cmd == 0xe3 { "top left" } else { "bottom right" },
+                val.bit_range(0, 9),
+                val.bit_range(10, 18),
+            )
+        }
+        0xe5 => {
+            let val = word(words, 0);
+            let x = ((val << 21) as i32) >> 21;
+            let y = ((val.bit_range(11, 21) << 21) as i32) >> 21;
+            write!(f, "draw offset ({x}, {y})")
+        }
+        0xe6 => {
+            let val = word(words, 0);
+            write!(
+                f,
+                "mask bit set {}, check {}",
+                val.bit(0),
+                val.bit(1),
+            )
+        }
+        _ => f.write_str("nop"),
+    }
+}
+
+/// Replays a [`Capture`] a step at a time.
+///
+/// Each GP0 command is executed to completion before the next one starts, so captures where
+/// immediate commands arrive while the GPU is busy drawing may not replay exactly the same.
+pub struct Replayer {
+    gpu: Gpu,
+    schedule: Schedule,
+    timers: Timers,
+    events: Vec<CaptureEvent>,
+    /// The index of the next event in `events`.
+    next: usize,
+    steps: usize,
+    frame: u32,
+}
+
+impl Replayer {
+    pub fn new(capture: &Capture) -> Self {
+        let mut schedule = Schedule::new();
+        let mut gpu = Gpu::new(&mut schedule, Rc::new(RefCell::new(())));
+
+        gpu.vram = capture.vram.clone();
+
+        let mut replayer = Self {
+            gpu,
+            schedule,
+            timers: Timers::new(),
+            events: capture.events.clone(),
+            next: 0,
+            steps: 0,
+            frame: 0,
+        };
+
+        for event in &capture.setup {
+            replayer.execute(*event);
+        }
+
+        replayer
+    }
+
+    pub fn gpu(&self) -> &Gpu {
+        &self.gpu
+    }
+
+    pub fn vram(&self) -> &Vram {
+        self.gpu.vram()
+    }
+
+    /// The number of steps executed so far.
+    pub fn steps(&self) -> usize {
+        self.steps
+    }
+
+    fn execute(&mut self, event: CaptureEvent) {
+        match event {
+            CaptureEvent::Gp0(val) => self.gpu.gp0_store(&mut self.schedule, val),
+            CaptureEvent::Gp1(val) => self.gpu.gp1_store(&mut self.schedule, val),
+            _ => (),
+        }
+        self.run_events();
+    }
+
+    /// Run events until the GPU is done with the command being executed.
+    fn run_events(&mut self) {
+        loop {
+            while let Some(event) = self.schedule.get_pending_event() {
+                match event {
+                    Event::Gpu(callback) => {
+                        callback(&mut self.gpu, &mut self.schedule, &mut self.timers);
+                    }
+                    Event::Timer(id, callback) => {
+                        callback(&mut self.timers, &mut self.schedule, id);
+                    }
+                    // Only the GPU and timers are around when replaying.
+                    _ => (),
+                }
+            }
+
+            // Reads from GPUREAD aren't captured, so transfers from VRAM are simply read right
+            // away. The limit is there in case the transfer never ends.
+            for _ in 0..Vram::SIZE / 2 {
+                if !self.gpu.state.is_vram_load() {
+                    break;
+                }
+                self.gpu.gpu_read(&mut self.schedule);
+            }
+
+            if let State::Drawing = self.gpu.state {
+                self.schedule.skip_to_next_event();
+            } else {
+                break;
+            }
+        }
+    }
+
+    /// Execute the next step. Returns `None` when the whole capture has been replayed.
+    pub fn step(&mut self) -> Option<Step> {
+        let event = *self.events.get(self.next)?;
+
+        self.next += 1;
+
+        let draw_offset = (self.gpu.x_offset, self.gpu.y_offset);
+        let draw_area = (
+            (self.gpu.da_x_min, self.gpu.da_y_min),
+            (self.gpu.da_x_max, self.gpu.da_y_max),
+        );
+
+        let kind = match event {
+            CaptureEvent::Gp0(val) => {
+                let continued = !self.gpu.is_between_commands();
+                let mut words = vec![val];
+
+                self.execute(event);
+
+                // Keep going until the command is done or there isn't any more GP0 writes.
+                while !self.gpu.is_between_commands() {
+                    let Some(&CaptureEvent::Gp0(val)) = self.events.get(self.next) else {
+                        break;
+                    };
+                    self.next += 1;
+                    words.push(val);
+                    self.execute(CaptureEvent::Gp0(val));
+                }
+
+                StepKind::Gp0 { words, continued }
+            }
+            CaptureEvent::Gp1(val) => {
+                self.execute(event);
+                StepKind::Gp1(val)
+            }
+            CaptureEvent::DmaNode { addr, size } => StepKind::DmaNode { addr, size },
+            CaptureEvent::Frame => {
+                self.frame += 1;
+                StepKind::Frame(self.frame)
+            }
+        };
+
+        let step = Step {
+            index: self.steps,
+            kind,
+            draw_offset,
+            draw_area,
+        };
+
+        self.steps += 1;
+
+        Some(step)
+    }
+}
+
+/// The area of VRAM changed between two snapshots.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct VramDiff {
+    /// The number of halfwords changed.
+    pub changed: usize,
+    pub top_left: (i32, i32),
+    pub bottom_right: (i32, i32),
+}
+
+impl VramDiff {
+    /// Returns `None` if `before` and `after` are the same.
+    pub fn new(before: &Vram, after: &Vram) -> Option<Self> {
+        let mut diff: Option<Self> = None;
+
+        let changed = before.data
+            .iter()
+            .zip(after.data.iter())
+            .enumerate()
+            .filter(|(_, (a, b))| a != b);
+
+        for (idx, _) in changed {
+            let (x, y) = ((idx % 1024) as i32, (idx / 1024) as i32);
+            let diff = diff.get_or_insert(Self {
+                changed: 0,
+                top_left: (x, y),
+                bottom_right: (x, y),
+            });
+            diff.changed += 1;
+            diff.top_left = (diff.top_left.0.min(x), diff.top_left.1.min(y));
+            diff.bottom_right = (diff.bottom_right.0.max(x), diff.bottom_right.1.max(y));
+        }
+
+        diff
+    }
+}
+
+impl fmt::Display for VramDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let ((left, top), (right, bottom)) = (self.top_left, self.bottom_right);
+        write!(
+            f,
+            "changed {} halfwords in ({left}, {top}) - ({right}, {bottom})",
+            self.changed,
+        )
+    }
+}
+
+#[test]
+fn capture_and_replay() {
+    let mut schedule = Schedule::new();
+    let mut gpu = Gpu::new(&mut schedule, Rc::new(RefCell::new(())));
+
+    // Set up the draw area and offset before starting the capture, which should be restored
+    // when replaying.
+    gpu.store::<u32>(&mut schedule, 0, 0xe3000000);
+    gpu.store::<u32>(&mut schedule, 0, 0xe4040100);
+    gpu.store::<u32>(&mut schedule, 0, 0xe5002808);
+
+    gpu.start_capture(1);
+    gpu.capture_frame_end();
+
+    // A flat triangle and a GP1 write.
+    gpu.store::<u32>(&mut schedule, 0, 0x2000ff00);
+    gpu.store::<u32>(&mut schedule, 0, 0x00000000);
+    gpu.store::<u32>(&mut schedule, 0, 0x00000020);
+    gpu.store::<u32>(&mut schedule, 0, 0x00200000);
+    gpu.store::<u32>(&mut schedule, 4, 0x03000000);
+
+    assert_eq!(gpu.capture_frames_left(), Some(1));
+    gpu.capture_frame_end();
+    assert_eq!(gpu.capture_frames_left(), None);
+
+    let capture = gpu.take_capture().expect("capture should be done");
+    assert!(gpu.take_capture().is_none());
+    assert_eq!(capture.frames(), 1);
+
+    let mut file = Vec::new();
+    capture.write(&mut file).unwrap();
+    let read = Capture::read(file.as_slice()).unwrap();
+
+    assert_eq!(read.setup, capture.setup);
+    assert_eq!(read.events, capture.events);
+    assert!(read.vram.data == capture.vram.data);
+
+    let mut replayer = Replayer::new(&read);
+
+    let step = replayer.step().unwrap();
+    assert_eq!(step.draw_offset, (8, 5));
+    assert_eq!(step.kind, StepKind::Gp0 {
+        words: vec![0x2000ff00, 0x00000000, 0x00000020, 0x00200000],
+        continued: false,
+    });
+
+    assert_eq!(replayer.step().unwrap().kind, StepKind::Gp1(0x03000000));
+    assert_eq!(replayer.step().unwrap().kind, StepKind::Frame(1));
+    assert!(replayer.step().is_none());
+
+    // The triangle should be drawn with the draw offset from before the capture.
+    let diff = VramDiff::new(&capture.vram, replayer.vram()).unwrap();
+    assert!(diff.top_left.0 >= 8 && diff.top_left.1 >= 5);
+    assert!(diff.bottom_right.0 <= 40 && diff.bottom_right.1 <= 37);
+    assert!(replayer.vram().data == gpu.vram().data);
+}
@@ -15,12 +15,26 @@ use std::ops::Index;
 ///
 /// To do that, it must keep track of which command it has recieved and is waiting for arguments
 /// for, which complicates the emulation a bit.
+///
+/// Poly-line commands don't have a fixed length, but instead take vertices until a terminator
+/// word is received. Since they can be longer than the FIFO, the GPU draws them a segment at a
+/// time as the vertices arrive.
 pub struct Fifo {
     data: [u32; Self::SIZE],
     head: u32,
     tail: u32,
     /// It has recived a non-immidiate command and is waiting for arguments.
-    cmd_words_left: Option<u8>,
+    cmd_words: Option<CmdWords>,
+}
+
+/// The arguments a command is waiting for.
+#[derive(Clone, Copy)]
+enum CmdWords {
+    /// A command with a fixed number of words left.
+    Fixed(u8),
+    /// A poly-line command, which takes words until a terminator is received. `words` is the
+    /// number of words received so far.
+    PolyLine { shaded: bool, words: u32 },
 }
 
 impl Fifo {
@@ -31,7 +45,7 @@ impl Fifo {
             data: [0x0; Self::SIZE],
             head: 0,
             tail: 0,
-            cmd_words_left: None,
+            cmd_words: None,
         }
     }
 
@@ -49,6 +63,7 @@ impl Fifo {
 
     pub fn clear(&mut self) {
         self.tail = self.head;
+        self.cmd_words = None;
     }
 
     fn push_internal(&mut self, val: u32) {
@@ -84,8 +99,8 @@ impl Fifo {
             return None;
         }
 
-        let words_left = match self.cmd_words_left.take() {
-            Some(words_left) => words_left,
+        let cmd_words = match self.cmd_words.take() {
+            Some(cmd_words) => cmd_words,
             None => {
                 let cmd = val.bit_range(24, 31);
 
@@ -93,17 +108,46 @@ impl Fifo {
                     return Some(PushAction::ImmCmd);
                 }
 
-                gp0::cmd_fifo_len(cmd)
+                if gp0::cmd_is_poly_line(cmd) {
+                    CmdWords::PolyLine { shaded: cmd.bit(4), words: 0 }
+                } else {
+                    CmdWords::Fixed(gp0::cmd_fifo_len(cmd))
+                }
             }
         };
 
         self.push_internal(val);
 
-        match words_left - 1 {
-            0 => Some(PushAction::FullCmd),
-            words => {
-                self.cmd_words_left = Some(words);
-                None
+        match cmd_words {
+            CmdWords::Fixed(words_left) => match words_left - 1 {
+                0 => Some(PushAction::FullCmd),
+                words => {
+                    self.cmd_words = Some(CmdWords::Fixed(words));
+                    None
+                }
+            }
+            CmdWords::PolyLine { shaded, words } => {
+                // The first word is the command and color, and the second the first vertex.
+                // After that each vertex is a color if shaded followed by a position. The
+                // terminator can replace the first word of any vertex after the first.
+                let vertex_len = if shaded { 2 } else { 1 };
+                let vertex_word = words.saturating_sub(2) % vertex_len;
+
+                if words >= 2 && vertex_word == 0 && gp0::is_poly_line_terminator(val) {
+                    return Some(PushAction::FullCmd);
+                }
+
+                self.cmd_words = Some(CmdWords::PolyLine {
+                    shaded,
+                    words: words.saturating_add(1),
+                });
+
+                // Signal when a new vertex has been received, so that the segment can be drawn.
+                if words >= 2 && vertex_word == vertex_len - 1 {
+                    Some(PushAction::FullCmd)
+                } else {
+                    None
+                }
             }
         }
     }
@@ -132,7 +176,24 @@ impl Fifo {
     }
 
     pub fn has_full_cmd(&self) -> bool {
-        self.next_cmd_len().map_or(false, |len| len <= self.len())
+        match self.next_cmd() {
+            // A poly-line only has to have it's first segment, or the terminator in place of the
+            // second vertex.
+            Some(cmd) if gp0::cmd_is_poly_line(cmd) => {
+                self.len() >= 3 && (gp0::is_poly_line_terminator(self[2])
+                    || self.len() >= gp0::cmd_fifo_len(cmd))
+            }
+            _ => self.next_cmd_len().map_or(false, |len| len <= self.len()),
+        }
+    }
+
+    /// If the FIFO contains the next vertex or the terminator of a poly-line being drawn.
+    pub fn has_poly_line_vertex(&self, shaded: bool) -> bool {
+        match self.len() {
+            0 => false,
+            1 => !shaded || gp0::is_poly_line_terminator(self[0]),
+            _ => true,
+        }
     }
 }
 
@@ -154,7 +215,7 @@ pub enum PushAction {
     /// This can only be the case if the FIFO isn't expecting the argument to a previous command.
     ImmCmd,
     /// If the pushed value was the last argument to a command and this a command is ready to be
-    /// executed.
+    /// executed. For poly-lines this happens for each vertex and the terminator.
     FullCmd,
 }
 
@@ -263,3 +324,31 @@ fn cmd2() {
     assert!(matches!(fifo.push_cmd(0x0), Some(PushAction::FullCmd)));
     assert!(matches!(fifo.push_cmd(0x0), Some(PushAction::ImmCmd)));
 }
+
+#[test]
+fn poly_line_cmd() {
+    use splst_util::BitSet;
+    let mut fifo = Fifo::new();
+
+    // Shaded poly-line with three vertices.
+    assert_eq!(fifo.push_cmd(0x0_u32.set_bit_range(24, 31, 0x58)), None);
+    assert_eq!(fifo.push_cmd(0x0), None);
+
+    assert_eq!(fifo.push_cmd(0x0), None);
+    assert_eq!(fifo.push_cmd(0x0), Some(PushAction::FullCmd));
+
+    assert!(fifo.has_full_cmd());
+
+    assert_eq!(fifo.push_cmd(0x0), None);
+    assert_eq!(fifo.push_cmd(0x0), Some(PushAction::FullCmd));
+
+    // The terminator is only checked at the start of a vertex.
+    assert_eq!(fifo.push_cmd(0x0), None);
+    assert_eq!(fifo.push_cmd(0x5555_5555), Some(PushAction::FullCmd));
+
+    assert_eq!(fifo.push_cmd(0x5000_5000), Some(PushAction::FullCmd));
+    assert_eq!(fifo.len(), 9);
+
+    // The poly-line is done, so the next word should be a new command.
+    assert_eq!(fifo.push_cmd(0x0), Some(PushAction::ImmCmd));
+}
@@ -0,0 +1,394 @@
+//! Post-processing filters run on the CPU.
+//!
+//! The filters take the decoded display area, see [`display_image`](super::display_image), so
+//! they look the same in the window, in screenshots and in recordings. Most of them scale the
+//! image by a fixed factor, and the result is then scaled to the final size with nearest
+//! neighbour, see [`Image::resize`].
+
+use serde::{Serialize, Deserialize};
+use thiserror::Error;
+
+use super::Image;
+
+use std::fmt;
+use std::str::FromStr;
+
+type Pixel = [u8; 3];
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Filter {
+    /// Leave the image as it is, so it's only scaled with nearest neighbour.
+    #[default]
+    Nearest,
+    /// Repeat each pixel a number of times in both directions. This avoids uneven pixels when
+    /// the final size is a multiple of the display area.
+    Integer(u32),
+    /// The Scale2x pixel art scaler, which rounds off diagonal edges without blending colors.
+    Scale2x,
+    /// Like [`Filter::Scale2x`], but three times the size.
+    Scale3x,
+    /// The 2xBR pixel art scaler, which blends colors along edges to smooth them.
+    Xbr,
+    /// Scale by 3 and darken every third line and the gaps between the red, green and blue
+    /// phosphors, like a CRT with an aperture grille.
+    Scanlines,
+}
+
+#[derive(Error, Debug)]
+#[error("unknown filter '{0}'")]
+pub struct UnknownFilter(String);
+
+impl Filter {
+    /// The factor the filter scales images by.
+    pub fn scale(self) -> u32 {
+        match self {
+            Filter::Nearest => 1,
+            Filter::Integer(scale) => scale.max(1),
+            Filter::Scale2x | Filter::Xbr => 2,
+            Filter::Scale3x | Filter::Scanlines => 3,
+        }
+    }
+
+    pub fn apply(self, image: &Image) -> Image {
+        match self {
+            Filter::Nearest => image.clone(),
+            Filter::Integer(_) => {
+                let scale = self.scale();
+                image.resize(image.width * scale, image.height * scale)
+            }
+            Filter::Scale2x => scale2x(image),
+            Filter::Scale3x => scale3x(image),
+            Filter::Xbr => xbr(image),
+            Filter::Scanlines => scanlines(image),
+        }
+    }
+}
+
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Filter::Nearest => f.write_str("Nearest"),
+            Filter::Integer(scale) => write!(f, "Integer {scale}x"),
+            Filter::Scale2x => f.write_str("Scale2x"),
+            Filter::Scale3x => f.write_str("Scale3x"),
+            Filter::Xbr => f.write_str("xBR"),
+            Filter::Scanlines => f.write_str("Scanlines"),
+        }
+    }
+}
+
+impl FromStr for Filter {
+    type Err = UnknownFilter;
+
+    /// Parse the name of a filter in lower case, or `<n>x` for integer scaling.
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        let filter = match name {
+            "nearest" => Filter::Nearest,
+            "scale2x" => Filter::Scale2x,
+            "scale3x" => Filter::Scale3x,
+            "xbr" => Filter::Xbr,
+            "scanlines" => Filter::Scanlines,
+            _ => name
+                .strip_suffix('x')
+                .and_then(|scale| scale.parse().ok())
+                .filter(|scale| *scale > 0)
+                .map(Filter::Integer)
+                .ok_or_else(|| UnknownFilter(name.to_string()))?,
+        };
+        Ok(filter)
+    }
+}
+
+impl Image {
+    /// Scale the image to `width` by `height` pixels with nearest neighbour. An empty image
+    /// becomes black.
+    pub fn resize(&self, width: u32, height: u32) -> Image {
+        let mut data = vec![0; (width * height * 3) as usize];
+
+        if self.width != 0 && self.height != 0 {
+            for y in 0..height {
+                let src_y = y * self.height / height;
+                for x in 0..width {
+                    let src_x = x * self.width / width;
+                    let src = (src_x + src_y * self.width) as usize * 3;
+                    let dst = (x + y * width) as usize * 3;
+                    data[dst..dst + 3].copy_from_slice(&self.data[src..src + 3]);
+                }
+            }
+        }
+
+        Image { width, height, data }
+    }
+
+    /// The pixel at `x` and `y`, clamped to the edges of the image.
+    fn pixel(&self, x: i32, y: i32) -> Pixel {
+        let x = x.clamp(0, self.width as i32 - 1) as usize;
+        let y = y.clamp(0, self.height as i32 - 1) as usize;
+        let i = (x + y * self.width as usize) * 3;
+        [self.data[i], self.data[i + 1], self.data[i + 2]]
+    }
+}
+
+/// Scale `image` by `N`, where each pixel is turned into a block of `N` by `N` pixels by
+/// `block`. `block` is given a function to get the pixels around the one being scaled by
+/// their offset.
+fn scale_blocks<const N: usize>(
+    image: &Image,
+    block: impl Fn(&dyn Fn(i32, i32) -> Pixel) -> [[Pixel; N]; N],
+) -> Image {
+    let width = image.width as usize * N;
+    let height = image.height as usize * N;
+    let mut data = vec![0; width * height * 3];
+
+    for y in 0..image.height as usize {
+        for x in 0..image.width as usize {
+            let block = block(&|dx, dy| image.pixel(x as i32 + dx, y as i32 + dy));
+            for (row, line) in block.iter().enumerate() {
+                for (col, pixel) in line.iter().enumerate() {
+                    let i = (x * N + col + (y * N + row) * width) * 3;
+                    data[i..i + 3].copy_from_slice(pixel);
+                }
+            }
+        }
+    }
+
+    Image { width: width as u32, height: height as u32, data }
+}
+
+// The pixel names used by the scalers below:
+//
+//   A B C
+//   D E F
+//   G H I
+//
+// where E is the pixel being scaled.
+
+fn scale2x(image: &Image) -> Image {
+    scale_blocks(image, |p| {
+        let (b, d, e, f, h) = (p(0, -1), p(-1, 0), p(0, 0), p(1, 0), p(0, 1));
+        if b == h || d == f {
+            return [[e; 2]; 2];
+        }
+        let pick = |cond: bool, pixel: Pixel| if cond { pixel } else { e };
+        [
+            [pick(d == b, d), pick(b == f, f)],
+            [pick(d == h, d), pick(h == f, f)],
+        ]
+    })
+}
+
+fn scale3x(image: &Image) -> Image {
+    scale_blocks(image, |p| {
+        let [a, b, c] = [p(-1, -1), p(0, -1), p(1, -1)];
+        let [d, e, f] = [p(-1, 0), p(0, 0), p(1, 0)];
+        let [g, h, i] = [p(-1, 1), p(0, 1), p(1, 1)];
+        if b == h || d == f {
+            return [[e; 3]; 3];
+        }
+        let pick = |cond: bool, pixel: Pixel| if cond { pixel } else { e };
+        [
+            [
+                pick(d == b, d),
+                pick((d == b && e != c) || (b == f && e != a), b),
+                pick(b == f, f),
+            ],
+            [
+                pick((d == b && e != g) || (d == h && e != a), d),
+                e,
+                pick((b == f && e != i) || (h == f && e != c), f),
+            ],
+            [
+                pick(d == h, d),
+                pick((d == h && e != i) || (h == f && e != g), h),
+                pick(h == f, f),
+            ],
+        ]
+    })
+}
+
+/// The difference between two pixels in Y, U and V.
+fn yuv_diff(p: Pixel, q: Pixel) -> [i32; 3] {
+    let [r, g, b] = [0, 1, 2].map(|i| p[i] as i32 - q[i] as i32);
+    [
+        (77 * r + 150 * g + 29 * b) >> 8,
+        (-43 * r - 85 * g + 128 * b) >> 8,
+        (128 * r - 107 * g - 21 * b) >> 8,
+    ]
+}
+
+fn distance(a: Pixel, b: Pixel) -> i32 {
+    yuv_diff(a, b).iter().map(|diff| diff.abs()).sum()
+}
+
+/// If two pixels look about the same.
+fn similar(a: Pixel, b: Pixel) -> bool {
+    let [y, u, v] = yuv_diff(a, b).map(i32::abs);
+    y <= 48 && u <= 7 && v <= 6
+}
+
+/// Blend `src` into `dst` by `weight` out of 256.
+fn blend(dst: &mut Pixel, src: Pixel, weight: u32) {
+    for (dst, src) in dst.iter_mut().zip(src) {
+        *dst = ((*dst as u32 * (256 - weight) + src as u32 * weight) / 256) as u8;
+    }
+}
+
+/// 2xBR by Hyllian. It looks for edges around each corner of a pixel by comparing the distance
+/// along the two diagonals, and blends the corner with the neighbour on the other side of the
+/// edge. The shape of the blend depends on the angle of the edge.
+fn xbr(image: &Image) -> Image {
+    scale_blocks(image, |p| {
+        let mut block = [[p(0, 0); 2]; 2];
+
+        // Each corner is handled as the bottom right one by rotating the pixels around it.
+        for turns in 0..4 {
+            let rotate = |x: i32, y: i32| (0..turns).fold((x, y), |(x, y), _| (y, -x));
+            let at = |x, y| {
+                let (x, y) = rotate(x, y);
+                p(x, y)
+            };
+
+            let (b, c, d, e, f) = (at(0, -1), at(1, -1), at(-1, 0), at(0, 0), at(1, 0));
+            let (g, h, i) = (at(-1, 1), at(0, 1), at(1, 1));
+            let (f4, i4, h5, i5) = (at(2, 0), at(2, 1), at(0, 2), at(1, 2));
+
+            if e == h || e == f {
+                continue;
+            }
+
+            let corner = |x, y| {
+                let (x, y) = rotate(x, y);
+                ((y > 0) as usize, (x > 0) as usize)
+            };
+
+            let (n3, n2, n1) = (corner(1, 1), corner(-1, 1), corner(1, -1));
+            let px = if distance(e, f) <= distance(e, h) { f } else { h };
+
+            let edge_e = distance(e, c)
+                + distance(e, g)
+                + distance(i, h5)
+                + distance(i, f4)
+                + 4 * distance(h, f);
+            let edge_i = distance(h, d)
+                + distance(h, i5)
+                + distance(f, i4)
+                + distance(f, b)
+                + 4 * distance(e, i);
+
+            let is_edge = (!similar(f, b) && !similar(h, d))
+                || (similar(e, i) && !similar(f, i4) && !similar(h, i5))
+                || similar(e, g)
+                || similar(e, c);
+
+            if edge_e < edge_i && is_edge {
+                let ke = distance(f, g);
+                let ki = distance(h, c);
+                let left = 2 * ke <= ki && e != g && d != g;
+                let up = ke >= 2 * ki && e != c && b != c;
+
+                match (left, up) {
+                    (true, true) => {
+                        blend(&mut block[n3.0][n3.1], px, 224);
+                        blend(&mut block[n2.0][n2.1], px, 64);
+                        block[n1.0][n1.1] = block[n2.0][n2.1];
+                    }
+                    (true, false) => {
+                        blend(&mut block[n3.0][n3.1], px, 192);
+                        blend(&mut block[n2.0][n2.1], px, 64);
+                    }
+                    (false, true) => {
+                        blend(&mut block[n3.0][n3.1], px, 192);
+                        blend(&mut block[n1.0][n1.1], px, 64);
+                    }
+                    (false, false) => blend(&mut block[n3.0][n3.1], px, 128),
+                }
+            } else if edge_e <= edge_i {
+                blend(&mut block[n3.0][n3.1], px, 128);
+            }
+        }
+
+        block
+    })
+}
+
+fn scanlines(image: &Image) -> Image {
+    scale_blocks(image, |p| {
+        let mut block = [[p(0, 0); 3]; 3];
+        for (row, line) in block.iter_mut().enumerate() {
+            for (col, pixel) in line.iter_mut().enumerate() {
+                // Each column is the phosphor of a single color, and the last line is the gap
+                // between scanlines.
+                for (channel, val) in pixel.iter_mut().enumerate() {
+                    let mut weight = if channel == col { 256 } else { 160 };
+                    if row == 2 {
+                        weight /= 2;
+                    }
+                    *val = (*val as u32 * weight / 256) as u8;
+                }
+            }
+        }
+        block
+    })
+}
+
+#[test]
+fn filter_sizes() {
+    let image = Image {
+        width: 3,
+        height: 2,
+        data: vec![200; 3 * 2 * 3],
+    };
+    let filters = [
+        Filter::Nearest,
+        Filter::Integer(4),
+        Filter::Scale2x,
+        Filter::Scale3x,
+        Filter::Xbr,
+        Filter::Scanlines,
+    ];
+    for filter in filters {
+        let scaled = filter.apply(&image);
+        let scale = filter.scale();
+
+        assert_eq!((scaled.width, scaled.height), (3 * scale, 2 * scale), "{filter}");
+        assert_eq!(scaled.data.len(), (scaled.width * scaled.height * 3) as usize);
+
+        // All but the scanlines should leave a flat image alone.
+        if filter != Filter::Scanlines {
+            assert!(scaled.data.iter().all(|val| *val == 200), "{filter}");
+        }
+    }
+    assert_eq!("3x".parse::<Filter>().unwrap(), Filter::Integer(3));
+    assert!("0x".parse::<Filter>().is_err());
+}
+
+#[test]
+fn diagonal_edges() {
+    // A white triangle in the bottom left corner of black.
+    let (w, b) = ([255; 3], [0; 3]);
+    let pixels = [
+        [w, b, b, b],
+        [w, w, b, b],
+        [w, w, w, b],
+        [w, w, w, w],
+    ];
+    let image = Image {
+        width: 4,
+        height: 4,
+        data: pixels.iter().flatten().flatten().copied().collect(),
+    };
+
+    // Scale2x moves the corners of the pixels along the edge over to the other color.
+    let scaled = Filter::Scale2x.apply(&image);
+    assert_eq!(scaled.pixel(2, 1), w);
+    assert_eq!(scaled.pixel(2, 0), b);
+    assert_eq!(scaled.pixel(3, 1), b);
+    assert_eq!(scaled.pixel(3, 2), b);
+    assert_eq!(scaled.pixel(2, 2), w);
+
+    // 2xBR blends them instead.
+    let scaled = Filter::Xbr.apply(&image);
+    assert!(scaled.data.iter().any(|val| *val > 0 && *val < 255));
+    assert_eq!(scaled.pixel(0, 7), w);
+    assert_eq!(scaled.pixel(7, 0), b);
+}
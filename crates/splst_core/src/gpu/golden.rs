@@ -0,0 +1,372 @@
+//! Golden image tests for the rasterizer.
+//!
+//! Each test draws a grid of primitives, one for each combination of modes, by replaying a
+//! stream of GP0 commands on a fresh GPU. The drawn part of VRAM is then compared against a
+//! reference image in `src/gpu/golden/`. The images are 16-bit binary PGM files holding the raw
+//! VRAM halfwords, so they are exact including the mask bit.
+//!
+//! The images are regression snapshots written by this rasterizer, not captures from hardware,
+//! so they only catch changes in the output, not whether it's right. Nothing in the tree is
+//! checked against hardware yet. The targeted tests next to the rasterizer, such as
+//! `shading_model`, check it against models written from other emulators.
+//!
+//! When the output of the rasterizer is meant to change, run the tests with the `SPLST_BLESS`
+//! environment variable set to write new reference images, and say why in the commit.
+
+use splst_util::BitSet;
+
+use super::capture::{Capture, CaptureEvent, Replayer, VramDiff};
+use super::Vram;
+
+use std::path::PathBuf;
+use std::{env, fs};
+
+/// The texture modes of primitives. `None` is untextured, otherwise it's if the texture is raw
+/// and the texel depth.
+const TEXTURES: [Option<(bool, u32)>; 7] = [
+    None,
+    Some((false, 0)),
+    Some((false, 1)),
+    Some((false, 2)),
+    Some((true, 0)),
+    Some((true, 1)),
+    Some((true, 2)),
+];
+
+/// Opaque or one of the four blend modes.
+const TRANSPARENCY: [Option<u32>; 5] = [None, Some(0), Some(1), Some(2), Some(3)];
+
+/// Colors used for each vertex of shaded primitives.
+const COLORS: [u32; 4] = [0x2040f0, 0xf04020, 0x40f020, 0x808080];
+
+/// The color of primitives which aren't shaded.
+const FLAT_COLOR: u32 = 0x60a0c0;
+
+/// Build VRAM with a background pattern to blend against and a 32x32 texture for each texel
+/// depth. The textures are at x 512, 640 and 768 and the CLUTs at line 480 and 481.
+fn test_vram() -> Box<Vram> {
+    let mut vram = Box::new(Vram::new());
+
+    for y in 0..512 {
+        for x in 0..512 {
+            let color = (x / 4 % 32) | (y / 4 % 32) << 5 | ((x + y) / 8 % 32) << 10;
+            vram.store_16(x, y, color as u16);
+        }
+    }
+
+    // Entry 0 is fully transparent and some entries have the semi-transparency bit set.
+    for idx in 0..256 {
+        let color = match idx {
+            0 => 0x0,
+            idx => (idx * 7 % 32) | (idx * 13 % 32) << 5 | (idx * 3 % 32) << 10,
+        };
+        let color = (color as u16).set_bit(15, idx % 4 == 3);
+        if idx < 16 {
+            vram.store_16(512 + idx, 480, color);
+        }
+        vram.store_16(512 + idx, 481, color);
+    }
+
+    for v in 0..32 {
+        for u in 0..32 {
+            let idx = (u / 4 + v / 4) % 16;
+            let val = vram.load_16(512 + u / 4, v) | (idx << (u % 4 * 4)) as u16;
+            vram.store_16(512 + u / 4, v, val);
+
+            let idx = (u * 7 + v * 3) % 256;
+            let val = vram.load_16(640 + u / 2, v) | (idx << (u % 2 * 8)) as u16;
+            vram.store_16(640 + u / 2, v, val);
+
+            let color = match (u + v) % 11 {
+                0 => 0x0,
+                _ => (u | v << 5 | (31 - u) << 10) as u16,
+            };
+            vram.store_16(768 + u, v, color.set_bit(15, (u ^ v) & 8 != 0));
+        }
+    }
+
+    vram
+}
+
+/// Texture page settings as used by GP0(e1) and textured polygons.
+fn tex_page(depth: u32, blend: u32) -> u32 {
+    [8, 10, 12][depth as usize] | blend << 5 | depth << 7
+}
+
+fn clut(depth: u32) -> u32 {
+    32 | (480 + depth.min(1)) << 6
+}
+
+fn point(x: i32, y: i32) -> u32 {
+    (x as u32 & 0x7ff) | (y as u32 & 0x7ff) << 16
+}
+
+fn uv(u: u32, v: u32) -> u32 {
+    u | v << 8
+}
+
+/// Write `width` by `height` halfwords from the top left of `vram` as a 16-bit PGM image.
+fn write_pgm(vram: &Vram, width: u32, height: u32) -> Vec<u8> {
+    let mut out = format!("P5\n{width} {height}\n65535\n").into_bytes();
+    for y in 0..height {
+        for x in 0..width {
+            out.extend(vram.load_16(x as i32, y as i32).to_be_bytes());
+        }
+    }
+    out
+}
+
+/// Read a 16-bit PGM image. Returns the width, height and halfwords.
+fn read_pgm(data: &[u8]) -> Option<(u32, u32, Vec<u16>)> {
+    // The header is 4 fields separated by whitespace, followed by a single whitespace.
+    let mut fields = Vec::new();
+    let mut pos = 0;
+
+    while fields.len() < 4 {
+        while data.get(pos)?.is_ascii_whitespace() {
+            pos += 1;
+        }
+        let start = pos;
+        while !data.get(pos)?.is_ascii_whitespace() {
+            pos += 1;
+        }
+        fields.push(std::str::from_utf8(&data[start..pos]).ok()?);
+    }
+
+    let [magic, width, height, max] = fields[..] else {
+        return None;
+    };
+
+    if magic != "P5" || max != "65535" {
+        return None;
+    }
+
+    let (width, height): (u32, u32) = (width.parse().ok()?, height.parse().ok()?);
+    let pixels: Vec<u16> = data
+        .get(pos + 1..)?
+        .chunks_exact(2)
+        .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+        .collect();
+
+    if pixels.len() != (width * height) as usize {
+        return None;
+    }
+
+    Some((width, height, pixels))
+}
+
+/// Replay `cmds` on a GPU with VRAM from [`test_vram`] and compare the top left `width` by
+/// `height` halfwords of VRAM against the reference image `name`.
+fn check_golden(name: &str, width: u32, height: u32, cmds: Vec<u32>) {
+    // Set the draw area to cover the image and reset the draw offset.
+    let setup = [0xe3000000, 0xe4000000 | (width - 1) | (height - 1) << 10, 0xe5000000];
+
+    let capture = Capture {
+        vram: test_vram(),
+        setup: Vec::new(),
+        events: setup
+            .into_iter()
+            .chain(cmds)
+            .map(CaptureEvent::Gp0)
+            .collect(),
+    };
+
+    let mut replayer = Replayer::new(&capture);
+    while replayer.step().is_some() {}
+
+    let output = write_pgm(replayer.vram(), width, height);
+
+    let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    path.push("src/gpu/golden");
+    path.push(format!("{name}.pgm"));
+
+    if env::var_os("SPLST_BLESS").is_some() {
+        fs::create_dir_all(path.parent().unwrap()).expect("failed to create directory");
+        fs::write(&path, output).expect("failed to write reference image");
+        return;
+    }
+
+    let Ok(reference) = fs::read(&path) else {
+        panic!("missing reference image {}, run with SPLST_BLESS=1 to create it", path.display());
+    };
+
+    let Some((_, _, pixels)) = read_pgm(&reference).filter(|(w, h, _)| (*w, *h) == (width, height))
+    else {
+        panic!("invalid reference image {}", path.display());
+    };
+
+    // Build VRAM from both to find the area that differs.
+    let mut expected = Box::new(Vram::new());
+    let mut actual = Box::new(Vram::new());
+
+    for (idx, val) in pixels.iter().enumerate() {
+        let (x, y) = ((idx as u32 % width) as i32, (idx as u32 / width) as i32);
+        expected.store_16(x, y, *val);
+        actual.store_16(x, y, replayer.vram().load_16(x, y));
+    }
+
+    if let Some(diff) = VramDiff::new(&expected, &actual) {
+        let mut out = env::temp_dir();
+        out.push(format!("{name}.actual.pgm"));
+        let _ = fs::write(&out, output);
+        panic!("{name} doesn't match the reference image: {diff}, output written to {}", out.display());
+    }
+}
+
+/// GP0(e1) with the texture page and blend mode. This sets the blend mode of everything but
+/// textured polygons.
+fn draw_mode(tex: Option<(bool, u32)>, trans: Option<u32>) -> u32 {
+    let depth = tex.map_or(0, |(_, depth)| depth);
+    0xe1000000 | tex_page(depth, trans.unwrap_or(0))
+}
+
+/// Draw a polygon with `verts` vertices for each combination of shading, texture and
+/// transparency, in a grid of 32x32 cells.
+fn poly_cmds(verts: usize) -> Vec<u32> {
+    let positions = [(2, 2), (29, 5), (4, 29), (27, 27)];
+    let coords = [(0, 0), (31, 2), (1, 31), (30, 30)];
+
+    let mut cmds = Vec::new();
+    let mut cell = 0;
+
+    for shaded in [false, true] {
+        for tex in TEXTURES {
+            for trans in TRANSPARENCY {
+                let (x, y) = (cell % 8 * 32, cell / 8 * 32);
+                cell += 1;
+
+                cmds.push(draw_mode(tex, trans));
+
+                let cmd = 0x20
+                    | (shaded as u32) << 4
+                    | ((verts == 4) as u32) << 3
+                    | (tex.is_some() as u32) << 2
+                    | (trans.is_some() as u32) << 1
+                    | tex.map_or(0, |(raw, _)| raw as u32);
+
+                let color = if shaded { COLORS[0] } else { FLAT_COLOR };
+                cmds.push(cmd << 24 | color);
+
+                for vert in 0..verts {
+                    if shaded && vert > 0 {
+                        cmds.push(COLORS[vert]);
+                    }
+
+                    let (dx, dy) = positions[vert];
+                    cmds.push(point(x + dx, y + dy));
+
+                    if let Some((_, depth)) = tex {
+                        let (u, v) = coords[vert];
+                        let extra = match vert {
+                            0 => clut(depth),
+                            1 => tex_page(depth, trans.unwrap_or(0)),
+                            _ => 0,
+                        };
+                        cmds.push(uv(u, v) | extra << 16);
+                    }
+                }
+            }
+        }
+    }
+
+    cmds
+}
+
+#[test]
+fn golden_triangles() {
+    check_golden("triangles", 256, 288, poly_cmds(3));
+}
+
+#[test]
+fn golden_quads() {
+    check_golden("quads", 256, 288, poly_cmds(4));
+}
+
+#[test]
+fn golden_rects() {
+    let mut cmds = Vec::new();
+    let mut cell = 0;
+
+    // Variable size, 1x1, 8x8 and 16x16.
+    for size in 0..4 {
+        for tex in TEXTURES {
+            for trans in TRANSPARENCY {
+                let (x, y) = (cell % 12 * 20 + 2, cell / 12 * 20 + 2);
+                cell += 1;
+
+                cmds.push(draw_mode(tex, trans));
+
+                let cmd = 0x60
+                    | size << 3
+                    | (tex.is_some() as u32) << 2
+                    | (trans.is_some() as u32) << 1
+                    | tex.map_or(0, |(raw, _)| raw as u32);
+
+                cmds.push(cmd << 24 | FLAT_COLOR);
+                cmds.push(point(x, y));
+
+                if let Some((_, depth)) = tex {
+                    cmds.push(uv(3, 5) | clut(depth) << 16);
+                }
+
+                if size == 0 {
+                    cmds.push(12 | 10 << 16);
+                }
+            }
+        }
+    }
+
+    check_golden("rects", 240, 240, cmds);
+}
+
+#[test]
+fn golden_lines() {
+    let positions = [(2, 3), (29, 10), (6, 28), (25, 29)];
+
+    let mut cmds = Vec::new();
+    let mut cell = 0;
+
+    for poly in [false, true] {
+        for shaded in [false, true] {
+            for trans in TRANSPARENCY {
+                let (x, y) = (cell % 8 * 32, cell / 8 * 32);
+                cell += 1;
+
+                cmds.push(draw_mode(None, trans));
+
+                let cmd = 0x40
+                    | (shaded as u32) << 4
+                    | (poly as u32) << 3
+                    | (trans.is_some() as u32) << 1;
+
+                let verts = if poly { 4 } else { 2 };
+                let color = if shaded { COLORS[0] } else { FLAT_COLOR };
+
+                cmds.push(cmd << 24 | color);
+
+                for vert in 0..verts {
+                    if shaded && vert > 0 {
+                        cmds.push(COLORS[vert]);
+                    }
+                    let (dx, dy) = positions[vert];
+                    cmds.push(point(x + dx, y + dy));
+                }
+
+                if poly {
+                    cmds.push(0x55555555);
+                }
+            }
+        }
+    }
+
+    check_golden("lines", 256, 96, cmds);
+}
+
+#[test]
+fn pgm_roundtrip() {
+    let vram = test_vram();
+    let (width, height, pixels) = read_pgm(&write_pgm(&vram, 16, 8)).unwrap();
+
+    assert_eq!((width, height), (16, 8));
+    assert_eq!(pixels[17], vram.load_16(1, 1));
+}
@@ -4,6 +4,8 @@ use crate::SysTime;
 
 use super::{Gpu, State, MemTransfer};
 use super::primitive::{Point, Color, TexCoord};
+use super::pgxp::{self, PreciseVertex};
+use super::timing::{POLY_SETUP, QUAD_SETUP};
 
 impl Gpu {
     /// GP0 commands which does nothing but aren't immediate.
@@ -15,6 +17,7 @@ impl Gpu {
     pub fn gp0_clear_texture_cache(&mut self) {
         self.fifo.pop();
         self.clut_cache.clear();
+        self.tex_cache.clear();
     }
 
     /// GP0(02) - Fill rectanlge in VRAM.
@@ -37,17 +40,16 @@ impl Gpu {
             y: (val >> 16) & 0x1ff,
         };
 
+        self.invalidate_textures(start, Point::new(start.x + dim.x - 1, start.y + dim.y - 1));
         self.fill_rect(start, dim, color);
-    
-        let line_time = (dim.x / 8) + 9;
 
-        self.dot_cycles_to_systime((46 + line_time * dim.y) as u64)
+        self.dot_cycles_to_systime(self.fill_rect_time(dim))
     }
 
     /// GP0(e1) - Draw Mode Setting.
     ///
     /// - 0..10 - Same as status register.
-    /// - 11 - Texture disabled.
+    /// - 11 - Texture disabled. Only if allowed by GP1(9).
     /// - 12 - Texture rectangle x-flip.
     /// - 13 - Texture rectangle y-flip.
     /// - 14..23 - Not used.
@@ -57,7 +59,7 @@ impl Gpu {
 
         self.status.0 = self.status.0
             .set_bit_range(0, 10, stat)
-            .set_bit(15, val.bit(11));
+            .set_bit(15, self.allow_tex_disable && val.bit(11));
 
         self.tex_x_flip = val.bit(12);
         self.tex_y_flip = val.bit(13);
@@ -125,6 +127,39 @@ impl Gpu {
         self.status.0 = self.status.0.set_bit_range(11, 12, val);
     }
 
+    /// GP0(80) - Copy rectangle within VRAM.
+    ///
+    /// Copy a rectangle from one place in VRAM to another. Positions and sizes are given in
+    /// halfword steps, and a size of 0 is treated as the max size. It's affected by the mask bit
+    /// settings, but not the draw offset or draw area.
+    pub fn gp0_copy_rect_vram_to_vram(&mut self) -> SysTime {
+        self.fifo.pop();
+
+        let (src, dst, dim) = (self.fifo.pop(), self.fifo.pop(), self.fifo.pop());
+
+        let src = Point::new(
+            src.bit_range(00, 09) as i32,
+            src.bit_range(16, 24) as i32,
+        );
+
+        let dst = Point::new(
+            dst.bit_range(00, 09) as i32,
+            dst.bit_range(16, 24) as i32,
+        );
+
+        let dim = Point::new(
+            (dim.bit_range(00, 15).wrapping_sub(1) & 0x3ff) as i32 + 1,
+            (dim.bit_range(16, 31).wrapping_sub(1) & 0x1ff) as i32 + 1,
+        );
+
+        self.invalidate_textures(dst, Point::new(dst.x + dim.x - 1, dst.y + dim.y - 1));
+        self.draw_upscaled(|gpu, shift| {
+            gpu.copy_rect(src.scaled(shift), dst.scaled(shift), dim.scaled(shift));
+        });
+
+        self.dot_cycles_to_systime(self.copy_rect_time(dim))
+    }
+
     /// GP0(a0) - Copy rectangle from CPU to VRAM.
     ///
     /// Transfers the a block of data from the CPU directly to VRAM. It's often used to transfer
@@ -141,6 +176,9 @@ impl Gpu {
             dim.bit_range(16, 24) as i32,
         );
 
+        // The transfer may write a single halfword past the end.
+        self.invalidate_textures(Point::new(x, y), Point::new(x + w, y + h));
+        self.sync_raster();
         self.state = State::VramStore(MemTransfer::new(x, y, w, h));
     }
 
@@ -158,13 +196,15 @@ impl Gpu {
             dim.bit_range(16, 25) as i32,
         );
 
+        self.sync_raster();
         self.state = State::VramLoad(MemTransfer::new(x, y, w, h));
     }
     
     /// Get data and interpret data from FIFO for polygon commands.
+    #[allow(clippy::type_complexity)]
     fn interp_poly<Shade, Tex, Trans, const N: usize>(
         &mut self
-    ) -> (Color, Point, [Point; N], [Color; N], [TexCoord; N])
+    ) -> (Color, Point, [Point; N], [Color; N], [TexCoord; N], [Option<PreciseVertex>; N])
     where
         Shade: draw_mode::Shading,
         Tex: draw_mode::Textureing,
@@ -175,6 +215,7 @@ impl Gpu {
         let mut points = [Point::default(); N];
         let mut colors = [Color::default(); N];
         let mut coords = [TexCoord::default(); N];
+        let mut precise = [None; N];
         
         let flat_shade = match Shade::IS_SHADED {
             true => Color::from_rgb(0, 0, 0),
@@ -185,10 +226,11 @@ impl Gpu {
             .iter_mut()
             .zip(colors.iter_mut())
             .zip(coords.iter_mut())
-            .map(|((point, color), coord)| (point, color, coord))
+            .zip(precise.iter_mut())
+            .map(|(((point, color), coord), precise)| (point, color, coord, precise))
             .enumerate();
 
-        for (i, (point, color, coord)) in verts {
+        for (i, (point, color, coord, precise)) in verts {
             if Shade::IS_SHADED {
                 *color = Color::from_cmd(self.fifo.pop());
             }
@@ -200,6 +242,8 @@ impl Gpu {
                 self.y_offset as i32,
             );
 
+            *precise = self.take_precise_vertex(pos);
+
             if Tex::IS_TEXTURED {
                 let val = self.fifo.pop();
                 match i {
@@ -207,12 +251,14 @@ impl Gpu {
                         clut.x = val.bit_range(16, 21) as i32 * 16;
                         clut.y = val.bit_range(22, 30) as i32;
                     }
+                    // The texture page attribute replaces the texture page and blend mode set by
+                    // GP0(e1), also for the primitives drawn after.
                     1 => {
                         let val = val >> 16;
 
                         self.status.0 = self.status.0
                             .set_bit_range(0, 8, val.bit_range(0, 8))
-                            .set_bit(11, val.bit(11));
+                            .set_bit(15, self.allow_tex_disable && val.bit(11));
                     }
                     _ => {}
                 }
@@ -224,7 +270,7 @@ impl Gpu {
             }
         }
         
-        (flat_shade, clut, points, colors, coords)
+        (flat_shade, clut, points, colors, coords, precise)
     }
 
     /// Handle GP0 triangle polygon commands.
@@ -234,12 +280,28 @@ impl Gpu {
         Tex: draw_mode::Textureing,
         Trans: draw_mode::Transparency,
     {
-        let (flat_shade, clut, points, colors, coords) = self.interp_poly::<Shade, Tex, Trans, 3>();
-        let cycles = self.draw_triangle::<Shade, Tex, Trans>(
-            flat_shade, clut, points, colors, coords,
-        );
+        let (flat_shade, clut, points, colors, coords, precise) =
+            self.interp_poly::<Shade, Tex, Trans, 3>();
+
+        let depths = self.perspective_depths(&precise);
+
+        let load = match Tex::IS_TEXTURED {
+            true => self.poly_texture_load_time(clut, &coords),
+            false => 0,
+        };
+
+        let cycles = self.draw_upscaled(|gpu, shift| {
+            gpu.draw_triangle::<Shade, Tex, Trans>(
+                flat_shade,
+                clut,
+                pgxp::precise_points(points, precise, shift),
+                colors,
+                coords,
+                depths,
+            )
+        });
         
-        self.dot_cycles_to_systime(cycles + 82)
+        self.dot_cycles_to_systime(load + cycles + POLY_SETUP)
     }
 
     /// Handle GP0 quad (four point) polygon command.
@@ -249,25 +311,47 @@ impl Gpu {
         Tex: draw_mode::Textureing,
         Trans: draw_mode::Transparency,
     {
-        let (flat_shade, clut, points, colors, coords) = self.interp_poly::<Shade, Tex, Trans, 4>();
-        
-        let tri1 = self.draw_triangle::<Shade, Tex, Trans>(
-            flat_shade,
-            clut,
-            points[..3].try_into().unwrap(),
-            colors[..3].try_into().unwrap(),
-            coords[..3].try_into().unwrap(),
-        );
+        let (flat_shade, clut, points, colors, coords, precise) =
+            self.interp_poly::<Shade, Tex, Trans, 4>();
 
-        let tri2 = self.draw_triangle::<Shade, Tex, Trans>(
-            flat_shade,
-            clut,
-            points[1..].try_into().unwrap(),
-            colors[1..].try_into().unwrap(),
-            coords[1..].try_into().unwrap(),
-        );
+        let depths = self.perspective_depths(&precise);
+
+        let load = match Tex::IS_TEXTURED {
+            true => self.poly_texture_load_time(clut, &coords),
+            false => 0,
+        };
+
+        let points = |range: std::ops::Range<usize>, shift: u32| -> [Point; 3] {
+            pgxp::precise_points(
+                points[range.clone()].try_into().unwrap(),
+                precise[range].try_into().unwrap(),
+                shift,
+            )
+        };
 
-        self.dot_cycles_to_systime(tri1 + tri2 + 82 + 46)
+        let tri1 = self.draw_upscaled(|gpu, shift| {
+            gpu.draw_triangle::<Shade, Tex, Trans>(
+                flat_shade,
+                clut,
+                points(0..3, shift),
+                colors[..3].try_into().unwrap(),
+                coords[..3].try_into().unwrap(),
+                depths.map(|depths| depths[..3].try_into().unwrap()),
+            )
+        });
+
+        let tri2 = self.draw_upscaled(|gpu, shift| {
+            gpu.draw_triangle::<Shade, Tex, Trans>(
+                flat_shade,
+                clut,
+                points(1..4, shift),
+                colors[1..].try_into().unwrap(),
+                coords[1..].try_into().unwrap(),
+                depths.map(|depths| depths[1..].try_into().unwrap()),
+            )
+        });
+
+        self.dot_cycles_to_systime(load + tri1 + tri2 + POLY_SETUP + QUAD_SETUP)
     }
 
     /// Handle GP0 line commands.
@@ -306,10 +390,168 @@ impl Gpu {
             self.y_offset as i32,
         );
 
-        let cycles = self.draw_line::<Shade, Trans>(points, colors, flat_shade);
+        let cycles = self.draw_upscaled(|gpu, shift| {
+            gpu.draw_line::<Shade, Trans>(points.map(|p| p.scaled(shift)), colors, flat_shade)
+        });
         self.dot_cycles_to_systime(cycles)
     }
 
+    /// GP0(20..3f) - Polygon commands.
+    ///
+    /// - 0 - Raw texture, ignored if not textured.
+    /// - 1 - Semi-transparent.
+    /// - 2 - Textured.
+    /// - 3 - Quad, otherwise triangle.
+    /// - 4 - Shaded.
+    pub fn gp0_poly_cmd(&mut self, cmd: u32) -> SysTime {
+        if cmd.bit(4) {
+            self.poly_with_shade::<draw_mode::Shaded>(cmd)
+        } else {
+            self.poly_with_shade::<draw_mode::UnShaded>(cmd)
+        }
+    }
+
+    fn poly_with_shade<Shade>(&mut self, cmd: u32) -> SysTime
+    where
+        Shade: draw_mode::Shading,
+    {
+        match (cmd.bit(2), cmd.bit(0)) {
+            (false, _) => self.poly_with_tex::<Shade, draw_mode::UnTextured>(cmd),
+            (true, false) => self.poly_with_tex::<Shade, draw_mode::Textured>(cmd),
+            (true, true) => self.poly_with_tex::<Shade, draw_mode::TexturedRaw>(cmd),
+        }
+    }
+
+    fn poly_with_tex<Shade, Tex>(&mut self, cmd: u32) -> SysTime
+    where
+        Shade: draw_mode::Shading,
+        Tex: draw_mode::Textureing,
+    {
+        match (cmd.bit(3), cmd.bit(1)) {
+            (false, false) => self.gp0_tri_poly::<Shade, Tex, draw_mode::Opaque>(),
+            (false, true) => self.gp0_tri_poly::<Shade, Tex, draw_mode::Transparent>(),
+            (true, false) => self.gp0_quad_poly::<Shade, Tex, draw_mode::Opaque>(),
+            (true, true) => self.gp0_quad_poly::<Shade, Tex, draw_mode::Transparent>(),
+        }
+    }
+
+    /// GP0(40..5f) - Line commands.
+    ///
+    /// - 1 - Semi-transparent.
+    /// - 3 - Poly-line, otherwise a single line. Poly-lines are handled by
+    ///   [`Gpu::gp0_poly_line_cmd`].
+    /// - 4 - Shaded.
+    pub fn gp0_line_cmd(&mut self, cmd: u32) -> SysTime {
+        match (cmd.bit(4), cmd.bit(1)) {
+            (false, false) => self.gp0_line::<draw_mode::UnShaded, draw_mode::Opaque>(),
+            (false, true) => self.gp0_line::<draw_mode::UnShaded, draw_mode::Transparent>(),
+            (true, false) => self.gp0_line::<draw_mode::Shaded, draw_mode::Opaque>(),
+            (true, true) => self.gp0_line::<draw_mode::Shaded, draw_mode::Transparent>(),
+        }
+    }
+
+    /// GP0(48..4f, 58..5f) - Poly-line commands.
+    ///
+    /// Same as line commands, but takes any number of vertices and is terminated by a word on the
+    /// form 0x5XXX5XXX. The first segment is drawn here, and the rest are drawn by
+    /// [`Gpu::gp0_poly_line_next`] as the vertices arrive. Returns `None` if the line is
+    /// terminated before the second vertex.
+    pub fn gp0_poly_line_cmd(&mut self, cmd: u32) -> Option<SysTime> {
+        let color = Color::from_cmd(self.fifo.pop());
+        let point = Point::from_cmd(self.fifo.pop()).with_offset(
+            self.x_offset as i32,
+            self.y_offset as i32,
+        );
+
+        self.poly_line = Some(PolyLine { cmd, point, color });
+        self.gp0_poly_line_next()
+    }
+
+    /// Draw the next segment of the poly-line currently being drawn. Should only be called if
+    /// the FIFO has the next vertex or terminator. Returns `None` if the line is terminated.
+    pub fn gp0_poly_line_next(&mut self) -> Option<SysTime> {
+        let line = self.poly_line.take().expect("no poly-line being drawn");
+
+        if is_poly_line_terminator(self.fifo[0]) {
+            self.fifo.pop();
+            return None;
+        }
+
+        let shaded = line.cmd.bit(4);
+
+        let color = if shaded {
+            Color::from_cmd(self.fifo.pop())
+        } else {
+            line.color
+        };
+
+        let point = Point::from_cmd(self.fifo.pop()).with_offset(
+            self.x_offset as i32,
+            self.y_offset as i32,
+        );
+
+        let colors = [line.color, color];
+
+        let cycles = self.draw_upscaled(|gpu, shift| {
+            let points = [line.point.scaled(shift), point.scaled(shift)];
+            match (shaded, line.cmd.bit(1)) {
+                (false, false) => gpu.draw_line::<draw_mode::UnShaded, draw_mode::Opaque>(
+                    points, colors, line.color,
+                ),
+                (false, true) => gpu.draw_line::<draw_mode::UnShaded, draw_mode::Transparent>(
+                    points, colors, line.color,
+                ),
+                (true, false) => gpu.draw_line::<draw_mode::Shaded, draw_mode::Opaque>(
+                    points, colors, line.color,
+                ),
+                (true, true) => gpu.draw_line::<draw_mode::Shaded, draw_mode::Transparent>(
+                    points, colors, line.color,
+                ),
+            }
+        });
+
+        // There is no need to wait for the terminator if it's already been received.
+        if !self.fifo.is_empty() && is_poly_line_terminator(self.fifo[0]) {
+            self.fifo.pop();
+        } else {
+            self.poly_line = Some(PolyLine { cmd: line.cmd, point, color });
+        }
+
+        Some(self.dot_cycles_to_systime(cycles))
+    }
+
+    /// GP0(60..7f) - Rectangle commands.
+    ///
+    /// - 0 - Raw texture, ignored if not textured.
+    /// - 1 - Semi-transparent.
+    /// - 2 - Textured.
+    /// - 3..4 - Size. 0 is variable size, 1 is 1x1, 2 is 8x8 and 3 is 16x16.
+    pub fn gp0_rect_cmd(&mut self, cmd: u32) -> SysTime {
+        let size = match cmd.bit_range(3, 4) {
+            0 => None,
+            1 => Some(1),
+            2 => Some(8),
+            _ => Some(16),
+        };
+
+        match (cmd.bit(2), cmd.bit(0)) {
+            (false, _) => self.rect_with_tex::<draw_mode::UnTextured>(cmd, size),
+            (true, false) => self.rect_with_tex::<draw_mode::Textured>(cmd, size),
+            (true, true) => self.rect_with_tex::<draw_mode::TexturedRaw>(cmd, size),
+        }
+    }
+
+    fn rect_with_tex<Tex>(&mut self, cmd: u32, size: Option<i32>) -> SysTime
+    where
+        Tex: draw_mode::Textureing,
+    {
+        if cmd.bit(1) {
+            self.gp0_rect::<Tex, draw_mode::Transparent>(size)
+        } else {
+            self.gp0_rect::<Tex, draw_mode::Opaque>(size)
+        }
+    }
+
     /// GP0 rectangle commands.
     pub fn gp0_rect<Tex, Trans>(&mut self, size: Option<i32>) -> SysTime
     where
@@ -341,16 +583,33 @@ impl Gpu {
             None => Point::from_cmd(self.fifo.pop()),
         };
 
-        let cycles = self.draw_rect::<Tex, Trans>(start, dim, color, uv, clut);
-        self.dot_cycles_to_systime(cycles)
+        let load = match Tex::IS_TEXTURED {
+            true => self.rect_texture_load_time(clut, uv, dim),
+            false => 0,
+        };
+
+        let cycles = self.draw_upscaled(|gpu, shift| {
+            gpu.draw_rect::<Tex, Trans>(start.scaled(shift), dim.scaled(shift), color, uv, clut)
+        });
+        self.dot_cycles_to_systime(load + cycles)
     }
 }
 
+/// A poly-line in the middle of being drawn.
+pub struct PolyLine {
+    /// The poly-line command.
+    pub cmd: u32,
+    /// The last vertex.
+    point: Point,
+    /// The color of the last vertex.
+    color: Color,
+}
+
 pub mod draw_mode {
     //! Type parameters for draw commands.
 
     /// The shading mode of a draw call.
-    pub trait Shading {
+    pub trait Shading: 'static {
         const IS_SHADED: bool;
     }
 
@@ -370,7 +629,7 @@ pub mod draw_mode {
     }
 
     /// The texture mode of a draw call.
-    pub trait Textureing {
+    pub trait Textureing: 'static {
         const IS_TEXTURED: bool;
         const IS_RAW: bool;
     }
@@ -401,7 +660,7 @@ pub mod draw_mode {
 
     /// The transparency mode of a draw call, basically how the color of a shape get's blended with the
     /// background color.
-    pub trait Transparency {
+    pub trait Transparency: 'static {
         const IS_TRANSPARENT: bool;
     }
 
@@ -445,6 +704,16 @@ const CMD_LEN: [u8; 0x100] = [
     1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
 ];
 
+/// If the command is a poly-line, which takes a variable number of vertices.
+pub fn cmd_is_poly_line(cmd: u32) -> bool {
+    matches!(cmd, 0x48..=0x4f | 0x58..=0x5f)
+}
+
+/// If `val` terminates a poly-line. Any word on the form 0x5XXX5XXX works.
+pub fn is_poly_line_terminator(val: u32) -> bool {
+    val & 0xf000_f000 == 0x5000_5000
+}
+
 pub fn cmd_is_imm(cmd: u32) -> bool {
     let imm = CMD_IS_IMM[(cmd / 16) as usize];
     imm.bit((cmd % 16) as usize)
@@ -479,3 +748,123 @@ fn imm_cmds() {
     assert_eq!(cmd_is_imm(0x3), true);
     assert_eq!(cmd_is_imm(0x30), false);
 }
+
+#[test]
+fn draw_cmd_len() {
+    use crate::schedule::Schedule;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    let mut schedule = Schedule::new();
+    let mut gpu = Gpu::new(&mut schedule, Rc::new(RefCell::new(())));
+
+    // Every draw command should consume exactly the number of words given by `cmd_fifo_len`.
+    for cmd in 0x20..=0x7f {
+        gpu.fifo.push(cmd << 24);
+
+        for _ in 1..cmd_fifo_len(cmd) {
+            gpu.fifo.push(0x0);
+        }
+
+        if cmd_is_poly_line(cmd) {
+            gpu.fifo.push(0x5555_5555);
+        }
+
+        gpu.state = State::Idle;
+        gpu.gp0_exec(&mut schedule);
+
+        assert!(gpu.fifo.is_empty(), "GP0({cmd:02x}) didn't consume all words");
+        assert!(gpu.poly_line.is_none());
+    }
+}
+
+#[test]
+fn poly_line() {
+    use crate::schedule::Schedule;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    let mut schedule = Schedule::new();
+    let mut gpu = Gpu::new(&mut schedule, Rc::new(RefCell::new(())));
+
+    gpu.da_x_max = 64;
+    gpu.da_y_max = 64;
+
+    // Flat poly-line going from (0, 0) to (8, 0) to (8, 8) to (0, 8).
+    let words = [0x48ff_ffff, 0x0000_0000, 0x0000_0008, 0x0008_0008, 0x0008_0000, 0x5555_5555];
+
+    for word in words {
+        gpu.fifo.push_cmd(word);
+    }
+
+    let mut segments = 0;
+
+    while !gpu.fifo.is_empty() {
+        gpu.state = State::Idle;
+        gpu.gp0_exec(&mut schedule);
+        segments += 1;
+    }
+
+    assert_eq!(segments, 3);
+    assert!(gpu.poly_line.is_none());
+
+    for (x, y) in [(4, 0), (8, 4), (4, 8)] {
+        assert_ne!(gpu.vram.load_16(x, y), 0, "({x}, {y}) should be drawn");
+    }
+
+    assert_eq!(gpu.vram.load_16(4, 4), 0);
+}
+
+#[test]
+fn copy_rect_vram_to_vram() {
+    use crate::schedule::Schedule;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    let mut schedule = Schedule::new();
+    let mut gpu = Gpu::new(&mut schedule, Rc::new(RefCell::new(())));
+
+    let copy = |gpu: &mut Gpu, schedule: &mut Schedule, src: u32, dst: u32, dim: u32| {
+        for word in [0x8000_0000, src, dst, dim] {
+            gpu.fifo.push(word);
+        }
+        gpu.state = State::Idle;
+        gpu.gp0_exec(schedule);
+        assert!(gpu.fifo.is_empty());
+    };
+
+    for x in 0..8 {
+        gpu.vram.store_16(x, 0, x as u16 + 1);
+    }
+
+    // Overlapping copy 2 halfwords to the right.
+    copy(&mut gpu, &mut schedule, 0x0000_0000, 0x0000_0002, 0x0001_0008);
+
+    for x in 0..8 {
+        assert_eq!(gpu.vram.load_16(x + 2, 0), x as u16 + 1);
+    }
+
+    // Wrap around the right and bottom edges.
+    copy(&mut gpu, &mut schedule, 0x0000_0000, 0x01ff_03fe, 0x0002_0004);
+
+    assert_eq!(gpu.vram.load_16(1022, 511), 1);
+    assert_eq!(gpu.vram.load_16(1023, 511), 2);
+    assert_eq!(gpu.vram.load_16(0, 511), 1);
+    assert_eq!(gpu.vram.load_16(1, 511), 2);
+    assert_eq!(gpu.vram.load_16(1022, 0), 0);
+
+    // Set mask bit while copying.
+    gpu.status.0 = gpu.status.0.set_bit(11, true);
+    copy(&mut gpu, &mut schedule, 0x0000_0002, 0x0010_0000, 0x0001_0002);
+
+    assert_eq!(gpu.vram.load_16(0, 16), 0x8001);
+    assert_eq!(gpu.vram.load_16(1, 16), 0x8002);
+
+    // Don't write to masked pixels.
+    gpu.status.0 = gpu.status.0.set_bit(11, false).set_bit(12, true);
+    gpu.vram.store_16(1, 16, 0x0);
+    copy(&mut gpu, &mut schedule, 0x0000_0004, 0x0010_0000, 0x0001_0002);
+
+    assert_eq!(gpu.vram.load_16(0, 16), 0x8001);
+    assert_eq!(gpu.vram.load_16(1, 16), 0x4);
+}
@@ -1,5 +1,5 @@
 use splst_util::{Bit, BitSet};
-use crate::schedule::Schedule;
+use crate::schedule::{Schedule, Event};
 
 use super::{Gpu, scanline_time, scanline_count};
 
@@ -7,7 +7,9 @@ impl Gpu {
     /// GP1(0) - Resets the state of the GPU.
     pub fn gp1_reset(&mut self, schedule: &mut Schedule) {
         self.fifo.clear();
+        self.poly_line = None;
         self.clut_cache.clear();
+        self.tex_cache.clear();
 
         let prev_video_mode = self.status.video_mode();        
 
@@ -20,6 +22,8 @@ impl Gpu {
             schedule.repeat_every(self.scanline_time, self.scanline_event);
         }
 
+        schedule.trigger(Event::Gpu(Self::sync_dot_clock));
+
         self.vram_x_start = 0;
         self.vram_y_start = 0;
 
@@ -29,6 +33,8 @@ impl Gpu {
         self.dis_y_start = 0x10;
         self.dis_y_end = 0x100;
 
+        self.allow_tex_disable = false;
+
         self.tex_x_flip = false;
         self.tex_y_flip = false;
 
@@ -51,6 +57,7 @@ impl Gpu {
     /// GP1(1) - Reset command buffer.
     pub fn gp1_reset_fifo(&mut self) {
         self.fifo.clear();
+        self.poly_line = None;
     }
 
     /// GP1(2) - Acknowledge GPU Interrupt.
@@ -123,5 +130,125 @@ impl Gpu {
         if prev_video_mode != self.status.video_mode() {
             schedule.repeat_every(self.scanline_time, self.scanline_event);
         }
+
+        schedule.trigger(Event::Gpu(Self::sync_dot_clock));
+    }
+
+    /// GP1(9) - New texture disable.
+    ///
+    /// - 0 - Allow GP0(e1) to disable textures.
+    pub fn gp1_texture_disable(&mut self, val: u32) {
+        self.allow_tex_disable = val.bit(0);
+    }
+
+    /// GP1(10..1f) - Get GPU info.
+    ///
+    /// Loads info into the GPUREAD register. The info is chosen by bits 0..3. Some of them
+    /// don't return anything, in which case GPUREAD keeps the previous value.
+    ///
+    /// - 2 - Texture window settings.
+    /// - 3 - Draw area top left.
+    /// - 4 - Draw area bottom right.
+    /// - 5 - Draw offset.
+    /// - 7 - GPU version. It's always 2 for the GPU emulated.
+    /// - 8 - Unknown, always 0.
+    pub fn gp1_gpu_info(&mut self, val: u32) {
+        let info = match val.bit_range(0, 3) {
+            0x2 => (self.tex_win_w as u32)
+                | (self.tex_win_h as u32) << 5
+                | (self.tex_win_x as u32) << 10
+                | (self.tex_win_y as u32) << 15,
+            0x3 => self.da_x_min as u32 | (self.da_y_min as u32) << 10,
+            0x4 => self.da_x_max as u32 | (self.da_y_max as u32) << 10,
+            0x5 => (self.x_offset as u32 & 0x7ff) | (self.y_offset as u32 & 0x7ff) << 11,
+            0x7 => 2,
+            0x8 => 0,
+            _ => return,
+        };
+        self.gpu_read = info;
     }
 }
+
+#[cfg(test)]
+fn test_gpu() -> (Gpu, Schedule) {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    let mut schedule = Schedule::new();
+    let gpu = Gpu::new(&mut schedule, Rc::new(RefCell::new(())));
+
+    (gpu, schedule)
+}
+
+#[test]
+fn gpu_info() {
+    let (mut gpu, mut schedule) = test_gpu();
+
+    // Texture window, draw area and draw offset.
+    gpu.store::<u32>(&mut schedule, 0, 0xe20a8421);
+    gpu.store::<u32>(&mut schedule, 0, 0xe3004010);
+    gpu.store::<u32>(&mut schedule, 0, 0xe403c13f);
+    gpu.store::<u32>(&mut schedule, 0, 0xe53ff801);
+
+    let mut info = |gpu: &mut Gpu, index: u32| {
+        gpu.store::<u32>(&mut schedule, 4, 0x10000000 | index);
+        gpu.load::<u32>(&mut schedule, 0)
+    };
+
+    assert_eq!(info(&mut gpu, 2), 0x0a8421);
+    assert_eq!(info(&mut gpu, 3), 0x004010);
+    assert_eq!(info(&mut gpu, 4), 0x03c13f);
+    assert_eq!(info(&mut gpu, 5), 0x3ff801);
+    assert_eq!(info(&mut gpu, 7), 2);
+
+    // Nothing is returned, so it should keep the GPU type.
+    assert_eq!(info(&mut gpu, 0), 2);
+    assert_eq!(info(&mut gpu, 6), 2);
+
+    assert_eq!(info(&mut gpu, 8), 0);
+
+    // GP1(11) to GP1(1f) are mirrors of GP1(10).
+    gpu.store::<u32>(&mut schedule, 4, 0x1f000005);
+
+    assert_eq!(gpu.load::<u32>(&mut schedule, 0), 0x3ff801);
+}
+
+#[test]
+fn texture_disable() {
+    let (mut gpu, mut schedule) = test_gpu();
+
+    // Textures can't be disabled before GP1(9).
+    gpu.store::<u32>(&mut schedule, 0, 0xe1000800);
+
+    assert!(!gpu.status().texture_disabled());
+
+    gpu.store::<u32>(&mut schedule, 4, 0x09000001);
+    gpu.store::<u32>(&mut schedule, 0, 0xe1000800);
+
+    assert!(gpu.status().texture_disabled());
+}
+
+#[test]
+fn gp1_mirror() {
+    let (mut gpu, mut schedule) = test_gpu();
+
+    // GP1(43) is display enable.
+    gpu.store::<u32>(&mut schedule, 4, 0x43000000);
+
+    assert!(gpu.status().display_enabled());
+
+    // GP1(85) is display start.
+    gpu.store::<u32>(&mut schedule, 4, 0x85000000 | 100 | (50 << 10));
+
+    assert_eq!(gpu.display_info().vram_x_start, 100);
+    assert_eq!(gpu.display_info().vram_y_start, 50);
+
+    // Unused commands shouldn't do anything.
+    let status = gpu.status().0;
+
+    for cmd in (0xa..=0xf).chain(0x20..=0x3f) {
+        gpu.store::<u32>(&mut schedule, 4, cmd << 24);
+    }
+
+    assert_eq!(gpu.status().0, status);
+}
@@ -0,0 +1,231 @@
+//! Exporting VRAM and the display area as images, and importing raw VRAM dumps.
+//!
+//! Images are written as PNG files, see [`png`](super::png). Raw VRAM dumps are the 1024x512
+//! halfwords of VRAM in little endian, line by line.
+
+use thiserror::Error;
+
+use super::primitive::Color;
+use super::png;
+use super::{ColorDepth, DisplayInfo, Gpu, Vram};
+
+use std::io::{self, Write};
+
+#[derive(Error, Debug)]
+pub enum VramImportError {
+    #[error("VRAM dump is {0} bytes, but should be {size} bytes", size = Vram::SIZE * 2)]
+    InvalidSize(usize),
+}
+
+/// An image with 8 bit RGB pixels.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    /// The red, green and blue bytes of each pixel, line by line from the top.
+    pub data: Vec<u8>,
+}
+
+impl Image {
+    /// Write the image as a PNG file.
+    pub fn write_png(&self, out: impl Write) -> io::Result<()> {
+        png::write(out, self.width, self.height, false, &self.data)
+    }
+}
+
+fn push_color(data: &mut Vec<u8>, color: Color) {
+    data.extend([color.r, color.g, color.b]);
+}
+
+impl Vram {
+    /// The whole VRAM as an image, with each halfword as a 15-bit pixel.
+    pub fn to_image(&self) -> Image {
+        let mut data = Vec::with_capacity(Vram::SIZE * 3);
+        for val in self.data.iter() {
+            push_color(&mut data, Color::from_u16(*val));
+        }
+        Image { width: 1024, height: 512, data }
+    }
+
+    /// The display area as shown on the screen. See [`display_image`].
+    pub fn display_image(&self, display: &DisplayInfo) -> Image {
+        display_image(&self.data, display)
+    }
+
+    /// The raw halfwords of VRAM in little endian.
+    pub fn to_raw(&self) -> Vec<u8> {
+        self.data.iter().flat_map(|val| val.to_le_bytes()).collect()
+    }
+
+    /// Load VRAM from a raw dump, as written by [`Vram::to_raw`].
+    pub fn load_raw(&mut self, raw: &[u8]) -> Result<(), VramImportError> {
+        if raw.len() != Vram::SIZE * 2 {
+            return Err(VramImportError::InvalidSize(raw.len()));
+        }
+        for (val, bytes) in self.data.iter_mut().zip(raw.chunks_exact(2)) {
+            *val = u16::from_le_bytes([bytes[0], bytes[1]]);
+        }
+        Ok(())
+    }
+}
+
+/// The display area of the VRAM halfwords `data` as shown on the screen, decoded as either 15 or
+/// 24-bit pixels. The image is black if the display is disabled.
+pub fn display_image(data: &[u16; Vram::SIZE], display: &DisplayInfo) -> Image {
+    let (width, height) = (display.width(), display.height());
+    let mut image = Vec::with_capacity((width * height * 3) as usize);
+
+    if !display.enabled {
+        image.resize((width * height * 3) as usize, 0);
+        return Image { width, height, data: image };
+    }
+
+    let x_start = display.vram_x_start as usize;
+
+    for y in 0..height as usize {
+        let line = (display.vram_y_start as usize + y) * 1024;
+        match display.color_depth {
+            ColorDepth::B15 => {
+                for x in 0..width as usize {
+                    let val = data[(x_start + x + line) % Vram::SIZE];
+                    push_color(&mut image, Color::from_u16(val));
+                }
+            }
+            ColorDepth::B24 => {
+                // Each pixel takes up 3 bytes, starting from the first column of the display
+                // area.
+                let start = (x_start + line) * 2;
+                for byte in 0..width as usize * 3 {
+                    let addr = (start + byte) % (Vram::SIZE * 2);
+                    image.push((data[addr / 2] >> (addr % 2 * 8)) as u8);
+                }
+            }
+        }
+    }
+
+    Image { width, height, data: image }
+}
+
+impl Gpu {
+    /// The display area as currently shown on the screen.
+    pub fn screenshot(&self) -> Image {
+        self.sync_raster();
+        self.vram.display_image(&self.display_info())
+    }
+
+    /// Replace VRAM with a raw dump. The upscaled VRAM is replaced by the new VRAM as well.
+    pub fn import_vram(&mut self, raw: &[u8]) -> Result<(), VramImportError> {
+        self.sync_raster();
+        self.vram.load_raw(raw)?;
+        self.clut_cache.clear();
+        self.forget_textures();
+
+        let scale = self.resolution_scale();
+        self.upscaled = None;
+        self.set_resolution_scale(scale);
+
+        Ok(())
+    }
+}
+
+#[test]
+fn png_encoding() {
+    let image = Image {
+        width: 2,
+        height: 2,
+        data: vec![255, 0, 0, 0, 255, 0, 0, 0, 255, 1, 2, 3],
+    };
+
+    let mut png = Vec::new();
+    image.write_png(&mut png).unwrap();
+
+    assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+    assert_eq!(&png[12..16], b"IHDR");
+    assert_eq!(&png[16..24], &[0, 0, 0, 2, 0, 0, 0, 2]);
+
+    // The CRC of an empty IEND chunk is always the same.
+    assert_eq!(&png[png.len() - 8..], &[b'I', b'E', b'N', b'D', 0xae, 0x42, 0x60, 0x82]);
+
+    // The image data is after the zlib header and a single stored block header.
+    let idat = 8 + 25 + 8 + 2 + 5;
+    assert_eq!(&png[idat..idat + 14], &[0, 255, 0, 0, 0, 255, 0, 0, 0, 0, 255, 1, 2, 3]);
+}
+
+#[test]
+fn display_24_bit() {
+    use super::{HorizontalRes, InterlaceField, VerticalRes, VideoMode};
+
+    let mut vram = Box::new(Vram::new());
+
+    // Two 24-bit pixels starting at (2, 1).
+    for (i, val) in [0x2211, 0x4433, 0x6655].into_iter().enumerate() {
+        vram.store_16(2 + i as i32, 1, val);
+    }
+
+    let display = DisplayInfo {
+        vram_x_start: 2,
+        vram_y_start: 1,
+        horizontal_res: HorizontalRes::P320,
+        vertical_res: VerticalRes::P240,
+        x_range: (0, 0),
+        y_range: (0, 0),
+        color_depth: ColorDepth::B24,
+        interlaced: false,
+        field: InterlaceField::Top,
+        video_mode: VideoMode::Ntsc,
+        enabled: true,
+    };
+
+    let image = vram.display_image(&display);
+
+    assert_eq!((image.width, image.height), (320, 240));
+    assert_eq!(&image.data[..6], &[0x11, 0x22, 0x33, 0x44, 0x55, 0x66]);
+
+    let mut raw = vram.to_raw();
+    let mut imported = Box::new(Vram::new());
+    imported.load_raw(&raw).unwrap();
+
+    assert!(imported.data == vram.data);
+
+    raw.pop();
+    assert!(imported.load_raw(&raw).is_err());
+}
+
+#[test]
+fn display_15_bit() {
+    use super::{HorizontalRes, InterlaceField, VerticalRes, VideoMode};
+
+    let mut vram = Box::new(Vram::new());
+
+    // White, mid-grey and a pixel with a different value in each channel at (4, 2).
+    for (i, val) in [0x7fff, 0x4210, 0x0c41].into_iter().enumerate() {
+        vram.store_16(4 + i as i32, 2, val);
+    }
+
+    let display = DisplayInfo {
+        vram_x_start: 4,
+        vram_y_start: 2,
+        horizontal_res: HorizontalRes::P320,
+        vertical_res: VerticalRes::P240,
+        x_range: (0, 0),
+        y_range: (0, 0),
+        color_depth: ColorDepth::B15,
+        interlaced: false,
+        field: InterlaceField::Top,
+        video_mode: VideoMode::Ntsc,
+        enabled: true,
+    };
+
+    let pixels = [248, 248, 248, 128, 128, 128, 8, 16, 24];
+
+    let image = vram.display_image(&display);
+    assert_eq!(&image.data[..9], &pixels);
+
+    let image = vram.to_image();
+    let start = (2 * 1024 + 4) * 3;
+    assert_eq!(&image.data[start..start + 9], &pixels);
+
+    let rgba = vram.to_rgba();
+    let start = (2 * 1024 + 4) * 4;
+    assert_eq!(&rgba[start..start + 8], &[248, 248, 248, 255, 128, 128, 128, 255]);
+}
@@ -1,39 +1,54 @@
 //! Emulation of the Playstations 1 GPU.
 //!
-//! # TODO
-//!
-//! - Maybe switch to integer fixed point for vertex attributes instead of floats in the
-//!   rasterizer.
-//!
-//! - DMA chopping wouldn't work correctly with the GPU, since the GPU updates it's DMA channel
-//!   after each GP0 write, which isn't handeled by the DMA.
+//! See the `timing` module for how long commands and transfers take.
 
 pub mod fifo;
+pub mod capture;
+pub mod pgxp;
 mod primitive;
 mod rasterize;
 mod gp0;
 mod gp1;
 mod vram;
 mod texture;
+mod upscale;
+mod tile;
+mod image;
+mod filter;
+mod png;
+mod texpack;
+mod timing;
+
+#[cfg(test)]
+mod golden;
 
 use splst_util::{Bit, BitSet};
 use crate::cpu::Irq;
 use crate::bus::{self, dma, Bus, BusMap, AddrUnit};
 use crate::schedule::{Event, EventId, Schedule};
-use crate::timer::Timers;
+use crate::timer::{Timers, DotClock};
 use crate::{VideoOutput, SysTime};
 use crate::{dump, dump::Dumper};
 
 use fifo::PushAction;
+use capture::{CaptureEvent, CaptureState};
+use gp0::PolyLine;
+use pgxp::Pgxp;
 use primitive::Color;
-use gp0::draw_mode;
-use texture::ClutCache;
+use texture::{ClutCache, TexCache};
+use texpack::Textures;
+use tile::RasterPool;
 
 use std::fmt;
 use std::cell::RefCell;
 use std::rc::Rc;
 
 pub use vram::Vram;
+pub use upscale::{ResolutionScale, UpscaledVram};
+pub use image::{display_image, Image, VramImportError};
+pub use filter::{Filter, UnknownFilter};
+pub use png::PngError;
+pub use texpack::TexturePackError;
 pub use fifo::Fifo;
 
 pub struct Gpu {
@@ -41,15 +56,33 @@ pub struct Gpu {
     /// The current state of the GPU.
     state: State,
     clut_cache: ClutCache,
+    /// Model of the texture cache used for timing.
+    tex_cache: TexCache,
     /// The GPU FIFO. Used to recieve commands and some kinds of data.
     fifo: Fifo,
+    /// Worker threads drawing queued primitives if enabled, see [`tile`]. It's declared before
+    /// the VRAM so that the workers are done drawing before the VRAM is dropped.
+    raster_pool: Option<RasterPool>,
     /// The Video Memory used to store texture data and the image buffer(s).
     vram: Box<Vram>,
+    /// VRAM at a higher resolution if enabled, see [`upscale`].
+    upscaled: Option<UpscaledVram>,
+    /// The number of bits coordinates are shifted by in the VRAM being drawn to. It's only
+    /// non-zero while drawing to `upscaled`.
+    draw_shift: u32,
+    /// Sub-pixel precision vertices, see [`pgxp`].
+    pgxp: Pgxp,
+    /// Texture dumping and replacement, see [`texpack`].
+    textures: Textures,
     /// The status register.
     status: Status,
     /// The GPUREAD register. This contains various info about the GPU, and is generated after each
     /// GP1(10) command call. It is read through the BUS at GPUREAD, if no transfer is ongoing.
     gpu_read: u32,
+    /// The poly-line being drawn, if the GPU is in the middle of drawing one.
+    poly_line: Option<PolyLine>,
+    /// If textures can be disabled by GP0(e1). Set by GP1(9).
+    allow_tex_disable: bool,
     /// Flips the texture of rectangles on the x-axis.
     tex_x_flip: bool,
     /// Flips the texture of rectangles on the y-axis.
@@ -96,6 +129,8 @@ pub struct Gpu {
     /// `dis_y_start` and `dis_y_end`.
     in_vblank: bool,
     scanline_event: EventId,
+    /// Capture of GPU commands being recorded, see [`capture`].
+    capture: CaptureState,
 }
 
 impl Gpu {
@@ -118,10 +153,18 @@ impl Gpu {
             renderer,
             state: State::Idle,
             clut_cache: ClutCache::default(),
+            tex_cache: TexCache::default(),
             fifo: Fifo::new(),
+            raster_pool: None,
             vram: Box::new(Vram::new()),
+            upscaled: None,
+            draw_shift: 0,
+            pgxp: Pgxp::default(),
+            textures: Textures::default(),
             status,
             gpu_read: 0x0,
+            poly_line: None,
+            allow_tex_disable: false,
             tex_x_flip: false,
             tex_y_flip: false,
             tex_win_w: 0x0,
@@ -145,6 +188,7 @@ impl Gpu {
             scanline_time,
             in_vblank: false,
             scanline_event,
+            capture: CaptureState::Off,
         }
     }
 
@@ -167,24 +211,23 @@ impl Gpu {
             offset => unreachable!("invalid GPU store at offset {offset:08x}"),
         }
 
-        // `dma_ready` can have changed here, which means that the GPU DMA should be updated.
-        // Maybe only check if something has changed which could change 'dma_ready', but that
-        // could be sketchy. Running a DMA channel is pretty fast anyway, but maybe just running
-        // the GPU channel every 1500 cycles or something could be faster?
-        
-        // This can be mess up chopping for CPU transfers. If theres already a pending event for a
-        // chopped transfer, the block will be transfered early.
+        // `dma_ready` can have changed here, which means that the GPU DMA should be updated. The
+        // DMA waits out chopped transfers by itself, so it doesn't matter if it's run early.
+        Self::wake_dma(schedule);
+    }
 
+    /// Run the GPU DMA channel, since the GPU may be ready for more data.
+    fn wake_dma(schedule: &mut Schedule) {
         schedule.trigger(Event::Dma(dma::Port::Gpu, Bus::run_dma_chan));
     }
 
-    pub fn load<T: AddrUnit>(&mut self, offset: u32) -> T {
+    pub fn load<T: AddrUnit>(&mut self, schedule: &mut Schedule, offset: u32) -> T {
         if !T::WIDTH.is_word() {
             warn!("load of {} from GPU", T::WIDTH);
         }
 
         let val = match bus::align_as::<u32>(offset) {
-            0 => self.gpu_read(),
+            0 => self.gpu_read(schedule),
             4 => self.status().0,
             offset => unreachable!("invalid GPU load at offset {offset:08x}"),
         };
@@ -203,7 +246,7 @@ impl Gpu {
     }
 
     /// The GPU read register. Either loads data from the VRAM or results from the GPU 
-    fn gpu_read(&mut self) -> u32 {
+    fn gpu_read(&mut self, schedule: &mut Schedule) -> u32 {
         if let State::VramLoad(ref mut tran) = self.state {
             self.gpu_read = [0, 16].iter().fold(0, |state, shift| {
                 let val = self.vram.load_16(tran.x, tran.y) as u32;
@@ -212,16 +255,25 @@ impl Gpu {
             });
             if tran.is_done() {
                 self.state = State::Idle;
+                Self::wake_dma(schedule);
             }
         }
         self.gpu_read
     }
 
+    /// If the GPU isn't executing a command or doing a transfer.
+    #[cfg(test)]
+    pub(crate) fn is_idle(&self) -> bool {
+        self.state.is_idle()
+    }
+
     /// Calculate if the GPU is ready to recieve data from the DMA.
     pub fn dma_block_ready(&self) -> bool {
         match self.state {
             State::VramStore(..) => !self.fifo.is_full(),
             State::Drawing | State::VramLoad(..) => false,
+            // The FIFO contains the vertices of the poly-line rather than a command.
+            State::Idle if self.poly_line.is_some() => !self.fifo.is_full(),
             State::Idle => {
                 if let Some(cmd) = self.fifo.next_cmd() {
                     // If the command is a line or polygon command, the dma ready flag get's
@@ -241,21 +293,25 @@ impl Gpu {
 
     pub fn status(&self) -> Status {
         trace!("GPU status load");
+        let vram_to_cpu_ready = self.state.is_vram_load();
+        let dma_block_ready = self.dma_block_ready();
         let status = self.status.0
-            .set_bit(27, self.state.is_vram_load())
-            .set_bit(28, self.dma_block_ready())
+            .set_bit(27, vram_to_cpu_ready)
+            .set_bit(28, dma_block_ready)
             .set_bit(26, self.state.is_idle() && self.fifo.is_empty())
             .set_bit(25, match self.status.dma_direction() {
                 DmaDir::Off => false,
                 DmaDir::Fifo => !self.fifo.is_full(),
-                DmaDir::CpuToGp0 => self.status.dma_block_ready(),
-                DmaDir::VramToCpu => self.status.vram_to_cpu_ready(),
+                DmaDir::CpuToGp0 => dma_block_ready,
+                DmaDir::VramToCpu => vram_to_cpu_ready,
             });
         Status(status)
     }
 
     /// Store value in the GP0 register.
     fn gp0_store(&mut self, schedule: &mut Schedule, val: u32) {
+        self.capture_event(CaptureEvent::Gp0(val));
+
         match self.state {
             State::Idle => match self.fifo.push_cmd(val) {
                 Some(PushAction::FullCmd) => self.gp0_exec(schedule),
@@ -284,6 +340,7 @@ impl Gpu {
     }
 
     pub fn vram(&self) -> &Vram {
+        self.sync_raster();
         &self.vram
     }
     
@@ -300,10 +357,28 @@ impl Gpu {
         self.in_vblank
     }
 
+    /// The current display configuration. This is send to the video output with each frame.
+    pub fn display_info(&self) -> DisplayInfo {
+        DisplayInfo {
+            vram_x_start: self.vram_x_start as u32,
+            vram_y_start: self.vram_y_start as u32,
+            horizontal_res: self.status.horizontal_res(),
+            vertical_res: self.status.vertical_res(),
+            x_range: (self.dis_x_start as u32, self.dis_x_end as u32),
+            y_range: (self.dis_y_start as u32, self.dis_y_end as u32),
+            color_depth: self.status.color_depth(),
+            interlaced: self.status.interlaced_480(),
+            field: self.status.interlace_field(),
+            video_mode: self.status.video_mode(),
+            enabled: self.status.display_enabled(),
+        }
+    }
+
     /// Handle that the GPU is at the end of the current scanline. This is used as an event
     /// callback.
     fn end_of_scanline(&mut self, schedule: &mut Schedule, timers: &mut Timers) {
-        timers.hblank(schedule, 1);
+        timers.hblank_start(schedule);
+        schedule.schedule(hblank_time(self.status), Event::Gpu(Self::end_of_hblank));
         
         self.scanline += 1;
 
@@ -311,60 +386,69 @@ impl Gpu {
             self.scanline = 0;
         } 
         
-        // We are on the last line.
-        if self.scanline == self.scanline_count - 1 {
-            let top_field = if self.status.vertical_interlace() {
-                !self.status.0.bit(13)
-            } else {
-                false  
-            };
-
-            self.status.0.set_bit(13, top_field);
-        }
-        
         // If we are entering leaving display area and thus entering Vblank.
         if self.scanline == self.dis_y_end {
             schedule.trigger(Event::Irq(Irq::VBlank));
-            
-            if self.status.interlaced_480() {
-                self.status.0 ^= 1 << 13;
-            }
-            
+
+            // Send the frame before switching field, so that the field just displayed is sent
+            // with it.
+            self.sync_raster();
             self.renderer.borrow_mut().send_frame(
-                (self.vram_x_start as u32, self.vram_y_start as u32),
-                &self.vram.raw_data(),
+                &self.display_info(),
+                self.vram.raw_data(),
+                self.upscaled.as_ref(),
             );
+            self.capture_frame_end();
+            self.pgxp_frame_end();
 
-            self.in_vblank = true;
+            // Switch field each frame if interlaced, otherwise the field is always odd.
+            let field = !self.status.vertical_interlace() || !self.status.0.bit(13);
+            self.status.0 = self.status.0.set_bit(13, field);
+            self.scanline_count = scanline_count(self.status);
 
-            // TODO: Timer sync. Enter vblank.
+            self.in_vblank = true;
+            timers.vblank_start(schedule);
         } else if self.scanline == self.dis_y_start {
             self.in_vblank = false;
-
-            // TODO: Timer sync. No longer in vblank.
+            timers.vblank_end(schedule);
         }
         
         let vram_offset = {
-            // Calculate offset from the first line in VRAM.
+            // Calculate offset from the first line in VRAM. In 480 line interlaced mode, only
+            // every other line of the field is displayed.
+            let line = self.scanline.wrapping_sub(self.dis_y_start);
             let offset = if self.status.interlaced_480() {
-                let bottom = self.in_vblank
-                    && self.status.interlace_field() == InterlaceField::Bottom;
-                (self.scanline  << 1) | bottom as u16
+                (line << 1) | self.status.0.bit(13) as u16
             } else {
-                self.scanline
+                line
             };
             
-            self.vram_y_start + offset
+            self.vram_y_start.wrapping_add(offset)
         };
         
         // Set bit 31 of the status register to indicite wether the current line being displayed
-        // in VRAM is even.
-        self.status.0 = self.status.0.set_bit(31, vram_offset.bit(0));
+        // in VRAM is odd. It's always even in vblank.
+        self.status.0 = self.status.0.set_bit(31, !self.in_vblank && vram_offset.bit(0));
+    }
+
+    /// Handle the end of hblank. This is used as an event callback.
+    fn end_of_hblank(&mut self, schedule: &mut Schedule, timers: &mut Timers) {
+        timers.hblank_end(schedule);
     }
 
-    /// Store value in GP0 register.
+    /// Update the speed of the dot clock used by the timers. This is used as an event callback
+    /// whenever the display mode changes.
+    fn sync_dot_clock(&mut self, schedule: &mut Schedule, timers: &mut Timers) {
+        let dot_clock = DotClock::new(self.status.video_mode(), self.status.horizontal_res());
+        timers.set_dot_clock(schedule, dot_clock);
+    }
+
+    /// Store value in GP1 register. Only the lower 6 bits of the command are used, so GP1(40)
+    /// to GP1(ff) mirror GP1(0) to GP1(3f).
     fn gp1_store(&mut self, schedule: &mut Schedule, val: u32) {
-        match val.bit_range(24, 31) {
+        self.capture_event(CaptureEvent::Gp1(val));
+
+        match val.bit_range(24, 29) {
             0x0 => self.gp1_reset(schedule),
             0x1 => self.gp1_reset_fifo(),
             0x2 => self.gp1_ack_gpu_irq(),
@@ -374,8 +458,10 @@ impl Gpu {
             0x6 => self.gp1_horizontal_display_range(val),
             0x7 => self.gp1_vertical_display_range(val),
             0x8 => self.gp1_display_mode(schedule, val),
-            0xff => warn!("weird GP1 command: GP1(ff)"),
-            cmd => unimplemented!("invalid GP1 command {cmd:08x}"),
+            0x9 => self.gp1_texture_disable(val),
+            0x10..=0x1f => self.gp1_gpu_info(val),
+            // GP1(20) sets the VRAM size on the arcade version of the GPU.
+            cmd => debug!("unused GP1 command: GP1({cmd:02x})"),
         }
     }
 
@@ -390,6 +476,9 @@ impl Gpu {
                 for (lo, hi) in [(0, 15), (16, 31)] {
                     let val = val.bit_range(lo, hi) as u16;
                     self.vram.store_16(tran.x, tran.y, val);
+                    if let Some(upscaled) = &mut self.upscaled {
+                        upscaled.store_native(tran.x, tran.y, val);
+                    }
                     tran.next();
                 }
 
@@ -418,7 +507,11 @@ impl Gpu {
     /// of the GPU is [`State::Idle`].
     fn try_gp0_exec(&mut self, schedule: &mut Schedule) {
         if let State::Idle = self.state {
-            if self.fifo.has_full_cmd() {
+            let ready = match &self.poly_line {
+                Some(line) => self.fifo.has_poly_line_vertex(line.cmd.bit(4)),
+                None => self.fifo.has_full_cmd(),
+            };
+            if ready {
                 self.gp0_exec(schedule);
             }
         }
@@ -427,6 +520,8 @@ impl Gpu {
     /// Execute GP0 command in FIFO. Should only be called if the FIFO has a full command.
     fn gp0_exec(&mut self, schedule: &mut Schedule) {
         let cycles = match self.fifo[0].bit_range(24, 31) {
+            // The next word is a vertex of the poly-line being drawn, not a command.
+            _ if self.poly_line.is_some() => self.gp0_poly_line_next(),
             0x1 => {
                 self.gp0_clear_texture_cache();
                 None
@@ -444,61 +539,11 @@ impl Gpu {
                 self.gp0_mask_bit_setting();
                 None
             }
-            0x27 => Some(self.gp0_tri_poly::<
-                draw_mode::UnShaded,
-                draw_mode::TexturedRaw,
-                draw_mode::Transparent,
-            >()),
-            0x28 => Some(self.gp0_quad_poly::<
-                draw_mode::UnShaded,
-                draw_mode::UnTextured,
-                draw_mode::Opaque,
-            >()),
-            0x2c => Some(self.gp0_quad_poly::<
-                draw_mode::UnShaded,
-                draw_mode::Textured,
-                draw_mode::Opaque,
-            >()),
-            0x2d => Some(self.gp0_quad_poly::<
-                draw_mode::UnShaded,
-                draw_mode::TexturedRaw,
-                draw_mode::Opaque,
-            >()),
-            0x2f => Some(self.gp0_quad_poly::<
-                draw_mode::UnShaded,
-                draw_mode::TexturedRaw,
-                draw_mode::Transparent,
-            >()),
-            0x30 => Some(self.gp0_tri_poly::<
-                draw_mode::Shaded,
-                draw_mode::UnTextured,
-                draw_mode::Opaque,
-            >()),
-            0x38 => Some(self.gp0_quad_poly::<
-                draw_mode::Shaded,
-                draw_mode::UnTextured,
-                draw_mode::Opaque,
-            >()),
-            0x40 => Some(self.gp0_line::<
-                draw_mode::UnShaded,
-                draw_mode::Opaque,
-            >()),
-            0x44 => Some(self.gp0_line::<
-                draw_mode::UnShaded,
-                draw_mode::Opaque,
-            >()),
-            0x55 => Some(self.gp0_line::<
-                draw_mode::Shaded,
-                draw_mode::Opaque,
-            >()),
-            0x64 => Some(self.gp0_rect::<
-                draw_mode::Textured,
-                draw_mode::Opaque,
-            >(None)),
-            0x65 => Some(self.gp0_rect::<
-                draw_mode::TexturedRaw,
-                draw_mode::Opaque,
-            >(None)),
+            cmd @ 0x20..=0x3f => Some(self.gp0_poly_cmd(cmd)),
+            cmd @ 0x40..=0x5f if gp0::cmd_is_poly_line(cmd) => self.gp0_poly_line_cmd(cmd),
+            cmd @ 0x40..=0x5f => Some(self.gp0_line_cmd(cmd)),
+            cmd @ 0x60..=0x7f => Some(self.gp0_rect_cmd(cmd)),
+            0x80..=0x9f => Some(self.gp0_copy_rect_vram_to_vram()),
             0xa0 => {
                 self.gp0_copy_rect_cpu_to_vram();
                 None
@@ -526,6 +571,7 @@ impl Gpu {
             schedule.schedule(cycles, Event::Gpu(|gpu, schedule, _| {
                 gpu.state = State::Idle;
                 gpu.try_gp0_exec(schedule);
+                Self::wake_dma(schedule);
             }));
         }
     }
@@ -595,7 +641,7 @@ impl fmt::Display for TransBlend {
 /// Video mode mainly determines the output framerate. It depends on the region of the console,
 /// North American consoles uses NTSC for instance, while European consoles uses PAL. Every console
 /// can output both modes, so it purely determined by bios and game.
-#[derive(Clone, Copy, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum VideoMode {
     /// ~ 60 Hz.
     Ntsc = 60,
@@ -632,7 +678,7 @@ impl fmt::Display for DmaDir {
 }
 
 /// Which lines being displayed.
-#[derive(PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum InterlaceField {
     Bottom = 0,
     Top = 1,
@@ -648,6 +694,7 @@ impl fmt::Display for InterlaceField {
 }
 
 /// Number of bits used to represent a single pixel shown to the screen.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum ColorDepth {
     /// The main mode used the majority of the time. This is the only resolution the GPU can
     /// rasterize to.
@@ -706,7 +753,7 @@ impl fmt::Display for TexelDepth {
 }
 
 /// Horizontal resolution.
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum HorizontalRes {
     P256 = 256,
     P320 = 320,
@@ -715,6 +762,19 @@ pub enum HorizontalRes {
     P640 = 640,
 }
 
+impl HorizontalRes {
+    /// The number of GPU cycles per pixel.
+    pub fn dot_clock_divider(self) -> u32 {
+        match self {
+            HorizontalRes::P256 => 10,
+            HorizontalRes::P320 => 8,
+            HorizontalRes::P368 => 7,
+            HorizontalRes::P512 => 5,
+            HorizontalRes::P640 => 4,
+        }
+    }
+}
+
 impl fmt::Display for HorizontalRes {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{} pixels", *self as usize)
@@ -722,7 +782,7 @@ impl fmt::Display for HorizontalRes {
 }
 
 /// Vertical resolution.
-#[derive(Clone, Copy, Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum VerticalRes {
     P240 = 240,
     P480 = 480,
@@ -780,6 +840,86 @@ impl fmt::Display for MemTransfer {
     }
 }
 
+/// How the display area in VRAM is shown on the screen.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DisplayInfo {
+    /// The first column of the display area in VRAM.
+    pub vram_x_start: u32,
+    /// The first line of the display area in VRAM.
+    pub vram_y_start: u32,
+    pub horizontal_res: HorizontalRes,
+    pub vertical_res: VerticalRes,
+    /// The horizontal display range set by GP1(6) in GPU cycles.
+    pub x_range: (u32, u32),
+    /// The vertical display range set by GP1(7) in scanlines.
+    pub y_range: (u32, u32),
+    pub color_depth: ColorDepth,
+    /// If both fields of a 480 line interlaced image are stored in VRAM.
+    pub interlaced: bool,
+    /// The field which has just been displayed if `interlaced`.
+    pub field: InterlaceField,
+    pub video_mode: VideoMode,
+    /// If the display is enabled. The screen is black when disabled.
+    pub enabled: bool,
+}
+
+impl DisplayInfo {
+    /// The number of GPU cycles of each scanline which is shown on the TV.
+    const VISIBLE_CYCLES: u32 = 2560;
+
+    /// The number of scanlines shown on the TV.
+    fn visible_lines(&self) -> u32 {
+        match self.video_mode {
+            VideoMode::Ntsc => 240,
+            VideoMode::Pal => 288,
+        }
+    }
+
+    /// The width of the displayed image in pixels. It's the display range divided by the dot
+    /// clock and rounded to 4 pixels. If the range is invalid, it uses the horizontal resolution.
+    pub fn width(&self) -> u32 {
+        let (start, end) = self.x_range;
+        if end > start {
+            let cycles = (end - start).min(Self::VISIBLE_CYCLES);
+            ((cycles / self.horizontal_res.dot_clock_divider() + 2) & !3).max(4)
+        } else {
+            self.horizontal_res as u32
+        }
+    }
+
+    /// The height of the displayed image in pixels. It's twice the amount of scanlines displayed
+    /// if interlaced. If the range is invalid, it uses all the visible lines.
+    pub fn height(&self) -> u32 {
+        let (start, end) = self.y_range;
+        let lines = if end > start {
+            (end - start).min(self.visible_lines())
+        } else {
+            self.visible_lines()
+        };
+        if self.interlaced {
+            lines * 2
+        } else {
+            lines
+        }
+    }
+
+    /// The aspect ratio of the image as shown on a 4:3 TV. The visible area of the TV is about
+    /// 2560 GPU cycles wide and 240 lines high for NTSC and 288 lines for PAL, so if the display
+    /// range covers less than that, the image isn't 4:3.
+    pub fn aspect_ratio(&self) -> f32 {
+        let (x_start, x_end) = self.x_range;
+        let (y_start, y_end) = self.y_range;
+        if x_end <= x_start || y_end <= y_start {
+            return 4.0 / 3.0;
+        }
+        let width = (x_end - x_start).min(Self::VISIBLE_CYCLES) as f32;
+        let height = (y_end - y_start).min(self.visible_lines()) as f32;
+        let width = width / Self::VISIBLE_CYCLES as f32;
+        let height = height / self.visible_lines() as f32;
+        (4.0 / 3.0) * (width / height)
+    }
+}
+
 /// Status register of the GPU.
 #[derive(Clone, Copy)]
 pub struct Status(pub u32);
@@ -900,9 +1040,9 @@ impl Status {
         self.0.bit(22)
     }
 
-    /// If image data gets send to the TV.
+    /// If image data gets send to the TV. The bit is set when the display is disabled.
     pub fn display_enabled(self) -> bool {
-        self.0.bit(23)
+        !self.0.bit(23)
     }
 
     pub fn irq_enabled(self) -> bool {
@@ -976,6 +1116,15 @@ pub(super) fn scanline_time(status: Status) -> SysTime {
     }
 }
 
+/// The amount of time of each scanline spent in hblank. The visible part of each scanline is
+/// about 2560 GPU cycles.
+pub(super) fn hblank_time(status: Status) -> SysTime {
+    match status.video_mode() {
+        VideoMode::Ntsc => SysTime::from_gpu_ntsc_cycles(3413 - 2560),
+        VideoMode::Pal => SysTime::from_gpu_pal_cycles(3405 - 2560),
+    }
+}
+
 pub(super) fn scanline_count(status: Status) -> u16 {
     if status.vertical_interlace() {
         let mut count = match status.video_mode() {
@@ -1030,16 +1179,17 @@ impl fmt::Display for State {
 }
 
 impl dma::Channel for Gpu {
-    fn dma_store(&mut self, schedule: &mut Schedule, val: u32) {
+    fn dma_store(&mut self, schedule: &mut Schedule, val: u32, addr: u32) {
+        self.pgxp_dma_word(addr, val);
         self.gp0_store(schedule, val);
     }
 
-    fn dma_load(&mut self, _: &mut Schedule, _: (u16, u32)) -> u32 {
+    fn dma_load(&mut self, schedule: &mut Schedule, _: (u16, u32)) -> u32 {
         if self.status.dma_direction() != DmaDir::VramToCpu {
             warn!("invalid DMA load from GPU");
             u32::MAX
         } else {
-            self.gpu_read()
+            self.gpu_read(schedule)
         }
     }
 
@@ -1049,9 +1199,115 @@ impl dma::Channel for Gpu {
             dma::Direction::ToPort => self.dma_block_ready(),
         }
     }
+
+    fn dma_word_ready(&self, dir: dma::Direction) -> bool {
+        match dir {
+            dma::Direction::ToRam => true,
+            dma::Direction::ToPort => !self.fifo.is_full(),
+        }
+    }
+
+    fn dma_word_time(&self) -> SysTime {
+        self.transfer_word_time()
+    }
+
+    fn dma_linked_node(&mut self, addr: u32, size: u32) {
+        self.capture_event(CaptureEvent::DmaNode { addr, size: size as u8 });
+    }
 }
 
 impl BusMap for Gpu {
     const BUS_BEGIN: u32 = 0x1f801810;
     const BUS_END: u32 = Self::BUS_BEGIN + 8 - 1;
 }
+
+#[test]
+fn display_size() {
+    let mut display = DisplayInfo {
+        vram_x_start: 0,
+        vram_y_start: 0,
+        horizontal_res: HorizontalRes::P320,
+        vertical_res: VerticalRes::P240,
+        x_range: (0x260, 0xc60),
+        y_range: (0x10, 0x100),
+        color_depth: ColorDepth::B15,
+        interlaced: false,
+        field: InterlaceField::Top,
+        video_mode: VideoMode::Ntsc,
+        enabled: true,
+    };
+
+    assert_eq!((display.width(), display.height()), (320, 240));
+    assert!((display.aspect_ratio() - 4.0 / 3.0).abs() < 0.001);
+
+    display.horizontal_res = HorizontalRes::P640;
+    display.vertical_res = VerticalRes::P480;
+    display.interlaced = true;
+
+    assert_eq!((display.width(), display.height()), (640, 480));
+
+    // Only half the width of the screen.
+    display.x_range = (0x260, 0x760);
+
+    assert_eq!(display.width(), 320);
+    assert!((display.aspect_ratio() - 2.0 / 3.0).abs() < 0.001);
+
+    // Interlaced PAL shows up to 576 lines, also if the range is invalid.
+    display.video_mode = VideoMode::Pal;
+    display.y_range = (0x10, 0x200);
+
+    assert_eq!(display.height(), 576);
+
+    display.y_range = (0, 0);
+
+    assert_eq!(display.height(), 576);
+}
+
+#[test]
+fn busy_status() {
+    use crate::test::run_gpu_until;
+    use dma::{Channel, Direction};
+    use primitive::Point;
+
+    let mut schedule = Schedule::new();
+    let mut gpu = Gpu::new(&mut schedule, Rc::new(RefCell::new(())));
+
+    // DMA direction CPU to GP0, so bit 25 follows bit 28.
+    gpu.store::<u32>(&mut schedule, 4, 0x0400_0002);
+
+    let status = gpu.status();
+    assert!(status.0.bit(25) && status.0.bit(26) && status.0.bit(28));
+
+    // Seven fills of a 64x64 rectangle. The first is drawn right away, and the FIFO is full after
+    // the first word of the last one.
+    let words: Vec<u32> = [0x0200_00ff, 0x0000_0000, 0x0040_0040]
+        .into_iter()
+        .cycle()
+        .take(3 * 7)
+        .collect();
+
+    let start = schedule.now();
+
+    for word in &words[..19] {
+        assert!(gpu.dma_word_ready(Direction::ToPort));
+        gpu.store::<u32>(&mut schedule, 0, *word);
+    }
+
+    let status = gpu.status();
+    assert!(!status.0.bit(25) && !status.0.bit(26) && !status.0.bit(28));
+    assert!(!gpu.dma_word_ready(Direction::ToPort));
+
+    run_gpu_until(&mut gpu, &mut schedule, |gpu| gpu.dma_word_ready(Direction::ToPort));
+
+    for word in &words[19..] {
+        gpu.store::<u32>(&mut schedule, 0, *word);
+    }
+
+    run_gpu_until(&mut gpu, &mut schedule, Gpu::is_idle);
+
+    let status = gpu.status();
+    assert!(status.0.bit(25) && status.0.bit(26) && status.0.bit(28));
+
+    let fill_time = gpu.fill_rect_time(Point::new(64, 64)) * 7;
+    assert!(schedule.now() >= start + gpu.dot_cycles_to_systime(fill_time));
+}
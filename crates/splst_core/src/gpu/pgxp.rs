@@ -0,0 +1,264 @@
+//! Sub-pixel precision geometry, similar to PGXP in other emulators.
+//!
+//! The GTE outputs integer screen coordinates, which makes polygons wobble and textures warp.
+//! The GTE keeps the precise coordinates of each vertex in the screen xy FIFO alongside the
+//! integer ones. When enabled, the precise vertex is recorded for the address in RAM each
+//! coordinate is stored to with SWC2. Each word sent to GP0 by DMA is then looked up by the address
+//! it's read from, and if the word still holds the integer coordinates of the precise vertex, the
+//! precise vertex is used when the word is used as a polygon vertex.
+//!
+//! Vertices written to GP0 by the CPU, or which have been copied around in memory, don't have any
+//! precise vertex and fall back to the integer coordinates. The precise coordinates are only used
+//! when drawing to upscaled VRAM, since native VRAM can't represent anything between pixels anyway,
+//! but perspective correct texturing is done at all resolutions.
+
+use serde::{Serialize, Deserialize};
+
+use super::primitive::Point;
+use super::Gpu;
+
+use std::collections::HashMap;
+
+/// A vertex output by the GTE with sub-pixel precision.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PreciseVertex {
+    pub x: f32,
+    pub y: f32,
+    /// The screen z coordinate. Used for perspective correct texturing.
+    pub z: f32,
+    /// The integer coordinates as stored in the SXY registers.
+    pub sxy: u32,
+}
+
+impl PreciseVertex {
+    /// A vertex without any more precision than the SXY value `sxy`.
+    pub fn from_sxy(sxy: u32) -> Self {
+        Self {
+            x: sxy as i16 as f32,
+            y: (sxy >> 16) as i16 as f32,
+            z: 1.0,
+            sxy,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PgxpSettings {
+    /// Use sub-pixel precision vertices when drawing polygons.
+    pub enabled: bool,
+    /// Interpolate texture coordinates with perspective correction when all vertices of a
+    /// polygon are precise.
+    pub perspective_correct: bool,
+}
+
+#[derive(Default)]
+pub(super) struct Pgxp {
+    settings: PgxpSettings,
+    /// Precise vertices by the address in RAM they're stored at.
+    stored: HashMap<u32, PreciseVertex>,
+    /// Precise vertices of words sent to GP0 by DMA, which haven't been used as a vertex yet, by
+    /// the value of the word.
+    pending: HashMap<u32, PreciseVertex>,
+}
+
+/// Get the address in RAM of `addr`, if it points to RAM.
+fn ram_addr(addr: u32) -> Option<u32> {
+    match addr & 0x1fff_ffff {
+        addr @ 0x0..=0x7f_ffff => Some(addr & 0x1f_fffc),
+        _ => None,
+    }
+}
+
+impl Gpu {
+    pub fn set_pgxp(&mut self, settings: PgxpSettings) {
+        if !settings.enabled {
+            self.pgxp.stored.clear();
+            self.pgxp.pending.clear();
+        }
+        self.pgxp.settings = settings;
+    }
+
+    pub fn pgxp(&self) -> PgxpSettings {
+        self.pgxp.settings
+    }
+
+    /// Record that `vertex` has been stored at `addr` by SWC2.
+    pub(crate) fn store_precise_vertex(&mut self, addr: u32, vertex: PreciseVertex) {
+        if !self.pgxp.settings.enabled {
+            return;
+        }
+        if let Some(addr) = ram_addr(addr) {
+            self.pgxp.stored.insert(addr, vertex);
+        }
+    }
+
+    /// Called for each word sent to GP0 by DMA with the address it's read from.
+    pub(super) fn pgxp_dma_word(&mut self, addr: u32, val: u32) {
+        if !self.pgxp.settings.enabled {
+            return;
+        }
+        let vertex = ram_addr(addr).and_then(|addr| self.pgxp.stored.get(&addr));
+        if let Some(vertex) = vertex.filter(|vertex| vertex.sxy == val) {
+            self.pgxp.pending.insert(val, *vertex);
+        }
+    }
+
+    /// Get the precise vertex of the vertex word `val` if there is one. The draw offset is added
+    /// to it.
+    pub(super) fn take_precise_vertex(&mut self, val: u32) -> Option<PreciseVertex> {
+        self.pgxp.pending.remove(&val).map(|vertex| PreciseVertex {
+            x: vertex.x + self.x_offset as f32,
+            y: vertex.y + self.y_offset as f32,
+            ..vertex
+        })
+    }
+
+    /// Forget the precise vertices sent but never drawn. Called every frame.
+    pub(super) fn pgxp_frame_end(&mut self) {
+        self.pgxp.pending.clear();
+    }
+
+    /// The depths used for perspective correct texturing, if enabled and all the vertices are
+    /// precise.
+    pub(super) fn perspective_depths<const N: usize>(
+        &self,
+        precise: &[Option<PreciseVertex>; N],
+    ) -> Option<[f32; N]> {
+        if !self.pgxp.settings.perspective_correct {
+            return None;
+        }
+        let mut depths = [0.0; N];
+        for (depth, vertex) in depths.iter_mut().zip(precise) {
+            *depth = vertex.as_ref()?.z;
+        }
+        Some(depths)
+    }
+}
+
+/// The points to draw a polygon at when the coordinates are shifted by `shift`. Precise vertices
+/// are used if there is one, otherwise the integer point.
+pub(super) fn precise_points<const N: usize>(
+    points: [Point; N],
+    precise: [Option<PreciseVertex>; N],
+    shift: u32,
+) -> [Point; N] {
+    let scale = (1 << shift) as f32;
+    let mut out = points.map(|point| point.scaled(shift));
+    if shift != 0 {
+        for (point, vertex) in out.iter_mut().zip(precise) {
+            if let Some(vertex) = vertex {
+                *point = Point::new(
+                    (vertex.x * scale).round() as i32,
+                    (vertex.y * scale).round() as i32,
+                );
+            }
+        }
+    }
+    out
+}
+
+#[test]
+fn precise_vertex_lookup() {
+    use crate::schedule::Schedule;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    let mut schedule = Schedule::new();
+    let mut gpu = Gpu::new(&mut schedule, Rc::new(RefCell::new(())));
+
+    gpu.set_pgxp(PgxpSettings { enabled: true, perspective_correct: false });
+
+    let sxy = 10 | 20 << 16;
+    let vertex = PreciseVertex { x: 10.25, y: 19.75, z: 100.0, sxy };
+
+    // Stored through a KSEG0 address and read by DMA through the physical address.
+    gpu.store_precise_vertex(0x8001_0000, vertex);
+    gpu.pgxp_dma_word(0x1_0000, sxy);
+
+    // The word at the address has been changed since the vertex was stored.
+    gpu.store_precise_vertex(0x8001_0004, vertex);
+    gpu.pgxp_dma_word(0x1_0004, 11 | 20 << 16);
+
+    gpu.x_offset = 1;
+
+    assert_eq!(gpu.take_precise_vertex(sxy).map(|v| (v.x, v.y)), Some((11.25, 19.75)));
+    assert_eq!(gpu.take_precise_vertex(sxy), None);
+    assert_eq!(gpu.take_precise_vertex(11 | 20 << 16), None);
+
+    let points = precise_points([Point::new(11, 20)], [Some(vertex)], 2);
+    assert_eq!(points, [Point::new(41, 79)]);
+}
+
+#[test]
+fn precise_textured_triangle() {
+    use super::ResolutionScale;
+    use crate::schedule::Schedule;
+    use crate::test::run_gp0;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    // A triangle from (0, 0) to (128, 0) and (0, 64), where the right vertex is three times as
+    // far away as the others, and the precise left vertices are at x 0.5. The texture has the u
+    // coordinate as the value of each texel.
+    let vertices = [
+        PreciseVertex { x: 0.5, y: 0.0, z: 1.0, sxy: 0 },
+        PreciseVertex { x: 128.0, y: 0.0, z: 3.0, sxy: 128 },
+        PreciseVertex { x: 0.5, y: 64.0, z: 1.0, sxy: 64 << 16 },
+    ];
+
+    let draw = |perspective_correct: bool| -> Gpu {
+        let mut schedule = Schedule::new();
+        let mut gpu = Gpu::new(&mut schedule, Rc::new(RefCell::new(())));
+
+        for v in 0..64 {
+            for u in 0..256 {
+                gpu.vram.store_16(640 + u, v, 0x8000 | u as u16);
+            }
+        }
+
+        gpu.set_resolution_scale(ResolutionScale::X2);
+        gpu.set_pgxp(PgxpSettings { enabled: true, perspective_correct });
+
+        for (i, vertex) in vertices.iter().enumerate() {
+            let addr = 0x1000 + i as u32 * 4;
+            gpu.store_precise_vertex(addr, *vertex);
+            gpu.pgxp_dma_word(addr, vertex.sxy);
+        }
+
+        let page = 10 | 2 << 7;
+        let [a, b, c] = vertices.map(|vertex| vertex.sxy);
+        let cmds = [
+            0xe3000000, 0xe4000000 | 1023 | 511 << 10, 0xe5000000,
+            0x25808080, a, 0, b, page << 16 | 128, c, 64 << 8,
+        ];
+
+        run_gp0(&mut gpu, &mut schedule, cmds);
+
+        gpu
+    };
+
+    let texel_u = |gpu: &Gpu, x: i32, y: i32| gpu.vram().load_16(x, y) & 0xff;
+
+    // Without perspective correction, u is interpolated linearly across the screen.
+    let affine = draw(false);
+    assert_eq!(texel_u(&affine, 64, 1), 64);
+    assert_eq!(texel_u(&affine, 96, 1), 96);
+
+    // With it, the weight of each vertex is divided by its depth. At x 64, the left and right
+    // vertices weigh 1/2 and 1/6, so u is 128 * (1/6) / (2/3). At x 96 they weigh 1/4 each, so
+    // it's only halfway through the texture.
+    let perspective = draw(true);
+    assert_eq!(texel_u(&perspective, 64, 1), 32);
+    assert_eq!(texel_u(&perspective, 96, 1), 64);
+
+    // The precise left vertices are half a pixel to the right, which moves the left edge by
+    // one pixel in upscaled VRAM, but not in native VRAM. Pixels right on the left edge aren't
+    // drawn.
+    for gpu in [&affine, &perspective] {
+        let upscaled = gpu.upscaled_vram().unwrap();
+        assert_eq!(gpu.vram().load_16(0, 1), 0);
+        assert_ne!(gpu.vram().load_16(1, 1), 0);
+        assert_eq!(upscaled.load_16(1, 2), 0);
+        assert_ne!(upscaled.load_16(2, 2), 0);
+    }
+}
@@ -0,0 +1,527 @@
+//! Minimal PNG encoding and decoding.
+//!
+//! Only 8-bit RGB and RGBA images without interlacing are supported, which is what's needed for
+//! screenshots and texture packs. Images are written without compression, which keeps the encoder
+//! simple, but the decoder handles compressed images since that's what image editors write.
+
+use thiserror::Error;
+
+use std::io::{self, Write};
+
+const SIGNATURE: &[u8; 8] = b"\x89PNG\r\n\x1a\n";
+
+#[derive(Error, Debug)]
+pub enum PngError {
+    #[error("not a PNG file")]
+    Signature,
+    #[error("unsupported PNG image: {0}")]
+    Unsupported(&'static str),
+    #[error("corrupt PNG image: {0}")]
+    Corrupt(&'static str),
+}
+
+/// An 8-bit RGBA image decoded by [`read`].
+pub(super) struct Rgba {
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<u8>,
+}
+
+/// Write an image with either RGB or RGBA pixels in `data`.
+pub(super) fn write(
+    mut out: impl Write,
+    width: u32,
+    height: u32,
+    alpha: bool,
+    data: &[u8],
+) -> io::Result<()> {
+    out.write_all(SIGNATURE)?;
+
+    let mut header = Vec::with_capacity(13);
+    header.extend(width.to_be_bytes());
+    header.extend(height.to_be_bytes());
+
+    // 8 bits per channel, RGB or RGBA, default compression, default filter and no interlacing.
+    header.extend([8, if alpha { 6 } else { 2 }, 0, 0, 0]);
+
+    write_chunk(&mut out, b"IHDR", &header)?;
+
+    let stride = width as usize * if alpha { 4 } else { 3 };
+
+    // Each line starts with the filter type, which is always none.
+    let mut lines = Vec::with_capacity(data.len() + height as usize);
+    for line in data.chunks_exact(stride.max(1)) {
+        lines.push(0);
+        lines.extend_from_slice(line);
+    }
+
+    write_chunk(&mut out, b"IDAT", &zlib_stored(&lines))?;
+    write_chunk(&mut out, b"IEND", &[])?;
+
+    out.flush()
+}
+
+/// Read an RGB or RGBA image. RGB images are given an opaque alpha channel.
+pub(super) fn read(png: &[u8]) -> Result<Rgba, PngError> {
+    let Some(mut rest) = png.strip_prefix(SIGNATURE) else {
+        return Err(PngError::Signature);
+    };
+
+    let mut header = None;
+    let mut compressed = Vec::new();
+
+    loop {
+        if rest.len() < 12 {
+            return Err(PngError::Corrupt("missing end of image"));
+        }
+
+        let len = u32::from_be_bytes([rest[0], rest[1], rest[2], rest[3]]) as usize;
+        let kind = &rest[4..8];
+
+        if rest.len() < len + 12 {
+            return Err(PngError::Corrupt("truncated chunk"));
+        }
+
+        let data = &rest[8..8 + len];
+        let crc = u32::from_be_bytes([
+            rest[8 + len], rest[9 + len], rest[10 + len], rest[11 + len],
+        ]);
+
+        if !crc32(crc32(!0, kind), data) != crc {
+            return Err(PngError::Corrupt("invalid chunk checksum"));
+        }
+
+        rest = &rest[len + 12..];
+
+        match kind {
+            b"IHDR" => {
+                if data.len() != 13 {
+                    return Err(PngError::Corrupt("invalid header"));
+                }
+                let width = u32::from_be_bytes([data[0], data[1], data[2], data[3]]);
+                let height = u32::from_be_bytes([data[4], data[5], data[6], data[7]]);
+                let alpha = match (data[8], data[9]) {
+                    (8, 2) => false,
+                    (8, 6) => true,
+                    _ => return Err(PngError::Unsupported("only 8-bit RGB and RGBA is supported")),
+                };
+                if data[12] != 0 {
+                    return Err(PngError::Unsupported("interlaced images aren't supported"));
+                }
+                header = Some((width, height, alpha));
+            }
+            b"IDAT" => compressed.extend_from_slice(data),
+            b"IEND" => break,
+            // Ancillary chunks such as gamma and text are ignored.
+            _ => (),
+        }
+    }
+
+    let Some((width, height, alpha)) = header else {
+        return Err(PngError::Corrupt("missing header"));
+    };
+
+    let bpp = if alpha { 4 } else { 3 };
+    let stride = width as usize * bpp;
+
+    let lines = zlib_inflate(&compressed)?;
+
+    if lines.len() != (stride + 1) * height as usize {
+        return Err(PngError::Corrupt("wrong amount of image data"));
+    }
+
+    let mut pixels = vec![0_u8; stride * height as usize];
+
+    for (y, line) in lines.chunks_exact(stride + 1).enumerate() {
+        let (filter, line) = (line[0], &line[1..]);
+        let (above, current) = pixels.split_at_mut(y * stride);
+        let above = above.get(above.len().wrapping_sub(stride)..).filter(|_| y > 0);
+        let current = &mut current[..stride];
+
+        for i in 0..stride {
+            let a = if i >= bpp { current[i - bpp] } else { 0 };
+            let b = above.map_or(0, |above| above[i]);
+            let c = match above {
+                Some(above) if i >= bpp => above[i - bpp],
+                _ => 0,
+            };
+            let prediction = match filter {
+                0 => 0,
+                1 => a,
+                2 => b,
+                3 => ((a as u16 + b as u16) / 2) as u8,
+                4 => paeth(a, b, c),
+                _ => return Err(PngError::Corrupt("invalid filter type")),
+            };
+            current[i] = line[i].wrapping_add(prediction);
+        }
+    }
+
+    let data = match alpha {
+        true => pixels,
+        false => pixels
+            .chunks_exact(3)
+            .flat_map(|rgb| [rgb[0], rgb[1], rgb[2], 255])
+            .collect(),
+    };
+
+    Ok(Rgba { width, height, data })
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let (pa, pb, pc) = ((p - a as i16).abs(), (p - b as i16).abs(), (p - c as i16).abs());
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+fn write_chunk(out: &mut impl Write, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    out.write_all(&(data.len() as u32).to_be_bytes())?;
+    out.write_all(kind)?;
+    out.write_all(data)?;
+
+    let crc = crc32(crc32(!0, kind), data);
+    out.write_all(&(!crc).to_be_bytes())
+}
+
+/// Update the CRC-32 `crc` with `data`, as used by PNG chunks.
+fn crc32(mut crc: u32, data: &[u8]) -> u32 {
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                0xedb88320 ^ (crc >> 1)
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    crc
+}
+
+/// Wrap `data` in a zlib stream of uncompressed deflate blocks.
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() + data.len() / 0xffff * 5 + 16);
+
+    // Deflate with a 32K window and no preset dictionary.
+    out.extend([0x78, 0x01]);
+
+    let mut blocks = data.chunks(0xffff).peekable();
+
+    if blocks.peek().is_none() {
+        out.extend([0x1, 0x0, 0x0, 0xff, 0xff]);
+    }
+
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none() as u8;
+        let len = block.len() as u16;
+        out.push(last);
+        out.extend(len.to_le_bytes());
+        out.extend((!len).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+
+    let (a, b) = data.iter().fold((1_u32, 0_u32), |(a, b), byte| {
+        let a = (a + *byte as u32) % 65521;
+        (a, (b + a) % 65521)
+    });
+
+    out.extend((b << 16 | a).to_be_bytes());
+    out
+}
+
+/// Decompress a zlib stream. The checksum at the end isn't checked, since the chunks are already
+/// checked.
+fn zlib_inflate(data: &[u8]) -> Result<Vec<u8>, PngError> {
+    match data {
+        [cmf, flg, ..] if cmf & 0xf == 8 && u16::from_be_bytes([*cmf, *flg]) % 31 == 0 => {
+            if flg & 0x20 != 0 {
+                return Err(PngError::Unsupported("preset dictionaries aren't supported"));
+            }
+            inflate(&data[2..])
+        }
+        _ => Err(PngError::Corrupt("invalid zlib header")),
+    }
+}
+
+struct Bits<'a> {
+    data: &'a [u8],
+    /// The position in bits.
+    pos: usize,
+}
+
+impl<'a> Bits<'a> {
+    fn bit(&mut self) -> Result<u16, PngError> {
+        let byte = self.data
+            .get(self.pos / 8)
+            .ok_or(PngError::Corrupt("truncated image data"))?;
+        let bit = (byte >> (self.pos % 8)) & 1;
+        self.pos += 1;
+        Ok(bit as u16)
+    }
+
+    /// Read `count` bits with the least significant first.
+    fn bits(&mut self, count: u32) -> Result<u16, PngError> {
+        let mut val = 0;
+        for i in 0..count {
+            val |= self.bit()? << i;
+        }
+        Ok(val)
+    }
+}
+
+/// A canonical Huffman code.
+struct Huffman {
+    /// The number of symbols of each code length.
+    counts: [u16; 16],
+    /// The symbols ordered by code length and then value.
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    /// Create from the code length of each symbol.
+    fn new(lengths: &[u8]) -> Self {
+        let mut counts = [0_u16; 16];
+        for len in lengths {
+            counts[*len as usize] += 1;
+        }
+        counts[0] = 0;
+
+        let mut offsets = [0_u16; 16];
+        for len in 1..15 {
+            offsets[len + 1] = offsets[len] + counts[len];
+        }
+
+        let mut symbols = vec![0; lengths.len()];
+        for (symbol, len) in lengths.iter().enumerate() {
+            if *len != 0 {
+                symbols[offsets[*len as usize] as usize] = symbol as u16;
+                offsets[*len as usize] += 1;
+            }
+        }
+
+        Self { counts, symbols }
+    }
+
+    fn decode(&self, bits: &mut Bits) -> Result<u16, PngError> {
+        // The first code and index into `symbols` of the current code length.
+        let (mut code, mut first, mut index) = (0_i32, 0_i32, 0_i32);
+        for count in &self.counts[1..] {
+            code |= bits.bit()? as i32;
+            let count = *count as i32;
+            if code - count < first {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(PngError::Corrupt("invalid huffman code"))
+    }
+}
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115,
+    131, 163, 195, 227, 258,
+];
+
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12,
+    13, 13,
+];
+
+/// The order the code lengths of the code length alphabet are stored in.
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+/// Decompress a raw deflate stream.
+fn inflate(data: &[u8]) -> Result<Vec<u8>, PngError> {
+    let mut bits = Bits { data, pos: 0 };
+    let mut out = Vec::new();
+
+    loop {
+        let last = bits.bit()? == 1;
+
+        match bits.bits(2)? {
+            0 => {
+                // Stored blocks start at the next byte.
+                bits.pos = (bits.pos + 7) & !7;
+                let start = bits.pos / 8;
+                let header = data
+                    .get(start..start + 4)
+                    .ok_or(PngError::Corrupt("truncated image data"))?;
+                let len = u16::from_le_bytes([header[0], header[1]]);
+                let nlen = u16::from_le_bytes([header[2], header[3]]);
+                if len != !nlen {
+                    return Err(PngError::Corrupt("invalid stored block"));
+                }
+                let block = data
+                    .get(start + 4..start + 4 + len as usize)
+                    .ok_or(PngError::Corrupt("truncated image data"))?;
+                out.extend_from_slice(block);
+                bits.pos = (start + 4 + len as usize) * 8;
+            }
+            1 => {
+                let mut lengths = [0; 288];
+                lengths[..144].fill(8);
+                lengths[144..256].fill(9);
+                lengths[256..280].fill(7);
+                lengths[280..].fill(8);
+                let lit = Huffman::new(&lengths);
+                let dist = Huffman::new(&[5; 30]);
+                inflate_block(&mut bits, &mut out, &lit, &dist)?;
+            }
+            2 => {
+                let lit_count = bits.bits(5)? as usize + 257;
+                let dist_count = bits.bits(5)? as usize + 1;
+                let code_count = bits.bits(4)? as usize + 4;
+
+                let mut code_lengths = [0; 19];
+                for i in CODE_LENGTH_ORDER.iter().take(code_count) {
+                    code_lengths[*i] = bits.bits(3)? as u8;
+                }
+                let code = Huffman::new(&code_lengths);
+
+                let mut lengths = Vec::with_capacity(lit_count + dist_count);
+                while lengths.len() < lit_count + dist_count {
+                    let (len, repeat) = match code.decode(&mut bits)? {
+                        len @ 0..=15 => (len as u8, 1),
+                        16 => {
+                            let prev = *lengths
+                                .last()
+                                .ok_or(PngError::Corrupt("repeat without code length"))?;
+                            (prev, bits.bits(2)? + 3)
+                        }
+                        17 => (0, bits.bits(3)? + 3),
+                        _ => (0, bits.bits(7)? + 11),
+                    };
+                    lengths.extend((0..repeat).map(|_| len));
+                }
+
+                if lengths.len() != lit_count + dist_count {
+                    return Err(PngError::Corrupt("too many code lengths"));
+                }
+
+                let lit = Huffman::new(&lengths[..lit_count]);
+                let dist = Huffman::new(&lengths[lit_count..]);
+                inflate_block(&mut bits, &mut out, &lit, &dist)?;
+            }
+            _ => return Err(PngError::Corrupt("invalid block type")),
+        }
+
+        if last {
+            return Ok(out);
+        }
+    }
+}
+
+/// Decompress a block compressed with the Huffman codes `lit` and `dist`.
+fn inflate_block(
+    bits: &mut Bits,
+    out: &mut Vec<u8>,
+    lit: &Huffman,
+    dist: &Huffman,
+) -> Result<(), PngError> {
+    loop {
+        let symbol = lit.decode(bits)? as usize;
+        match symbol {
+            0..=255 => out.push(symbol as u8),
+            256 => return Ok(()),
+            _ => {
+                let i = symbol - 257;
+                if i >= LENGTH_BASE.len() {
+                    return Err(PngError::Corrupt("invalid length code"));
+                }
+                let len = LENGTH_BASE[i] + bits.bits(LENGTH_EXTRA[i] as u32)?;
+
+                let i = dist.decode(bits)? as usize;
+                if i >= DIST_BASE.len() {
+                    return Err(PngError::Corrupt("invalid distance code"));
+                }
+                let dist = (DIST_BASE[i] + bits.bits(DIST_EXTRA[i] as u32)?) as usize;
+
+                if dist > out.len() {
+                    return Err(PngError::Corrupt("distance too far back"));
+                }
+
+                // The copy may overlap with the bytes being written.
+                for _ in 0..len {
+                    out.push(out[out.len() - dist]);
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn png_round_trip() {
+    let data: Vec<u8> = (0..5 * 3 * 4).map(|i| (i * 37) as u8).collect();
+
+    let mut png = Vec::new();
+    write(&mut png, 5, 3, true, &data).unwrap();
+
+    let image = read(&png).unwrap();
+    assert_eq!((image.width, image.height), (5, 3));
+    assert!(image.data == data);
+}
+
+#[cfg(test)]
+fn from_hex(hex: &str) -> Vec<u8> {
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+        .collect()
+}
+
+#[test]
+fn compressed_png() {
+    // A 2x4 RGB image compressed by zlib, where the lines use the sub, up, average and paeth
+    // filters.
+    let png = from_hex(
+        "89504e470d0a1a0a0000000d49484452000000020000000408020000002b8d796e00000020494441547\
+         8da636438c160c410c5c4f548038898f9cd6de43e3ab280780c1a005e08069ff7e42e570000000049454\
+         e44ae426082",
+    );
+
+    let image = read(&png).unwrap();
+    let rgb: Vec<u8> = image.data
+        .chunks_exact(4)
+        .flat_map(|rgba| &rgba[..3])
+        .copied()
+        .collect();
+
+    assert_eq!((image.width, image.height), (2, 4));
+    assert_eq!(rgb, [
+        0, 200, 0, 50, 200, 90,
+        10, 170, 40, 60, 170, 130,
+        20, 140, 80, 70, 140, 170,
+        30, 110, 120, 80, 110, 210,
+    ]);
+    assert!(image.data.chunks_exact(4).all(|rgba| rgba[3] == 255));
+}
+
+#[test]
+fn dynamic_huffman() {
+    let data = from_hex(
+        "78da1d88091100300cc2accc5a42fd6b182dc773010211a6a95ce675277a9f59b738fcdf6512d1",
+    );
+    assert_eq!(
+        zlib_inflate(&data).unwrap(),
+        b"aacaacbaadbaaaaabacaaa abadcbbbaaaabcabcacababaaaa",
+    );
+}
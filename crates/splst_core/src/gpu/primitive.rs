@@ -31,6 +31,11 @@ impl Point {
     pub fn transpose(self) -> Self {
         Self::new(self.y, self.x)
     }
+
+    /// Shift both coordinates left by `shift`. Used to scale points to upscaled VRAM.
+    pub fn scaled(self, shift: u32) -> Self {
+        Self::new(self.x << shift, self.y << shift)
+    }
 }
 
 impl Sub for Point {
@@ -70,6 +75,10 @@ impl Texel {
         Color::from_u16(self.0)
     }
 
+    pub fn as_u16(self) -> u16 {
+        self.0
+    }
+
     pub fn is_transparent(self) -> bool {
         self.0.bit(15)
     }
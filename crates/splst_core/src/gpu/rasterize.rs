@@ -1,194 +1,68 @@
 use super::primitive::{Color, Point, TexCoord, Texel};
-use super::{Gpu, TexelDepth};
+use super::{Gpu, Status, TexelDepth, InterlaceField};
 use super::gp0::draw_mode;
+use super::texture::ClutCache;
+use super::texpack::Replacement;
+use super::tile::{Raster, VramPtr};
 
-use std::simd::{f32x4, i32x4, i32x8, f32x8};
+use std::simd::{i32x4, i32x8};
+use std::marker::PhantomData;
+use std::ops::Range;
+use std::sync::Arc;
+
+/// The number of fractional bits of the fixed point attributes of triangles.
+pub(super) const ATTR_FRAC_BITS: i32 = 12;
 
 impl Gpu {
-    /// Draw a single pixel to the screen. It handles transparency and texture but not dithering.
+    /// Draw a single pixel to the screen. It handles transparency, texture and mask bit settings
+    /// but not dithering. `masked` is bit 15 of the texel if textured.
     fn draw_pixel<Tran, Tex>(&mut self, x: i32, y: i32, color: Color, masked: bool)
     where
         Tran: draw_mode::Transparency,
         Tex: draw_mode::Textureing
     {
-        let color = match Tran::IS_TRANSPARENT {
-            false => color,
-            true => {
-                let bg = Color::from_u16(self.vram.load_16(x, y));
-                match Tex::IS_TEXTURED {
-                    true if masked => self.status.blend_mode().blend(color, bg),
-                    true => color,
-                    false => self.status.blend_mode().blend(color, bg),
-                }
-            }
-        };
-        self.vram.store_16(x, y, color.as_u16());
-    }
-
-    /// Load a texel at a given texture coordinate.
-    fn load_texel(
-        &self,
-        coord: TexCoord,
-        tex_param_cache: TexParamCache
-    ) -> Texel {
-        let u = (coord.u & tex_param_cache.tex_win_u) as i32;
-        let v = (coord.v & tex_param_cache.tex_win_v) as i32;
-       
-        match self.status.texel_depth() {
-            TexelDepth::B4 => {
-                let val = self.vram.load_16(
-                    self.status.tex_page_x() + u / 4,
-                    self.status.tex_page_y() + v,
-                );
-
-                let offset = (val >> ((u & 3) * 4)) as i32 & 0xf;
-
-                self.clut_cache.get(offset)
-            }
-            TexelDepth::B8 => {
-                let val = self.vram.load_16(
-                    self.status.tex_page_x() + u / 2,
-                    self.status.tex_page_y() + v,
-                );
-
-                let offset = (val >> ((u & 1) * 8)) as i32 & 0xff;
-
-                self.clut_cache.get(offset)
-            }
-            TexelDepth::B15 => {
-                let val = self.vram.load_16(
-                    self.status.tex_page_x() + u,
-                    self.status.tex_page_y() + v,
-                );
-
-                Texel::new(val)
-            }
-        }
-    }
-
-    /// Calculate the amount of GPU cycles to draw a triangle.
-    fn triangle_draw_time<Shade, Tex, Trans>(&self, mut pixels: u64) -> u64
-    where
-        Shade: draw_mode::Shading,
-        Tex: draw_mode::Textureing,
-        Trans: draw_mode::Transparency,
-    {
-        let mut cycles = 0;
-
-        // Start by calculating the constant factor.
-
-        if Shade::IS_SHADED {
-            cycles += 300;
-        }
-
-        if Tex::IS_TEXTURED {
-            cycles += 150;
-        }
-
-        let mut pixel_cost = 1.0;
-
-        if Tex::IS_TEXTURED || Shade::IS_SHADED {
-            pixel_cost += 0.8;
+        if self.is_displayed_line(y) {
+            return;
         }
 
-        if Trans::IS_TRANSPARENT {
-            pixel_cost += 0.3;
-        }
+        let bg = self.target_load(x, y);
 
-        // Hack for now until we emulate only drawing to not displayed lines.
-        if !self.status.draw_to_display() && self.status.interlaced_480() {
-            pixels /= 2;
+        if let Some(val) = blend_pixel::<Tran, Tex>(self.status, bg, color, masked) {
+            self.target_store(x, y, val);
         }
+    }
 
-        cycles + (pixels as f64 * pixel_cost) as u64
+    fn is_displayed_line(&self, y: i32) -> bool {
+        is_displayed_line(self.status, self.draw_shift, y)
     }
 
-    /// Timings from mednafen.
-    fn _triangle_draw_time_mdnf<Shade, Tex, Trans>(&self, pixels: u64) -> u64
+    /// If a primitive should be dithered. Only shaded and texture blended primitives are
+    /// dithered, and only if dithering is enabled.
+    fn should_dither<Shade, Tex>(&self) -> bool
     where
         Shade: draw_mode::Shading,
         Tex: draw_mode::Textureing,
-        Trans: draw_mode::Transparency,
     {
-        let mut draw_time = 0;
-
-        draw_time += if Shade::IS_SHADED {
-            if Tex::IS_TEXTURED {
-                150 * 3
-            } else {
-                96 * 3
-            }
-        } else if Tex::IS_TEXTURED {
-            60 * 3 
-        } else {
-            0
-        };
-
-        draw_time += if Tex::IS_TEXTURED || Shade::IS_SHADED {
-            pixels * 2
-        } else if Trans::IS_TRANSPARENT || !self.status.draw_masked_pixels() {
-            (pixels / 2) * 3
-        } else {
-            pixels
-        };
-
-        if !self.status.draw_to_display() && self.status.interlaced_480() {
-            draw_time /= 2;
-        }
-
-        draw_time
+        self.status.dithering_enabled()
+            && (Shade::IS_SHADED || (Tex::IS_TEXTURED && !Tex::IS_RAW))
     }
 
-    /// The amount of GPU cycles to draw a rectangle.
-    fn rect_draw_time<Tex, Trans>(&self, mut pixels: u64) -> u64
-    where
-        Tex: draw_mode::Textureing,
-        Trans: draw_mode::Transparency,
-    {
-        let cycles = 30;
-
-        let mut pixel_cost = 1.0;
-
-        if Tex::IS_TEXTURED {
-            pixel_cost += 0.4;
-        }
-
-        if Trans::IS_TRANSPARENT {
-            pixel_cost += 0.2;
-        }
-
-        if !self.status.draw_to_display() && self.status.interlaced_480() {
-            pixels /= 2;
-        }
-
-        cycles + (pixels as f64 * pixel_cost) as u64
+    /// Load a texel at a given texture coordinate.
+    fn load_texel(
+        &self,
+        coord: TexCoord,
+        tex_param_cache: TexParamCache
+    ) -> Texel {
+        fetch_texel(self.status, &self.clut_cache, coord, tex_param_cache, |x, y| {
+            self.vram.load_16(x, y)
+        })
     }
 
-    /// Amount of GPU cycles to draw a line.
-    fn line_draw_time<Shade, Trans>(&self, mut pixels: u64) -> u64
-    where
-        Shade: draw_mode::Shading,
-        Trans: draw_mode::Transparency,
-    {
-        let cycles = 30;
-        let mut pixel_cost = 1.0;
-
-        if Shade::IS_SHADED {
-            pixel_cost += 0.5;
-        }
-
-        if Trans::IS_TRANSPARENT {
-            pixel_cost += 0.5;
-        }
-
-        if !self.status.draw_to_display() && self.status.interlaced_480() {
-            pixels /= 2;
-        }
-
-        cycles + (pixels as f64 * pixel_cost) as u64
+    /// Load a texel of the current texture page, ignoring the texture window.
+    pub(super) fn page_texel(&self, coord: TexCoord) -> Texel {
+        self.load_texel(coord, TexParamCache::new(0, 0, 0, 0))
     }
 
-    /// FIXME: This may draw outside the draw area.
     pub fn draw_triangle<Shade, Tex, Trans>(
         &mut self,
         flat_shade: Color,
@@ -196,6 +70,7 @@ impl Gpu {
         mut points: [Point; 3],
         mut colors: [Color; 3],
         mut coords: [TexCoord; 3],
+        mut depths: Option<[f32; 3]>,
     ) -> u64
     where
         Shade: draw_mode::Shading,
@@ -209,10 +84,17 @@ impl Gpu {
             self.tex_win_y,
         );
         
-        if let TexelDepth::B4 | TexelDepth::B8 = self.status.texel_depth() {
-            self.clut_cache.maybe_fetch(clut, self.status.texel_depth(), &self.vram);
+        if Tex::IS_TEXTURED {
+            self.load_clut(clut);
         }
 
+        let replacement = match Tex::IS_TEXTURED {
+            true => self.texture_replacement(),
+            false => None,
+        };
+
+        let dither = self.should_dither::<Shade, Tex>();
+
         // The determinant of a 3x3 matrix of where arranged as:
         //
         //     a.x | b.x | c.x
@@ -228,6 +110,18 @@ impl Gpu {
             (b.x - a.x) * (c.y - a.y) - (b.y - a.y) * (c.x - a.x)
         }
         
+        // The attributes are stepped from the leftmost vertex like Mednafen does, which changes
+        // how the deltas get rounded. Ties go to the later vertex, except between the first and
+        // last, so it has to be picked before swapping. This hasn't been checked against console
+        // captures yet.
+        let mut core = if points[1].x <= points[0].x {
+            if points[2].x <= points[1].x { 2 } else { 1 }
+        } else if points[2].x < points[0].x {
+            2
+        } else {
+            0
+        };
+
         // Assure the triangle is wound counter-clockwise, i.e. the vertex 'c' lies to the left of
         // the edge ab. If that isn't the case, vertex 'b' and 'c' must be swapped, which makes it
         // counter-clockwise.
@@ -242,6 +136,10 @@ impl Gpu {
             points.swap(1, 2);
             colors.swap(1, 2);
             coords.swap(1, 2);
+            if let Some(depths) = &mut depths {
+                depths.swap(1, 2);
+            }
+            core = [0, 2, 1][core];
         }
 
         // Check if an edge is the top most edge, and that the edge is 'left' meaning that the end
@@ -274,7 +172,7 @@ impl Gpu {
         ];
                 
         // The double area of the triangle.
-        let area = edge_function(points[0], points[1], points[2]) as f32;
+        let area = edge_function(points[0], points[1], points[2]);
 
         // Find the bounding box of the triangle.
         let bb_max = Point {
@@ -287,26 +185,19 @@ impl Gpu {
             y: i32::min(points[0].y, i32::min(points[1].y, points[2].y)),
         };
 
+        let (da_min, da_max) = self.target_draw_area();
+
         // Clip bounding box against screen bounds.
         let max = Point {
-            // Since check four pixels at a time, this must be a multiple of 4.
-            x: i32::min(bb_max.x, self.da_x_max).next_multiple_of(4),
-            y: i32::min(bb_max.y, self.da_y_max),
+            x: i32::min(bb_max.x, da_max.x),
+            y: i32::min(bb_max.y, da_max.y),
         };
 
         let min = Point {
-            x: i32::max(bb_min.x, self.da_x_min),
-            y: i32::max(bb_min.y, self.da_y_min),
+            x: i32::max(bb_min.x, da_min.x),
+            y: i32::max(bb_min.y, da_min.y),
         };
-        
-        // This is to keep track of how many pixels gets drawn to calculate timing.
-        let mut pixels_drawn: u64 = 0;
-        
-        #[derive(Default)]
-        struct Delta {
-            x: f32,
-            y: f32,
-        }
+
 
         // The barycentric coordinates delta along the x and y axes.
         let dx = [
@@ -321,346 +212,88 @@ impl Gpu {
             points[1].x - points[0].x,
         ];
         
-        let u_delta;
-        let v_delta;
-        
-        if Tex::IS_TEXTURED {
-            let delta: f32x4 = coords.iter()
-                .zip(dx.iter())
-                .zip(dy.iter())
-                .fold(i32x4::default(), |delta, ((uv, dx), dy)| {
-                    let uv = i32x4::from([
-                        uv.u.into(),
-                        uv.u.into(),
-                        uv.v.into(),
-                        uv.v.into()
-                    ]);
-                    delta + uv * i32x4::from([*dx, *dy, *dx, *dy])
-                })
-                .cast();
-
-            let delta = delta / f32x4::from([area; 4]);
-            
-            u_delta = Delta {
-                x: delta[0],
-                y: delta[1],
-            };
-
-            v_delta = Delta {
-                x: delta[2],
-                y: delta[3],
-            };
-        } else {
-            u_delta = Delta::default();  
-            v_delta = Delta::default();  
-        };
-        
-        let r_delta;
-        let g_delta;
-        let b_delta;
-        
-        if Shade::IS_SHADED {
-            let delta: f32x8 = colors
-                .iter()
-                .zip(dx.iter())
-                .zip(dy.iter())
-                .fold(i32x8::default(), |delta, ((color, dx), dy)| {
-                    let rgb = i32x8::from([
-                        color.r.into(),
-                        color.r.into(),
-                        color.g.into(),
-                        color.g.into(),
-                        color.b.into(),
-                        color.b.into(), 
-                        0,
-                        0
-                    ]);
-                    delta + rgb * i32x8::from([*dx, *dy, *dx, *dy, *dx, *dy, 0, 0])
-                })
-                .cast();
-
-            let delta = delta / f32x8::from([area; 8]);
-
-            r_delta = Delta {
-                x: delta[0],
-                y: delta[1],
-            };
-
-            g_delta = Delta {
-                x: delta[2],
-                y: delta[3],
-            };
-
-            b_delta = Delta {
-                x: delta[4],
-                y: delta[5],
-            };
-        } else {
-            r_delta = Delta::default();
-            g_delta = Delta::default();
-            b_delta = Delta::default();
-        };
-
-        let attr_base;
-        let fbias;
-        
-        if Tex::IS_TEXTURED || Shade::IS_SHADED {
-            attr_base = [
-                (points[1].x * points[2].y - points[2].x * points[1].y) as f32,
-                (points[2].x * points[0].y - points[0].x * points[2].y) as f32,
-                (points[0].x * points[1].y - points[1].x * points[0].y) as f32,
-            ];
-            fbias = [bias[0] as f32, bias[1] as f32, bias[2] as f32];
-        } else {
-            attr_base = [0.0; 3];
-            fbias = [0.0; 3];
-        }
-        
-        let (mut u_row, mut v_row) = if Tex::IS_TEXTURED {
-            let u = attr_base[0] * coords[0].u as f32 - fbias[0]
-                + attr_base[1] * coords[1].u as f32 - fbias[1]
-                + attr_base[2] * coords[2].u as f32 - fbias[2];
-            let v = attr_base[0] * coords[0].v as f32 - fbias[0]
-                + attr_base[1] * coords[1].v as f32 - fbias[1]
-                + attr_base[2] * coords[2].v as f32 - fbias[2];
-            ((u / area) + 0.5, (v / area) + 0.5)
-        } else {
-            (0.0, 0.0)
-        };
-
-        let (mut r_row, mut g_row, mut b_row) = if Shade::IS_SHADED {
-            let r = attr_base[0] * colors[0].r as f32 - fbias[0]
-                + attr_base[1] * colors[1].r as f32 - fbias[1]
-                + attr_base[2] * colors[2].r as f32 - fbias[2];
-            let g = attr_base[0] * colors[0].g as f32 - fbias[0]
-                + attr_base[1] * colors[1].g as f32 - fbias[1]
-                + attr_base[2] * colors[2].g as f32 - fbias[2];
-            let b = attr_base[0] * colors[0].b as f32 - fbias[0]
-                + attr_base[1] * colors[1].b as f32 - fbias[1]
-                + attr_base[2] * colors[2].b as f32 - fbias[2];
-            ((r / area) + 0.5, (g / area) + 0.5, (b / area) + 0.5)
-        } else {
-            (0.0, 0.0, 0.0)
-        };
-        
-        struct Edges {
-            // The barycentric coordinates at the start of each line. They change every time the 
-            // rasterizer goes down a line.
-            y_bary: [i32x4; 3],
-            // The current barycentric coordinates. They change for each column step.
-            x_bary: [i32x4; 3],
-            // The delta to the barycentric coordinates for each step along the x-axis.
-            x_delta: [i32x4; 3],
-            // The delta to the barycentric coordinates for each step along the y-axis.
-            y_delta: [i32x4; 3],
-        }
-        
-        impl Edges {
-            fn new(v0: Point, v1: Point, v2: Point, origin: Point, bias: &[i32; 3]) -> Self {
-                fn setup(v0: Point, v1: Point) -> [i32; 3] {
-                    [v0.y - v1.y, v1.x - v0.x, v0.x * v1.y - v0.y * v1.x]
+        // The attributes are interpolated in fixed point like the hardware does. The delta of
+        // each attribute along the x and y axes is calculated from the vertices, and the value at
+        // each pixel is then found by stepping from the leftmost vertex. The attributes are `u`,
+        // `v`, `r`, `g` and `b`, in that order.
+        let attrs: [[i32; 5]; 3] = [0, 1, 2].map(|i| {
+            let (uv, color) = (coords[i], colors[i]);
+            [uv.u, uv.v, color.r, color.g, color.b].map(i32::from)
+        });
+
+        let interpolate = Tex::IS_TEXTURED || Shade::IS_SHADED;
+
+        let delta = |deltas: [i32; 3]| -> i32x8 {
+            let mut out = [0; 8];
+            if interpolate && area != 0 {
+                for (attr, out) in out.iter_mut().take(5).enumerate() {
+                    let sum: i64 = (0..3)
+                        .map(|i| i64::from(attrs[i][attr]) * i64::from(deltas[i]))
+                        .sum();
+                    // Slivers with a tiny area can have deltas too large to fit, which would
+                    // otherwise be truncated into something arbitrary.
+                    let delta = (sum << ATTR_FRAC_BITS) / i64::from(area);
+                    *out = delta.clamp(i64::from(i32::MIN), i64::from(i32::MAX)) as i32;
                 }
-
-                let s0 = setup(v1, v2);
-                let s1 = setup(v2, v0);
-                let s2 = setup(v0, v1);
-                
-                let x_delta = [
-                    i32x4::from([s0[0] * 4; 4]),
-                    i32x4::from([s1[0] * 4; 4]),
-                    i32x4::from([s2[0] * 4; 4]),
-                ];
-                
-                let y_delta = [
-                    i32x4::from([s0[1]; 4]),
-                    i32x4::from([s1[1]; 4]),
-                    i32x4::from([s2[1]; 4]),
-                ];
-                
-                let x = i32x4::from([origin.x; 4]) + i32x4::from([0, 1, 2, 3]);
-                let y = i32x4::from([origin.y; 4]);
-                
-                let y_bary = [
-                      i32x4::from([s0[0]; 4]) * x
-                        + i32x4::from([s0[1]; 4]) * y
-                        + i32x4::from([s0[2]; 4])
-                        + i32x4::from([bias[0]; 4]),
-                      i32x4::from([s1[0]; 4]) * x
-                        + i32x4::from([s1[1]; 4]) * y
-                        + i32x4::from([s1[2]; 4])
-                        + i32x4::from([bias[1]; 4]),
-                      i32x4::from([s2[0]; 4]) * x
-                        + i32x4::from([s2[1]; 4]) * y
-                        + i32x4::from([s2[2]; 4])
-                        + i32x4::from([bias[2]; 4]),
-                ];
-                
-                Self {
-                    x_bary: y_bary.clone(),
-                    y_bary,
-                    x_delta,
-                    y_delta,
-                }
-            }
-            
-            fn x_step(&mut self) {
-                self.x_bary[0] += self.x_delta[0];
-                self.x_bary[1] += self.x_delta[1];
-                self.x_bary[2] += self.x_delta[2];
             }
-            
-            fn y_step(&mut self) {
-                self.x_bary = self.y_bary.clone();
-                self.y_bary[0] += self.y_delta[0];
-                self.y_bary[1] += self.y_delta[1];
-                self.y_bary[2] += self.y_delta[2];
-            }
-        }
-        
-        let mut edges = Edges::new(points[0], points[1], points[2], min, &bias);
+            i32x8::from(out)
+        };
 
-        if Tex::IS_TEXTURED {
-            u_row += u_delta.y * min.y as f32;
-            u_row += u_delta.x * min.x as f32;
-            v_row += v_delta.y * min.y as f32;
-            v_row += v_delta.x * min.x as f32;
-        }
+        let attr_dx = delta(dx);
+        let attr_dy = delta(dy);
 
-        if Shade::IS_SHADED {
-            r_row += r_delta.y * min.y as f32;
-            r_row += r_delta.x * min.x as f32;
-            g_row += g_delta.y * min.y as f32;
-            g_row += g_delta.x * min.x as f32;
-            b_row += b_delta.y * min.y as f32;
-            b_row += b_delta.x * min.x as f32;
-        }
-        
-        // Loop through all points in the bounding box, and draw the pixel if it's inside the
-        // triangle.
-        for y in min.y..=max.y {
-            edges.y_step();
-            
-            let mut u = u_row;
-            let mut v = v_row;
-            
-            let mut r = r_row;
-            let mut g = g_row;
-            let mut b = b_row;
-            
-            if Tex::IS_TEXTURED {
-                u_row += u_delta.y;
-                v_row += v_delta.y;
+        // The attributes at the top left corner of the bounding box. The attributes are rounded
+        // by adding a half. Points far outside the triangle may overflow, but it always wraps
+        // back around inside the triangle.
+        let attr_row = {
+            let mut base = [0; 8];
+            for (attr, base) in base.iter_mut().take(5).enumerate() {
+                *base = (attrs[core][attr] << ATTR_FRAC_BITS) + (1 << (ATTR_FRAC_BITS - 1));
             }
-            
-            if Shade::IS_SHADED {
-                r_row += r_delta.y;
-                g_row += g_delta.y;
-                b_row += b_delta.y;
-            }
-
-            for x in (min.x..=max.x).step_by(4) {
-                // All three barycentric coordinates must all be positive for the point
-                // to be inside the triangle. To check that we only have to check the
-                // sign bit of all the weights.
-                let mask = (edges.x_bary[0] | edges.x_bary[1] | edges.x_bary[2]).is_negative();
-
-                edges.x_step();
-
-                // If all the pixels are outside the triangle.
-                if mask.all() {
-                    if Tex::IS_TEXTURED {
-                        u += u_delta.x * 4.0;
-                        v += v_delta.x * 4.0;
-                    }
-            
-                    if Shade::IS_SHADED {
-                        r += r_delta.x * 4.0;
-                        g += g_delta.x * 4.0;
-                        b += b_delta.x * 4.0;
-                    }
-
-                    continue;
-                }
-                
-                // Some of the pixels are in the triangle.
-                for (i, ignore) in mask.to_array().iter().enumerate() {
-                    if *ignore {
-                        if Shade::IS_SHADED {
-                            r += r_delta.x;
-                            g += g_delta.x;
-                            b += b_delta.x;
-                        }
-                        if Tex::IS_TEXTURED {
-                            u += u_delta.x;
-                            v += v_delta.x;
-                        }
-
-                        continue;
-                    }
-                    
-                    let x = x + i as i32;
-                    
-                    let shade = if Shade::IS_SHADED {
-                        let color = Color::from_rgb(r as u8, g as u8, b as u8);
-                    
-                        r += r_delta.x;
-                        g += g_delta.x;
-                        b += b_delta.x;
-
-                        color
-                    } else {
-                        flat_shade
-                    };
-
-                    let (color, masked) = if Tex::IS_TEXTURED {
-                        let uv = TexCoord {
-                            u: u as u8,
-                            v: v as u8,
-                        };
-
-                        u += u_delta.x;
-                        v += v_delta.x;
-
-                        // let texel = self.load_texel(uv, tex_param_cache);
-                        let texel = self.load_texel(uv, tex_param_cache);
-
-                        if texel.is_invisible() {
-                            continue;
-                        }
+            i32x8::from(base)
+                + attr_dx * i32x8::splat(min.x.wrapping_sub(points[core].x))
+                + attr_dy * i32x8::splat(min.y.wrapping_sub(points[core].y))
+        };
 
-                        // If the triangle is not textured raw, the texture color get's blended with the
-                        // shade. Otherwise it doesn't.
-                        let color = match Tex::IS_RAW {
-                            true => texel.as_color(),
-                            false => texel.as_color().shade_blend(shade),
-                        };
+        let edges = Edges::new(points[0], points[1], points[2], min, &bias);
 
-                        (color, texel.is_transparent())
-                    } else {
-                        (shade, false)
-                    };
+        // For perspective correct texturing, the barycentric coordinates are weighted by the
+        // inverse depth of each vertex.
+        let inv_depths = match Tex::IS_TEXTURED {
+            true => depths.map(|depths| depths.map(|z| 1.0 / z.max(1.0))),
+            false => None,
+        };
 
+        let raster = TriangleRaster::<Shade, Tex, Trans> {
+            flat_shade,
+            dither,
+            tex_param_cache,
+            status: self.status,
+            draw_shift: self.draw_shift,
+            coords,
+            inv_depths,
+            replacement,
+            min,
+            max,
+            edges,
+            attr_row,
+            attr_dx,
+            attr_dy,
+            draw_mode: PhantomData,
+        };
 
-                    let color = match self.status.dithering_enabled() {
-                        true => color.dither(x, y),
-                        false => color,
-                    };
-
-                    pixels_drawn += 1;
-                    self.draw_pixel::<Trans, Tex>(x, y, color, masked);
-                }
-            }
-            
-        }
+        let pixels_drawn = raster.pixels();
+        self.draw_raster(raster, min, max, Tex::IS_TEXTURED);
 
         self.triangle_draw_time::<Shade, Tex, Trans>(pixels_drawn)
     }
 
     /// Clamp a point to the draw area.
     fn clamp_to_da(&self, point: Point) -> Point {
+        let (min, max) = self.target_draw_area();
         Point {
-            x: point.x.clamp(self.da_x_min, self.da_x_max),
-            y: point.y.clamp(self.da_y_min, self.da_y_max),
+            x: point.x.clamp(min.x, max.x),
+            y: point.y.clamp(min.y, max.y),
         }
     }
 
@@ -675,42 +308,61 @@ impl Gpu {
         Shade: draw_mode::Shading,
         Trans: draw_mode::Transparency,
     {
+        self.sync_raster();
+
         points[0] = self.clamp_to_da(points[0]);
         points[1] = self.clamp_to_da(points[1]);
 
+        if self.draw_shift == 0 {
+            let min = Point::new(points[0].x.min(points[1].x), points[0].y.min(points[1].y));
+            let max = Point::new(points[0].x.max(points[1].x), points[0].y.max(points[1].y));
+            self.invalidate_textures(min, max);
+        }
+
         let dx = points[1].x - points[0].x;
         let dy = points[1].y - points[0].y;
 
         let abs_dx = dx.abs();
         let abs_dy = dy.abs();
 
-        let longest = abs_dx.max(abs_dy) as u8;
+        // Avoid dividing by zero if both points are the same.
+        let longest = abs_dx.max(abs_dy).max(1);
 
-        // Color delta values.
-        // FIXME: Pretty sure this is wrong.
-        let (dr, dg, db) = match Shade::IS_SHADED {
-            false => (0, 0, 0),
-            true => (
-                colors[1].r.abs_diff(colors[0].r) / longest,
-                colors[1].g.abs_diff(colors[0].g) / longest,
-                colors[1].b.abs_diff(colors[0].b) / longest,
-            ),
+        // The color of the pixel `step` pixels along the line.
+        let shade = |step: i32| match Shade::IS_SHADED {
+            false => flat_shade,
+            true => {
+                let lerp = |start: u8, end: u8| {
+                    let delta = end as i32 - start as i32;
+                    (start as i32 + delta * step / longest) as u8
+                };
+                Color::from_rgb(
+                    lerp(colors[0].r, colors[1].r),
+                    lerp(colors[0].g, colors[1].g),
+                    lerp(colors[0].b, colors[1].b),
+                )
+            }
         };
 
+        // Only shaded lines are dithered. The dither pattern is at native resolution.
+        let dither = self.should_dither::<Shade, draw_mode::UnTextured>();
+        let shift = self.draw_shift;
+        let dither = |color: Color, x: i32, y: i32| match dither {
+            true => color.dither(x >> shift, y >> shift),
+            false => color,
+        };
+
+        let x_major = abs_dx > abs_dy;
         let Point { mut x, mut y } = points[0];
-        let Color { mut r, mut g, mut b } = colors[0];
 
-        // Lines are always dithered.
-        self.draw_pixel::<Trans, draw_mode::UnTextured>(
-            x, y, flat_shade.dither(x, y), false
-        );
+        self.draw_line_pixel::<Trans>(x, y, dither(shade(0), x, y), x_major);
 
         let mut pixels_drawn = 1;
 
-        if abs_dx > abs_dy {
+        if x_major {
             let mut d = 2 * abs_dy - abs_dx; 
 
-            for _ in 0..abs_dx {
+            for step in 1..=abs_dx {
                 x = if dx < 0 { x - 1 } else { x + 1 };
 
                 if d < 0 {
@@ -720,27 +372,13 @@ impl Gpu {
                     d += 2 * abs_dy - 2 * abs_dx;
                 }
 
-                let color = match Shade::IS_SHADED {
-                    false => flat_shade,
-                    true => {
-                        r = r.wrapping_add(dr);
-                        g = g.wrapping_add(dg);
-                        b = b.wrapping_add(db);
-
-                        Color::from_rgb(r, g, b)
-                    }
-                };
-
                 pixels_drawn += 1;
-            
-                self.draw_pixel::<Trans, draw_mode::UnTextured>(
-                    x, y, color.dither(x, y), false
-                );
+                self.draw_line_pixel::<Trans>(x, y, dither(shade(step), x, y), x_major);
             }
         } else {
             let mut d = 2 * abs_dx - abs_dy;
 
-            for _ in 0..abs_dy {
+            for step in 1..=abs_dy {
                 y = if dy < 0 { y - 1 } else { y + 1 };
 
                 if d < 0 {
@@ -750,34 +388,77 @@ impl Gpu {
                     d += 2 * abs_dx - 2 * abs_dy;
                 }
 
-                let color = match Shade::IS_SHADED {
-                    false => flat_shade,
-                    true => {
-                        r = r.wrapping_add(dr);
-                        g = g.wrapping_add(dg);
-                        b = b.wrapping_add(db);
-
-                        Color::from_rgb(r, g, b)
-                    }
-                };
-
                 pixels_drawn += 1;
-
-                self.draw_pixel::<Trans, draw_mode::UnTextured>(
-                    x, y, color.dither(x, y), false
-                );
+                self.draw_line_pixel::<Trans>(x, y, dither(shade(step), x, y), x_major);
             }
         }
 
         self.line_draw_time::<Shade, Trans>(pixels_drawn)
     }
 
+    /// Draw a pixel of a line. When drawing to upscaled VRAM, it's drawn as a run of pixels
+    /// across the line, so that lines are as thick as at native resolution.
+    fn draw_line_pixel<Trans>(&mut self, x: i32, y: i32, color: Color, x_major: bool)
+    where
+        Trans: draw_mode::Transparency,
+    {
+        let (_, max) = self.target_draw_area();
+        for offset in 0..1 << self.draw_shift {
+            let (x, y) = match x_major {
+                true => (x, y + offset),
+                false => (x + offset, y),
+            };
+            if x <= max.x && y <= max.y {
+                self.draw_pixel::<Trans, draw_mode::UnTextured>(x, y, color, false);
+            }
+        }
+    }
+
     /// Fill rectangle in VRAM with a solid color.
     pub fn fill_rect(&mut self, start: Point, dim: Point, color: Color) {
+        self.sync_raster();
         let color = color.as_u16();
         for y in 0..dim.y {
             for x in 0..dim.x {
                 self.vram.store_16(start.x + x, start.y + y, color);
+                if let Some(upscaled) = &mut self.upscaled {
+                    upscaled.store_native(start.x + x, start.y + y, color);
+                }
+            }
+        }
+    }
+
+    /// Copy rectangle within VRAM. Coordinates wrap around the edges of VRAM. The source is read
+    /// before anything is written, so overlapping rectangles are copied as if they weren't.
+    pub fn copy_rect(&mut self, src: Point, dst: Point, dim: Point) {
+        self.sync_raster();
+        let (width, height) = (1024 << self.draw_shift, 512 << self.draw_shift);
+        let wrap = |x: i32, y: i32| (x & (width - 1), y & (height - 1));
+
+        let mut pixels = Vec::with_capacity((dim.x * dim.y) as usize);
+
+        for y in 0..dim.y {
+            for x in 0..dim.x {
+                let (x, y) = wrap(src.x + x, src.y + y);
+                pixels.push(self.target_load(x, y));
+            }
+        }
+
+        let set_mask = (self.status.set_mask_bit() as u16) << 15;
+        let check_mask = self.status.draw_masked_pixels();
+
+        let mut pixels = pixels.into_iter();
+
+        for y in 0..dim.y {
+            for x in 0..dim.x {
+                let (x, y) = wrap(dst.x + x, dst.y + y);
+                let val = pixels.next().unwrap();
+
+                if check_mask && self.target_load(x, y) & 0x8000 != 0 {
+                    continue;
+                }
+
+                self.target_store(x, y, val | set_mask);
             }
         }
     }
@@ -787,7 +468,7 @@ impl Gpu {
         start: Point,
         dim: Point,
         shade: Color,
-        mut tc_start: TexCoord,
+        tc_start: TexCoord,
         clut: Point,
     ) -> u64
     where
@@ -801,8 +482,8 @@ impl Gpu {
             self.tex_win_y,
         );
         
-        if let TexelDepth::B4 | TexelDepth::B8 = self.status.texel_depth() {
-            self.clut_cache.maybe_fetch(clut, self.status.texel_depth(), &self.vram);
+        if Tex::IS_TEXTURED {
+            self.load_clut(clut);
         }
 
         // Calculate the uv delta for each step in x and y direction. Nocash specifies that the
@@ -816,85 +497,570 @@ impl Gpu {
             ),
         };
 
-        // Clip to left and bottom draw area limits.
-        let end_x = i32::min(start.x + dim.x, self.da_x_max + 1);
-        let end_y = i32::min(start.y + dim.y, self.da_y_max + 1);
+        let replacement = match Tex::IS_TEXTURED {
+            true => self.texture_replacement(),
+            false => None,
+        };
 
-        // Clip to right draw area limit.
-        let start_x = if start.x < self.da_x_min.into() {
-            if Tex::IS_TEXTURED {
-                let delta = (self.da_x_min - start.x) * u_delta;
-                tc_start.u = tc_start.u.wrapping_add(delta as u8);
-            }
-            self.da_x_min.into()
-        } else {
-            start.x 
+        let (da_min, da_max) = self.target_draw_area();
+
+        // Clip to the draw area.
+        let min = Point::new(i32::max(start.x, da_min.x), i32::max(start.y, da_min.y));
+        let max = Point::new(
+            i32::min(start.x + dim.x - 1, da_max.x),
+            i32::min(start.y + dim.y - 1, da_max.y),
+        );
+
+        let raster = RectRaster::<Tex, Trans> {
+            shade,
+            tex_param_cache,
+            status: self.status,
+            draw_shift: self.draw_shift,
+            replacement,
+            start,
+            tc_start,
+            u_delta,
+            v_delta,
+            min,
+            max,
+            draw_mode: PhantomData,
         };
 
-        // Clip to top draw area limit.
-        let start_y = if start.y < self.da_y_min.into() {
-            if Tex::IS_TEXTURED {
-                let delta = (self.da_y_min - start.y) * v_delta;
-                tc_start.v = tc_start.v.wrapping_add(delta as u8);
+        let pixels_drawn = raster.pixels();
+        self.draw_raster(raster, min, max, Tex::IS_TEXTURED);
+
+        self.rect_draw_time::<Tex, Trans>(pixels_drawn)
+    }
+
+    /// Load the CLUT at `clut` into the CLUT cache if the texel depth uses one.
+    fn load_clut(&mut self, clut: Point) {
+        let depth = self.status.texel_depth();
+        if let TexelDepth::B4 | TexelDepth::B8 = depth {
+            if !self.clut_cache.is_loaded(clut, depth) {
+                self.sync_raster();
             }
-            self.da_y_min.into()
-        } else {
-            start.y 
+            self.clut_cache.maybe_fetch(clut, depth, &self.vram);
+        }
+    }
+}
+
+/// Everything needed to draw a rectangle after it's been set up, like [`TriangleRaster`].
+struct RectRaster<Tex, Trans> {
+    shade: Color,
+    tex_param_cache: TexParamCache,
+    status: Status,
+    draw_shift: u32,
+    replacement: Option<Arc<Replacement>>,
+    /// The top left corner before clipping, where the texture coordinate is `tc_start`.
+    start: Point,
+    tc_start: TexCoord,
+    u_delta: i32,
+    v_delta: i32,
+    /// The top left corner clipped to the draw area.
+    min: Point,
+    /// The bottom right corner clipped to the draw area.
+    max: Point,
+    draw_mode: PhantomData<fn() -> (Tex, Trans)>,
+}
+
+impl<Tex, Trans> RectRaster<Tex, Trans> {
+    /// The number of pixels covered by the rectangle, not counting displayed lines.
+    fn pixels(&self) -> u64 {
+        let width = (self.max.x - self.min.x + 1).max(0) as u64;
+        let rows = (self.min.y..=self.max.y)
+            .filter(|y| !is_displayed_line(self.status, self.draw_shift, *y))
+            .count() as u64;
+        width * rows
+    }
+
+    /// The fractional part of the texture coordinate in fixed point, `offset` pixels from the
+    /// start. It's for replacements with a higher resolution than native, since the texture
+    /// coordinate only changes every native pixel when drawing to upscaled VRAM.
+    fn frac(&self, offset: i32, delta: i32) -> i32 {
+        let shift = self.draw_shift;
+        let frac = (offset & ((1 << shift) - 1)) << (ATTR_FRAC_BITS - shift as i32);
+        match delta {
+            -1 => (1 << ATTR_FRAC_BITS) - 1 - frac,
+            _ => frac,
+        }
+    }
+}
+
+impl<Tex, Trans> Raster for RectRaster<Tex, Trans>
+where
+    Tex: draw_mode::Textureing,
+    Trans: draw_mode::Transparency,
+{
+    unsafe fn draw_rows(
+        &self,
+        target: VramPtr,
+        vram: VramPtr,
+        clut: &ClutCache,
+        next_rows: &mut dyn FnMut() -> Option<Range<i32>>,
+    ) {
+        let RectRaster {
+            shade,
+            tex_param_cache,
+            status,
+            draw_shift: shift,
+            start,
+            tc_start,
+            u_delta,
+            v_delta,
+            min,
+            max,
+            ..
+        } = *self;
+
+        while let Some(rows) = next_rows() {
+            for y in rows.start.max(min.y)..rows.end.min(max.y + 1) {
+                if is_displayed_line(status, shift, y) {
+                    continue;
+                }
+
+                let v = tc_start.v.wrapping_add((((y - start.y) >> shift) * v_delta) as u8);
+
+                for x in min.x..=max.x {
+                    let (color, masked) = if Tex::IS_TEXTURED {
+                        let u = tc_start.u.wrapping_add((((x - start.x) >> shift) * u_delta) as u8);
+                        let texel = match &self.replacement {
+                            Some(replacement) => {
+                                let uv = tex_param_cache.apply_window(TexCoord { u, v });
+                                let frac = [
+                                    self.frac(x - start.x, u_delta),
+                                    self.frac(y - start.y, v_delta),
+                                ];
+                                replacement.texel(uv, frac)
+                            }
+                            None => {
+                                let uv = TexCoord { u, v };
+                                fetch_texel(status, clut, uv, tex_param_cache, |x, y| {
+                                    vram.load(x, y)
+                                })
+                            }
+                        };
+
+                        if texel.is_invisible() {
+                            continue;
+                        }
+
+                        let color = match Tex::IS_RAW {
+                            true => texel.as_color(),
+                            false => texel.as_color().shade_blend(shade),
+                        };
+
+                        (color, texel.is_transparent())
+                    } else {
+                        (shade, false)
+                    };
+
+                    let bg = target.load(x, y);
+                    if let Some(val) = blend_pixel::<Trans, Tex>(status, bg, color, masked) {
+                        target.store(x, y, val);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// The halfword to store when drawing `color` over `bg`, or `None` if `bg` can't be drawn over.
+/// It handles transparency, texture and mask bit settings. `masked` is bit 15 of the texel if
+/// textured. Semi-transparent textured primitives only blend the texels with bit 15 set, using
+/// the blend mode of the current texture page.
+fn blend_pixel<Tran, Tex>(status: Status, bg: u16, color: Color, masked: bool) -> Option<u16>
+where
+    Tran: draw_mode::Transparency,
+    Tex: draw_mode::Textureing
+{
+    // Pixels with the mask bit set can't be drawn over if mask checking is enabled.
+    if status.draw_masked_pixels() && bg & 0x8000 != 0 {
+        return None;
+    }
+
+    let color = match Tran::IS_TRANSPARENT {
+        false => color,
+        true => {
+            let bg = Color::from_u16(bg);
+            match Tex::IS_TEXTURED {
+                true if masked => status.blend_mode().blend(color, bg),
+                true => color,
+                false => status.blend_mode().blend(color, bg),
+            }
+        }
+    };
+
+    // The mask bit is copied from the texel, but is forced on by the set mask setting.
+    let mask = (masked || status.set_mask_bit()) as u16;
+
+    Some(color.as_u16() | (mask << 15))
+}
+
+/// If line `y` can't be drawn to because it's part of the field being displayed. This is only
+/// the case in 480 line interlaced mode if drawing to the display area isn't allowed.
+fn is_displayed_line(status: Status, draw_shift: u32, y: i32) -> bool {
+    let y = y >> draw_shift;
+    !status.draw_to_display()
+        && status.interlaced_480()
+        && (y & 1 == 1) == (status.interlace_field() == InterlaceField::Top)
+}
+
+/// Load a texel at a given texture coordinate, with `load` loading halfwords from VRAM.
+fn fetch_texel(
+    status: Status,
+    clut: &ClutCache,
+    coord: TexCoord,
+    tex_param_cache: TexParamCache,
+    load: impl Fn(i32, i32) -> u16,
+) -> Texel {
+    let TexCoord { u, v } = tex_param_cache.apply_window(coord);
+    let (u, v) = (u as i32, v as i32);
+
+    match status.texel_depth() {
+        TexelDepth::B4 => {
+            let val = load(
+                status.tex_page_x() + u / 4,
+                status.tex_page_y() + v,
+            );
+
+            let offset = (val >> ((u & 3) * 4)) as i32 & 0xf;
+
+            clut.get(offset)
+        }
+        TexelDepth::B8 => {
+            let val = load(
+                status.tex_page_x() + u / 2,
+                status.tex_page_y() + v,
+            );
+
+            let offset = (val >> ((u & 1) * 8)) as i32 & 0xff;
+
+            clut.get(offset)
+        }
+        TexelDepth::B15 => {
+            let val = load(
+                status.tex_page_x() + u,
+                status.tex_page_y() + v,
+            );
+
+            Texel::new(val)
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Edges {
+    // The barycentric coordinates at the start of each line. They change every time the 
+    // rasterizer goes down a line.
+    y_bary: [i32x4; 3],
+    // The current barycentric coordinates. They change for each column step.
+    x_bary: [i32x4; 3],
+    // The delta to the barycentric coordinates for each step along the x-axis.
+    x_delta: [i32x4; 3],
+    // The delta to the barycentric coordinates for each step along the y-axis.
+    y_delta: [i32x4; 3],
+}
+
+impl Edges {
+    fn new(v0: Point, v1: Point, v2: Point, origin: Point, bias: &[i32; 3]) -> Self {
+        fn setup(v0: Point, v1: Point) -> [i32; 3] {
+            [v0.y - v1.y, v1.x - v0.x, v0.x * v1.y - v0.y * v1.x]
+        }
+
+        let s0 = setup(v1, v2);
+        let s1 = setup(v2, v0);
+        let s2 = setup(v0, v1);
+
+        let x_delta = [
+            i32x4::from([s0[0] * 4; 4]),
+            i32x4::from([s1[0] * 4; 4]),
+            i32x4::from([s2[0] * 4; 4]),
+        ];
+
+        let y_delta = [
+            i32x4::from([s0[1]; 4]),
+            i32x4::from([s1[1]; 4]),
+            i32x4::from([s2[1]; 4]),
+        ];
+
+        let x = i32x4::from([origin.x; 4]) + i32x4::from([0, 1, 2, 3]);
+        let y = i32x4::from([origin.y; 4]);
+
+        let y_bary = [
+              i32x4::from([s0[0]; 4]) * x
+                + i32x4::from([s0[1]; 4]) * y
+                + i32x4::from([s0[2]; 4])
+                + i32x4::from([bias[0]; 4]),
+              i32x4::from([s1[0]; 4]) * x
+                + i32x4::from([s1[1]; 4]) * y
+                + i32x4::from([s1[2]; 4])
+                + i32x4::from([bias[1]; 4]),
+              i32x4::from([s2[0]; 4]) * x
+                + i32x4::from([s2[1]; 4]) * y
+                + i32x4::from([s2[2]; 4])
+                + i32x4::from([bias[2]; 4]),
+        ];
+
+        Self {
+            x_bary: y_bary.clone(),
+            y_bary,
+            x_delta,
+            y_delta,
+        }
+    }
+
+    fn x_step(&mut self) {
+        self.x_bary[0] += self.x_delta[0];
+        self.x_bary[1] += self.x_delta[1];
+        self.x_bary[2] += self.x_delta[2];
+    }
+
+    fn y_step(&mut self) {
+        self.x_bary = self.y_bary.clone();
+        self.y_bary[0] += self.y_delta[0];
+        self.y_bary[1] += self.y_delta[1];
+        self.y_bary[2] += self.y_delta[2];
+    }
+}
+
+/// Everything needed to rasterize a triangle after it's been set up, so that the rows of it
+/// can be drawn on any thread, see [`tile`](super::tile).
+struct TriangleRaster<Shade, Tex, Trans> {
+    flat_shade: Color,
+    dither: bool,
+    tex_param_cache: TexParamCache,
+    status: Status,
+    draw_shift: u32,
+    coords: [TexCoord; 3],
+    inv_depths: Option<[f32; 3]>,
+    /// The texture replacing the texture page, see [`texpack`](super::texpack).
+    replacement: Option<Arc<Replacement>>,
+    /// The top left corner of the bounding box.
+    min: Point,
+    /// The bottom right corner of the bounding box. Pixels are checked four at a time, so
+    /// blocks may go past `x`, but pixels past it are never drawn.
+    max: Point,
+    edges: Edges,
+    /// The fixed point attributes at the start of the first row. The lanes are `u`, `v`, `r`,
+    /// `g` and `b`.
+    attr_row: i32x8,
+    /// The delta of the attributes for each step along the x-axis.
+    attr_dx: i32x8,
+    /// The delta of the attributes for each step along the y-axis.
+    attr_dy: i32x8,
+    #[allow(clippy::type_complexity)]
+    draw_mode: PhantomData<fn() -> (Shade, Tex, Trans)>,
+}
+
+impl<Shade, Tex, Trans> TriangleRaster<Shade, Tex, Trans> {
+    /// The number of pixels covered by the triangle, not counting displayed lines. Pixels are
+    /// counted even if the texel is invisible, since it still takes time to draw them.
+    fn pixels(&self) -> u64 {
+        let (min, max) = (self.min, self.max);
+        let mut edges = self.edges.clone();
+        let mut pixels = 0;
+
+        for y in min.y..=max.y {
+            edges.y_step();
+
+            if is_displayed_line(self.status, self.draw_shift, y) {
+                continue;
+            }
+
+            for x in (min.x..=max.x).step_by(4) {
+                let mask = (edges.x_bary[0] | edges.x_bary[1] | edges.x_bary[2]).is_negative();
+                edges.x_step();
+
+                pixels += mask
+                    .to_array()
+                    .iter()
+                    .zip(x..=max.x)
+                    .filter(|(outside, _)| !**outside)
+                    .count() as u64;
+            }
+        }
+
+        pixels
+    }
+}
+
+impl<Shade, Tex, Trans> Raster for TriangleRaster<Shade, Tex, Trans>
+where
+    Shade: draw_mode::Shading,
+    Tex: draw_mode::Textureing,
+    Trans: draw_mode::Transparency,
+{
+    unsafe fn draw_rows(
+        &self,
+        target: VramPtr,
+        vram: VramPtr,
+        clut: &ClutCache,
+        next_rows: &mut dyn FnMut() -> Option<Range<i32>>,
+    ) {
+        let TriangleRaster {
+            flat_shade,
+            dither,
+            tex_param_cache,
+            status,
+            draw_shift,
+            coords,
+            inv_depths,
+            min,
+            max,
+            attr_dx,
+            attr_dy,
+            ..
+        } = *self;
+
+        let mut edges = self.edges.clone();
+        let mut attr_row = self.attr_row;
+
+        // The delta of the attributes for each block of four pixels.
+        let attr_block_dx = attr_dx * i32x8::splat(4);
+
+        let Some(mut rows) = next_rows() else {
+            return;
         };
 
-        let mut tc = tc_start;
+        // Loop through all points in the bounding box, and draw the pixel if it's inside the
+        // triangle.
+        for y in min.y..=max.y {
+            edges.y_step();
+
+            let mut attrs = attr_row;
+            attr_row += attr_dy;
+
+            while y >= rows.end {
+                match next_rows() {
+                    Some(next) => rows = next,
+                    None => return,
+                }
+            }
+
+            if y < rows.start || is_displayed_line(status, draw_shift, y) {
+                continue;
+            }
 
-        let mut pixels_drawn = 0;
+            for x in (min.x..=max.x).step_by(4) {
+                // All three barycentric coordinates must all be positive for the point
+                // to be inside the triangle. To check that we only have to check the
+                // sign bit of all the weights.
+                let mask = (edges.x_bary[0] | edges.x_bary[1] | edges.x_bary[2]).is_negative();
+                let bary = edges.x_bary;
 
-        for y in start_y..end_y {
-            tc.u = tc_start.u;
+                let block = attrs;
 
-            for x in start_x..end_x {
-                let (color, masked) = if Tex::IS_TEXTURED {
-                    let texel = self.load_texel(tc, tex_param_cache);
+                edges.x_step();
+                attrs += attr_block_dx;
 
-                    if texel.is_invisible() {
-                        tc.u = tc.u.wrapping_add(u_delta as u8);
+                // If all the pixels are outside the triangle.
+                if mask.all() {
+                    continue;
+                }
+
+                // Some of the pixels are in the triangle.
+                for (i, ignore) in mask.to_array().iter().enumerate() {
+                    if *ignore {
                         continue;
                     }
 
-                    let tex_color = match Tex::IS_RAW {
-                        true => texel.as_color(),
-                        false => texel.as_color().shade_blend(shade),
+                    // The last block may go past the right edge of the draw area, and wrap into
+                    // the next row of VRAM which may be drawn by another thread.
+                    let x = x + i as i32;
+                    if x > max.x {
+                        break;
+                    }
+
+                    let fixed = block + attr_dx * i32x8::splat(i as i32);
+                    let attrs = fixed >> i32x8::splat(ATTR_FRAC_BITS);
+
+                    let shade = if Shade::IS_SHADED {
+                        let [r, g, b] = [attrs[2], attrs[3], attrs[4]]
+                            .map(|c| c.clamp(0, 255) as u8);
+                        Color::from_rgb(r, g, b)
+                    } else {
+                        flat_shade
                     };
 
-                    let color = match Trans::IS_TRANSPARENT {
-                        false => tex_color,
-                        true => {
-                            let bg = Color::from_u16(self.vram.load_16(x, y));
-                            self.status.blend_mode().blend(tex_color, bg)
+                    let (color, masked) = if Tex::IS_TEXTURED {
+                        // The texture coordinates in fixed point.
+                        let (u, v) = match inv_depths {
+                            Some(inv_depths) => {
+                                let weights = [0, 1, 2].map(|k| {
+                                    bary[k][i].max(0) as f32 * inv_depths[k]
+                                });
+                                let sum: f32 = weights.iter().sum();
+                                let lerp = |attr: [u8; 3]| -> i32 {
+                                    let val = weights
+                                        .iter()
+                                        .zip(attr)
+                                        .map(|(w, attr)| w * attr as f32)
+                                        .sum::<f32>() / sum + 0.5;
+                                    (val * (1 << ATTR_FRAC_BITS) as f32) as i32
+                                };
+                                (lerp(coords.map(|c| c.u)), lerp(coords.map(|c| c.v)))
+                            }
+                            None => (fixed[0], fixed[1]),
+                        };
+
+                        // Texture coordinates wrap around like on the hardware.
+                        let uv = TexCoord {
+                            u: (u >> ATTR_FRAC_BITS) as u8,
+                            v: (v >> ATTR_FRAC_BITS) as u8,
+                        };
+
+                        let texel = match &self.replacement {
+                            Some(replacement) => {
+                                replacement.texel(tex_param_cache.apply_window(uv), [u, v])
+                            }
+                            None => fetch_texel(status, clut, uv, tex_param_cache, |x, y| {
+                                vram.load(x, y)
+                            }),
+                        };
+
+                        if texel.is_invisible() {
+                            continue;
                         }
-                    };
 
-                    (color, texel.is_transparent())
-                } else {
-                    (shade, false)
-                };
+                        // If the triangle is not textured raw, the texture color get's blended with the
+                        // shade. Otherwise it doesn't.
+                        let color = match Tex::IS_RAW {
+                            true => texel.as_color(),
+                            false => texel.as_color().shade_blend(shade),
+                        };
 
-                pixels_drawn += 1;
-                self.draw_pixel::<Trans, Tex>(x, y, color, masked);
+                        (color, texel.is_transparent())
+                    } else {
+                        (shade, false)
+                    };
 
-                tc.u = tc.u.wrapping_add(u_delta as u8);
+                    // The dither pattern is at native resolution.
+                    let color = match dither {
+                        true => color.dither(x >> draw_shift, y >> draw_shift),
+                        false => color,
+                    };
+
+                    let bg = target.load(x, y);
+                    if let Some(val) = blend_pixel::<Trans, Tex>(status, bg, color, masked) {
+                        target.store(x, y, val);
+                    }
+                }
             }
-            tc.v = tc.v.wrapping_add(v_delta as u8);
         }
-
-        self.rect_draw_time::<Tex, Trans>(pixels_drawn)
     }
 }
 
 /// Cache for static info used to render each textured pixel.
 #[derive(Clone, Copy)]
 struct TexParamCache {
-    /// !(tex_win_w * 8) | ((tex_win_x & tex_win_w) * 8).
-    tex_win_u: u8,
-    /// !(tex_win_h * 8) | ((tex_win_y & tex_win_h) * 8).
-    tex_win_v: u8,
+    /// !(tex_win_w * 8).
+    tex_win_u_mask: u8,
+    /// !(tex_win_h * 8).
+    tex_win_v_mask: u8,
+    /// (tex_win_x & tex_win_w) * 8.
+    tex_win_u_offset: u8,
+    /// (tex_win_y & tex_win_h) * 8.
+    tex_win_v_offset: u8,
 }
 
 impl TexParamCache {
@@ -905,8 +1071,299 @@ impl TexParamCache {
         tex_win_y: u8,
     ) -> Self {
         Self {
-            tex_win_u: !(tex_win_w * 8) | ((tex_win_x & tex_win_w) * 8),
-            tex_win_v: !(tex_win_h * 8) | ((tex_win_y & tex_win_h) * 8),
+            tex_win_u_mask: !(tex_win_w * 8),
+            tex_win_v_mask: !(tex_win_h * 8),
+            tex_win_u_offset: (tex_win_x & tex_win_w) * 8,
+            tex_win_v_offset: (tex_win_y & tex_win_h) * 8,
         }
     }
+
+    /// Apply the texture window to a texture coordinate. The bits of the coordinate which are
+    /// set in the window mask are replaced by the bits of the window offset, which makes the
+    /// texture repeat within the window.
+    fn apply_window(self, coord: TexCoord) -> TexCoord {
+        TexCoord {
+            u: (coord.u & self.tex_win_u_mask) | self.tex_win_u_offset,
+            v: (coord.v & self.tex_win_v_mask) | self.tex_win_v_offset,
+        }
+    }
+}
+
+#[test]
+fn texture_window() {
+    let cache = TexParamCache::new(1, 3, 1, 2);
+
+    assert_eq!(cache.apply_window(TexCoord::new(3, 5)), TexCoord::new(11, 21));
+    assert_eq!(cache.apply_window(TexCoord::new(12, 24)), TexCoord::new(12, 16));
+
+    // No window shouldn't change anything.
+    let cache = TexParamCache::new(0, 0, 0, 0);
+
+    assert_eq!(cache.apply_window(TexCoord::new(200, 13)), TexCoord::new(200, 13));
+}
+
+#[test]
+fn mask_bit() {
+    use crate::schedule::Schedule;
+    use splst_util::BitSet;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    let mut schedule = Schedule::new();
+    let mut gpu = Gpu::new(&mut schedule, Rc::new(RefCell::new(())));
+
+    gpu.da_x_max = 16;
+    gpu.da_y_max = 16;
+
+    let white = Color::from_rgb(0xff, 0xff, 0xff);
+
+    gpu.vram.store_16(1, 0, 0x8000);
+
+    // Check mask.
+    gpu.status.0 = gpu.status.0.set_bit(12, true);
+    gpu.draw_rect::<draw_mode::UnTextured, draw_mode::Opaque>(
+        Point::new(0, 0), Point::new(2, 1), white, TexCoord::default(), Point::default(),
+    );
+
+    assert_eq!(gpu.vram.load_16(0, 0), 0x7fff);
+    assert_eq!(gpu.vram.load_16(1, 0), 0x8000);
+
+    // Set mask.
+    gpu.status.0 = gpu.status.0.set_bit(11, true).set_bit(12, false);
+    gpu.draw_rect::<draw_mode::UnTextured, draw_mode::Opaque>(
+        Point::new(0, 1), Point::new(2, 1), white, TexCoord::default(), Point::default(),
+    );
+
+    assert_eq!(gpu.vram.load_16(0, 1), 0xffff);
+    assert_eq!(gpu.vram.load_16(1, 1), 0xffff);
+}
+
+#[test]
+fn interlaced_field() {
+    use crate::schedule::Schedule;
+    use splst_util::BitSet;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    let mut schedule = Schedule::new();
+    let mut gpu = Gpu::new(&mut schedule, Rc::new(RefCell::new(())));
+
+    gpu.da_x_max = 16;
+    gpu.da_y_max = 16;
+
+    let white = Color::from_rgb(0xff, 0xff, 0xff);
+
+    // 480 lines interlaced, displaying the odd field and drawing to the display area not allowed.
+    gpu.status.0 = gpu.status.0
+        .set_bit(19, true)
+        .set_bit(22, true)
+        .set_bit(13, true)
+        .set_bit(10, false);
+
+    gpu.draw_rect::<draw_mode::UnTextured, draw_mode::Opaque>(
+        Point::new(0, 0), Point::new(1, 4), white, TexCoord::default(), Point::default(),
+    );
+
+    assert_eq!(gpu.vram.load_16(0, 0), 0x7fff);
+    assert_eq!(gpu.vram.load_16(0, 1), 0x0);
+    assert_eq!(gpu.vram.load_16(0, 2), 0x7fff);
+    assert_eq!(gpu.vram.load_16(0, 3), 0x0);
+
+    // Drawing to the display area is allowed.
+    gpu.status.0 = gpu.status.0.set_bit(10, true);
+
+    gpu.draw_rect::<draw_mode::UnTextured, draw_mode::Opaque>(
+        Point::new(1, 0), Point::new(1, 2), white, TexCoord::default(), Point::default(),
+    );
+
+    assert_eq!(gpu.vram.load_16(1, 0), 0x7fff);
+    assert_eq!(gpu.vram.load_16(1, 1), 0x7fff);
+}
+
+#[test]
+fn fixed_point_shading() {
+    use crate::schedule::Schedule;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    let mut schedule = Schedule::new();
+    let mut gpu = Gpu::new(&mut schedule, Rc::new(RefCell::new(())));
+
+    gpu.da_x_max = 64;
+    gpu.da_y_max = 64;
+
+    let colors = [
+        Color::from_rgb(0, 0, 0),
+        Color::from_rgb(128, 0, 0),
+        Color::from_rgb(0, 255, 0),
+    ];
+
+    gpu.draw_triangle::<draw_mode::Shaded, draw_mode::UnTextured, draw_mode::Opaque>(
+        Color::default(),
+        Point::default(),
+        [Point::new(0, 0), Point::new(64, 0), Point::new(0, 64)],
+        colors,
+        [TexCoord::default(); 3],
+        None,
+    );
+
+    // Red steps by exactly 2 for each pixel along the x-axis, and green by 255 / 64 along the
+    // y-axis, rounded to nearest. The top and left edges aren't drawn.
+    assert_eq!(gpu.vram.load_16(32, 0), 0x0);
+    assert_eq!(gpu.vram.load_16(32, 1), 64 >> 3);
+    assert_eq!(gpu.vram.load_16(61, 2), 122 >> 3 | (8 >> 3) << 5);
+    assert_eq!(gpu.vram.load_16(1, 62), 247 >> 3 << 5);
+}
+
+#[test]
+fn shading_model() {
+    use crate::schedule::Schedule;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    // A model of Mednafen's triangle setup written from its formulas. The deltas are the
+    // gradients of each attribute truncated to 12 fractional bits, and every pixel is found from
+    // the leftmost vertex, rounded by adding a half. The ties between vertices with the same x go
+    // to the later one, except between the first and last. It's written out per pixel here
+    // instead of stepped, so it catches the stepping going wrong, but it makes the same choices
+    // as the rasterizer, so a wrong tie-break or rounding rule passes both.
+    //
+    // TODO: Check pixels from console captures or Mednafen's output as well.
+    fn reference(points: [Point; 3], attrs: [i32; 3], x: i32, y: i32) -> i32 {
+        let [a, b, c] = points;
+        let [ra, rb, rc] = attrs;
+
+        let core = if b.x <= a.x {
+            if c.x <= b.x { 2 } else { 1 }
+        } else if c.x < a.x {
+            2
+        } else {
+            0
+        };
+
+        let denom = (b.x - a.x) * (c.y - b.y) - (c.x - b.x) * (b.y - a.y);
+        let dr_dx = ((rb - ra) * (c.y - b.y) - (rc - rb) * (b.y - a.y)) * (1 << 12) / denom;
+        let dr_dy = ((b.x - a.x) * (rc - rb) - (c.x - b.x) * (rb - ra)) * (1 << 12) / denom;
+
+        let base = (attrs[core] << 12) + (1 << 11);
+        (base + dr_dx * (x - points[core].x) + dr_dy * (y - points[core].y)) >> 12
+    }
+
+    let triangles = [
+        ([(0, 0), (64, 0), (0, 64)], [(0, 0, 0), (128, 0, 0), (0, 255, 0)]),
+        ([(90, 3), (10, 40), (60, 97)], [(255, 10, 200), (3, 250, 17), (128, 64, 99)]),
+        ([(31, 90), (100, 20), (5, 7)], [(17, 33, 250), (200, 1, 1), (90, 180, 70)]),
+        ([(50, 10), (50, 80), (120, 45)], [(0, 200, 100), (255, 0, 50), (13, 77, 255)]),
+        ([(7, 99), (7, 1), (70, 50)], [(240, 15, 60), (30, 220, 130), (128, 128, 0)]),
+    ];
+
+    for (points, colors) in triangles {
+        let mut schedule = Schedule::new();
+        let mut gpu = Gpu::new(&mut schedule, Rc::new(RefCell::new(())));
+
+        gpu.da_x_max = 127;
+        gpu.da_y_max = 127;
+
+        // Pixels drawn have the mask bit cleared.
+        for y in 0..128 {
+            for x in 0..128 {
+                gpu.vram.store_16(x, y, 0xffff);
+            }
+        }
+
+        let points = points.map(|(x, y)| Point::new(x, y));
+        let colors = colors.map(|(r, g, b)| Color::from_rgb(r, g, b));
+
+        gpu.draw_triangle::<draw_mode::Shaded, draw_mode::UnTextured, draw_mode::Opaque>(
+            Color::default(),
+            Point::default(),
+            points,
+            colors,
+            [TexCoord::default(); 3],
+            None,
+        );
+
+        let mut drawn = 0;
+        for y in 0..128 {
+            for x in 0..128 {
+                let val = gpu.vram.load_16(x, y);
+                if val & 0x8000 != 0 {
+                    continue;
+                }
+                drawn += 1;
+                let [r, g, b] = [colors.map(|c| c.r), colors.map(|c| c.g), colors.map(|c| c.b)]
+                    .map(|attrs| {
+                        let val = reference(points, attrs.map(i32::from), x, y);
+                        assert!((0..=255).contains(&val), "({x}, {y}) out of range: {val}");
+                        val as u16 >> 3
+                    });
+                assert_eq!(val, r | g << 5 | b << 10, "({x}, {y}) of {points:?}");
+            }
+        }
+        assert!(drawn > 1000);
+    }
+}
+
+#[test]
+fn semi_transparency() {
+    use crate::schedule::Schedule;
+    use crate::test::run_gp0;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    let mut schedule = Schedule::new();
+    let mut gpu = Gpu::new(&mut schedule, Rc::new(RefCell::new(())));
+
+    // A background of 16 in each channel, and a 15-bit texture at (640, 0) with an invisible
+    // texel, an opaque texel and a semi-transparent texel of 4 in each channel.
+    for y in 0..12 {
+        for x in 0..8 {
+            gpu.vram.store_16(x, y, 0x4210);
+        }
+    }
+    for (u, texel) in [0x0, 0x1084, 0x9084].into_iter().enumerate() {
+        gpu.vram.store_16(640 + u as i32, 0, texel);
+    }
+
+    let point = |x: u32, y: u32| x | y << 16;
+    let tex_page = |mode: u32| 10 | mode << 5 | 2 << 7;
+
+    let mut cmds = vec![0xe3000000, 0xe4000000 | 1023 | 511 << 10, 0xe5000000];
+
+    for mode in 0..4 {
+        let y = mode * 3;
+        cmds.extend([
+            0xe1000000 | tex_page(mode),
+            // Raw textured rectangle.
+            0x67000000, point(0, y), 0, point(3, 1),
+            // 1x1 rectangle.
+            0x6a202020, point(3, y),
+            // Line.
+            0x42202020, point(0, y + 1), point(3, y + 1),
+            // Raw textured quad with another blend mode than GP0(e1).
+            0xe1000000 | tex_page((mode + 1) % 4),
+            0x2f000000,
+            point(4, y), 0,
+            point(7, y), 3 | tex_page(mode) << 16,
+            point(4, y + 2), 0,
+            point(7, y + 2), 3,
+            // The blend mode of the quad stays.
+            0x6a202020, point(7, y),
+        ]);
+    }
+
+    run_gp0(&mut gpu, &mut schedule, cmds);
+
+    // Average, add, subtract and add a quarter.
+    for (mode, blended) in [0x294a, 0x5294, 0x318c, 0x4631].into_iter().enumerate() {
+        let y = mode as i32 * 3;
+        let row = |y: i32| -> Vec<u16> { (0..8).map(|x| gpu.vram.load_16(x, y)).collect() };
+
+        let texels = [0x4210, 0x1084, 0x8000 | blended];
+
+        assert_eq!(row(y)[..4], [texels[0], texels[1], texels[2], blended], "mode {mode}");
+        assert_eq!(row(y)[7], blended, "mode {mode}");
+        assert_eq!(row(y + 1)[..4], [blended; 4], "mode {mode}");
+        assert_eq!(row(y + 1)[4..7], texels, "mode {mode}");
+    }
 }
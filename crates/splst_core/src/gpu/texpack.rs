@@ -0,0 +1,455 @@
+//! Texture dumping and replacement.
+//!
+//! Textures are identified by a hash of the whole texture page they are sampled from, combined
+//! with the CLUT in the CLUT cache for 4 and 8-bit textures. The hash doesn't depend on where in
+//! VRAM the texture is, so it stays the same when a game loads it to a different place. Hashing a
+//! texture page is fairly slow, so the hash of each page is kept until the part of VRAM it was
+//! hashed from is written to.
+//!
+//! # Texture packs
+//!
+//! A texture pack is a directory with a `manifest.txt` file, mapping each hash to an image file
+//! relative to the directory:
+//!
+//! ```text
+//! # Comments and empty lines are ignored.
+//! 00f1a2b3c4d5e6f7 title-text.png
+//! ```
+//!
+//! Images are PNG files covering the whole 256x256 texels of the texture page, or a power of two
+//! multiple of that for higher resolution textures. The alpha channel decides the mask bit of
+//! each texel: Fully transparent pixels are invisible like black texels are on the hardware,
+//! partially transparent pixels are semi-transparent and opaque pixels are drawn as is.
+//!
+//! Replacements only affect the VRAM shown on screen, so when rendering at a higher resolution
+//! native VRAM is drawn with the original textures and stays accurate.
+//!
+//! # Dumping
+//!
+//! Dumping writes each unique texture to a directory as `<hash>.png` along with a manifest, so a
+//! dump can be edited and loaded as a texture pack directly.
+
+use thiserror::Error;
+
+use super::png::{self, PngError, Rgba};
+use super::primitive::{Point, TexCoord, Texel};
+use super::rasterize::ATTR_FRAC_BITS;
+use super::{Gpu, TexelDepth};
+
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+const MANIFEST: &str = "manifest.txt";
+
+/// Replacements can at most be 16 times the resolution of the texture page.
+const MAX_SCALE_SHIFT: u32 = 4;
+
+#[derive(Error, Debug)]
+pub enum TexturePackError {
+    #[error("failed to read {path}: {err}", path = path.display())]
+    Io { path: PathBuf, err: io::Error },
+    #[error("line {line} of the manifest: {msg}")]
+    Manifest { line: usize, msg: String },
+    #[error("{path}: {err}", path = path.display())]
+    Png { path: PathBuf, err: PngError },
+    #[error(
+        "{path} is {width}x{height}, but must be 256x256 or a power of two multiple of it",
+        path = path.display(),
+    )]
+    Size { path: PathBuf, width: u32, height: u32 },
+}
+
+/// A replacement for a whole texture page.
+pub(super) struct Replacement {
+    /// The number of bits the texture coordinates are shifted by.
+    shift: u32,
+    texels: Vec<u16>,
+}
+
+impl Replacement {
+    fn from_rgba(image: &Rgba) -> Option<Self> {
+        let shift = (image.width / 256).trailing_zeros();
+        if shift > MAX_SCALE_SHIFT || image.width != image.height || image.width != 256 << shift {
+            return None;
+        }
+        let texels = image.data
+            .chunks_exact(4)
+            .map(|rgba| {
+                let [r, g, b] = [0, 1, 2].map(|i| rgba[i] as u16 >> 3);
+                match rgba[3] {
+                    0 => 0x0,
+                    // Opaque black is made slightly blue, since black texels are invisible.
+                    255 => match r | g << 5 | b << 10 {
+                        0x0 => 1 << 10,
+                        val => val,
+                    },
+                    _ => r | g << 5 | b << 10 | 0x8000,
+                }
+            })
+            .collect();
+        Some(Self { shift, texels })
+    }
+
+    /// The texel at texture coordinate `coord`, where `frac` is the fractional part of the
+    /// coordinate in fixed point with [`ATTR_FRAC_BITS`] bits.
+    pub(super) fn texel(&self, coord: TexCoord, frac: [i32; 2]) -> Texel {
+        let [u, v] = [(coord.u, frac[0]), (coord.v, frac[1])].map(|(int, frac)| {
+            let frac = (frac & ((1 << ATTR_FRAC_BITS) - 1)) >> (ATTR_FRAC_BITS as u32 - self.shift);
+            (int as usize) << self.shift | frac as usize
+        });
+        Texel::new(self.texels[u + (v << (8 + self.shift))])
+    }
+}
+
+/// The 15-bit color and alpha of a texel when dumped.
+fn texel_rgba(texel: u16) -> [u8; 4] {
+    let [r, g, b] = [0, 5, 10].map(|shift| (((texel >> shift) & 0x1f) as u8) << 3);
+    let alpha = match texel {
+        0x0 => 0,
+        _ if texel & 0x8000 != 0 => 128,
+        _ => 255,
+    };
+    [r, g, b, alpha]
+}
+
+/// The blocks of 64x256 halfwords of VRAM touched by the rectangle from `min` to `max`, as a
+/// bitmask with a bit for each of the 32 blocks. Coordinates past the right edge of VRAM wrap
+/// around to the next line.
+fn vram_blocks(min: Point, max: Point) -> u32 {
+    let cols = ((max.x >> 6) - (min.x >> 6) + 1).clamp(1, 17);
+    let rows = ((max.y >> 8) - (min.y >> 8) + 1).clamp(1, 2);
+    let mut blocks = 0;
+    for row in 0..rows {
+        for col in 0..cols {
+            let x = (min.x >> 6) + col;
+            let y = (min.y >> 8) + row + (x >> 4);
+            blocks |= 1 << ((y & 1) * 16 + (x & 15));
+        }
+    }
+    blocks
+}
+
+const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+
+/// Update the FNV-1a hash `hash` with `vals`. It's used since it's stable between runs and
+/// platforms unlike the hasher of the standard library.
+fn fnv1a(mut hash: u64, vals: impl IntoIterator<Item = u16>) -> u64 {
+    for val in vals {
+        for byte in val.to_le_bytes() {
+            hash = (hash ^ byte as u64).wrapping_mul(0x100000001b3);
+        }
+    }
+    hash
+}
+
+/// The hash of a texture page and the VRAM blocks it was hashed from.
+struct PageHash {
+    hash: u64,
+    blocks: u32,
+}
+
+struct TextureDump {
+    dir: PathBuf,
+    /// The hash of every texture already dumped.
+    dumped: HashSet<u64>,
+}
+
+struct TexturePack {
+    dir: PathBuf,
+    replacements: HashMap<u64, Arc<Replacement>>,
+}
+
+#[derive(Default)]
+pub(super) struct Textures {
+    /// The hash of each texture page by position and texel depth.
+    pages: HashMap<(i32, i32, u8), PageHash>,
+    dump: Option<TextureDump>,
+    pack: Option<TexturePack>,
+}
+
+/// Read the manifest in `dir` as pairs of hashes and paths.
+fn read_manifest(dir: &Path) -> Result<Vec<(u64, PathBuf)>, TexturePackError> {
+    let path = dir.join(MANIFEST);
+    let manifest = fs::read_to_string(&path)
+        .map_err(|err| TexturePackError::Io { path, err })?;
+
+    let mut entries = Vec::new();
+
+    for (i, line) in manifest.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let err = |msg: &str| TexturePackError::Manifest { line: i + 1, msg: msg.to_string() };
+
+        let (hash, file) = line
+            .split_once(char::is_whitespace)
+            .ok_or_else(|| err("expected a hash followed by a file"))?;
+
+        let hash = u64::from_str_radix(hash, 16).map_err(|_| err("invalid hash"))?;
+
+        entries.push((hash, dir.join(file.trim())));
+    }
+
+    Ok(entries)
+}
+
+fn load_replacement(path: &Path) -> Result<Replacement, TexturePackError> {
+    let data = fs::read(path).map_err(|err| {
+        TexturePackError::Io { path: path.to_path_buf(), err }
+    })?;
+
+    let image = png::read(&data).map_err(|err| {
+        TexturePackError::Png { path: path.to_path_buf(), err }
+    })?;
+
+    Replacement::from_rgba(&image).ok_or_else(|| TexturePackError::Size {
+        path: path.to_path_buf(),
+        width: image.width,
+        height: image.height,
+    })
+}
+
+impl Gpu {
+    /// Start dumping textures to `dir`, or stop dumping if `None`. Textures already in the
+    /// manifest of `dir` aren't dumped again.
+    pub fn set_texture_dump(&mut self, dir: Option<PathBuf>) {
+        if self.texture_dump_dir() == dir.as_deref() {
+            return;
+        }
+        self.textures.dump = dir.map(|dir| {
+            let dumped = read_manifest(&dir)
+                .map(|entries| entries.into_iter().map(|(hash, _)| hash).collect())
+                .unwrap_or_default();
+            TextureDump { dir, dumped }
+        });
+    }
+
+    pub fn texture_dump_dir(&self) -> Option<&Path> {
+        self.textures.dump.as_ref().map(|dump| dump.dir.as_path())
+    }
+
+    /// Load the texture pack in `dir`, replacing the current one. Returns the number of
+    /// textures in the pack.
+    pub fn load_texture_pack(&mut self, dir: &Path) -> Result<usize, TexturePackError> {
+        let replacements = read_manifest(dir)?
+            .into_iter()
+            .map(|(hash, path)| Ok((hash, Arc::new(load_replacement(&path)?))))
+            .collect::<Result<HashMap<_, _>, TexturePackError>>()?;
+
+        let count = replacements.len();
+
+        self.textures.pack = Some(TexturePack {
+            dir: dir.to_path_buf(),
+            replacements,
+        });
+
+        Ok(count)
+    }
+
+    pub fn unload_texture_pack(&mut self) {
+        self.textures.pack = None;
+    }
+
+    pub fn texture_pack_dir(&self) -> Option<&Path> {
+        self.textures.pack.as_ref().map(|pack| pack.dir.as_path())
+    }
+
+    /// Forget the hash of textures in the VRAM rectangle from `min` to `max`, since it's about
+    /// to be written to.
+    pub(super) fn invalidate_textures(&mut self, min: Point, max: Point) {
+        if !self.textures.pages.is_empty() {
+            let blocks = vram_blocks(min, max);
+            self.textures.pages.retain(|_, page| page.blocks & blocks == 0);
+        }
+    }
+
+    pub(super) fn forget_textures(&mut self) {
+        self.textures.pages.clear();
+    }
+
+    /// The hash of the current texture page combined with the CLUT cache.
+    fn texture_hash(&mut self) -> u64 {
+        let depth = self.status.texel_depth();
+        let (x, y) = (self.status.tex_page_x(), self.status.tex_page_y());
+
+        let width = match depth {
+            TexelDepth::B4 => 64,
+            TexelDepth::B8 => 128,
+            TexelDepth::B15 => 256,
+        };
+
+        if !self.textures.pages.contains_key(&(x, y, depth as u8)) {
+            self.sync_raster();
+        }
+
+        let vram = &self.vram;
+        let page = self.textures.pages.entry((x, y, depth as u8)).or_insert_with(|| {
+            let vals = (0..256).flat_map(|dy| (0..width).map(move |dx| (dx, dy)));
+            PageHash {
+                hash: fnv1a(FNV_OFFSET, vals.map(|(dx, dy)| vram.load_16(x + dx, y + dy))),
+                blocks: vram_blocks(Point::new(x, y), Point::new(x + width - 1, y + 255)),
+            }
+        });
+
+        let clut = match depth {
+            TexelDepth::B4 => self.clut_cache.entries(16),
+            TexelDepth::B8 => self.clut_cache.entries(256),
+            TexelDepth::B15 => &[],
+        };
+
+        let hash = fnv1a(page.hash, [depth as u16]);
+        fnv1a(hash, clut.iter().copied())
+    }
+
+    /// Write the current texture page as an image to `dir` and add it to the manifest.
+    fn dump_texture(&self, dir: &Path, hash: u64) -> io::Result<()> {
+        self.sync_raster();
+
+        let mut data = Vec::with_capacity(256 * 256 * 4);
+        for v in 0..=255 {
+            for u in 0..=255 {
+                let texel = self.page_texel(TexCoord { u, v });
+                data.extend(texel_rgba(texel.as_u16()));
+            }
+        }
+
+        fs::create_dir_all(dir)?;
+
+        let file = format!("{hash:016x}.png");
+        png::write(BufWriter::new(File::create(dir.join(&file))?), 256, 256, true, &data)?;
+
+        let mut manifest = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(dir.join(MANIFEST))?;
+
+        writeln!(manifest, "{hash:016x} {file}")
+    }
+
+    /// Dump the current texture if dumping, and get the replacement for it if there is one and
+    /// the VRAM drawn to is shown on screen. Should be called after the CLUT cache is loaded.
+    pub(super) fn texture_replacement(&mut self) -> Option<Arc<Replacement>> {
+        if self.textures.dump.is_none() && self.textures.pack.is_none() {
+            return None;
+        }
+
+        let hash = self.texture_hash();
+
+        let dump_dir = self.textures.dump.as_mut().and_then(|dump| {
+            dump.dumped.insert(hash).then(|| dump.dir.clone())
+        });
+
+        if let Some(dir) = dump_dir {
+            if let Err(err) = self.dump_texture(&dir, hash) {
+                warn!("failed to dump texture {hash:016x}: {err}");
+            }
+        }
+
+        if self.upscaled.is_some() && self.draw_shift == 0 {
+            return None;
+        }
+
+        self.textures.pack
+            .as_ref()
+            .and_then(|pack| pack.replacements.get(&hash).cloned())
+    }
+}
+
+#[test]
+fn replace_texture() {
+    use crate::schedule::Schedule;
+    use crate::test::run_gp0;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    let dir = std::env::temp_dir().join(format!("splst-texpack-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+
+    let mut schedule = Schedule::new();
+    let mut gpu = Gpu::new(&mut schedule, Rc::new(RefCell::new(())));
+
+    // A 15-bit texture page at (640, 0) with a red texel at (0, 0).
+    gpu.vram.store_16(640, 0, 0x1f);
+
+    let mut draw = |gpu: &mut Gpu| {
+        let cmds = [
+            0xe100010a, 0xe3000000, 0xe4000000 | 1023 | 511 << 10, 0xe5000000,
+            0x65808080, 0, 0, 2 | 2 << 16,
+        ];
+        run_gp0(gpu, &mut schedule, cmds);
+    };
+
+    gpu.set_texture_dump(Some(dir.clone()));
+    draw(&mut gpu);
+
+    assert_eq!(gpu.vram.load_16(0, 0), 0x1f);
+
+    let manifest = fs::read_to_string(dir.join(MANIFEST)).unwrap();
+    let (_, file) = manifest.trim().split_once(' ').unwrap();
+
+    // Edit the dumped texture and load it as a texture pack at twice the resolution, where the
+    // top left 2x2 pixels are blue and the rest is white.
+    let mut data = vec![255; 512 * 512 * 4];
+    for (x, y) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+        data[(x + y * 512) * 4..][..3].copy_from_slice(&[0, 0, 255]);
+    }
+    png::write(File::create(dir.join(file)).unwrap(), 512, 512, true, &data).unwrap();
+
+    gpu.set_texture_dump(None);
+    assert_eq!(gpu.load_texture_pack(&dir).unwrap(), 1);
+
+    draw(&mut gpu);
+
+    assert_eq!(gpu.vram.load_16(0, 0), 0x7c00);
+    assert_eq!(gpu.vram.load_16(1, 1), 0x7fff);
+
+    // The texture changes, so it no longer matches the pack.
+    gpu.vram.store_16(640, 1, 0x1f);
+    gpu.invalidate_textures(Point::new(640, 1), Point::new(640, 1));
+    draw(&mut gpu);
+
+    assert_eq!(gpu.vram.load_16(0, 0), 0x1f);
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn draws_invalidate_textures() {
+    use crate::schedule::Schedule;
+    use crate::test::run_gp0;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    let dir = std::env::temp_dir().join(format!("splst-invalidate-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+
+    let mut schedule = Schedule::new();
+    let mut gpu = Gpu::new(&mut schedule, Rc::new(RefCell::new(())));
+
+    gpu.vram.store_16(640, 0, 0x1f);
+    gpu.set_texture_dump(Some(dir.clone()));
+
+    // Hash the 15-bit texture page at (640, 0) by drawing with it.
+    let textured = [
+        0xe100010a, 0xe3000000, 0xe4000000 | 1023 | 511 << 10, 0xe5000000,
+        0x65808080, 0, 0, 2 | 2 << 16,
+    ];
+    run_gp0(&mut gpu, &mut schedule, textured);
+    assert_eq!(gpu.textures.pages.len(), 1);
+
+    // A line and a rectangle inside the draw area but away from the page keep the hash.
+    run_gp0(&mut gpu, &mut schedule, [0x40ffffff, 300 << 16, 500 | 300 << 16]);
+    run_gp0(&mut gpu, &mut schedule, [0x60ffffff, 300 << 16, 600 | 100 << 16]);
+    assert_eq!(gpu.textures.pages.len(), 1);
+
+    // A triangle drawn over it doesn't.
+    run_gp0(&mut gpu, &mut schedule, [0x20ffffff, 700, 710, 700 | 10 << 16]);
+    assert!(gpu.textures.pages.is_empty());
+
+    let _ = fs::remove_dir_all(&dir);
+}
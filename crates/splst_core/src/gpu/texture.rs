@@ -1,7 +1,8 @@
-use super::primitive::{Point, Texel};
+use super::primitive::{Point, TexCoord, Texel};
 use super::vram::Vram;
 use super::TexelDepth;
 
+#[derive(Clone)]
 pub struct ClutCache {
     data: [u16; 256],
     status: Option<(Point, TexelDepth)>,
@@ -10,13 +11,10 @@ pub struct ClutCache {
 impl ClutCache {
     // Maybe fetch a new cacheline. Should only be called when 'depth' is either 4 or 8.
     pub fn maybe_fetch(&mut self, pos: Point, depth: TexelDepth, vram: &Vram) {
-        // The cache is already loaded.
-        if let Some((prev_pos, prev_depth)) = self.status {
-            // If the depth is lower or the same, and if the position matches, the cacheline is
-            // intact.
-            if depth as usize <= prev_depth as usize && pos == prev_pos {
-                return;
-            }
+        // If the depth is lower or the same, and if the position matches, the cacheline is
+        // intact.
+        if self.is_loaded(pos, depth) {
+            return;
         }
         
         let load = match depth {
@@ -37,7 +35,18 @@ impl ClutCache {
     pub fn clear(&mut self) {
         self.status = None;
     }
+
+    /// If the entries of the CLUT at `pos` are already loaded for `depth`.
+    pub fn is_loaded(&self, pos: Point, depth: TexelDepth) -> bool {
+        matches!(self.status, Some((prev_pos, prev_depth))
+            if depth as usize <= prev_depth as usize && pos == prev_pos)
+    }
     
+    /// The first `count` entries of the cache.
+    pub fn entries(&self, count: usize) -> &[u16] {
+        &self.data[..count]
+    }
+
     pub fn get(&self, offset: i32) -> Texel {
         Texel::new(self.data[offset as usize])
     }
@@ -51,3 +60,77 @@ impl Default for ClutCache {
         }
     }
 }
+
+/// Model of the texture cache, only used for timing. Texels are always loaded straight from VRAM
+/// when drawing.
+///
+/// The cache is 2 KiB of 8 byte lines, modeled as 64 rows of 4 direct mapped lines. The row is
+/// picked by the lower bits of the v coordinate and the line by the bits of the u coordinate
+/// right above a line, so a 64x64 texture fits in the cache when 4 bit texels are used. It's only
+/// cleared by GP0(01) and resets, not by writes to VRAM, like the real one.
+pub struct TexCache {
+    tags: [Option<u32>; 256],
+}
+
+impl TexCache {
+    /// Load the lines covering the texels from `min` to `max` of the texture page at `page`.
+    /// Returns the number of lines which weren't already in the cache.
+    pub fn load(&mut self, page: Point, depth: TexelDepth, min: TexCoord, max: TexCoord) -> u64 {
+        let line_texels = match depth {
+            TexelDepth::B4 => 16,
+            TexelDepth::B8 => 8,
+            TexelDepth::B15 => 4,
+        };
+
+        // The page is 64 halfwords wide and 256 lines tall.
+        let page = (page.x / 64) as u32 | ((page.y / 256) as u32) << 4;
+
+        let mut misses = 0;
+
+        for v in min.v as u32..=max.v as u32 {
+            for line in min.u as u32 / line_texels..=max.u as u32 / line_texels {
+                let index = (v % 64 * 4 + line % 4) as usize;
+                let tag = page | (depth as u32) << 5 | (line / 4) << 10 | (v / 64) << 16;
+
+                if self.tags[index] != Some(tag) {
+                    self.tags[index] = Some(tag);
+                    misses += 1;
+                }
+            }
+        }
+
+        misses
+    }
+
+    pub fn clear(&mut self) {
+        self.tags = [None; 256];
+    }
+}
+
+impl Default for TexCache {
+    fn default() -> Self {
+        Self { tags: [None; 256] }
+    }
+}
+
+#[test]
+fn tex_cache_misses() {
+    let mut cache = TexCache::default();
+    let page = Point::new(0, 0);
+
+    let (min, max) = (TexCoord { u: 0, v: 0 }, TexCoord { u: 63, v: 63 });
+
+    // A 64x64 4 bit texture fills the whole cache.
+    assert_eq!(cache.load(page, TexelDepth::B4, min, max), 256);
+    assert_eq!(cache.load(page, TexelDepth::B4, min, max), 0);
+
+    // The same texels in another page replace them.
+    assert_eq!(cache.load(Point::new(64, 0), TexelDepth::B4, min, min), 1);
+    assert_eq!(cache.load(page, TexelDepth::B4, min, max), 1);
+
+    // 15 bit texels take four times the space.
+    assert_eq!(cache.load(page, TexelDepth::B15, min, TexCoord { u: 15, v: 63 }), 256);
+
+    cache.clear();
+    assert_eq!(cache.load(page, TexelDepth::B15, min, min), 1);
+}
@@ -0,0 +1,501 @@
+//! Rasterizing primitives on multiple threads.
+//!
+//! Triangles and rectangles are set up on the emulation thread, and then queued to be drawn by a
+//! pool of worker threads. Each primitive is split into tiles of rows, which any of the workers
+//! can draw. Each thread steps through every row of a triangle to accumulate the attributes
+//! exactly as the serial rasterizer does, so the output is identical no matter which thread draws
+//! which tile. The primitives are drawn in order, so the workers only start on the next one once
+//! all the tiles of the current one are drawn.
+//!
+//! The time it takes the GPU to draw a primitive is based on the pixels it covers, which is
+//! counted when it's set up, so the emulation thread never waits for the workers just to draw.
+//! Instead everything else reading or writing VRAM, such as lines, fills, copies, transfers
+//! to and from the CPU, loading the CLUT cache, hashing textures and fetching the display, waits
+//! for the queue to be drawn first with [`Gpu::sync_raster`], and helps drawing it while waiting.
+//! Textures are loaded by the workers themselves, so primitives drawn in order always see the
+//! textures drawn before them. Primitives which may draw over their own texture page are drawn
+//! as a single tile, since the texels loaded would otherwise depend on how far the other threads
+//! have come. Small primitives are drawn right away on the emulation thread if the queue is
+//! empty, since it's faster than waking up the workers.
+
+use super::primitive::Point;
+use super::texture::ClutCache;
+use super::{Gpu, TexelDepth, Vram};
+
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::thread::{self, JoinHandle};
+use std::panic::{self, AssertUnwindSafe};
+use std::ops::Range;
+use std::mem;
+
+/// The number of rows in each tile.
+const TILE_ROWS: i32 = 16;
+
+/// Primitives with a smaller bounding box are drawn right away on the emulation thread if
+/// nothing is queued.
+const MIN_TILED_PIXELS: i32 = 64 * 64;
+
+/// Pointer to the halfwords of either native or upscaled VRAM, which can be shared between
+/// threads drawing to different rows.
+#[derive(Clone, Copy)]
+pub(super) struct VramPtr {
+    ptr: *mut u16,
+    mask: usize,
+    width: i32,
+}
+
+// Safety: It's up to the users of `load` and `store` to not access the same halfwords from
+// multiple threads.
+unsafe impl Send for VramPtr {}
+unsafe impl Sync for VramPtr {}
+
+impl VramPtr {
+    /// `data` must be a power of two in length.
+    fn new(data: &mut [u16], width: u32) -> Self {
+        debug_assert!(data.len().is_power_of_two());
+        Self {
+            ptr: data.as_mut_ptr(),
+            mask: data.len() - 1,
+            width: width as i32,
+        }
+    }
+
+    fn offset(self, x: i32, y: i32) -> usize {
+        (x + y * self.width) as usize & self.mask
+    }
+
+    /// # Safety
+    ///
+    /// No other thread may be storing to the same halfword, and the VRAM must still be alive.
+    pub(super) unsafe fn load(self, x: i32, y: i32) -> u16 {
+        *self.ptr.add(self.offset(x, y))
+    }
+
+    /// # Safety
+    ///
+    /// No other thread may be accessing the same halfword, and the VRAM must still be alive.
+    pub(super) unsafe fn store(self, x: i32, y: i32, val: u16) {
+        *self.ptr.add(self.offset(x, y)) = val;
+    }
+}
+
+/// A primitive which has been set up, and can be drawn a range of rows at a time on any thread.
+pub(super) trait Raster: Send + Sync + 'static {
+    /// Draw the rows given by `next_rows` to `target`, with textures loaded from `vram`.
+    /// `next_rows` is called for the next range of rows once the current one is done, and must
+    /// return ranges in increasing order. Rows outside the range aren't accessed.
+    ///
+    /// # Safety
+    ///
+    /// No other thread may access the rows drawn to in `target` while drawing, or write to the
+    /// part of `vram` textures are loaded from.
+    unsafe fn draw_rows(
+        &self,
+        target: VramPtr,
+        vram: VramPtr,
+        clut: &ClutCache,
+        next_rows: &mut dyn FnMut() -> Option<Range<i32>>,
+    );
+}
+
+/// A primitive queued to be drawn.
+struct Draw {
+    raster: Box<dyn Raster>,
+    target: VramPtr,
+    vram: VramPtr,
+    /// The CLUT cache as it was when the primitive was set up.
+    clut: ClutCache,
+    /// The first row of the first tile.
+    min_y: i32,
+    /// The number of rows in each tile.
+    tile_rows: i32,
+    tiles: i32,
+    /// The next tile to be drawn. It may count past `tiles` once they are all taken.
+    next_tile: AtomicI32,
+    /// The number of tiles drawn.
+    done: AtomicI32,
+}
+
+impl Draw {
+    fn has_tiles(&self) -> bool {
+        self.next_tile.load(Ordering::Relaxed) < self.tiles
+    }
+
+    fn take_tile(&self) -> Option<Range<i32>> {
+        let tile = self.next_tile.fetch_add(1, Ordering::Relaxed);
+        (tile < self.tiles).then(|| {
+            let start = self.min_y + tile * self.tile_rows;
+            start..start + self.tile_rows
+        })
+    }
+}
+
+#[derive(Default)]
+struct Queue {
+    draws: VecDeque<Arc<Draw>>,
+    /// Set when the pool is dropped to stop the workers.
+    closed: bool,
+    /// Set if a thread panicked while drawing, in which case the queue is thrown away.
+    panicked: bool,
+}
+
+/// The queue shared between the emulation thread and the workers.
+#[derive(Default)]
+struct Shared {
+    queue: Mutex<Queue>,
+    /// Notified whenever a primitive is queued or done, or the queue is closed.
+    changed: Condvar,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, Queue> {
+        // Nothing panics while holding the lock, but the pool may be dropped while unwinding.
+        self.queue.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn wait<'a>(&self, queue: MutexGuard<'a, Queue>) -> MutexGuard<'a, Queue> {
+        self.changed.wait(queue).unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Draw tiles of the queued primitives. The workers keep going until the pool is closed,
+    /// while the emulation thread returns as soon as the queue is empty.
+    fn work(&self, worker: bool) {
+        let mut queue = self.lock();
+        loop {
+            let draw = match queue.draws.front() {
+                Some(draw) if draw.has_tiles() => draw.clone(),
+                // Wait for the other threads to finish the last tiles.
+                Some(_) => {
+                    queue = self.wait(queue);
+                    continue;
+                }
+                None if !worker || queue.closed => return,
+                None => {
+                    queue = self.wait(queue);
+                    continue;
+                }
+            };
+
+            drop(queue);
+
+            if let Err(err) = panic::catch_unwind(AssertUnwindSafe(|| self.draw_tiles(&draw))) {
+                // The tile being drawn never gets done, so the rest of the queue has to be thrown
+                // away to not leave the other threads waiting for it.
+                let mut queue = self.lock();
+                queue.panicked = true;
+                queue.draws.clear();
+                self.changed.notify_all();
+                if !worker {
+                    drop(queue);
+                    panic::resume_unwind(err);
+                }
+            }
+
+            queue = self.lock();
+        }
+    }
+
+    /// Draw tiles of `draw` until there are no more left.
+    fn draw_tiles(&self, draw: &Arc<Draw>) {
+        let mut held = false;
+        let mut next_rows = || {
+            if mem::take(&mut held) {
+                self.tile_done(draw);
+            }
+            let rows = draw.take_tile();
+            held = rows.is_some();
+            rows
+        };
+
+        // Safety: Each tile is only drawn by the thread which took it, and the primitive is only
+        // drawn over its own texture if it's drawn as a single tile. The emulation thread doesn't
+        // touch VRAM before the queue is empty, and the VRAM outlives the pool.
+        unsafe {
+            draw.raster.draw_rows(draw.target, draw.vram, &draw.clut, &mut next_rows);
+        }
+
+        if held {
+            self.tile_done(draw);
+        }
+    }
+
+    fn tile_done(&self, draw: &Arc<Draw>) {
+        if draw.done.fetch_add(1, Ordering::AcqRel) + 1 == draw.tiles {
+            let mut queue = self.lock();
+            // It may be gone already if another thread panicked.
+            if queue.draws.front().map_or(false, |front| Arc::ptr_eq(front, draw)) {
+                queue.draws.pop_front();
+            }
+            self.changed.notify_all();
+        }
+    }
+}
+
+/// A pool of worker threads drawing queued primitives.
+pub(super) struct RasterPool {
+    shared: Arc<Shared>,
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl RasterPool {
+    /// Create a pool to draw on `threads` threads including the emulation thread, which helps
+    /// out when waiting for the queue.
+    fn new(threads: usize) -> Self {
+        let shared = Arc::new(Shared::default());
+        let threads = (1..threads)
+            .map(|i| {
+                let shared = shared.clone();
+                thread::Builder::new()
+                    .name(format!("raster {i}"))
+                    .spawn(move || shared.work(true))
+                    .expect("failed to spawn rasterizer thread")
+            })
+            .collect();
+
+        Self { shared, threads }
+    }
+
+    fn threads(&self) -> usize {
+        self.threads.len() + 1
+    }
+
+    fn is_empty(&self) -> bool {
+        self.shared.lock().draws.is_empty()
+    }
+
+    fn push(&self, draw: Draw) {
+        self.shared.lock().draws.push_back(Arc::new(draw));
+        self.shared.changed.notify_all();
+    }
+
+    /// Wait for the queue to be drawn, helping the workers while waiting.
+    fn sync(&self) {
+        self.shared.work(false);
+        if self.shared.lock().panicked {
+            panic!("rasterizer thread panicked");
+        }
+    }
+}
+
+impl Drop for RasterPool {
+    fn drop(&mut self) {
+        // The workers stop once the queue is drawn.
+        self.shared.lock().closed = true;
+        self.shared.changed.notify_all();
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// If the halfwords `a..a + a_len` and `b..b + b_len` overlap, where both wrap around VRAM.
+fn wrapping_overlap(a: usize, a_len: usize, b: usize, b_len: usize) -> bool {
+    let dist = b.wrapping_sub(a) & (Vram::SIZE - 1);
+    dist < a_len || (Vram::SIZE - dist) & (Vram::SIZE - 1) < b_len
+}
+
+impl Gpu {
+    /// Set the number of threads used to rasterize primitives. With more than one, triangles
+    /// and rectangles are queued to be drawn by worker threads.
+    pub fn set_raster_threads(&mut self, threads: usize) {
+        if threads.max(1) != self.raster_threads() {
+            // Dropping the old pool waits for the queue to be drawn.
+            self.raster_pool = (threads > 1).then(|| RasterPool::new(threads));
+        }
+    }
+
+    pub fn raster_threads(&self) -> usize {
+        self.raster_pool.as_ref().map_or(1, RasterPool::threads)
+    }
+
+    /// Wait for all queued primitives to be drawn. Must be called before VRAM is accessed
+    /// other than by drawing a [`Raster`].
+    pub(super) fn sync_raster(&self) {
+        if let Some(pool) = &self.raster_pool {
+            pool.sync();
+        }
+    }
+
+    /// If drawing to the bounding box from `min` to `max` in native VRAM may draw over the
+    /// current texture page.
+    fn draws_over_texture(&self, min: Point, max: Point) -> bool {
+        let offset = |x: i32, y: i32| (x + y * 1024) as usize & (Vram::SIZE - 1);
+
+        let tex_width = match self.status.texel_depth() {
+            TexelDepth::B4 => 64,
+            TexelDepth::B8 => 128,
+            TexelDepth::B15 => 256,
+        };
+
+        let (tex_x, tex_y) = (self.status.tex_page_x(), self.status.tex_page_y());
+        let draw_width = (max.x + 1 - min.x) as usize;
+
+        // Rows of both the texture page and bounding box may spill over into the next line,
+        // but never further, so only the lines next to each row can overlap.
+        (min.y..=max.y).any(|y| {
+            let draw = offset(min.x, y);
+            (y - 1..=y + 1).any(|ty| {
+                (ty - tex_y).rem_euclid(512) < 256
+                    && wrapping_overlap(offset(tex_x, ty), tex_width, draw, draw_width)
+            })
+        })
+    }
+
+    /// Draw a primitive which has been set up to the VRAM being drawn to. `min` and `max` is the
+    /// bounding box of it, clipped to the draw area, and `textured` is if it loads textures from
+    /// VRAM. With worker threads, it's queued to be drawn in tiles, otherwise it's drawn right
+    /// away. The hashes of textures in the bounding box are forgotten.
+    pub(super) fn draw_raster(
+        &mut self,
+        raster: impl Raster,
+        min: Point,
+        max: Point,
+        textured: bool,
+    ) {
+        let rows = max.y - min.y + 1;
+        if rows <= 0 || max.x < min.x {
+            return;
+        }
+
+        if self.draw_shift == 0 {
+            self.invalidate_textures(min, max);
+        }
+
+        let vram = VramPtr::new(&mut self.vram.data, 1024);
+        let target = match &mut self.upscaled {
+            Some(upscaled) if self.draw_shift != 0 => {
+                let width = upscaled.width();
+                VramPtr::new(upscaled.data_mut(), width)
+            }
+            _ => vram,
+        };
+
+        let small = (max.x - min.x + 1) * rows < MIN_TILED_PIXELS;
+        let queued = self.raster_pool
+            .as_ref()
+            .map_or(false, |pool| !small || !pool.is_empty());
+
+        if !queued {
+            let mut rows = Some(min.y..max.y + 1);
+            // Safety: Nothing is queued, so only this thread is drawing.
+            unsafe {
+                raster.draw_rows(target, vram, &self.clut_cache, &mut || rows.take());
+            }
+            return;
+        }
+
+        let over_texture = textured && self.draw_shift == 0 && self.draws_over_texture(min, max);
+        let tile_rows = if over_texture { rows } else { TILE_ROWS };
+
+        let draw = Draw {
+            raster: Box::new(raster),
+            target,
+            vram,
+            clut: self.clut_cache.clone(),
+            min_y: min.y,
+            tile_rows,
+            tiles: (rows + tile_rows - 1) / tile_rows,
+            next_tile: AtomicI32::new(0),
+            done: AtomicI32::new(0),
+        };
+
+        if let Some(pool) = &self.raster_pool {
+            pool.push(draw);
+        }
+    }
+}
+
+#[test]
+fn tiles_match_serial() {
+    use super::ResolutionScale;
+    use crate::schedule::Schedule;
+    use crate::test::run_gp0;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    let pattern = |x: i32, y: i32| ((x * 7) ^ (y * 13)) as u16;
+
+    let draw = |threads: usize, scale: ResolutionScale, cmds: &[u32]| -> Gpu {
+        let mut schedule = Schedule::new();
+        let mut gpu = Gpu::new(&mut schedule, Rc::new(RefCell::new(())));
+
+        for y in 0..512 {
+            for x in 0..1024 {
+                gpu.vram.store_16(x, y, pattern(x, y));
+            }
+        }
+
+        gpu.set_raster_threads(threads);
+        gpu.set_resolution_scale(scale);
+
+        run_gp0(&mut gpu, &mut schedule, cmds.iter().copied());
+
+        gpu
+    };
+
+    let check = |cmds: &[u32]| -> Vec<Gpu> {
+        [ResolutionScale::X1, ResolutionScale::X2]
+            .into_iter()
+            .flat_map(|scale| {
+                let serial = draw(1, scale, cmds);
+                let tiled = draw(4, scale, cmds);
+
+                assert_eq!(tiled.raster_threads(), 4);
+                assert!(serial.vram().data == tiled.vram().data);
+
+                let upscaled = (serial.upscaled_vram(), tiled.upscaled_vram());
+                if let (Some(serial), Some(tiled)) = upscaled {
+                    assert!(serial.data() == tiled.data());
+                }
+
+                [serial, tiled]
+            })
+            .collect()
+    };
+
+    let mut cmds = vec![0xe3000000, 0xe4000000 | 1023 | 511 << 10, 0xe5000000];
+
+    // Flat, shaded and blended triangles.
+    cmds.extend([0x20607080, 10 | 5 << 16, 300 | 40 << 16, 40 | 230 << 16]);
+    cmds.extend([0x30ff0000, 320 | 10 << 16, 0x00ff00, 600 | 200 << 16, 0x0000ff, 350 | 250 << 16]);
+    cmds.extend([0xe1000040, 0x32ff0000, 0, 0x00ff00, 400 | 100 << 16, 0x0000ff, 50 | 300 << 16]);
+
+    // Textured quads with the texture page at x 640 in each texel depth, followed by one with
+    // the texture page right where it's drawn.
+    for (depth, page) in [(0, 10), (1, 10), (2, 10), (2, 0)] {
+        let page = page | depth << 7;
+        cmds.extend([
+            0x2c808080, 20 | 260 << 16, 480 << 16,
+            300 | 250 << 16, page << 16 | 255,
+            30 | 500 << 16, 255 << 8,
+            280 | 480 << 16, 255 | 255 << 8,
+        ]);
+    }
+
+    // Textured and flat rectangles, which are queued along with the triangles.
+    cmds.extend([0x64808080, 600 | 300 << 16, 10 << 16, 200 | 150 << 16]);
+    cmds.extend([0x62203040, 650 | 350 << 16, 300 | 100 << 16]);
+
+    check(&cmds);
+
+    // The draw area ending right before the right edge of VRAM, where blocks of four pixels
+    // would wrap around into the next row, which may be drawn by another thread.
+    for right in [1021, 1022, 1023] {
+        let cmds = [
+            0xe3000000 | 512, 0xe4000000 | right | 511 << 10, 0xe5000000,
+            0x30ff0000, 520, 0x00ff00, 1023 | 20 << 16, 0x0000ff, 1023 | 511 << 16,
+            0x60203040, 900 | 100 << 16, 200 | 100 << 16,
+        ];
+
+        for gpu in check(&cmds) {
+            let vram = gpu.vram();
+            for y in 0..512 {
+                for x in (0..512).chain(right as i32 + 1..1024) {
+                    assert_eq!(vram.load_16(x, y), pattern(x, y), "({x}, {y}) drawn outside");
+                }
+            }
+        }
+    }
+}
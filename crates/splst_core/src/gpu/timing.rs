@@ -0,0 +1,180 @@
+//! Timing of GPU commands and transfers.
+//!
+//! Times are counted in GPU cycles and turned into [`SysTime`] by
+//! [`Gpu::dot_cycles_to_systime`]. The draw times are mostly from Mednafen, the rest are
+//! estimates. The model is as follows:
+//!
+//! - Commands are pushed to a FIFO of 16 words and start once all the words have arrived. The GPU
+//!   is in [`State::Drawing`] until the draw time has passed, and can't start the next command
+//!   before then. Immediate commands skip the FIFO and take no time.
+//! - Status bit 26, ready for command words, is only set when the GPU is idle and the FIFO is
+//!   empty. Bit 28, ready for DMA blocks, is cleared while drawing and as soon as the first word
+//!   of a polygon or line command is received. Bit 25 mirrors either of them or the VRAM to CPU
+//!   ready bit depending on the DMA direction.
+//! - Triangles take a setup time depending on shading and texturing. Each pixel takes two cycles
+//!   if shaded or textured, otherwise one, or one and a half if the background has to be read for
+//!   blending or mask checking. Rectangles and lines work the same way, but with a fixed setup
+//!   time. Every pixel covered counts, also those with invisible texels, so the time is known
+//!   when the primitive is set up and doesn't depend on the rasterizer threads, see
+//!   [`tile`](super::tile).
+//! - Textured primitives also pay one cycle for each CLUT entry loaded and [`TEX_CACHE_MISS`]
+//!   cycles for each miss in the texture cache, which is modeled by
+//!   [`TexCache`](super::texture::TexCache).
+//! - Fills write 8 halfwords each cycle, with some overhead for each line. Copies within VRAM
+//!   take two cycles for each halfword since they have to be both read and written. Transfers
+//!   between the CPU and VRAM move a halfword each cycle, which limits how fast the DMA can go.
+//! - The DMA only starts new blocks when bit 28 is set, and stalls in the middle of a block if
+//!   the FIFO is full. The GPU channel is run again whenever the GPU goes idle.
+
+use super::primitive::{Point, TexCoord};
+use super::gp0::draw_mode;
+use super::{Gpu, State, TexelDepth};
+use crate::SysTime;
+
+/// Setup cycles of all polygons.
+pub(super) const POLY_SETUP: u64 = 82;
+
+/// Extra setup cycles of quads, on top of drawing them as two triangles.
+pub(super) const QUAD_SETUP: u64 = 46;
+
+/// Setup cycles of rectangles and lines.
+const RECT_SETUP: u64 = 30;
+
+/// Cycles to load a cache line into the texture cache.
+const TEX_CACHE_MISS: u64 = 8;
+
+impl Gpu {
+    /// Cycles for drawing `pixels` pixels. `interpolate` is if the primitive is either shaded or
+    /// textured.
+    fn pixel_cycles<Trans>(&self, interpolate: bool, pixels: u64) -> u64
+    where
+        Trans: draw_mode::Transparency,
+    {
+        if interpolate {
+            pixels * 2
+        } else if Trans::IS_TRANSPARENT || self.status.draw_masked_pixels() {
+            pixels * 3 / 2
+        } else {
+            pixels
+        }
+    }
+
+    /// GPU cycles to draw a triangle, not including texture loads.
+    pub(super) fn triangle_draw_time<Shade, Tex, Trans>(&self, pixels: u64) -> u64
+    where
+        Shade: draw_mode::Shading,
+        Tex: draw_mode::Textureing,
+        Trans: draw_mode::Transparency,
+    {
+        let setup = match (Shade::IS_SHADED, Tex::IS_TEXTURED) {
+            (true, true) => 150 * 3,
+            (true, false) => 96 * 3,
+            (false, true) => 60 * 3,
+            (false, false) => 0,
+        };
+        setup + self.pixel_cycles::<Trans>(Shade::IS_SHADED || Tex::IS_TEXTURED, pixels)
+    }
+
+    /// GPU cycles to draw a rectangle, not including texture loads.
+    pub(super) fn rect_draw_time<Tex, Trans>(&self, pixels: u64) -> u64
+    where
+        Tex: draw_mode::Textureing,
+        Trans: draw_mode::Transparency,
+    {
+        RECT_SETUP + self.pixel_cycles::<Trans>(Tex::IS_TEXTURED, pixels)
+    }
+
+    /// GPU cycles to draw a line.
+    pub(super) fn line_draw_time<Shade, Trans>(&self, mut pixels: u64) -> u64
+    where
+        Shade: draw_mode::Shading,
+        Trans: draw_mode::Transparency,
+    {
+        // Lines count every pixel, even those on displayed lines which doesn't get drawn.
+        if !self.status.draw_to_display() && self.status.interlaced_480() {
+            pixels /= 2;
+        }
+        RECT_SETUP + self.pixel_cycles::<Trans>(Shade::IS_SHADED, pixels)
+    }
+
+    /// GPU cycles to fill a rectangle of size `dim` in VRAM.
+    pub(super) fn fill_rect_time(&self, dim: Point) -> u64 {
+        let line_time = (dim.x / 8) + 9;
+        (46 + line_time * dim.y) as u64
+    }
+
+    /// GPU cycles to copy a rectangle of size `dim` within VRAM.
+    pub(super) fn copy_rect_time(&self, dim: Point) -> u64 {
+        // Each halfword has to be both read and written.
+        (dim.x * dim.y * 2) as u64
+    }
+
+    /// GPU cycles to load the CLUT at `clut` and the texels from `min` to `max` in the current
+    /// texture page into the caches. It must be called before the primitive is drawn, since
+    /// drawing loads the CLUT.
+    pub(super) fn texture_load_time(
+        &mut self,
+        clut: Point,
+        min: TexCoord,
+        max: TexCoord,
+    ) -> u64 {
+        let depth = self.status.texel_depth();
+        let clut = match depth {
+            TexelDepth::B15 => 0,
+            depth if self.clut_cache.is_loaded(clut, depth) => 0,
+            TexelDepth::B4 => 16,
+            TexelDepth::B8 => 256,
+        };
+        let page = Point::new(self.status.tex_page_x(), self.status.tex_page_y());
+        clut + self.tex_cache.load(page, depth, min, max) * TEX_CACHE_MISS
+    }
+
+    /// [`Gpu::texture_load_time`] of a polygon with the texture coordinates `coords`. The texels
+    /// are taken to cover the bounding box of the coordinates.
+    pub(super) fn poly_texture_load_time(&mut self, clut: Point, coords: &[TexCoord]) -> u64 {
+        let (min, max) = coords.iter().fold(
+            (TexCoord { u: u8::MAX, v: u8::MAX }, TexCoord { u: 0, v: 0 }),
+            |(min, max), coord| (
+                TexCoord { u: min.u.min(coord.u), v: min.v.min(coord.v) },
+                TexCoord { u: max.u.max(coord.u), v: max.v.max(coord.v) },
+            ),
+        );
+        self.texture_load_time(clut, min, max)
+    }
+
+    /// [`Gpu::texture_load_time`] of a rectangle of size `dim` starting at texture coordinate
+    /// `uv`.
+    pub(super) fn rect_texture_load_time(&mut self, clut: Point, uv: TexCoord, dim: Point) -> u64 {
+        if dim.x <= 0 || dim.y <= 0 {
+            return 0;
+        }
+
+        // The range of a coordinate, which covers the whole page if it wraps around.
+        let range = |start: u8, len: i32, flip: bool| -> (u8, u8) {
+            let (start, len) = (start as i32, len.min(256) - 1);
+            let (lo, hi) = if flip { (start - len, start) } else { (start, start + len) };
+            if lo < 0 || hi > 255 {
+                (0, 255)
+            } else {
+                (lo as u8, hi as u8)
+            }
+        };
+
+        let (u_min, u_max) = range(uv.u, dim.x, self.tex_x_flip);
+        let (v_min, v_max) = range(uv.v, dim.y, self.tex_y_flip);
+
+        self.texture_load_time(
+            clut,
+            TexCoord { u: u_min, v: v_min },
+            TexCoord { u: u_max, v: v_max },
+        )
+    }
+
+    /// The time it takes for a word to be transferred to or from the GPU by DMA.
+    pub(super) fn transfer_word_time(&self) -> SysTime {
+        match self.state {
+            State::VramStore(..) | State::VramLoad(..) => self.dot_cycles_to_systime(2),
+            _ => SysTime::new(1),
+        }
+    }
+}
@@ -0,0 +1,268 @@
+//! Rendering at a higher internal resolution.
+//!
+//! When enabled, polygons, lines and rectangles are drawn a second time to [`UpscaledVram`],
+//! which is VRAM scaled up by 2, 4 or 8 in each direction. Native VRAM is still drawn to as
+//! before and is what everything else uses, so CPU reads, transfers to the CPU and texture
+//! sampling work just as without upscaling. Fills and transfers from the CPU are written to the
+//! upscaled VRAM as blocks of pixels to keep it coherent, and copies within VRAM are done at the
+//! upscaled resolution. The display area is presented from the upscaled VRAM.
+
+use serde::{Serialize, Deserialize};
+
+use super::primitive::Point;
+use super::{Gpu, Vram};
+
+use std::fmt;
+
+/// The internal resolution relative to the native resolution.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ResolutionScale {
+    #[default]
+    X1,
+    X2,
+    X4,
+    X8,
+}
+
+impl ResolutionScale {
+    pub const ALL: [Self; 4] = [Self::X1, Self::X2, Self::X4, Self::X8];
+
+    /// The number of bits coordinates are shifted left by.
+    pub fn shift(self) -> u32 {
+        match self {
+            ResolutionScale::X1 => 0,
+            ResolutionScale::X2 => 1,
+            ResolutionScale::X4 => 2,
+            ResolutionScale::X8 => 3,
+        }
+    }
+
+    pub fn scale(self) -> u32 {
+        1 << self.shift()
+    }
+}
+
+impl fmt::Display for ResolutionScale {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ResolutionScale::X1 => f.write_str("Native"),
+            scale => write!(f, "{}x", scale.scale()),
+        }
+    }
+}
+
+/// VRAM at a higher resolution. Coordinates wrap around the edges like native VRAM.
+pub struct UpscaledVram {
+    scale: ResolutionScale,
+    data: Vec<u16>,
+}
+
+impl UpscaledVram {
+    /// Create from native VRAM, with each pixel covering a block of pixels.
+    fn new(scale: ResolutionScale, vram: &Vram) -> Self {
+        let mut upscaled = Self {
+            scale,
+            data: vec![0x0; Vram::SIZE << (scale.shift() * 2)],
+        };
+
+        for y in 0..512 {
+            for x in 0..1024 {
+                upscaled.store_native(x, y, vram.load_16(x, y));
+            }
+        }
+
+        upscaled
+    }
+
+    pub fn scale(&self) -> u32 {
+        self.scale.scale()
+    }
+
+    pub fn width(&self) -> u32 {
+        1024 << self.scale.shift()
+    }
+
+    pub fn height(&self) -> u32 {
+        512 << self.scale.shift()
+    }
+
+    /// The halfwords of each line from top to bottom.
+    pub fn data(&self) -> &[u16] {
+        &self.data
+    }
+
+    pub(super) fn data_mut(&mut self) -> &mut [u16] {
+        &mut self.data
+    }
+
+    pub fn load_16(&self, x: i32, y: i32) -> u16 {
+        self.data[self.offset(x, y)]
+    }
+
+    pub fn store_16(&mut self, x: i32, y: i32, val: u16) {
+        let offset = self.offset(x, y);
+        self.data[offset] = val;
+    }
+
+    /// Store a halfword at native coordinates `x` and `y`, covering the whole block of pixels.
+    pub fn store_native(&mut self, x: i32, y: i32, val: u16) {
+        let shift = self.scale.shift();
+        for dy in 0..1 << shift {
+            for dx in 0..1 << shift {
+                self.store_16((x << shift) + dx, (y << shift) + dy, val);
+            }
+        }
+    }
+
+    fn offset(&self, x: i32, y: i32) -> usize {
+        (x + y * self.width() as i32) as usize & (self.data.len() - 1)
+    }
+}
+
+impl Gpu {
+    /// Set the internal resolution. The upscaled VRAM starts out as a copy of native VRAM, so
+    /// changing it in the middle of a game makes everything blocky until it's drawn again.
+    pub fn set_resolution_scale(&mut self, scale: ResolutionScale) {
+        if scale == self.resolution_scale() {
+            return;
+        }
+        self.sync_raster();
+        self.upscaled = match scale {
+            ResolutionScale::X1 => None,
+            scale => Some(UpscaledVram::new(scale, &self.vram)),
+        };
+    }
+
+    pub fn resolution_scale(&self) -> ResolutionScale {
+        self.upscaled
+            .as_ref()
+            .map_or(ResolutionScale::X1, |upscaled| upscaled.scale)
+    }
+
+    /// The upscaled VRAM if rendering at a higher resolution.
+    pub fn upscaled_vram(&self) -> Option<&UpscaledVram> {
+        self.sync_raster();
+        self.upscaled.as_ref()
+    }
+
+    /// Call `draw` for the upscaled VRAM if enabled, and then for native VRAM. `draw` is given
+    /// the shift to apply to coordinates, and the result of drawing to native VRAM is returned.
+    ///
+    /// The upscaled VRAM is drawn to first, so that both see the same textures if a primitive
+    /// draws over it's own texture.
+    pub(super) fn draw_upscaled<T>(&mut self, mut draw: impl FnMut(&mut Self, u32) -> T) -> T {
+        if let Some(shift) = self.upscaled.as_ref().map(|upscaled| upscaled.scale.shift()) {
+            self.draw_shift = shift;
+            draw(self, shift);
+            self.draw_shift = 0;
+        }
+        draw(self, 0)
+    }
+
+    /// Load a halfword from the VRAM currently being drawn to.
+    pub(super) fn target_load(&self, x: i32, y: i32) -> u16 {
+        match &self.upscaled {
+            Some(upscaled) if self.draw_shift != 0 => upscaled.load_16(x, y),
+            _ => self.vram.load_16(x, y),
+        }
+    }
+
+    /// Store a halfword to the VRAM currently being drawn to.
+    pub(super) fn target_store(&mut self, x: i32, y: i32, val: u16) {
+        match &mut self.upscaled {
+            Some(upscaled) if self.draw_shift != 0 => upscaled.store_16(x, y, val),
+            _ => self.vram.store_16(x, y, val),
+        }
+    }
+
+    /// The top left and bottom right corner of the draw area in the VRAM currently being drawn
+    /// to. Both are inclusive.
+    pub(super) fn target_draw_area(&self) -> (Point, Point) {
+        let shift = self.draw_shift;
+        let min = Point::new(self.da_x_min << shift, self.da_y_min << shift);
+        let max = Point::new(
+            ((self.da_x_max + 1) << shift) - 1,
+            ((self.da_y_max + 1) << shift) - 1,
+        );
+        (min, max)
+    }
+}
+
+#[test]
+fn upscaled_coherence() {
+    use crate::schedule::Schedule;
+    use crate::test::run_gpu_until;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    let mut schedule = Schedule::new();
+    let mut gpu = Gpu::new(&mut schedule, Rc::new(RefCell::new(())));
+
+    gpu.vram.store_16(3, 2, 0x1234);
+    gpu.set_resolution_scale(ResolutionScale::X4);
+
+    let upscaled = gpu.upscaled_vram().unwrap();
+    assert_eq!((upscaled.width(), upscaled.height()), (4096, 2048));
+    assert_eq!(upscaled.load_16(12, 8), 0x1234);
+    assert_eq!(upscaled.load_16(15, 11), 0x1234);
+    assert_eq!(upscaled.load_16(16, 11), 0x0);
+
+    // Copy the halfword from CPU to VRAM at (10, 10).
+    for val in [0xa0000000, 10 | 10 << 16, 1 | 1 << 16, 0x5678] {
+        gpu.store::<u32>(&mut schedule, 0, val);
+    }
+
+    assert_eq!(gpu.vram.load_16(10, 10), 0x5678);
+    assert_eq!(gpu.upscaled_vram().unwrap().load_16(43, 43), 0x5678);
+
+    // Draw a 2x2 white rectangle at (20, 20), and copy it to (40, 20).
+    for val in [0xe3000000, 0xe4000000 | 63 | 63 << 10, 0x60ffffff, 20 | 20 << 16, 2 | 2 << 16] {
+        gpu.store::<u32>(&mut schedule, 0, val);
+    }
+    for val in [0x80000000, 20 | 20 << 16, 40 | 20 << 16, 2 | 2 << 16] {
+        gpu.store::<u32>(&mut schedule, 0, val);
+    }
+
+    // Run the GPU until it's done with the copy.
+    run_gpu_until(&mut gpu, &mut schedule, |gpu| gpu.is_idle() && gpu.fifo.is_empty());
+
+    // Every pixel of the upscaled VRAM should match native VRAM, since nothing drawn has
+    // any detail below a native pixel.
+    let upscaled = gpu.upscaled_vram().unwrap();
+    for y in 0..64 {
+        for x in 0..64 {
+            let native = gpu.vram.load_16(x, y);
+            for (dx, dy) in [(0, 0), (3, 0), (0, 3), (3, 3)] {
+                assert_eq!(upscaled.load_16(x * 4 + dx, y * 4 + dy), native, "({x}, {y})");
+            }
+        }
+    }
+    assert_eq!(gpu.vram.load_16(41, 21), 0x7fff);
+
+    // Draw a white triangle with a diagonal edge from (140, 100) to (100, 140). It cuts through
+    // the native pixels along it, so only part of each of them is drawn in upscaled VRAM.
+    let triangle = [0x20ffffff, 100 | 100 << 16, 140 | 100 << 16, 100 | 140 << 16];
+    for val in [0xe4000000 | 1023 | 511 << 10].into_iter().chain(triangle) {
+        gpu.store::<u32>(&mut schedule, 0, val);
+    }
+
+    run_gpu_until(&mut gpu, &mut schedule, |gpu| gpu.is_idle() && gpu.fifo.is_empty());
+
+    assert_eq!(gpu.vram.load_16(119, 120), 0x7fff);
+    assert_eq!(gpu.vram.load_16(121, 120), 0x0);
+
+    let upscaled = gpu.upscaled_vram().unwrap();
+    let block = |x: i32, y: i32| -> Vec<u16> {
+        (0..16).map(|i| upscaled.load_16(x * 4 + i % 4, y * 4 + i / 4)).collect()
+    };
+
+    // The block of the native pixel (119, 120) is drawn above the edge, but not below it.
+    assert_eq!(upscaled.load_16(476, 480), 0x7fff);
+    assert_eq!(upscaled.load_16(479, 483), 0x0);
+
+    // Every native pixel along the edge is split the same way.
+    for y in 100..140 {
+        let block = block(139 - (y - 100), y);
+        assert!(block.contains(&0x7fff) && block.contains(&0x0), "({}, {y})", 139 - (y - 100));
+    }
+}
@@ -34,9 +34,9 @@ impl Vram {
         for y in 0..512 {
             for x in 0..1024 {
                 let color = Color::from_u16(self.load_16(x, y));
-                img.push(color.r << 3);
-                img.push(color.g << 3);
-                img.push(color.b << 3);
+                img.push(color.r);
+                img.push(color.g);
+                img.push(color.b);
                 img.push(255);
             }
         }
@@ -1,5 +1,4 @@
 #![feature(
-    int_roundings,
     portable_simd,
     let_else,
     binary_heap_retain,
@@ -27,11 +26,17 @@ pub mod cpu;
 pub mod time;
 pub mod debug;
 pub mod dump;
+pub mod trace;
+pub mod search;
+pub mod cheat;
+pub mod profile;
+pub mod record;
 
 use splst_util::Exe;
 use io_port::{pad, memcard};
 use schedule::Schedule;
 use cpu::irq::IrqState;
+use cheat::Cheats;
 
 pub use cdrom::CdRom;
 pub use time::{SysTime, Timestamp};
@@ -39,7 +44,7 @@ pub use bus::{dma::Dma, Bus};
 pub use timer::Timers;
 pub use gpu::Gpu;
 pub use cpu::Cpu;
-pub use gpu::Vram;
+pub use gpu::{Vram, UpscaledVram, DisplayInfo};
 pub use bus::bios::Bios;
 pub use cdrom::Disc;
 pub use io_port::IoPort;
@@ -60,8 +65,11 @@ impl System {
         disc: Rc<RefCell<Disc>>,
         gamepads: Rc<RefCell<pad::GamePads>>,
         memcards: Rc<RefCell<memcard::MemCards>>,
+        cheats: Rc<RefCell<Cheats>>,
     ) -> Self {
-        Self { cpu: Cpu::new(bios, video_output, audio_output, disc, gamepads, memcards) }
+        Self {
+            cpu: Cpu::new(bios, video_output, audio_output, disc, gamepads, memcards, cheats),
+        }
     }
 
     /// Load executable file into RAM and path the BIOS to run `exe`.
@@ -138,6 +146,10 @@ impl System {
     pub fn bus(&self) -> &Bus {
         &self.cpu.bus
     }
+
+    pub fn bus_mut(&mut self) -> &mut Bus {
+        &mut self.cpu.bus
+    }
     
     pub fn dma(&self) -> &Dma {
         &self.cpu.bus.dma
@@ -151,6 +163,10 @@ impl System {
         &self.cpu.bus.gpu
     }
 
+    pub fn gpu_mut(&mut self) -> &mut Gpu {
+        &mut self.cpu.bus.gpu
+    }
+
     pub fn schedule(&self) -> &Schedule {
         &self.cpu.bus.schedule
     }
@@ -178,11 +194,19 @@ pub enum StopReason {
 }
 
 pub trait VideoOutput {
-    fn send_frame(&mut self, vram_start: (u32, u32), vram_data: &[u16; 512 * 1024]);
+    /// Called at the start of vblank with the frame just displayed. `upscaled` is set if the GPU
+    /// renders at a higher resolution, in which case the display area should be shown from it
+    /// unless it's in 24-bit color depth.
+    fn send_frame(
+        &mut self,
+        display: &DisplayInfo,
+        vram_data: &[u16; 512 * 1024],
+        upscaled: Option<&UpscaledVram>,
+    );
 }
 
 impl VideoOutput for () {
-    fn send_frame(&mut self, _: (u32, u32), _: &[u16; 512 * 1024]) {}
+    fn send_frame(&mut self, _: &DisplayInfo, _: &[u16; 512 * 1024], _: Option<&UpscaledVram>) {}
 }
 
 pub trait AudioOutput {
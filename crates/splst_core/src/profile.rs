@@ -0,0 +1,487 @@
+//! Function level profiler.
+//!
+//! [`Profiler`] is a [`Debugger`] which keeps track of the call stack by watching for function
+//! calls (JAL, JALR, BLTZAL and BGEZAL) and returns, and attributes the elapsed CPU cycles,
+//! instruction cache misses and DMA stall time of each instruction to the call stack it was
+//! executed in. Exception handlers are treated as functions called from wherever the exception
+//! occurred.
+//!
+//! Functions are named using [`Symbols`] if any are loaded, otherwise by their address.
+
+use crate::cpu::{Cpu, Opcode};
+use crate::debug::Debugger;
+
+use thiserror::Error;
+
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, Write};
+use std::ops::AddAssign;
+use std::path::Path;
+use std::fs;
+
+#[derive(Error, Debug)]
+pub enum SymbolError {
+    #[error("failed to load symbols: {0}")]
+    IoError(#[from] io::Error),
+    #[error("invalid symbol file:{line}: expected `<address> <name>`")]
+    InvalidLine { line: usize },
+}
+
+/// Function names loaded from a symbol file.
+///
+/// The symbol file has a symbol on each line of the form `<address> <name>`, where the address
+/// is in hex. Empty lines and lines starting with `#` are ignored.
+#[derive(Default)]
+pub struct Symbols(BTreeMap<u32, String>);
+
+impl Symbols {
+    pub fn parse(source: &str) -> Result<Self, SymbolError> {
+        let mut symbols = BTreeMap::new();
+
+        for (i, line) in source.lines().enumerate() {
+            let line = line.trim();
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let invalid = || SymbolError::InvalidLine { line: i + 1 };
+
+            let (addr, name) = line.split_once(char::is_whitespace).ok_or_else(invalid)?;
+            let addr = addr.trim_start_matches("0x");
+            let addr = u32::from_str_radix(addr, 16).map_err(|_| invalid())?;
+
+            symbols.insert(addr, name.trim().to_string());
+        }
+
+        Ok(Self(symbols))
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self, SymbolError> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Get the name of the function at `addr`. If there is no symbol at `addr`, it uses the
+    /// closest symbol before `addr` with an offset.
+    pub fn name(&self, addr: u32) -> String {
+        match self.0.range(..=addr).next_back() {
+            Some((sym, name)) if *sym == addr => name.clone(),
+            Some((sym, name)) => format!("{name}+{:x}", addr - sym),
+            None => format!("{addr:08x}"),
+        }
+    }
+}
+
+/// The cost of running some code.
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Cost {
+    pub instructions: u64,
+    pub cycles: u64,
+    pub icache_misses: u64,
+    /// CPU cycles where the CPU was halted by the DMA.
+    pub dma_stall: u64,
+}
+
+impl AddAssign for Cost {
+    fn add_assign(&mut self, other: Self) {
+        self.instructions += other.instructions;
+        self.cycles += other.cycles;
+        self.icache_misses += other.icache_misses;
+        self.dma_stall += other.dma_stall;
+    }
+}
+
+/// The profile of a single function.
+#[derive(Clone, Debug)]
+pub struct FunctionProfile {
+    pub addr: u32,
+    pub name: String,
+    /// The cost of the function itself, not including functions it calls.
+    pub exclusive: Cost,
+    /// The cost of the function including all functions it calls.
+    pub inclusive: Cost,
+}
+
+/// A node in the call tree. Each node represents a unique call stack.
+struct Node {
+    func: u32,
+    parent: Option<usize>,
+    children: HashMap<u32, usize>,
+    cost: Cost,
+}
+
+struct Frame {
+    /// The node of the function.
+    node: usize,
+    /// The return address of the function. `None` for exception handlers.
+    ret: Option<u32>,
+}
+
+/// The exception vectors.
+const EXCEPTION_VECTORS: [u32; 2] = [0x8000_0080, 0xbfc0_0180];
+
+/// The address used for the root of the call tree, which all code not called from a detected
+/// function is attributed to.
+const ROOT: u32 = 0xffff_ffff;
+
+pub struct Profiler {
+    symbols: Symbols,
+    /// Call tree. The first node is the root.
+    nodes: Vec<Node>,
+    frames: Vec<Frame>,
+    /// The return address and instructions left until entering the function, if a call
+    /// instruction has been executed. It's used to skip the delay slot.
+    pending_call: Option<(u32, u8)>,
+    /// Set when executing RFE, which means that the exception handler returns after the next
+    /// instruction.
+    pending_rfe: bool,
+    /// Timestamp, icache misses and DMA stall of the last instruction.
+    last: Option<(u64, u64, u64)>,
+}
+
+impl Profiler {
+    pub fn new(symbols: Symbols) -> Self {
+        Self {
+            symbols,
+            nodes: vec![Node {
+                func: ROOT,
+                parent: None,
+                children: HashMap::new(),
+                cost: Cost::default(),
+            }],
+            frames: Vec::new(),
+            pending_call: None,
+            pending_rfe: false,
+            last: None,
+        }
+    }
+
+    pub fn symbols(&self) -> &Symbols {
+        &self.symbols
+    }
+
+    pub fn set_symbols(&mut self, symbols: Symbols) {
+        self.symbols = symbols;
+    }
+
+    /// Clear all samples.
+    pub fn reset(&mut self) {
+        let symbols = std::mem::take(&mut self.symbols);
+        *self = Self::new(symbols);
+    }
+
+    /// Should be called when the profiler stops receiving instructions for a while, so that the
+    /// time in between isn't attributed to anything. The call stack is lost, so code run after
+    /// resuming is attributed to the root until the next call.
+    pub fn pause(&mut self) {
+        self.frames.clear();
+        self.pending_call = None;
+        self.pending_rfe = false;
+        self.last = None;
+    }
+
+    /// The total cost of everything profiled.
+    pub fn total(&self) -> Cost {
+        let mut total = Cost::default();
+        for node in &self.nodes {
+            total += node.cost;
+        }
+        total
+    }
+
+    fn current_node(&self) -> usize {
+        self.frames.last().map(|frame| frame.node).unwrap_or(0)
+    }
+
+    fn push_frame(&mut self, func: u32, ret: Option<u32>) {
+        let parent = self.current_node();
+        let next = self.nodes.len();
+
+        let node = *self.nodes[parent].children.entry(func).or_insert(next);
+
+        if node == next {
+            self.nodes.push(Node {
+                func,
+                parent: Some(parent),
+                children: HashMap::new(),
+                cost: Cost::default(),
+            });
+        }
+
+        self.frames.push(Frame { node, ret });
+    }
+
+    fn func_name(&self, func: u32) -> String {
+        if func == ROOT {
+            "root".to_string()
+        } else {
+            self.symbols.name(func)
+        }
+    }
+
+    /// Get the functions in the call stack of `node` from the outermost function.
+    fn stack(&self, mut node: usize) -> Vec<u32> {
+        let mut stack = Vec::new();
+        while let Some(parent) = self.nodes[node].parent {
+            stack.push(self.nodes[node].func);
+            node = parent;
+        }
+        stack.reverse();
+        stack
+    }
+
+    /// Write the profile as folded stacks, which can be used to generate flame graphs. Each line
+    /// is a call stack separated by `;` followed by the amount of cycles spent in it.
+    pub fn write_folded(&self, out: &mut impl Write) -> io::Result<()> {
+        for (i, node) in self.nodes.iter().enumerate() {
+            if node.cost.cycles == 0 {
+                continue;
+            }
+
+            let names: Vec<String> = std::iter::once(ROOT)
+                .chain(self.stack(i))
+                .map(|func| self.func_name(func))
+                .collect();
+
+            writeln!(out, "{} {}", names.join(";"), node.cost.cycles)?;
+        }
+
+        Ok(())
+    }
+
+    /// Get the profile of each function, sorted by exclusive cycles.
+    pub fn functions(&self) -> Vec<FunctionProfile> {
+        let mut funcs: HashMap<u32, (Cost, Cost)> = HashMap::new();
+
+        for (i, node) in self.nodes.iter().enumerate() {
+            funcs.entry(node.func).or_default().0 += node.cost;
+
+            // Add the cost to the inclusive cost of each function in the stack, but only once
+            // for recursive functions.
+            let mut stack = self.stack(i);
+            stack.sort_unstable();
+            stack.dedup();
+
+            for func in stack {
+                funcs.entry(func).or_default().1 += node.cost;
+            }
+        }
+
+        let mut funcs: Vec<_> = funcs
+            .into_iter()
+            .map(|(addr, (exclusive, inclusive))| FunctionProfile {
+                addr,
+                name: self.func_name(addr),
+                exclusive,
+                inclusive: if addr == ROOT { self.total() } else { inclusive },
+            })
+            .collect();
+
+        funcs.sort_unstable_by_key(|func| std::cmp::Reverse(func.exclusive.cycles));
+        funcs
+    }
+}
+
+impl Debugger for Profiler {
+    fn instruction(&mut self, cpu: &Cpu, addr: u32, op: Opcode) {
+        let now = cpu.bus.schedule.now().time_since_startup().as_cpu_cycles();
+        let icache_misses = cpu.icache_misses();
+        let dma_stall = cpu.bus.dma.stall_time().as_cpu_cycles();
+
+        // Attribute the cost since the last instruction to the stack it was run in.
+        if let Some((last_now, last_misses, last_stall)) = self.last {
+            let node = self.current_node();
+            self.nodes[node].cost += Cost {
+                instructions: 1,
+                cycles: now.saturating_sub(last_now),
+                icache_misses: icache_misses.saturating_sub(last_misses),
+                dma_stall: dma_stall.saturating_sub(last_stall),
+            };
+        }
+
+        self.last = Some((now, icache_misses, dma_stall));
+
+        if self.pending_rfe {
+            self.pending_rfe = false;
+            if let Some(idx) = self.frames.iter().rposition(|frame| frame.ret.is_none()) {
+                self.frames.truncate(idx);
+            }
+        }
+
+        if EXCEPTION_VECTORS.contains(&addr) {
+            self.pending_call = None;
+            self.push_frame(addr, None);
+        } else if let Some(idx) = self.frames.iter().rposition(|frame| frame.ret == Some(addr)) {
+            // Returned from the function, or from a function further up the stack.
+            self.frames.truncate(idx);
+        }
+
+        match self.pending_call {
+            Some((ret, 0)) => {
+                self.pending_call = None;
+                self.push_frame(addr, Some(ret));
+            }
+            Some((ret, left)) => self.pending_call = Some((ret, left - 1)),
+            None => (),
+        }
+
+        if op.is_call() {
+            // The function is entered after the delay slot.
+            self.pending_call = Some((addr.wrapping_add(8), 1));
+        }
+
+        if op.op() == 0x10 && op.cop_op() == 0x10 {
+            self.pending_rfe = true;
+        }
+    }
+
+    fn should_break(&mut self) -> bool {
+        false
+    }
+}
+
+#[test]
+fn symbols() {
+    let symbols = Symbols::parse("# comment\n80010000 main\n0x80010100 update\n").unwrap();
+
+    assert_eq!(symbols.len(), 2);
+    assert_eq!(symbols.name(0x80010000), "main");
+    assert_eq!(symbols.name(0x80010108), "update+8");
+    assert_eq!(symbols.name(0x80000000), "80000000");
+
+    assert!(Symbols::parse("80010000").is_err());
+}
+
+#[test]
+fn call_tree() {
+    let mut profiler = Profiler::new(Symbols::parse("1000 main\n2000 fib\n").unwrap());
+
+    let add_cost = |profiler: &mut Profiler, cycles| {
+        let node = profiler.current_node();
+        profiler.nodes[node].cost += Cost { instructions: 1, cycles, ..Default::default() };
+    };
+
+    // main -> fib -> fib.
+    profiler.push_frame(0x1000, Some(0));
+    add_cost(&mut profiler, 10);
+    profiler.push_frame(0x2000, Some(0x1008));
+    add_cost(&mut profiler, 5);
+    profiler.push_frame(0x2000, Some(0x2008));
+    add_cost(&mut profiler, 3);
+
+    let funcs = profiler.functions();
+
+    let main = funcs.iter().find(|func| func.name == "main").unwrap();
+    assert_eq!(main.exclusive.cycles, 10);
+    assert_eq!(main.inclusive.cycles, 18);
+
+    // Recursive calls should only be counted once.
+    let fib = funcs.iter().find(|func| func.name == "fib").unwrap();
+    assert_eq!(fib.exclusive.cycles, 8);
+    assert_eq!(fib.inclusive.cycles, 8);
+
+    let mut folded = Vec::new();
+    profiler.write_folded(&mut folded).unwrap();
+
+    assert_eq!(
+        String::from_utf8(folded).unwrap(),
+        "root;main 10\nroot;main;fib 5\nroot;main;fib;fib 3\n",
+    );
+}
+
+#[test]
+fn profile_code() {
+    use crate::bus::bios::Bios;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    let (code, _) = splst_asm::assemble(r#"
+            j    main
+            nop
+        leaf:
+            jr   $ra
+            addiu $t1, $t1, 1
+        outer:
+            move $s0, $ra
+            jal  leaf
+            nop
+            syscall 0
+            move $ra, $s0
+            jr   $ra
+            nop
+        main:
+            jal  leaf
+            nop
+            la   $t0, outer
+            jalr $ra, $t0
+            nop
+            syscall 0
+        end:
+            j    end
+            nop
+    "#, 0xbfc00000).unwrap();
+
+    let mut cpu = crate::Cpu::new(
+        Bios::from_code(0xbfc00000, &code),
+        Rc::new(RefCell::new(())),
+        Rc::new(RefCell::new(())),
+        Default::default(),
+        Default::default(),
+        Default::default(),
+        Default::default(),
+    );
+
+    // The exception handler returns to the instruction after the syscall. It's made up of
+    // `mfc0 $k0, $14`, `nop`, `addiu $k0, $k0, 4`, `jr $k0` and `rfe`.
+    let handler = [0x401a7000, 0x0, 0x275a0004, 0x03400008, 0x42000010];
+    for (i, op) in handler.into_iter().enumerate() {
+        cpu.bus.ram.store::<u32>(0x80 + i as u32 * 4, op);
+    }
+
+    let symbols = "bfc00008 leaf\nbfc00010 outer\n80000080 exception\n";
+    let mut profiler = Profiler::new(Symbols::parse(symbols).unwrap());
+
+    for _ in 0..40 {
+        cpu.step(&mut profiler);
+    }
+
+    let funcs = profiler.functions();
+    let func = |name: &str| funcs.iter().find(|func| func.name == name).unwrap().clone();
+
+    // The delay slot of `jr $ra` is part of the function returning, and the delay slot of the
+    // call is part of the caller.
+    assert_eq!(func("leaf").exclusive.instructions, 4);
+    assert_eq!(func("leaf").inclusive.instructions, 4);
+
+    // The handler is left after `rfe`, so the rest of `outer` isn't counted as part of it.
+    assert_eq!(func("outer").exclusive.instructions, 7);
+    assert_eq!(func("outer").inclusive.instructions, 7 + 2 + 5);
+    assert_eq!(func("exception").exclusive.instructions, 10);
+
+    let mut folded = Vec::new();
+    profiler.write_folded(&mut folded).unwrap();
+
+    let mut stacks: Vec<_> = String::from_utf8(folded)
+        .unwrap()
+        .lines()
+        .map(|line| line.rsplit_once(' ').unwrap().0.to_string())
+        .collect();
+    stacks.sort();
+
+    assert_eq!(stacks, [
+        "root",
+        "root;exception",
+        "root;leaf",
+        "root;outer",
+        "root;outer;exception",
+        "root;outer;leaf",
+    ]);
+}
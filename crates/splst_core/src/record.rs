@@ -0,0 +1,289 @@
+//! Recording video and audio to files.
+//!
+//! [`Recorder`] is both a [`VideoOutput`] and an [`AudioOutput`], so it can be given to
+//! [`System::new`](crate::System::new) directly, or be fed by the outputs of a frontend. Video is
+//! written as an uncompressed YUV4MPEG2 stream with 4:4:4 chroma and audio as a 16-bit stereo WAV
+//! file.
+//!
+//! The two are kept in sync by emulated time. The SPU sends exactly 44100 samples for each
+//! emulated second, so the audio samples received are used as the clock. Each frame is written as
+//! many times as needed to fill the time up to where it was sent, or dropped if the video is
+//! ahead, so the video always has a constant frame rate.
+//!
+//! Frames are run through a [`Filter`] before being scaled to the size of the video.
+
+use crate::gpu::{self, DisplayInfo, Filter, Image, VideoMode};
+use crate::{AudioOutput, UpscaledVram, VideoOutput, Vram};
+
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+/// The sample rate of the SPU.
+const SAMPLE_RATE: u64 = 44100;
+
+/// The size of the WAV header, which comes before the samples.
+const WAV_HEADER_SIZE: u32 = 44;
+
+/// The frame rate of a video mode as a fraction. It's the GPU clock rate divided by the number of
+/// GPU cycles per frame when not interlaced.
+fn frame_rate(mode: VideoMode) -> (u64, u64) {
+    match mode {
+        // 53693181.818 Hz and 263 lines of 3413 cycles.
+        VideoMode::Ntsc => (590_625_000, 11 * 263 * 3413),
+        // 53203425 Hz and 314 lines of 3405 cycles.
+        VideoMode::Pal => (53_203_425, 314 * 3405),
+    }
+}
+
+/// Records video and audio to files. See the [module documentation](self).
+pub struct Recorder<V: Write = BufWriter<File>, A: Write + Seek = BufWriter<File>> {
+    video: V,
+    audio: A,
+    /// The size of each video frame. The display area is scaled to fit.
+    width: u32,
+    height: u32,
+    filter: Filter,
+    /// The frame rate of the video, which is decided by the video mode of the first frame.
+    frame_rate: Option<(u64, u64)>,
+    /// The last frame in YUV planes.
+    frame: Vec<u8>,
+    frames: u64,
+    samples: u64,
+    /// The first error when writing. Nothing more is written after an error.
+    error: Option<io::Error>,
+}
+
+impl Recorder {
+    /// Create a recorder writing video to `video` and audio to `audio`, replacing the files if
+    /// they exist.
+    pub fn create(
+        video: &Path,
+        audio: &Path,
+        width: u32,
+        height: u32,
+    ) -> io::Result<Self> {
+        let video = BufWriter::new(File::create(video)?);
+        let audio = BufWriter::new(File::create(audio)?);
+        Self::new(video, audio, width, height)
+    }
+}
+
+impl<V: Write, A: Write + Seek> Recorder<V, A> {
+    /// Create a recorder writing frames of `width` by `height` pixels.
+    pub fn new(video: V, mut audio: A, width: u32, height: u32) -> io::Result<Self> {
+        assert!(width > 0 && height > 0, "empty video frames");
+
+        write_wav_header(&mut audio, 0)?;
+
+        Ok(Self {
+            video,
+            audio,
+            width,
+            height,
+            filter: Filter::default(),
+            frame_rate: None,
+            frame: Vec::new(),
+            frames: 0,
+            samples: 0,
+            error: None,
+        })
+    }
+
+    /// Set the filter run on each frame before it's scaled to the size of the video.
+    pub fn set_filter(&mut self, filter: Filter) {
+        self.filter = filter;
+    }
+
+    /// The number of frames written, including repeated frames.
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// The number of stereo samples written.
+    pub fn samples(&self) -> u64 {
+        self.samples
+    }
+
+    /// Stop recording and fill in the size of the WAV file. Returns the first error which
+    /// happened while recording, if any.
+    pub fn finish(&mut self) -> io::Result<()> {
+        if let Some(err) = self.error.take() {
+            return Err(err);
+        }
+
+        let data_size = u32::try_from(self.samples * 4)
+            .ok()
+            .filter(|size| *size <= u32::MAX - (WAV_HEADER_SIZE - 8))
+            .ok_or_else(|| {
+                io::Error::new(io::ErrorKind::Other, "audio is too long for a WAV file")
+            })?;
+
+        self.audio.seek(SeekFrom::Start(0))?;
+        write_wav_header(&mut self.audio, data_size)?;
+        self.audio.seek(SeekFrom::End(0))?;
+        self.audio.flush()?;
+        self.video.flush()
+    }
+
+    fn write_frame(&mut self, display: &DisplayInfo, vram: &[u16; Vram::SIZE]) -> io::Result<()> {
+        let (num, den) = match self.frame_rate {
+            Some(rate) => rate,
+            None => {
+                let (num, den) = frame_rate(display.video_mode);
+                writeln!(
+                    self.video,
+                    "YUV4MPEG2 W{} H{} F{num}:{den} Ip A1:1 C444",
+                    self.width,
+                    self.height,
+                )?;
+                self.frame_rate = Some((num, den));
+                (num, den)
+            }
+        };
+
+        // The number of frames which should have been written once this frame is, if the first
+        // frame was sent at time zero.
+        let due = self.samples * num / (den * SAMPLE_RATE) + 1;
+
+        if self.frames >= due {
+            return Ok(());
+        }
+
+        let image = self.filter.apply(&gpu::display_image(vram, display));
+        self.frame = to_yuv(&image.resize(self.width, self.height));
+
+        while self.frames < due {
+            self.video.write_all(b"FRAME\n")?;
+            self.video.write_all(&self.frame)?;
+            self.frames += 1;
+        }
+
+        Ok(())
+    }
+}
+
+impl<V: Write, A: Write + Seek> VideoOutput for Recorder<V, A> {
+    fn send_frame(
+        &mut self,
+        display: &DisplayInfo,
+        vram_data: &[u16; 512 * 1024],
+        _: Option<&UpscaledVram>,
+    ) {
+        if self.error.is_none() {
+            self.error = self.write_frame(display, vram_data).err();
+        }
+    }
+}
+
+impl<V: Write, A: Write + Seek> AudioOutput for Recorder<V, A> {
+    fn send_audio(&mut self, samples: [i16; 2]) {
+        if self.error.is_some() {
+            return;
+        }
+        let [left, right] = samples.map(i16::to_le_bytes);
+        self.error = self.audio.write_all(&[left[0], left[1], right[0], right[1]]).err();
+        self.samples += 1;
+    }
+}
+
+/// Convert `image` to Y, U and V planes, using the BT.601 limited range.
+fn to_yuv(image: &Image) -> Vec<u8> {
+    let size = (image.width * image.height) as usize;
+    let mut yuv = vec![0; size * 3];
+
+    for (i, rgb) in image.data.chunks_exact(3).enumerate() {
+        let [r, g, b] = [0, 1, 2].map(|i| rgb[i] as i32);
+
+        yuv[i] = (16 + ((66 * r + 129 * g + 25 * b + 128) >> 8)) as u8;
+        yuv[size + i] = (128 + ((-38 * r - 74 * g + 112 * b + 128) >> 8)) as u8;
+        yuv[size * 2 + i] = (128 + ((112 * r - 94 * g - 18 * b + 128) >> 8)) as u8;
+    }
+
+    yuv
+}
+
+fn write_wav_header(out: &mut impl Write, data_size: u32) -> io::Result<()> {
+    let channels = 2_u16;
+    let bits = 16_u16;
+    let block_align = channels * bits / 8;
+
+    out.write_all(b"RIFF")?;
+    out.write_all(&(WAV_HEADER_SIZE - 8 + data_size).to_le_bytes())?;
+    out.write_all(b"WAVEfmt ")?;
+    out.write_all(&16_u32.to_le_bytes())?;
+    // PCM format.
+    out.write_all(&1_u16.to_le_bytes())?;
+    out.write_all(&channels.to_le_bytes())?;
+    out.write_all(&(SAMPLE_RATE as u32).to_le_bytes())?;
+    out.write_all(&(SAMPLE_RATE as u32 * block_align as u32).to_le_bytes())?;
+    out.write_all(&block_align.to_le_bytes())?;
+    out.write_all(&bits.to_le_bytes())?;
+    out.write_all(b"data")?;
+    out.write_all(&data_size.to_le_bytes())
+}
+
+#[test]
+fn frames_follow_audio() {
+    use crate::gpu::{ColorDepth, HorizontalRes, InterlaceField, VerticalRes};
+    use std::io::Cursor;
+
+    let display = DisplayInfo {
+        vram_x_start: 0,
+        vram_y_start: 0,
+        horizontal_res: HorizontalRes::P320,
+        vertical_res: VerticalRes::P240,
+        x_range: (0, 0),
+        y_range: (0, 0),
+        color_depth: ColorDepth::B15,
+        interlaced: false,
+        field: InterlaceField::Top,
+        video_mode: VideoMode::Pal,
+        enabled: true,
+    };
+
+    let vram = Box::new(Vram::new());
+
+    let mut recorder = Recorder::new(Vec::new(), Cursor::new(Vec::new()), 4, 2).unwrap();
+
+    // A PAL frame is 1069170 / 53203425 seconds, which is about 886.2 samples.
+    let send_samples = |recorder: &mut Recorder<_, _>, count: usize| {
+        for _ in 0..count {
+            recorder.send_audio([1, -1]);
+        }
+    };
+
+    recorder.send_frame(&display, &vram.data, None);
+    send_samples(&mut recorder, 887);
+    recorder.send_frame(&display, &vram.data, None);
+
+    // The video is ahead, so this frame is dropped.
+    recorder.send_frame(&display, &vram.data, None);
+    assert_eq!(recorder.frames(), 2);
+
+    // Four frames are due, so the last is repeated.
+    send_samples(&mut recorder, 886 * 2);
+    recorder.send_frame(&display, &vram.data, None);
+    assert_eq!(recorder.frames(), 4);
+
+    recorder.finish().unwrap();
+
+    let header = b"YUV4MPEG2 W4 H2 F53203425:1069170 Ip A1:1 C444\n";
+    let frame_size = b"FRAME\n".len() + 4 * 2 * 3;
+
+    assert_eq!(&recorder.video[..header.len()], header);
+    assert_eq!(recorder.video.len(), header.len() + frame_size * 4);
+
+    // Black in limited range.
+    let frame = &recorder.video[header.len() + 6..header.len() + frame_size];
+    assert_eq!(&frame[..8], &[16; 8]);
+    assert_eq!(&frame[8..], &[128; 16]);
+
+    let wav = recorder.audio.into_inner();
+    let data_size = 4 * (887 + 886 * 2);
+
+    assert_eq!(wav.len(), WAV_HEADER_SIZE as usize + data_size);
+    assert_eq!(&wav[4..8], &(36 + data_size as u32).to_le_bytes());
+    assert_eq!(&wav[40..44], &(data_size as u32).to_le_bytes());
+    assert_eq!(&wav[44..48], &[1, 0, 0xff, 0xff]);
+}
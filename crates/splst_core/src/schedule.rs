@@ -221,6 +221,11 @@ impl Schedule {
     pub(crate) fn skip_to(&mut self, time: Timestamp) {
         self.now = self.now.max(time);
     }
+
+    /// Skip to when the next event is ready. There must be a pending event.
+    pub(crate) fn skip_to_next_event(&mut self) {
+        self.skip_to(self.next_event);
+    }
 }
 
 /// A unique ID for each event. This can be used to modify, cancel or run the event early.
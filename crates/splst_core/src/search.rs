@@ -0,0 +1,259 @@
+//! Searching main RAM and the scratchpad for values.
+//!
+//! Works by taking snapshots of memory and narrowing down a list of candidate addresses by
+//! comparing the value at each address with the value in the last snapshot. This is useful to
+//! find where a game stores things like health or the position of the player.
+
+use crate::bus::{Bus, BusMap};
+use crate::bus::ram::Ram;
+use crate::bus::scratchpad::ScratchPad;
+
+use std::fmt;
+
+/// The type of values to search for.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ValueType {
+    U8,
+    I8,
+    U16,
+    I16,
+    U32,
+    I32,
+}
+
+impl ValueType {
+    pub const ITEMS: [Self; 6] = [
+        Self::U8,
+        Self::I8,
+        Self::U16,
+        Self::I16,
+        Self::U32,
+        Self::I32,
+    ];
+
+    /// The width of the value in bytes.
+    pub fn width(self) -> u32 {
+        match self {
+            ValueType::U8 | ValueType::I8 => 1,
+            ValueType::U16 | ValueType::I16 => 2,
+            ValueType::U32 | ValueType::I32 => 4,
+        }
+    }
+
+    pub fn is_signed(self) -> bool {
+        matches!(self, ValueType::I8 | ValueType::I16 | ValueType::I32)
+    }
+
+    /// Decode a value of this type from the start of `bytes`.
+    fn decode(self, bytes: &[u8]) -> i64 {
+        match self {
+            ValueType::U8 => bytes[0] as i64,
+            ValueType::I8 => bytes[0] as i8 as i64,
+            ValueType::U16 => u16::from_le_bytes([bytes[0], bytes[1]]) as i64,
+            ValueType::I16 => i16::from_le_bytes([bytes[0], bytes[1]]) as i64,
+            ValueType::U32 => {
+                u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as i64
+            }
+            ValueType::I32 => {
+                i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as i64
+            }
+        }
+    }
+
+    /// Convert `val` into the raw bits stored in memory.
+    pub fn encode(self, val: i64) -> u32 {
+        match self.width() {
+            1 => val as u8 as u32,
+            2 => val as u16 as u32,
+            _ => val as u32,
+        }
+    }
+}
+
+impl fmt::Display for ValueType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            ValueType::U8 => "u8",
+            ValueType::I8 => "i8",
+            ValueType::U16 => "u16",
+            ValueType::I16 => "i16",
+            ValueType::U32 => "u32",
+            ValueType::I32 => "i32",
+        };
+        f.write_str(name)
+    }
+}
+
+/// How to compare the current value with the value in the last snapshot.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Comparison {
+    /// The value hasn't changed.
+    Equal,
+    /// The value has changed.
+    Changed,
+    Increased,
+    Decreased,
+    /// The value has increased by exactly N.
+    IncreasedBy(i64),
+    /// The value has decreased by exactly N.
+    DecreasedBy(i64),
+    /// The value is currently equal to a specific value.
+    Value(i64),
+}
+
+impl Comparison {
+    fn matches(self, old: i64, new: i64) -> bool {
+        match self {
+            Comparison::Equal => new == old,
+            Comparison::Changed => new != old,
+            Comparison::Increased => new > old,
+            Comparison::Decreased => new < old,
+            Comparison::IncreasedBy(n) => new - old == n,
+            Comparison::DecreasedBy(n) => old - new == n,
+            Comparison::Value(val) => new == val,
+        }
+    }
+}
+
+impl fmt::Display for Comparison {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Comparison::Equal => f.write_str("Equal"),
+            Comparison::Changed => f.write_str("Changed"),
+            Comparison::Increased => f.write_str("Increased"),
+            Comparison::Decreased => f.write_str("Decreased"),
+            Comparison::IncreasedBy(n) => write!(f, "Increased by {n}"),
+            Comparison::DecreasedBy(n) => write!(f, "Decreased by {n}"),
+            Comparison::Value(val) => write!(f, "Value {val}"),
+        }
+    }
+}
+
+/// A copy of main RAM and the scratchpad.
+struct Snapshot {
+    ram: Box<[u8]>,
+    scratchpad: Box<[u8]>,
+}
+
+impl Snapshot {
+    fn take(bus: &Bus) -> Self {
+        Self {
+            ram: bus.ram.data().into(),
+            scratchpad: bus.scratchpad.data().into(),
+        }
+    }
+
+    /// Get the bytes starting at bus address `addr`.
+    fn bytes(&self, addr: u32) -> &[u8] {
+        match addr {
+            Ram::BUS_BEGIN..=Ram::BUS_END => &self.ram[addr as usize..],
+            ScratchPad::BUS_BEGIN..=ScratchPad::BUS_END => {
+                &self.scratchpad[(addr - ScratchPad::BUS_BEGIN) as usize..]
+            }
+            _ => unreachable!("address {addr:08x} not in snapshot"),
+        }
+    }
+}
+
+pub struct MemSearch {
+    ty: ValueType,
+    snapshot: Snapshot,
+    /// The bus addresses of the values that still match all comparisons.
+    candidates: Vec<u32>,
+}
+
+impl MemSearch {
+    /// Start a new search for values of type `ty`. All aligned addresses in main RAM and the
+    /// scratchpad starts out as candidates.
+    pub fn new(bus: &Bus, ty: ValueType) -> Self {
+        let width = ty.width() as usize;
+
+        let ram = (Ram::BUS_BEGIN..=Ram::BUS_END).step_by(width);
+        let scratchpad = (ScratchPad::BUS_BEGIN..=ScratchPad::BUS_END).step_by(width);
+
+        Self {
+            ty,
+            snapshot: Snapshot::take(bus),
+            candidates: ram.chain(scratchpad).collect(),
+        }
+    }
+
+    pub fn value_type(&self) -> ValueType {
+        self.ty
+    }
+
+    /// Remove all candidates that doesn't match `cmp` when comparing the current value on `bus`
+    /// with the last snapshot. Takes a new snapshot afterwards.
+    pub fn filter(&mut self, bus: &Bus, cmp: Comparison) {
+        let current = Snapshot::take(bus);
+
+        let ty = self.ty;
+        let old = &self.snapshot;
+
+        self.candidates.retain(|addr| {
+            let old = ty.decode(old.bytes(*addr));
+            let new = ty.decode(current.bytes(*addr));
+            cmp.matches(old, new)
+        });
+
+        self.snapshot = current;
+    }
+
+    /// The addresses that still match.
+    pub fn candidates(&self) -> &[u32] {
+        &self.candidates
+    }
+
+    /// The value of candidate `addr` in the last snapshot.
+    pub fn snapshot_value(&self, addr: u32) -> i64 {
+        self.ty.decode(self.snapshot.bytes(addr))
+    }
+
+    /// The current value of candidate `addr`.
+    pub fn current_value(&self, bus: &Bus, addr: u32) -> i64 {
+        let val = bus.peek::<u32>(addr & !3).unwrap_or(0);
+        let bytes = val.to_le_bytes();
+        self.ty.decode(&bytes[(addr & 3) as usize..])
+    }
+}
+
+#[test]
+fn narrow_down() {
+    use crate::bus::bios::Bios;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    let mut bus = Bus::new(
+        Bios::from_code(0xbfc00000, &[]),
+        Rc::new(RefCell::new(())),
+        Rc::new(RefCell::new(())),
+        Default::default(),
+        Default::default(),
+        Default::default(),
+    );
+
+    bus.store::<u16>(0x100, 10);
+    bus.store::<u16>(0x1f800010, 10);
+
+    let mut search = MemSearch::new(&bus, ValueType::I16);
+
+    bus.store::<u16>(0x100, 7);
+    bus.store::<u16>(0x1f800010, 12);
+
+    search.filter(&bus, Comparison::Changed);
+    assert_eq!(search.candidates(), &[0x100, 0x1f800010]);
+
+    search.filter(&bus, Comparison::Equal);
+    assert_eq!(search.candidates().len(), 2);
+
+    bus.store::<u16>(0x100, (-1_i16) as u16);
+    bus.store::<u16>(0x1f800010, 15);
+
+    search.filter(&bus, Comparison::DecreasedBy(8));
+    assert_eq!(search.candidates(), &[0x100]);
+    assert_eq!(search.snapshot_value(0x100), -1);
+    assert_eq!(search.current_value(&bus, 0x100), -1);
+
+    search.filter(&bus, Comparison::Value(-1));
+    assert_eq!(search.candidates(), &[0x100]);
+}
@@ -227,7 +227,7 @@ impl dma::Channel for Spu {
         (hi << 16) | lo
     }
 
-    fn dma_store(&mut self, schedule: &mut Schedule, val: u32) {
+    fn dma_store(&mut self, schedule: &mut Schedule, val: u32, _: u32) {
         let lo = val.bit_range(00, 15) as u16;
         let hi = val.bit_range(16, 31) as u16;
         
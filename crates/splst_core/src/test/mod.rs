@@ -1,3 +1,28 @@
+use crate::gpu::Gpu;
+use crate::schedule::{Event, Schedule};
+use crate::timer::Timers;
+
+/// Run the events of the GPU until `done` returns true. Other events are dropped.
+pub fn run_gpu_until(gpu: &mut Gpu, schedule: &mut Schedule, mut done: impl FnMut(&Gpu) -> bool) {
+    let mut timers = Timers::new();
+    while !done(gpu) {
+        schedule.skip_to_next_event();
+        while let Some(event) = schedule.get_pending_event() {
+            if let Event::Gpu(callback) = event {
+                callback(gpu, schedule, &mut timers);
+            }
+        }
+    }
+}
+
+/// Store each word of `cmds` in GP0 and wait for the GPU to be idle before the next one.
+pub fn run_gp0(gpu: &mut Gpu, schedule: &mut Schedule, cmds: impl IntoIterator<Item = u32>) {
+    for cmd in cmds {
+        gpu.store::<u32>(schedule, 0, cmd);
+        run_gpu_until(gpu, schedule, Gpu::is_idle);
+    }
+}
+
 /*
 mod cpu;
 mod dma;
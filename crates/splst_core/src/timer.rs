@@ -5,6 +5,7 @@ use crate::bus::BusMap;
 use crate::schedule::{Schedule, Event, EventId};
 use crate::{SysTime, Timestamp};
 use crate::bus::{self, AddrUnit};
+use crate::gpu::{HorizontalRes, VideoMode};
 use crate::{dump, dump::Dumper};
 
 use std::fmt;
@@ -90,33 +91,69 @@ impl fmt::Display for SyncMode {
 #[derive(Clone, Copy, PartialEq, Eq)]
 pub enum ClockSource {
     SystemClock,
-    // Since the dot clock runs at different speed depending in video mode, i'm not quite sure which
-    // one this is. TODO: Test this.
+    /// The GPU dot clock. See [`DotClock`].
     DotClock,
     Hblank,
     SystemClockDiv8,
 }
 
 impl ClockSource {
-    fn time_to_ticks(self, time: SysTime) -> u64 {
+    fn time_to_ticks(self, time: SysTime, dot_clock: DotClock) -> u64 {
         match self {
             ClockSource::SystemClock => time.as_cpu_cycles(),
             ClockSource::SystemClockDiv8 => time.as_cpu_cycles() / 8,
-            ClockSource::DotClock => time.as_gpu_ntsc_cycles(),
+            ClockSource::DotClock => dot_clock.time_to_dots(time),
             ClockSource::Hblank => 0,
         }
     }
 
-    fn ticks_to_time(self, ticks: u64) -> SysTime {
+    fn ticks_to_time(self, ticks: u64, dot_clock: DotClock) -> SysTime {
         match self {
             ClockSource::SystemClock => SysTime::new(ticks),
             ClockSource::SystemClockDiv8 => SysTime::new(ticks * 8),
-            ClockSource::DotClock => SysTime::from_gpu_ntsc_cycles(ticks),
+            ClockSource::DotClock => dot_clock.dots_to_time(ticks),
             ClockSource::Hblank => SysTime::ZERO,
         }
     }
 }
 
+/// The GPU dot clock, which ticks once for each pixel drawn to the screen. It runs at the speed
+/// of the GPU clock divided by a number depending on the horizontal resolution, and the speed of
+/// the GPU clock depends on the video mode.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DotClock {
+    video_mode: VideoMode,
+    /// GPU cycles per dot.
+    divider: u64,
+}
+
+impl DotClock {
+    pub fn new(video_mode: VideoMode, horizontal_res: HorizontalRes) -> Self {
+        Self { video_mode, divider: horizontal_res.dot_clock_divider().into() }
+    }
+
+    fn time_to_dots(self, time: SysTime) -> u64 {
+        let cycles = match self.video_mode {
+            VideoMode::Ntsc => time.as_gpu_ntsc_cycles(),
+            VideoMode::Pal => time.as_gpu_pal_cycles(),
+        };
+        cycles / self.divider
+    }
+
+    fn dots_to_time(self, dots: u64) -> SysTime {
+        match self.video_mode {
+            VideoMode::Ntsc => SysTime::from_gpu_ntsc_cycles(dots * self.divider),
+            VideoMode::Pal => SysTime::from_gpu_pal_cycles(dots * self.divider),
+        }
+    }
+}
+
+impl Default for DotClock {
+    fn default() -> Self {
+        Self::new(VideoMode::Ntsc, HorizontalRes::P256)
+    }
+}
+
 impl fmt::Display for ClockSource {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{}", match *self {
@@ -213,15 +250,11 @@ impl Mode {
 
     fn store(&mut self, val: u16) {
         // Bit 10..12 are readonly.
-        self.0 |= val & 0x3ff;
+        self.0 = (self.0 & !0x3ff) | (val & 0x3ff);
 
         // In toggle mode, the irq master flag is always set after each store. When not in toggle
         // mode, it will more or less always be on.
         self.set_master_irq_flag(true);
-
-        if self.sync_enabled() {
-            warn!("Sync Enabled");
-        }
     }
 
     fn load(&mut self) -> u16 {
@@ -257,6 +290,8 @@ pub struct Timer {
     has_triggered: bool,
     /// The [`EventId`] for any update events, and the timestamp.
     next_update: Option<EventId>,
+    /// If the GPU is in the blank the timer syncs to. Hblank for timer 0 and vblank for timer 1.
+    in_blank: bool,
 }
 
 impl Timer {
@@ -268,6 +303,7 @@ impl Timer {
             target: 0,
             has_triggered: false,
             next_update: None,
+            in_blank: false,
         }
     }
 
@@ -311,10 +347,6 @@ impl Timer {
                 self.mode.store(val);
 
                 trace!("Timer {} mode set", self.id);
-
-                if self.mode.sync_enabled() {
-                    warn!("Sync enabled for timer {}", self.id);
-                }
             }
             8 => self.target = val,
             _ => unreachable!(),
@@ -382,8 +414,49 @@ impl Timer {
         }
     }
 
+    /// If the counter is paused by the sync mode.
+    fn is_paused(&self) -> bool {
+        if !self.mode.sync_enabled() {
+            return false;
+        }
+        match self.sync_mode() {
+            SyncMode::HblankPause | SyncMode::VblankPause => self.in_blank,
+            SyncMode::HblankResetAndRun | SyncMode::VblankResetAndRun => !self.in_blank,
+            // Sync gets disabled when entering blank.
+            SyncMode::HblankWait | SyncMode::VblankWait => true,
+            SyncMode::Stop => true,
+            SyncMode::HblankReset | SyncMode::VblankReset | SyncMode::FreeRun => false,
+        }
+    }
+
+    /// Called when entering the blank this timer is synced to.
+    fn blank_start(&mut self) {
+        self.in_blank = true;
+
+        if !self.mode.sync_enabled() {
+            return;
+        }
+
+        match self.sync_mode() {
+            SyncMode::HblankReset
+                | SyncMode::VblankReset
+                | SyncMode::HblankResetAndRun
+                | SyncMode::VblankResetAndRun => self.counter = 0,
+            // Switch to free run.
+            SyncMode::HblankWait | SyncMode::VblankWait => {
+                self.mode.0 = self.mode.0.set_bit(0, false);
+            }
+            _ => (),
+        }
+    }
+
+    /// Called when leaving the blank this timer is synced to.
+    fn blank_end(&mut self) {
+        self.in_blank = false;
+    }
+
     /// Choose the amount of time until this timer should run again.
-    fn predict_next_irq(&self) -> Option<SysTime> {
+    fn predict_next_irq(&self, dot_clock: DotClock) -> Option<SysTime> {
         if !self.mode.irq_on_overflow() && !self.mode.irq_on_target() {
             return None;
         }
@@ -396,7 +469,7 @@ impl Timer {
             return None;
         }
 
-        if self.mode.sync_enabled() && self.mode.sync_mode(self.id) == SyncMode::Stop {
+        if self.is_paused() {
             return None;
         }
 
@@ -414,14 +487,14 @@ impl Timer {
         };
 
         let ticks_left = target - self.counter;
-        Some(self.clock_source().ticks_to_time(ticks_left.into()))
+        Some(self.clock_source().ticks_to_time(ticks_left.into(), dot_clock))
     }
 
-    fn schedule_next_run(&mut self, schedule: &mut Schedule) {
-        self.next_update = self.predict_next_irq().map(|time| {
-            if let Some(id) = self.next_update {
-                schedule.unschedule(id); 
-            }
+    fn schedule_next_run(&mut self, schedule: &mut Schedule, dot_clock: DotClock) {
+        if let Some(id) = self.next_update.take() {
+            schedule.unschedule(id); 
+        }
+        self.next_update = self.predict_next_irq(dot_clock).map(|time| {
             schedule.schedule(time, Event::Timer(self.id, Timers::run_timer))
         });
     }
@@ -444,12 +517,13 @@ impl Timer {
 
     pub fn dump(&self, d: &mut impl Dumper) {
         dump!(d, "counter", "{}", self.counter);
-        dump!(d, "target", "{}", self.counter);
+        dump!(d, "target", "{}", self.target);
 
         let mode = self.mode;
 
         dump!(d, "sync enabled", "{}", mode.sync_enabled());
         dump!(d, "sync mode", "{}", self.sync_mode());
+        dump!(d, "paused", "{}", self.is_paused());
         dump!(d, "reset on target", "{}", mode.reset_on_target());
         dump!(d, "irq on target", "{}", mode.irq_on_target());
         dump!(d, "irq on overflow", "{}", mode.irq_on_overflow());
@@ -467,8 +541,12 @@ impl Timer {
 ///
 /// All the timers can run simultaneously. Each timer can be configured to take different sources,
 /// have different targets and what to do when reaching the target such as triggering an interrupt.
+///
+/// Timer 0 and 1 can be synced to the GPU hblank and vblank respectively, which the GPU reports
+/// with [`Timers::hblank_start`], [`Timers::vblank_start`] and so on.
 pub struct Timers {
     pub timers: [(Timer, Timestamp); 3],
+    dot_clock: DotClock,
 }
 
 impl Timers {
@@ -479,6 +557,7 @@ impl Timers {
                 (Timer::new(TimerId::Tmr1), Timestamp::STARTUP),
                 (Timer::new(TimerId::Tmr2), Timestamp::STARTUP),
             ],
+            dot_clock: DotClock::default(),
         }
     }
 
@@ -493,7 +572,7 @@ impl Timers {
         // TODO: Check what happens when you read an unaligned byte for instance.
         let val = tmr.load(offset);
 
-        tmr.schedule_next_run(schedule);
+        tmr.schedule_next_run(schedule, self.dot_clock);
 
         T::from_u32(u32::from(val))
     }
@@ -516,20 +595,33 @@ impl Timers {
         let (tmr, _) = &mut self.timers[id as usize];
 
         tmr.store(offset, val.as_u16());
-        tmr.schedule_next_run(schedule);
+        tmr.schedule_next_run(schedule, self.dot_clock);
     }
 
     /// Update the timer. If the clock source is derivable from clock cycles ie. not Hblanks, then
     /// the timer gets run.
     fn update_timer(&mut self, schedule: &mut Schedule, id: TimerId) {
         let (tmr, last_update) = &mut self.timers[id as usize];
-        let time = schedule.now().time_since(last_update);
 
-        *last_update = schedule.now();
+        // Keep the fractional cycles, since the dot clock is slower than the CPU clock.
+        let time = schedule
+            .now()
+            .time_since_startup()
+            .saturating_sub(last_update.time_since_startup());
 
-        if tmr.clock_source() != ClockSource::Hblank {
-            tmr.run(schedule, tmr.clock_source().time_to_ticks(time));
+        if tmr.clock_source() == ClockSource::Hblank || tmr.is_paused() {
+            *last_update = schedule.now();
+            return;
         }
+
+        let ticks = tmr.clock_source().time_to_ticks(time, self.dot_clock);
+
+        // Only skip ahead the time of whole ticks, so that the remainder isn't lost for slower
+        // clock sources.
+        let elapsed = tmr.clock_source().ticks_to_time(ticks, self.dot_clock);
+        *last_update = (*last_update + elapsed).min(schedule.now());
+
+        tmr.run(schedule, ticks);
     }
 
     pub fn timer(&self, id: TimerId) -> &Timer {
@@ -542,7 +634,7 @@ impl Timers {
 
         let (tmr, _) = &mut self.timers[id as usize];
 
-        tmr.schedule_next_run(schedule);
+        tmr.schedule_next_run(schedule, self.dot_clock);
     }
 
     pub(crate) fn enable_irq_master_flag(&mut self, _: &mut Schedule, id: TimerId) {
@@ -550,16 +642,142 @@ impl Timers {
         tmr.mode.set_master_irq_flag(true);
     }
 
-    pub(crate) fn hblank(&mut self, schedule: &mut Schedule, count: u64) {
+    /// Set the speed of the dot clock. Should be called whenever the video mode or horizontal
+    /// resolution changes.
+    pub(crate) fn set_dot_clock(&mut self, schedule: &mut Schedule, dot_clock: DotClock) {
+        if dot_clock == self.dot_clock {
+            return;
+        }
+
+        // Run timer 0 with the previous dot clock first.
+        self.update_timer(schedule, TimerId::Tmr0);
+        self.dot_clock = dot_clock;
+
+        let (tmr0, _) = &mut self.timers[0];
+        tmr0.schedule_next_run(schedule, dot_clock);
+    }
+
+    pub fn dot_clock(&self) -> DotClock {
+        self.dot_clock
+    }
+
+    /// Update timer `id` after it's sync state has changed by `func`.
+    fn sync_timer(&mut self, schedule: &mut Schedule, id: TimerId, func: fn(&mut Timer)) {
+        self.update_timer(schedule, id);
+
+        let (tmr, _) = &mut self.timers[id as usize];
+
+        func(tmr);
+        tmr.schedule_next_run(schedule, self.dot_clock);
+    }
+
+    /// Called by the GPU when entering hblank. Timer 1 counts hblanks and timer 0 can sync to it.
+    pub(crate) fn hblank_start(&mut self, schedule: &mut Schedule) {
+        self.sync_timer(schedule, TimerId::Tmr0, Timer::blank_start);
+
         let (tmr1, _) = &mut self.timers[1];
 
-        if let ClockSource::Hblank = tmr1.clock_source() {
-            tmr1.run(schedule, count);
+        if tmr1.clock_source() == ClockSource::Hblank && !tmr1.is_paused() {
+            tmr1.run(schedule, 1);
         }
     }
+
+    /// Called by the GPU when leaving hblank.
+    pub(crate) fn hblank_end(&mut self, schedule: &mut Schedule) {
+        self.sync_timer(schedule, TimerId::Tmr0, Timer::blank_end);
+    }
+
+    /// Called by the GPU when entering vblank. Timer 1 can sync to it.
+    pub(crate) fn vblank_start(&mut self, schedule: &mut Schedule) {
+        self.sync_timer(schedule, TimerId::Tmr1, Timer::blank_start);
+    }
+
+    /// Called by the GPU when leaving vblank.
+    pub(crate) fn vblank_end(&mut self, schedule: &mut Schedule) {
+        self.sync_timer(schedule, TimerId::Tmr1, Timer::blank_end);
+    }
 }
 
 impl BusMap for Timers {
     const BUS_BEGIN: u32 = 0x1f801100;
     const BUS_END: u32 = Self::BUS_BEGIN + 48 - 1;
 }
+
+#[test]
+fn vblank_sync() {
+    let mut schedule = Schedule::new();
+    let mut timers = Timers::new();
+
+    let counter = |timers: &mut Timers, schedule: &mut Schedule| {
+        timers.load::<u32>(schedule, 0x10)
+    };
+
+    // Pause during vblank.
+    timers.store::<u32>(&mut schedule, 0x14, 0x1);
+    schedule.advance(SysTime::new(100));
+    timers.vblank_start(&mut schedule);
+    schedule.advance(SysTime::new(50));
+
+    assert_eq!(counter(&mut timers, &mut schedule), 100);
+
+    timers.vblank_end(&mut schedule);
+    schedule.advance(SysTime::new(10));
+
+    assert_eq!(counter(&mut timers, &mut schedule), 110);
+
+    // Reset when entering vblank.
+    timers.store::<u32>(&mut schedule, 0x14, 0x3);
+    schedule.advance(SysTime::new(100));
+    timers.vblank_start(&mut schedule);
+    schedule.advance(SysTime::new(20));
+
+    assert_eq!(counter(&mut timers, &mut schedule), 20);
+
+    timers.vblank_end(&mut schedule);
+
+    // Reset when entering vblank and pause outside vblank.
+    timers.store::<u32>(&mut schedule, 0x14, 0x5);
+    schedule.advance(SysTime::new(100));
+
+    assert_eq!(counter(&mut timers, &mut schedule), 0);
+
+    timers.vblank_start(&mut schedule);
+    schedule.advance(SysTime::new(30));
+    timers.vblank_end(&mut schedule);
+    schedule.advance(SysTime::new(10));
+
+    assert_eq!(counter(&mut timers, &mut schedule), 30);
+
+    // Wait until vblank and switch to free run.
+    timers.store::<u32>(&mut schedule, 0x14, 0x7);
+    schedule.advance(SysTime::new(100));
+
+    assert_eq!(counter(&mut timers, &mut schedule), 0);
+
+    timers.vblank_start(&mut schedule);
+    timers.vblank_end(&mut schedule);
+    schedule.advance(SysTime::new(40));
+
+    assert_eq!(counter(&mut timers, &mut schedule), 40);
+    assert!(!timers.timer(TimerId::Tmr1).mode.sync_enabled());
+}
+
+#[test]
+fn dot_clock() {
+    let mut schedule = Schedule::new();
+    let mut timers = Timers::new();
+
+    timers.set_dot_clock(&mut schedule, DotClock::new(VideoMode::Ntsc, HorizontalRes::P320));
+    timers.store::<u32>(&mut schedule, 0x04, 0x100);
+
+    // Update the timer often, so that less than a dot passes between each update.
+    for _ in 0..200 {
+        schedule.advance(SysTime::from_gpu_ntsc_cycles(4));
+        timers.load::<u32>(&mut schedule, 0x0);
+    }
+
+    // Converting between CPU and GPU cycles isn't exact, so give it a single extra cycle.
+    schedule.advance(SysTime::from_gpu_ntsc_cycles(1));
+
+    assert_eq!(timers.load::<u32>(&mut schedule, 0x0), 100);
+}
@@ -0,0 +1,698 @@
+//! Execution traces.
+//!
+//! [`TraceRecorder`] is a [`Debugger`] which records every instruction executed along with the
+//! registers it changed, the memory it accessed and the cycle it was executed on. Traces are
+//! written in a compact binary format, but can also be written and read in a simple text format,
+//! which makes it possible to compare against traces produced by other emulators.
+//!
+//! # Binary format
+//!
+//! The file starts with the magic [`MAGIC`] followed by a single version byte. After that
+//! follows a record for each instruction, all integers are little endian:
+//!
+//! - pc: u32
+//! - opcode: u32
+//! - cycles since the last record: LEB128 encoded u64
+//! - number of changed registers: u8, followed by each register as an u8 index and u32 value.
+//! - number of memory accesses: u8, followed by each access as an u8 kind, u32 address and u32
+//!   value. The low 3 bits of the kind is the width in bytes and bit 7 is set for stores.
+//!
+//! # Text format
+//!
+//! Each line is a single instruction. Empty lines and everything after a `#` is ignored:
+//!
+//! ```text
+//! <pc> <opcode> [c=<cycle>] [<reg>=<value>]... [l<width>@<addr>=<value>]... [s<width>@<addr>=<value>]...
+//! ```
+//!
+//! All numbers besides the cycle and widths are hexadecimal. Registers can be named either by
+//! their index (`r8`) or name (`t0`) and `hi` and `lo`. Widths are in bits, so a word store to
+//! 0x1f801810 would be `s32@1f801810=deadbeef`. The cycle is optional, and is only compared if both
+//! traces has it.
+
+use crate::cpu::{Cpu, Opcode, REGISTER_NAMES};
+use crate::bus::AddrUnit;
+use crate::debug::Debugger;
+
+use thiserror::Error;
+
+use std::collections::VecDeque;
+use std::io::{self, Read, Write, BufRead};
+use std::fmt;
+
+/// The magic bytes at the start of every binary trace.
+pub const MAGIC: &[u8; 8] = b"SPLTRACE";
+
+const VERSION: u8 = 1;
+
+/// Register index used for the hi register.
+pub const REG_HI: u8 = 32;
+
+/// Register index used for the lo register.
+pub const REG_LO: u8 = 33;
+
+#[derive(Error, Debug)]
+pub enum TraceError {
+    #[error("failed to access trace: {0}")]
+    IoError(#[from] io::Error),
+    #[error("invalid trace file: {0}")]
+    InvalidBinary(&'static str),
+    #[error("invalid trace:{line}: {msg}")]
+    InvalidText { line: usize, msg: String },
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum AccessKind {
+    Load,
+    Store,
+}
+
+/// A single memory access done by an instruction.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct MemAccess {
+    pub kind: AccessKind,
+    /// The width of the access in bytes.
+    pub width: u8,
+    pub addr: u32,
+    pub val: u32,
+}
+
+impl fmt::Display for MemAccess {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let kind = match self.kind {
+            AccessKind::Load => 'l',
+            AccessKind::Store => 's',
+        };
+        write!(f, "{kind}{}@{:08x}={:x}", self.width as u32 * 8, self.addr, self.val)
+    }
+}
+
+/// A single executed instruction.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct TraceEntry {
+    pub pc: u32,
+    pub op: u32,
+    /// The amount of CPU cycles since startup when the instruction was executed.
+    pub cycles: Option<u64>,
+    /// The registers changed by the instruction, as pairs of register index and new value.
+    ///
+    /// Note that loads from memory shows up on the instruction after the load because of the
+    /// load delay slot.
+    pub regs: Vec<(u8, u32)>,
+    pub mem: Vec<MemAccess>,
+}
+
+fn reg_name(reg: u8) -> &'static str {
+    match reg {
+        REG_HI => "hi",
+        REG_LO => "lo",
+        reg => REGISTER_NAMES[reg as usize],
+    }
+}
+
+fn parse_reg(name: &str) -> Option<u8> {
+    match name {
+        "hi" => Some(REG_HI),
+        "lo" => Some(REG_LO),
+        name => REGISTER_NAMES
+            .iter()
+            .position(|reg| *reg == name)
+            .map(|idx| idx as u8)
+            .or_else(|| {
+                name.strip_prefix('r')
+                    .and_then(|idx| idx.parse::<u8>().ok())
+                    .filter(|idx| *idx < 32)
+            }),
+    }
+}
+
+impl fmt::Display for TraceEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:08x} {:08x}", self.pc, self.op)?;
+        if let Some(cycles) = self.cycles {
+            write!(f, " c={cycles}")?;
+        }
+        for (reg, val) in &self.regs {
+            write!(f, " {}={val:x}", reg_name(*reg))?;
+        }
+        for access in &self.mem {
+            write!(f, " {access}")?;
+        }
+        Ok(())
+    }
+}
+
+impl TraceEntry {
+    /// Parse a single line of text. Returns `None` if the line is empty.
+    fn parse_line(line: &str) -> Result<Option<Self>, String> {
+        let line = match line.find('#') {
+            Some(idx) => &line[..idx],
+            None => line,
+        };
+
+        let mut tokens = line.split_whitespace();
+
+        let Some(pc) = tokens.next() else {
+            return Ok(None);
+        };
+
+        let parse_hex = |val: &str| {
+            u32::from_str_radix(val, 16).map_err(|_| format!("invalid hex value `{val}`"))
+        };
+
+        let pc = parse_hex(pc)?;
+        let op = tokens
+            .next()
+            .ok_or_else(|| "missing opcode".to_string())
+            .and_then(parse_hex)?;
+
+        let mut entry = TraceEntry { pc, op, ..Default::default() };
+
+        for token in tokens {
+            let Some((key, val)) = token.split_once('=') else {
+                return Err(format!("invalid field `{token}`"));
+            };
+
+            if key == "c" {
+                let cycles = val
+                    .parse::<u64>()
+                    .map_err(|_| format!("invalid cycle count `{val}`"))?;
+                entry.cycles = Some(cycles);
+                continue;
+            }
+
+            if let Some((access, addr)) = key.split_once('@') {
+                let kind = match access.as_bytes().first() {
+                    Some(b'l') => AccessKind::Load,
+                    Some(b's') => AccessKind::Store,
+                    _ => return Err(format!("invalid memory access `{token}`")),
+                };
+                let width = match &access[1..] {
+                    "8" => 1,
+                    "16" => 2,
+                    "32" => 4,
+                    _ => return Err(format!("invalid access width in `{token}`")),
+                };
+                entry.mem.push(MemAccess {
+                    kind,
+                    width,
+                    addr: parse_hex(addr)?,
+                    val: parse_hex(val)?,
+                });
+                continue;
+            }
+
+            let reg = parse_reg(key).ok_or_else(|| format!("invalid register `{key}`"))?;
+            entry.regs.push((reg, parse_hex(val)?));
+        }
+
+        entry.regs.sort_unstable_by_key(|(reg, _)| *reg);
+
+        Ok(Some(entry))
+    }
+
+    fn write_binary(&self, out: &mut impl Write, last_cycles: u64) -> io::Result<()> {
+        out.write_all(&self.pc.to_le_bytes())?;
+        out.write_all(&self.op.to_le_bytes())?;
+
+        // Write the cycle delta as LEB128.
+        let mut delta = self.cycles.unwrap_or(last_cycles).wrapping_sub(last_cycles);
+        loop {
+            let byte = (delta & 0x7f) as u8;
+            delta >>= 7;
+            if delta == 0 {
+                out.write_all(&[byte])?;
+                break;
+            }
+            out.write_all(&[byte | 0x80])?;
+        }
+
+        out.write_all(&[self.regs.len() as u8])?;
+        for (reg, val) in &self.regs {
+            out.write_all(&[*reg])?;
+            out.write_all(&val.to_le_bytes())?;
+        }
+
+        out.write_all(&[self.mem.len() as u8])?;
+        for access in &self.mem {
+            let kind = match access.kind {
+                AccessKind::Load => access.width,
+                AccessKind::Store => access.width | 0x80,
+            };
+            out.write_all(&[kind])?;
+            out.write_all(&access.addr.to_le_bytes())?;
+            out.write_all(&access.val.to_le_bytes())?;
+        }
+
+        Ok(())
+    }
+
+    /// Check if `self` and `other` are equal. The cycles are only compared if both have them.
+    pub fn matches(&self, other: &Self) -> bool {
+        let cycles_match = match (self.cycles, other.cycles) {
+            (Some(a), Some(b)) => a == b,
+            _ => true,
+        };
+        cycles_match
+            && self.pc == other.pc
+            && self.op == other.op
+            && self.regs == other.regs
+            && self.mem == other.mem
+    }
+
+    /// Describe how `self` differs from `other`.
+    fn differences(&self, other: &Self) -> Vec<String> {
+        let mut diffs = Vec::new();
+
+        if self.pc != other.pc {
+            diffs.push(format!("pc {:08x} != {:08x}", self.pc, other.pc));
+        }
+
+        if self.op != other.op {
+            diffs.push(format!(
+                "opcode `{}` != `{}`",
+                Opcode::new(self.op),
+                Opcode::new(other.op),
+            ));
+        }
+
+        if let (Some(a), Some(b)) = (self.cycles, other.cycles) {
+            if a != b {
+                diffs.push(format!("cycle {a} != {b}"));
+            }
+        }
+
+        for reg in 0..=REG_LO {
+            let a = self.regs.iter().find(|(r, _)| *r == reg).map(|(_, val)| *val);
+            let b = other.regs.iter().find(|(r, _)| *r == reg).map(|(_, val)| *val);
+            if a != b {
+                let show = |val: Option<u32>| {
+                    val.map(|val| format!("{val:x}")).unwrap_or_else(|| "unchanged".to_string())
+                };
+                diffs.push(format!("{} {} != {}", reg_name(reg), show(a), show(b)));
+            }
+        }
+
+        if self.mem != other.mem {
+            let show = |mem: &[MemAccess]| {
+                let accesses: Vec<_> = mem.iter().map(|a| a.to_string()).collect();
+                format!("[{}]", accesses.join(" "))
+            };
+            diffs.push(format!("memory {} != {}", show(&self.mem), show(&other.mem)));
+        }
+
+        diffs
+    }
+}
+
+/// Records a trace of all instructions executed.
+///
+/// Since the [`Debugger`] is called before each instruction, the register changes of an
+/// instruction is found when the next instruction is about to be executed. [`Self::finish`] must
+/// therefore be called when done to write the last instruction.
+pub struct TraceRecorder<W: Write> {
+    out: W,
+    /// The instruction currently being executed.
+    current: Option<TraceEntry>,
+    /// The registers before executing `current`.
+    regs: [u32; 34],
+    last_cycles: u64,
+    recorded: u64,
+    /// The first error when writing to `out`. Recording stops after an error.
+    error: Option<io::Error>,
+}
+
+impl<W: Write> TraceRecorder<W> {
+    pub fn new(mut out: W) -> Result<Self, TraceError> {
+        out.write_all(MAGIC)?;
+        out.write_all(&[VERSION])?;
+        Ok(Self {
+            out,
+            current: None,
+            regs: [0x0; 34],
+            last_cycles: 0,
+            recorded: 0,
+            error: None,
+        })
+    }
+
+    /// The amount of instructions recorded so far.
+    pub fn recorded(&self) -> u64 {
+        self.recorded
+    }
+
+    fn cpu_regs(cpu: &Cpu) -> [u32; 34] {
+        let mut regs = [0x0; 34];
+        regs[..32].copy_from_slice(cpu.registers().values());
+        regs[REG_HI as usize] = cpu.hi();
+        regs[REG_LO as usize] = cpu.lo();
+        regs
+    }
+
+    /// Write `current` if there is any.
+    fn flush_current(&mut self, cpu: &Cpu) {
+        let regs = Self::cpu_regs(cpu);
+        if let Some(mut entry) = self.current.take() {
+            entry.regs = (0..34)
+                .filter(|reg| regs[*reg] != self.regs[*reg])
+                .map(|reg| (reg as u8, regs[reg]))
+                .collect();
+            if self.error.is_none() {
+                if let Err(err) = entry.write_binary(&mut self.out, self.last_cycles) {
+                    self.error = Some(err);
+                }
+                self.last_cycles = entry.cycles.unwrap_or(self.last_cycles);
+                self.recorded += 1;
+            }
+        }
+        self.regs = regs;
+    }
+
+    fn access<T: AddrUnit>(&mut self, kind: AccessKind, addr: u32, val: T) {
+        if let Some(entry) = &mut self.current {
+            entry.mem.push(MemAccess {
+                kind,
+                width: T::WIDTH as u8,
+                addr,
+                val: val.into(),
+            });
+        }
+    }
+
+    /// Write the last instruction and flush the output.
+    pub fn finish(mut self, cpu: &Cpu) -> Result<W, TraceError> {
+        self.flush_current(cpu);
+        if let Some(err) = self.error {
+            return Err(err.into());
+        }
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+impl<W: Write> Debugger for TraceRecorder<W> {
+    fn instruction(&mut self, cpu: &Cpu, addr: u32, op: Opcode) {
+        self.flush_current(cpu);
+        self.current = Some(TraceEntry {
+            pc: addr,
+            op: op.raw(),
+            cycles: Some(cpu.bus.schedule.now().time_since_startup().as_cpu_cycles()),
+            regs: Vec::new(),
+            mem: Vec::new(),
+        });
+    }
+
+    fn load<T: AddrUnit>(&mut self, _: &Cpu, addr: u32, val: T) {
+        self.access(AccessKind::Load, addr, val);
+    }
+
+    fn store<T: AddrUnit>(&mut self, _: &Cpu, addr: u32, val: T) {
+        self.access(AccessKind::Store, addr, val);
+    }
+
+    fn should_break(&mut self) -> bool {
+        false
+    }
+}
+
+/// Reads binary traces.
+pub struct BinaryTraceReader<R: Read> {
+    input: R,
+    last_cycles: u64,
+}
+
+impl<R: Read> BinaryTraceReader<R> {
+    pub fn new(mut input: R) -> Result<Self, TraceError> {
+        let mut header = [0x0; 9];
+        input.read_exact(&mut header)?;
+        if &header[..8] != MAGIC {
+            return Err(TraceError::InvalidBinary("missing magic"));
+        }
+        if header[8] != VERSION {
+            return Err(TraceError::InvalidBinary("unsupported version"));
+        }
+        Ok(Self { input, last_cycles: 0 })
+    }
+
+    fn read_u8(&mut self) -> io::Result<u8> {
+        let mut buf = [0x0; 1];
+        self.input.read_exact(&mut buf)?;
+        Ok(buf[0])
+    }
+
+    fn read_u32(&mut self) -> io::Result<u32> {
+        let mut buf = [0x0; 4];
+        self.input.read_exact(&mut buf)?;
+        Ok(u32::from_le_bytes(buf))
+    }
+
+    fn read_entry(&mut self) -> Result<Option<TraceEntry>, TraceError> {
+        // Check for the end of the trace, which is only valid at the start of a record.
+        let mut first = [0x0; 1];
+        if self.input.read(&mut first)? == 0 {
+            return Ok(None);
+        }
+
+        let mut rest = [0x0; 3];
+        self.input.read_exact(&mut rest)?;
+
+        let pc = u32::from_le_bytes([first[0], rest[0], rest[1], rest[2]]);
+        let op = self.read_u32()?;
+
+        let mut delta = 0_u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.read_u8()?;
+            delta |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                break;
+            }
+        }
+
+        self.last_cycles = self.last_cycles.wrapping_add(delta);
+
+        let reg_count = self.read_u8()?;
+        let mut regs = Vec::with_capacity(reg_count as usize);
+        for _ in 0..reg_count {
+            let reg = self.read_u8()?;
+            if reg > REG_LO {
+                return Err(TraceError::InvalidBinary("invalid register index"));
+            }
+            regs.push((reg, self.read_u32()?));
+        }
+
+        let mem_count = self.read_u8()?;
+        let mut mem = Vec::with_capacity(mem_count as usize);
+        for _ in 0..mem_count {
+            let kind = self.read_u8()?;
+            let width = kind & 0x7;
+            if !matches!(width, 1 | 2 | 4) {
+                return Err(TraceError::InvalidBinary("invalid access width"));
+            }
+            mem.push(MemAccess {
+                kind: if kind & 0x80 != 0 { AccessKind::Store } else { AccessKind::Load },
+                width,
+                addr: self.read_u32()?,
+                val: self.read_u32()?,
+            });
+        }
+
+        Ok(Some(TraceEntry { pc, op, cycles: Some(self.last_cycles), regs, mem }))
+    }
+}
+
+impl<R: Read> Iterator for BinaryTraceReader<R> {
+    type Item = Result<TraceEntry, TraceError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_entry().transpose()
+    }
+}
+
+/// Reads traces in the text format.
+pub struct TextTraceReader<R: BufRead> {
+    lines: io::Lines<R>,
+    line: usize,
+}
+
+impl<R: BufRead> TextTraceReader<R> {
+    pub fn new(input: R) -> Self {
+        Self { lines: input.lines(), line: 0 }
+    }
+}
+
+impl<R: BufRead> Iterator for TextTraceReader<R> {
+    type Item = Result<TraceEntry, TraceError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            self.line += 1;
+            let line = match self.lines.next()? {
+                Ok(line) => line,
+                Err(err) => return Some(Err(err.into())),
+            };
+            match TraceEntry::parse_line(&line) {
+                Ok(Some(entry)) => return Some(Ok(entry)),
+                Ok(None) => continue,
+                Err(msg) => {
+                    return Some(Err(TraceError::InvalidText { line: self.line, msg }));
+                }
+            }
+        }
+    }
+}
+
+/// Open a trace, either binary or text depending on if it starts with [`MAGIC`].
+pub fn read_trace<R: BufRead + 'static>(
+    mut input: R,
+) -> Result<Box<dyn Iterator<Item = Result<TraceEntry, TraceError>>>, TraceError> {
+    let is_binary = input.fill_buf()?.starts_with(MAGIC);
+    if is_binary {
+        Ok(Box::new(BinaryTraceReader::new(input)?))
+    } else {
+        Ok(Box::new(TextTraceReader::new(input)))
+    }
+}
+
+/// Write `trace` in the text format.
+pub fn write_text(
+    trace: impl Iterator<Item = Result<TraceEntry, TraceError>>,
+    out: &mut impl Write,
+) -> Result<(), TraceError> {
+    for entry in trace {
+        writeln!(out, "{}", entry?)?;
+    }
+    Ok(())
+}
+
+/// The first point where two traces differ.
+pub struct Divergence {
+    /// The index of the diverging instruction.
+    pub index: u64,
+    /// The instructions leading up to the divergence, which are equal in both traces.
+    pub context: Vec<TraceEntry>,
+    /// The diverging instruction from each trace. `None` if the trace ended early.
+    pub left: Option<TraceEntry>,
+    pub right: Option<TraceEntry>,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "traces diverge at instruction {}:", self.index)?;
+        for entry in &self.context {
+            writeln!(f, "  {entry}    # {}", Opcode::new(entry.op))?;
+        }
+        match &self.left {
+            Some(entry) => writeln!(f, "- {entry}    # {}", Opcode::new(entry.op))?,
+            None => writeln!(f, "- <end of trace>")?,
+        }
+        match &self.right {
+            Some(entry) => writeln!(f, "+ {entry}    # {}", Opcode::new(entry.op))?,
+            None => writeln!(f, "+ <end of trace>")?,
+        }
+        if let (Some(left), Some(right)) = (&self.left, &self.right) {
+            for diff in left.differences(right) {
+                writeln!(f, "  {diff}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Compare two traces and find the first point where they differ. `context` is the amount of
+/// instructions before the divergence to include. Returns `None` if the traces are equal.
+pub fn diff(
+    mut left: impl Iterator<Item = Result<TraceEntry, TraceError>>,
+    mut right: impl Iterator<Item = Result<TraceEntry, TraceError>>,
+    context: usize,
+) -> Result<Option<Divergence>, TraceError> {
+    let mut prev = VecDeque::with_capacity(context + 1);
+    let mut index = 0;
+
+    loop {
+        let entry = match (left.next().transpose()?, right.next().transpose()?) {
+            (None, None) => return Ok(None),
+            (Some(l), Some(r)) if l.matches(&r) => l,
+            (l, r) => {
+                return Ok(Some(Divergence {
+                    index,
+                    context: prev.into(),
+                    left: l,
+                    right: r,
+                }));
+            }
+        };
+
+        if context != 0 {
+            if prev.len() == context {
+                prev.pop_front();
+            }
+            prev.push_back(entry);
+        }
+
+        index += 1;
+    }
+}
+
+#[test]
+fn binary_roundtrip() {
+    let entries = vec![
+        TraceEntry {
+            pc: 0xbfc00000,
+            op: 0x3c080013,
+            cycles: Some(5),
+            regs: vec![(8, 0x130000)],
+            mem: vec![],
+        },
+        TraceEntry {
+            pc: 0xbfc00004,
+            op: 0xad0a0000,
+            cycles: Some(1000),
+            regs: vec![(REG_HI, 3), (REG_LO, 0xffff_ffff)],
+            mem: vec![MemAccess {
+                kind: AccessKind::Store,
+                width: 4,
+                addr: 0x1f801010,
+                val: 0xdeadbeef,
+            }],
+        },
+    ];
+
+    let mut out = Vec::new();
+    out.extend_from_slice(MAGIC);
+    out.push(VERSION);
+
+    let mut last_cycles = 0;
+    for entry in &entries {
+        entry.write_binary(&mut out, last_cycles).unwrap();
+        last_cycles = entry.cycles.unwrap();
+    }
+
+    let read: Vec<_> = BinaryTraceReader::new(out.as_slice())
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap();
+
+    assert_eq!(read, entries);
+}
+
+#[test]
+fn text_diff() {
+    let a = "bfc00000 3c080013 c=5 t0=130000\n\
+             bfc00004 3508243f t0=13243f\n\
+             bfc00008 ad0a0000 s32@1f801010=0\n";
+    let b = "# Reference trace.\n\
+             bfc00000 3c080013 r8=130000\n\
+             \n\
+             bfc00004 3508243f r8=13243f\n\
+             bfc00008 ad0a0000 s32@1f801010=1\n";
+
+    let read = |text: &'static str| TextTraceReader::new(text.as_bytes());
+
+    let div = diff(read(a), read(b), 4).unwrap().unwrap();
+
+    assert_eq!(div.index, 2);
+    assert_eq!(div.context.len(), 2);
+    assert_eq!(div.right.unwrap().mem[0].val, 1);
+
+    assert!(diff(read(a), read(a), 4).unwrap().is_none());
+}
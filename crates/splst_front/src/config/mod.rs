@@ -1,7 +1,11 @@
 mod quick_access;
 
-use splst_core::{Bios, io_port::{IoSlot, pad, memcard}, Disc};
+use splst_core::{Bios, Gpu, io_port::{IoSlot, pad, memcard}, Disc};
+use splst_core::cheat::{Cheat, Cheats};
+use splst_core::gpu::{Filter, ResolutionScale};
+use splst_core::gpu::pgxp::PgxpSettings;
 use splst_util::Exe;
+use splst_render::Deinterlace;
 use crate::keys;
 use crate::gui::Popups;
 
@@ -558,6 +562,334 @@ impl ExeConfig {
     }
 }
 
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+struct CheatEntry {
+    name: String,
+    code: String,
+    enabled: bool,
+}
+
+/// Cheats for each game, stored by the name of the disc or executable.
+#[derive(Default, serde::Serialize, serde::Deserialize)]
+pub struct CheatConfig {
+    #[serde(skip)]
+    name_input: String,
+
+    #[serde(skip)]
+    code_input: String,
+
+    /// The game which cheats are currently loaded into the system.
+    #[serde(skip)]
+    loaded_game: Option<String>,
+
+    #[serde(skip)]
+    modified: bool,
+
+    games: HashMap<String, Vec<CheatEntry>>,
+}
+
+impl CheatConfig {
+    fn is_modified(&self) -> bool {
+        self.modified
+    }
+
+    fn mark_as_saved(&mut self) {
+        self.modified = false;
+    }
+
+    /// Load the cheats of `game` into `cheats`.
+    fn load(&mut self, game: Option<&str>, cheats: &mut Cheats, popups: &mut Popups) {
+        self.loaded_game = game.map(|game| game.to_string());
+
+        let entries = game
+            .and_then(|game| self.games.get(game))
+            .map(|entries| entries.as_slice())
+            .unwrap_or(&[]);
+
+        let loaded = entries
+            .iter()
+            .filter_map(|entry| {
+                match Cheat::new(entry.name.clone(), entry.code.clone()) {
+                    Ok(mut cheat) => {
+                        cheat.enabled = entry.enabled;
+                        Some(cheat)
+                    }
+                    Err(err) => {
+                        popups.add("Cheat Error", format!("{}: {err}", entry.name));
+                        None
+                    }
+                }
+            })
+            .collect();
+
+        cheats.load(loaded);
+    }
+
+    /// Make sure that the cheats for `game` is loaded into `cheats`.
+    pub fn sync(&mut self, game: Option<&str>, cheats: &mut Cheats, popups: &mut Popups) {
+        if self.loaded_game.as_deref() != game {
+            self.load(game, cheats, popups);
+        }
+    }
+
+    pub fn show(
+        &mut self,
+        game: Option<&str>,
+        cheats: &mut Cheats,
+        popups: &mut Popups,
+        ui: &mut egui::Ui,
+    ) {
+        let Some(game) = game else {
+            ui.label("No disc or executable loaded");
+            return;
+        };
+
+        ui.horizontal(|ui| {
+            ui.add(egui::TextEdit::singleline(&mut self.name_input).hint_text("name"));
+
+            if ui.button("Add").clicked() {
+                // Validate the code before adding it.
+                match Cheat::new(self.name_input.clone(), self.code_input.clone()) {
+                    Err(err) => popups.add("Cheat Error", err.to_string()),
+                    Ok(_) => {
+                        let entry = CheatEntry {
+                            name: std::mem::take(&mut self.name_input),
+                            code: std::mem::take(&mut self.code_input),
+                            enabled: true,
+                        };
+                        self.games.entry(game.to_string()).or_default().push(entry);
+                        self.modified = true;
+                        self.load(Some(game), cheats, popups);
+                    }
+                }
+            }
+        });
+
+        ui.add(
+            egui::TextEdit::multiline(&mut self.code_input)
+                .hint_text("8009c6e4 03e7")
+                .code_editor()
+                .desired_rows(4),
+        );
+
+        ui.add_space(10.0);
+
+        let Some(entries) = self.games.get_mut(game) else {
+            ui.label(format!("No cheats saved for {game}"));
+            return;
+        };
+
+        let mut changed = false;
+
+        egui::Grid::new("cheat_grid").show(ui, |ui| {
+            entries.retain_mut(|entry| {
+                changed |= ui.checkbox(&mut entry.enabled, entry.name.as_str())
+                    .on_hover_text(entry.code.as_str())
+                    .changed();
+
+                let retain = !ui.button("Remove").clicked();
+                changed |= !retain;
+
+                ui.end_row();
+
+                retain
+            });
+        });
+
+        if changed {
+            self.modified = true;
+            self.load(Some(game), cheats, popups);
+        }
+    }
+}
+
+#[derive(Default, serde::Serialize, serde::Deserialize)]
+pub struct VideoConfig {
+    #[serde(default)]
+    deinterlace: Deinterlace,
+
+    #[serde(default)]
+    resolution_scale: ResolutionScale,
+
+    #[serde(default)]
+    filter: Filter,
+
+    #[serde(default)]
+    pgxp: PgxpSettings,
+
+    #[serde(default)]
+    raster_threads: usize,
+
+    #[serde(default)]
+    dump_textures: bool,
+
+    #[serde(default)]
+    texture_pack: Option<PathBuf>,
+
+    /// The texture pack which failed to load, so that it isn't tried again every frame.
+    #[serde(skip)]
+    failed_pack: Option<PathBuf>,
+
+    #[serde(skip)]
+    modified: bool,
+}
+
+/// The directory textures are dumped to.
+const TEXTURE_DUMP_DIR: &str = "texture-dump";
+
+impl VideoConfig {
+    fn is_modified(&self) -> bool {
+        self.modified
+    }
+
+    fn mark_as_saved(&mut self) {
+        self.modified = false;
+    }
+
+    pub fn deinterlace(&self) -> Deinterlace {
+        self.deinterlace
+    }
+
+    pub fn resolution_scale(&self) -> ResolutionScale {
+        self.resolution_scale
+    }
+
+    pub fn filter(&self) -> Filter {
+        self.filter
+    }
+
+    pub fn pgxp(&self) -> PgxpSettings {
+        self.pgxp
+    }
+
+    pub fn raster_threads(&self) -> usize {
+        self.raster_threads.max(1)
+    }
+
+    /// Make sure that texture dumping and the texture pack of `gpu` matches the config.
+    pub fn sync_textures(&mut self, gpu: &mut Gpu, popups: &mut Popups) {
+        gpu.set_texture_dump(self.dump_textures.then(|| PathBuf::from(TEXTURE_DUMP_DIR)));
+
+        match &self.texture_pack {
+            None => gpu.unload_texture_pack(),
+            Some(dir) if gpu.texture_pack_dir() == Some(dir.as_path()) => (),
+            Some(dir) if self.failed_pack.as_ref() == Some(dir) => (),
+            Some(dir) => match gpu.load_texture_pack(dir) {
+                Ok(count) => info!("loaded {count} textures from {}", dir.display()),
+                Err(err) => {
+                    popups.add("Failed to load texture pack", err.to_string());
+                    self.failed_pack = Some(dir.clone());
+                }
+            },
+        }
+    }
+
+    pub fn show(&mut self, popups: &mut Popups, ui: &mut egui::Ui) {
+        let before = (
+            self.deinterlace,
+            self.resolution_scale,
+            self.filter,
+            self.pgxp,
+            self.raster_threads,
+            self.dump_textures,
+            self.texture_pack.clone(),
+        );
+
+        egui::ComboBox::from_label("Deinterlacing")
+            .selected_text(format!("{}", self.deinterlace))
+            .show_ui(ui, |ui| {
+                for mode in Deinterlace::ALL {
+                    ui.selectable_value(&mut self.deinterlace, mode, format!("{mode}"));
+                }
+            });
+
+        egui::ComboBox::from_label("Internal Resolution")
+            .selected_text(format!("{}", self.resolution_scale))
+            .show_ui(ui, |ui| {
+                for scale in ResolutionScale::ALL {
+                    ui.selectable_value(&mut self.resolution_scale, scale, format!("{scale}"));
+                }
+            });
+
+        let filters = [
+            Filter::Nearest,
+            Filter::Integer(2),
+            Filter::Scale2x,
+            Filter::Scale3x,
+            Filter::Xbr,
+            Filter::Scanlines,
+        ];
+
+        egui::ComboBox::from_label("Filter")
+            .selected_text(format!("{}", self.filter))
+            .show_ui(ui, |ui| {
+                for filter in filters {
+                    // Keep the scale if integer scaling is already selected.
+                    let selected = std::mem::discriminant(&self.filter)
+                        == std::mem::discriminant(&filter);
+                    if ui.selectable_label(selected, format!("{filter}")).clicked() && !selected {
+                        self.filter = filter;
+                    }
+                }
+            });
+
+        if let Filter::Integer(scale) = &mut self.filter {
+            ui.add(egui::Slider::new(scale, 2..=8).text("Integer scale"));
+        }
+
+        ui.checkbox(&mut self.pgxp.enabled, "Sub-pixel precision");
+        ui.add_enabled(
+            self.pgxp.enabled,
+            egui::Checkbox::new(&mut self.pgxp.perspective_correct, "Perspective correct textures"),
+        );
+
+        let max_threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+        ui.add(
+            egui::Slider::new(&mut self.raster_threads, 1..=max_threads).text("Rasterizer threads"),
+        );
+
+        ui.checkbox(&mut self.dump_textures, "Dump textures")
+            .on_hover_text(format!("Textures are dumped to '{TEXTURE_DUMP_DIR}'"));
+
+        ui.horizontal(|ui| {
+            let pack = self.texture_pack
+                .as_ref()
+                .map_or("None".to_string(), |dir| dir.display().to_string());
+
+            ui.label(format!("Texture pack: {pack}"));
+
+            if ui.button("Select").clicked() {
+                match FileDialog::new().set_location(".").show_open_single_dir() {
+                    Ok(Some(dir)) => {
+                        self.texture_pack = Some(dir);
+                        self.failed_pack = None;
+                    }
+                    Ok(None) => (),
+                    Err(err) => popups.add("Invalid path", err.to_string()),
+                }
+            }
+
+            if ui.button("Remove").clicked() {
+                self.texture_pack = None;
+            }
+        });
+
+        let after = (
+            self.deinterlace,
+            self.resolution_scale,
+            self.filter,
+            self.pgxp,
+            self.raster_threads,
+            self.dump_textures,
+            self.texture_pack.clone(),
+        );
+
+        if before != after {
+            self.modified = true;
+        }
+    }
+}
+
 /// Configuration for the emulator. This holds all the settings for the emulator like controller
 /// key bindings, the disc loaded and the BIOS used. It's can be serialized and deserialized to
 /// allow for saving the settings the a config file. It can also be rendered as GUI.
@@ -593,6 +925,12 @@ pub struct Config {
 
     #[serde(default)]
     pub memcard: MemCardConfig,
+
+    #[serde(default)]
+    pub cheats: CheatConfig,
+
+    #[serde(default)]
+    pub video: VideoConfig,
 }
 
 impl Config {
@@ -604,6 +942,8 @@ impl Config {
             || self.disc.is_modified()
             || self.exe.is_modified()
             || self.memcard.is_modified()
+            || self.cheats.is_modified()
+            || self.video.is_modified()
     }
 
     /// Show the BIOS menu. Used when trying to start the emulator without a loaded BIOS.
@@ -682,6 +1022,8 @@ impl Config {
                 self.bios.mark_as_saved();
                 self.exe.mark_as_saved();
                 self.memcard.mark_as_saved();
+                self.cheats.mark_as_saved();
+                self.video.mark_as_saved();
             }
         }
     }
@@ -707,9 +1049,17 @@ impl Config {
         gamepads: &mut pad::GamePads,
         memcards: &mut memcard::MemCards,
         disc: &mut Disc,
+        cheats: &mut Cheats,
         popups: &mut Popups,
         ui: &mut egui::Ui,
     ) {
+        let game = disc
+            .cd()
+            .map(|cd| cd.name().to_string())
+            .or_else(|| self.exe.name.clone());
+
+        self.cheats.sync(game.as_deref(), cheats, popups);
+
         ui.horizontal(|ui| {
             let name = self.config_path
                 .as_ref()
@@ -744,6 +1094,8 @@ impl Config {
         ui.collapsing("Disc", |ui| self.disc.show(disc, popups, ui));
         ui.collapsing("Executable", |ui| self.exe.show(popups, ui));
         ui.collapsing("Memory Card", |ui| self.memcard.show(memcards, popups, ui));
+        ui.collapsing("Cheats", |ui| self.cheats.show(game.as_deref(), cheats, popups, ui));
+        ui.collapsing("Video", |ui| self.video.show(popups, ui));
         
         if self.show_bios {
             self.show_bios = false;
@@ -757,12 +1109,13 @@ impl Config {
         gamepads: &mut pad::GamePads,
         memcards: &mut memcard::MemCards,
         disc: &mut Disc,
+        cheats: &mut Cheats,
         popups: &mut Popups,
         ctx: &egui::Context,
     ) {
         egui::SidePanel::left("settings").show(ctx, |ui| {
             egui::ScrollArea::vertical().show(ui, |ui| {
-                self.show_inside(used_bios, gamepads, memcards, disc, popups, ui)
+                self.show_inside(used_bios, gamepads, memcards, disc, cheats, popups, ui)
             });
         });
     }
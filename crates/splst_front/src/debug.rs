@@ -2,9 +2,20 @@ use crate::{gui::Popups, RunMode};
 use splst_core::bus::AddrUnit;
 use splst_core::cpu::{Cpu, Irq, Opcode};
 use splst_core::dump::Dumper;
+use splst_core::trace::{TraceRecorder, TraceError};
+use splst_core::gpu::capture::CaptureError;
+use splst_core::gpu::Filter;
+use splst_core::search::{Comparison, MemSearch, ValueType};
+use splst_core::profile::{Profiler, Symbols};
 use splst_core::{debug, StopReason, System};
+use splst_core::debug::Debugger as _;
 
-use std::time::Duration;
+use native_dialog::FileDialog;
+
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
 use std::{fmt, mem, str};
 
 struct BreakPoint<T> {
@@ -94,6 +105,13 @@ struct WatchPoint {
     addr: u32,
 }
 
+/// Memory value which is kept at a constant value.
+struct Frozen {
+    addr: u32,
+    ty: ValueType,
+    val: i64,
+}
+
 enum BreakKind {
     Irq(Irq),
     Instruction { addr: u32, op: Opcode },
@@ -124,6 +142,26 @@ enum ExecuteMode {
     Run,
 }
 
+#[derive(Clone, Copy)]
+enum StepKind {
+    /// Step a single instruction.
+    Into,
+    /// Step over function calls and branch delay slots.
+    Over,
+    /// Run until the current function returns.
+    Out,
+}
+
+/// Temporary breakpoints used when stepping.
+enum TempBreak {
+    /// Break when the next instruction to be executed is at `addr` and the stack pointer is at
+    /// least `sp`. The stack pointer check is to avoid breaking in recursive calls when stepping
+    /// over a function call.
+    Addr { addr: u32, sp: u32 },
+    /// Break when the current function returns. `depth` is the number of nested function calls.
+    Return { depth: u32 },
+}
+
 struct Debugger {
     instructions: Vec<BreakPoint<u32>>,
     loads: Vec<BreakPoint<u32>>,
@@ -131,10 +169,26 @@ struct Debugger {
     irqs: Vec<BreakPoint<Irq>>,
     watch: Vec<WatchPoint>,
     breaks: Vec<Break>,
+    frozen: Vec<Frozen>,
+
+    /// Trace being recorded if any.
+    trace: Option<TraceRecorder<BufWriter<File>>>,
+
+    /// Where to save the GPU capture being recorded.
+    gpu_capture: Option<PathBuf>,
+    /// The number of frames to capture.
+    capture_frames: u32,
+
+    profiler: Profiler,
+    /// If `profiler` is currently sampling.
+    profiling: bool,
 
     execute_mode: ExecuteMode,
     instruction_hz: u64,
-    stepped: bool,
+    step: Option<StepKind>,
+    temp_break: Option<TempBreak>,
+    /// Set when `temp_break` is hit.
+    temp_hit: bool,
     remainder: Duration,
 }
 
@@ -147,17 +201,63 @@ impl Default for Debugger {
             irqs: Vec::default(),
             watch: Vec::default(),
             breaks: Vec::default(),
+            frozen: Vec::default(),
+
+            trace: None,
+
+            gpu_capture: None,
+            capture_frames: 1,
+
+            profiler: Profiler::new(Symbols::default()),
+            profiling: false,
 
             execute_mode: ExecuteMode::Step,
             instruction_hz: 1,
-            stepped: false,
+            step: None,
+            temp_break: None,
+            temp_hit: false,
             remainder: Duration::ZERO,
         }
     }
 }
 
 impl debug::Debugger for Debugger {
-    fn instruction(&mut self, _: &Cpu, addr: u32, op: Opcode) {
+    fn instruction(&mut self, cpu: &Cpu, addr: u32, op: Opcode) {
+        if let Some(trace) = &mut self.trace {
+            trace.instruction(cpu, addr, op);
+        }
+
+        if self.profiling {
+            self.profiler.instruction(cpu, addr, op);
+        }
+
+        // `cpu.pc()` points at the next instruction to be executed at this point.
+        match &mut self.temp_break {
+            Some(TempBreak::Addr { addr, sp }) => {
+                if cpu.pc() == *addr && cpu.registers().values()[29] >= *sp {
+                    self.temp_hit = true;
+                }
+            }
+            Some(TempBreak::Return { depth }) => {
+                if op.is_call() {
+                    *depth += 1;
+                } else if op.is_return() {
+                    match depth.checked_sub(1) {
+                        Some(new) => *depth = new,
+                        None => {
+                            // Break after the delay slot, when the next instruction is at the
+                            // return address.
+                            self.temp_break = Some(TempBreak::Addr {
+                                addr: cpu.registers().values()[31],
+                                sp: 0,
+                            });
+                        }
+                    }
+                }
+            }
+            None => (),
+        }
+
         for bp in self.instructions.iter().filter(|bp| bp.on == addr) {
             self.breaks.push(Break {
                 name: bp.name.clone(),
@@ -166,7 +266,11 @@ impl debug::Debugger for Debugger {
         }
     }
 
-    fn load<T: AddrUnit>(&mut self, _: &Cpu, addr: u32, val: T) {
+    fn load<T: AddrUnit>(&mut self, cpu: &Cpu, addr: u32, val: T) {
+        if let Some(trace) = &mut self.trace {
+            trace.load(cpu, addr, val);
+        }
+
         for bp in self.loads.iter().filter(|bp| bp.on == addr) {
             self.breaks.push(Break {
                 name: bp.name.clone(),
@@ -178,7 +282,11 @@ impl debug::Debugger for Debugger {
         }
     }
 
-    fn store<T: AddrUnit>(&mut self, _: &Cpu, addr: u32, val: T) {
+    fn store<T: AddrUnit>(&mut self, cpu: &Cpu, addr: u32, val: T) {
+        if let Some(trace) = &mut self.trace {
+            trace.store(cpu, addr, val);
+        }
+
         for bp in self.stores.iter().filter(|bp| bp.on == addr) {
             self.breaks.push(Break {
                 name: bp.name.clone(),
@@ -200,11 +308,43 @@ impl debug::Debugger for Debugger {
     }
 
     fn should_break(&mut self) -> bool {
-        !self.breaks.is_empty()
+        !self.breaks.is_empty() || self.temp_hit
     }
 }
 
 impl Debugger {
+    /// The max number of instructions to run each update when running until a temporary
+    /// breakpoint, to keep the GUI responsive.
+    const TEMP_BREAK_STEPS: u64 = 500_000;
+
+    fn step_over(&mut self, system: &mut System) {
+        let pc = system.cpu.pc();
+        let op = system.bus().peek::<u32>(pc).map(Opcode::new);
+
+        match op {
+            Some(op) if op.is_call() => {
+                // Run until the instruction after the delay slot.
+                self.temp_break = Some(TempBreak::Addr {
+                    addr: pc.wrapping_add(8),
+                    sp: system.cpu.registers().values()[29],
+                });
+            }
+            // Run both the branch and the delay slot.
+            Some(op) if op.is_branch() => {
+                system.step_debug(2, self);
+            }
+            _ => {
+                system.step_debug(1, self);
+            }
+        }
+    }
+
+    /// Run until the next instruction to be executed is at `addr`.
+    fn run_to(&mut self, addr: u32) {
+        self.execute_mode = ExecuteMode::Step;
+        self.temp_break = Some(TempBreak::Addr { addr, sp: 0 });
+    }
+
     fn run(&mut self, system: &mut System, dt: Duration) {
         match self.execute_mode {
             ExecuteMode::Run => {
@@ -217,10 +357,27 @@ impl Debugger {
                 }
             }
             ExecuteMode::Step => {
-                if self.stepped {
-                    self.stepped = false;
-                    system.step_debug(1, self);
+                match self.step.take() {
+                    Some(StepKind::Into) => {
+                        system.step_debug(1, self);
+                    }
+                    Some(StepKind::Over) => self.step_over(system),
+                    Some(StepKind::Out) => {
+                        self.temp_break = Some(TempBreak::Return { depth: 0 });
+                    }
+                    None => (),
+                }
+
+                if self.temp_break.is_some() {
+                    system.step_debug(Self::TEMP_BREAK_STEPS, self);
+
+                    // Stop on both temporary and normal breakpoints.
+                    if self.temp_hit || !self.breaks.is_empty() {
+                        self.temp_break = None;
+                    }
                 }
+
+                self.temp_hit = false;
             }
         }
     }
@@ -492,6 +649,336 @@ impl WatchPointMenu {
     }
 }
 
+#[derive(PartialEq, Clone, Copy)]
+enum ComparisonKind {
+    Equal,
+    Changed,
+    Increased,
+    Decreased,
+    IncreasedBy,
+    DecreasedBy,
+    Value,
+}
+
+impl ComparisonKind {
+    const ITEMS: [Self; 7] = [
+        Self::Equal,
+        Self::Changed,
+        Self::Increased,
+        Self::Decreased,
+        Self::IncreasedBy,
+        Self::DecreasedBy,
+        Self::Value,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            ComparisonKind::Equal => "Equal",
+            ComparisonKind::Changed => "Changed",
+            ComparisonKind::Increased => "Increased",
+            ComparisonKind::Decreased => "Decreased",
+            ComparisonKind::IncreasedBy => "Increased By",
+            ComparisonKind::DecreasedBy => "Decreased By",
+            ComparisonKind::Value => "Value",
+        }
+    }
+
+    fn takes_input(self) -> bool {
+        matches!(
+            self,
+            ComparisonKind::IncreasedBy | ComparisonKind::DecreasedBy | ComparisonKind::Value
+        )
+    }
+}
+
+/// Parse a decimal or hex (prefixed with "0x") integer.
+fn parse_int(input: &str) -> Option<i64> {
+    let input = input.trim();
+    match input.strip_prefix("0x") {
+        Some(hex) => i64::from_str_radix(hex, 16).ok(),
+        None => input.parse().ok(),
+    }
+}
+
+/// Menu to search for values in RAM and the scratchpad.
+struct SearchMenu {
+    ty: ValueType,
+    cmp: ComparisonKind,
+    input: String,
+    search: Option<MemSearch>,
+}
+
+impl Default for SearchMenu {
+    fn default() -> Self {
+        Self {
+            ty: ValueType::U32,
+            cmp: ComparisonKind::Changed,
+            input: String::default(),
+            search: None,
+        }
+    }
+}
+
+impl SearchMenu {
+    /// The max number of candidates to show.
+    const MAX_SHOWN: usize = 256;
+
+    fn show(&mut self, system: &System, dbg: &mut Debugger, popups: &mut Popups, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            egui::ComboBox::from_id_source("search_value_type")
+                .selected_text(self.ty.to_string())
+                .show_ui(ui, |ui| {
+                    for ty in ValueType::ITEMS {
+                        ui.selectable_value(&mut self.ty, ty, ty.to_string());
+                    }
+                });
+
+            if ui.button("New Search").clicked() {
+                self.search = Some(MemSearch::new(system.bus(), self.ty));
+            }
+
+            if self.search.is_some() && ui.button("Clear").clicked() {
+                self.search = None;
+            }
+        });
+
+        ui.horizontal(|ui| {
+            egui::ComboBox::from_id_source("search_comparison")
+                .selected_text(self.cmp.name())
+                .show_ui(ui, |ui| {
+                    for cmp in ComparisonKind::ITEMS {
+                        ui.selectable_value(&mut self.cmp, cmp, cmp.name());
+                    }
+                });
+
+            if self.cmp.takes_input() {
+                ui.add_sized([100.0, 15.0], egui::TextEdit::singleline(&mut self.input));
+            }
+
+            let Some(search) = &mut self.search else {
+                return;
+            };
+
+            if ui.button("Filter").clicked() {
+                let input = if self.cmp.takes_input() {
+                    match parse_int(&self.input) {
+                        Some(input) => input,
+                        None => {
+                            popups.add(
+                                "Invalid value",
+                                format!("`{}` is not a valid integer", self.input),
+                            );
+                            return;
+                        }
+                    }
+                } else {
+                    0
+                };
+
+                let cmp = match self.cmp {
+                    ComparisonKind::Equal => Comparison::Equal,
+                    ComparisonKind::Changed => Comparison::Changed,
+                    ComparisonKind::Increased => Comparison::Increased,
+                    ComparisonKind::Decreased => Comparison::Decreased,
+                    ComparisonKind::IncreasedBy => Comparison::IncreasedBy(input),
+                    ComparisonKind::DecreasedBy => Comparison::DecreasedBy(input),
+                    ComparisonKind::Value => Comparison::Value(input),
+                };
+
+                search.filter(system.bus(), cmp);
+            }
+        });
+
+        ui.separator();
+
+        if let Some(search) = &self.search {
+            let candidates = search.candidates();
+
+            ui.label(format!("{} candidates", candidates.len()));
+
+            egui::ScrollArea::vertical().max_height(300.0).show(ui, |ui| {
+                egui::Grid::new("search_grid").striped(true).show(ui, |ui| {
+                    ui.strong("Address");
+                    ui.strong("Previous");
+                    ui.strong("Current");
+                    ui.end_row();
+
+                    for addr in candidates.iter().take(Self::MAX_SHOWN) {
+                        let ty = search.value_type();
+                        let current = search.current_value(system.bus(), *addr);
+
+                        ui.label(format!("{addr:08x}"));
+                        ui.label(search.snapshot_value(*addr).to_string());
+                        ui.label(current.to_string());
+
+                        if ui.button("Watch").clicked() {
+                            let mode = if ty.is_signed() {
+                                IntDisplayMode::Signed
+                            } else {
+                                IntDisplayMode::Unsigned
+                            };
+                            let kind = match ty.width() {
+                                1 => ValueKind::Byte(mode),
+                                2 => ValueKind::HalfWord(mode),
+                                _ => ValueKind::Word(mode),
+                            };
+                            dbg.watch.push(WatchPoint {
+                                name: format!("{addr:08x}"),
+                                kind,
+                                addr: *addr,
+                            });
+                        }
+
+                        if ui.button("Freeze").clicked() {
+                            dbg.frozen.push(Frozen { addr: *addr, ty, val: current });
+                        }
+
+                        ui.end_row();
+                    }
+                });
+            });
+        }
+
+        if !dbg.frozen.is_empty() {
+            ui.separator();
+            ui.strong("Frozen");
+
+            egui::Grid::new("frozen_grid").striped(true).show(ui, |ui| {
+                dbg.frozen.retain_mut(|frozen| {
+                    ui.label(format!("{:08x}", frozen.addr));
+                    ui.label(frozen.ty.to_string());
+                    ui.add(egui::DragValue::new(&mut frozen.val));
+                    let retain = !ui.button("\u{2297}").clicked();
+                    ui.end_row();
+                    retain
+                });
+            });
+        }
+    }
+}
+
+struct ProfilerMenu {
+    /// The number of functions to show.
+    shown: usize,
+}
+
+impl Default for ProfilerMenu {
+    fn default() -> Self {
+        Self { shown: 20 }
+    }
+}
+
+impl ProfilerMenu {
+    fn show(&mut self, dbg: &mut Debugger, popups: &mut Popups, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            if dbg.profiling {
+                if ui.button("Stop").clicked() {
+                    dbg.profiling = false;
+                    dbg.profiler.pause();
+                }
+            } else if ui.button("Start").clicked() {
+                dbg.profiling = true;
+            }
+
+            if ui.button("Reset").clicked() {
+                dbg.profiler.reset();
+            }
+
+            if ui.button("Load Symbols").clicked() {
+                let path = FileDialog::new()
+                    .set_location(".")
+                    .show_open_single_file();
+                match path {
+                    Ok(Some(path)) => match Symbols::from_file(&path) {
+                        Ok(symbols) => dbg.profiler.set_symbols(symbols),
+                        Err(err) => popups.add("Failed to load symbols", err.to_string()),
+                    },
+                    Ok(None) => (),
+                    Err(err) => popups.add("Invalid path", err.to_string()),
+                }
+            }
+
+            if ui.button("Export Folded").clicked() {
+                let path = FileDialog::new()
+                    .set_location(".")
+                    .add_filter("Folded stacks", &["folded"])
+                    .show_save_single_file();
+                match path {
+                    Ok(Some(path)) => {
+                        let result = File::create(&path).and_then(|file| {
+                            let mut out = BufWriter::new(file);
+                            dbg.profiler.write_folded(&mut out)?;
+                            out.flush()
+                        });
+                        if let Err(err) = result {
+                            popups.add("Failed to export profile", err.to_string());
+                        }
+                    }
+                    Ok(None) => (),
+                    Err(err) => popups.add("Invalid path", err.to_string()),
+                }
+            }
+        });
+
+        let symbols = dbg.profiler.symbols().len();
+        if symbols != 0 {
+            ui.label(format!("{symbols} symbols loaded"));
+        }
+
+        ui.add(egui::Slider::new(&mut self.shown, 1..=200).text("Functions"));
+
+        ui.separator();
+
+        let total = dbg.profiler.total();
+
+        ui.label(format!(
+            "{} instructions, {} cycles, {} icache misses, {} DMA stall cycles",
+            total.instructions,
+            total.cycles,
+            total.icache_misses,
+            total.dma_stall,
+        ));
+
+        let percent = |cycles: u64| {
+            if total.cycles == 0 {
+                0.0
+            } else {
+                cycles as f64 / total.cycles as f64 * 100.0
+            }
+        };
+
+        egui::ScrollArea::vertical().max_height(400.0).show(ui, |ui| {
+            egui::Grid::new("profiler_grid").striped(true).show(ui, |ui| {
+                ui.strong("Function");
+                ui.strong("Self");
+                ui.strong("Total");
+                ui.strong("Instructions");
+                ui.strong("Icache Misses");
+                ui.strong("DMA Stall");
+                ui.end_row();
+
+                for func in dbg.profiler.functions().iter().take(self.shown) {
+                    ui.label(func.name.as_str());
+                    ui.label(format!(
+                        "{} ({:.1}%)",
+                        func.exclusive.cycles,
+                        percent(func.exclusive.cycles),
+                    ));
+                    ui.label(format!(
+                        "{} ({:.1}%)",
+                        func.inclusive.cycles,
+                        percent(func.inclusive.cycles),
+                    ));
+                    ui.label(func.exclusive.instructions.to_string());
+                    ui.label(func.exclusive.icache_misses.to_string());
+                    ui.label(func.exclusive.dma_stall.to_string());
+                    ui.end_row();
+                }
+            });
+        });
+    }
+}
+
 fn int_display_mode_selector(mode: &mut IntDisplayMode, ui: &mut egui::Ui) {
     egui::ComboBox::from_id_source("int_display_mode")
         .selected_text(mode.to_string())
@@ -504,7 +991,7 @@ fn int_display_mode_selector(mode: &mut IntDisplayMode, ui: &mut egui::Ui) {
         });
 }
 
-fn show_executor(dbg: &mut Debugger, ui: &mut egui::Ui) {
+fn show_executor(system: &mut System, dbg: &mut Debugger, popups: &mut Popups, ui: &mut egui::Ui) {
     ui.horizontal(|ui| {
         use ExecuteMode::*;
 
@@ -523,8 +1010,163 @@ fn show_executor(dbg: &mut Debugger, ui: &mut egui::Ui) {
                 .smart_aim(true),
         );
 
-        dbg.stepped = ui.button("Step").clicked();
     });
+
+    ui.horizontal(|ui| {
+        if ui.button("Step").clicked() {
+            dbg.step = Some(StepKind::Into);
+        }
+        if ui.button("Step Over").clicked() {
+            dbg.step = Some(StepKind::Over);
+        }
+        if ui.button("Step Out").clicked() {
+            dbg.step = Some(StepKind::Out);
+        }
+    });
+
+    if let Some(temp) = &dbg.temp_break {
+        ui.horizontal(|ui| {
+            match temp {
+                TempBreak::Addr { addr, .. } => ui.label(format!("Running to {addr:08x}")),
+                TempBreak::Return { .. } => ui.label("Running until return"),
+            };
+            if ui.button("Stop").clicked() {
+                dbg.temp_break = None;
+            }
+        });
+    }
+
+    ui.separator();
+
+    ui.horizontal(|ui| {
+        if let Some(trace) = &dbg.trace {
+            ui.label(format!("Recorded {} instructions", trace.recorded()));
+            if ui.button("Stop Trace").clicked() {
+                if let Some(trace) = dbg.trace.take() {
+                    if let Err(err) = trace.finish(&system.cpu) {
+                        popups.add("Failed to record trace", err.to_string());
+                    }
+                }
+            }
+        } else if ui.button("Record Trace").clicked() {
+            let path = FileDialog::new()
+                .set_location(".")
+                .add_filter("Trace", &["trace"])
+                .show_save_single_file();
+            match path {
+                Ok(Some(path)) => {
+                    let trace = File::create(&path)
+                        .map_err(TraceError::from)
+                        .and_then(|file| TraceRecorder::new(BufWriter::new(file)));
+                    match trace {
+                        Ok(trace) => dbg.trace = Some(trace),
+                        Err(err) => popups.add("Failed to record trace", err.to_string()),
+                    }
+                }
+                Ok(None) => (),
+                Err(err) => popups.add("Invalid path", err.to_string()),
+            }
+        }
+    });
+
+    ui.horizontal(|ui| {
+        if let Some(frames) = system.gpu().capture_frames_left() {
+            ui.label(format!("Capturing GPU commands, {frames} frames left"));
+            if ui.button("Stop Capture").clicked() {
+                system.gpu_mut().stop_capture();
+            }
+        } else {
+            ui.add(
+                egui::DragValue::new(&mut dbg.capture_frames)
+                    .clamp_range(1..=600)
+                    .suffix(" frames"),
+            );
+            if ui.button("Capture GPU").clicked() {
+                let path = FileDialog::new()
+                    .set_location(".")
+                    .add_filter("GPU Capture", &["gpucap"])
+                    .show_save_single_file();
+                match path {
+                    Ok(Some(path)) => {
+                        dbg.gpu_capture = Some(path);
+                        system.gpu_mut().start_capture(dbg.capture_frames);
+                    }
+                    Ok(None) => (),
+                    Err(err) => popups.add("Invalid path", err.to_string()),
+                }
+            }
+        }
+    });
+
+    ui.horizontal(|ui| {
+        if ui.button("Screenshot").clicked() {
+            // The raw display area is more useful when debugging.
+            save_screenshot(system, Filter::Nearest, popups);
+        }
+        if ui.button("Export VRAM").clicked() {
+            let path = FileDialog::new()
+                .set_location(".")
+                .add_filter("PNG Image", &["png"])
+                .add_filter("Raw VRAM", &["vram"])
+                .show_save_single_file();
+            match path {
+                Ok(Some(path)) => {
+                    if let Err(err) = export_vram(system, &path) {
+                        popups.add("Failed to export VRAM", err.to_string());
+                    }
+                }
+                Ok(None) => (),
+                Err(err) => popups.add("Invalid path", err.to_string()),
+            }
+        }
+        if ui.button("Import VRAM").clicked() {
+            let path = FileDialog::new()
+                .set_location(".")
+                .add_filter("Raw VRAM", &["vram"])
+                .show_open_single_file();
+            match path {
+                Ok(Some(path)) => {
+                    let result = fs::read(&path)
+                        .map_err(|err| err.to_string())
+                        .and_then(|raw| {
+                            system.gpu_mut().import_vram(&raw).map_err(|err| err.to_string())
+                        });
+                    if let Err(err) = result {
+                        popups.add("Failed to import VRAM", err);
+                    }
+                }
+                Ok(None) => (),
+                Err(err) => popups.add("Invalid path", err.to_string()),
+            }
+        }
+    });
+}
+
+/// Write VRAM to `path`, as a PNG image if the extension is `png` and otherwise as a raw dump.
+fn export_vram(system: &System, path: &Path) -> std::io::Result<()> {
+    let vram = system.gpu().vram();
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("png") => vram.to_image().write_png(BufWriter::new(File::create(path)?)),
+        _ => fs::write(path, vram.to_raw()),
+    }
+}
+
+/// Save the display area run through `filter` as a PNG image in the current directory, named
+/// after the time it was taken.
+pub fn save_screenshot(system: &System, filter: Filter, popups: &mut Popups) {
+    let secs = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |time| time.as_secs());
+    let path = PathBuf::from(format!("screenshot-{secs}.png"));
+    let result = File::create(&path)
+        .and_then(|file| {
+            let image = filter.apply(&system.gpu().screenshot());
+            image.write_png(BufWriter::new(file))
+        });
+    match result {
+        Ok(()) => info!("saved screenshot to {}", path.display()),
+        Err(err) => popups.add("Failed to save screenshot", err.to_string()),
+    }
 }
 
 #[derive(PartialEq)]
@@ -562,7 +1204,13 @@ impl MemoryMenu {
     }
     */
 
-    fn show(&mut self, errors: &mut Popups, system: &System, ui: &mut egui::Ui) {
+    fn show(
+        &mut self,
+        errors: &mut Popups,
+        system: &System,
+        dbg: &mut Debugger,
+        ui: &mut egui::Ui,
+    ) {
         use MemoryDisplayMode::*;
 
         ui.horizontal(|ui| {
@@ -608,6 +1256,10 @@ impl MemoryMenu {
                         for row in 0..Self::ROW_COUNT {
                             let addr = start + row as u32 * 4;
 
+                            if ui.button("\u{25b6}").on_hover_text("Run to address").clicked() {
+                                dbg.run_to(addr);
+                            }
+
                             ui.strong(format!("{addr:06x}\t"));
 
                             let op = match system.bus().peek::<u32>(addr) {
@@ -1061,6 +1713,8 @@ pub struct DebugMenu {
 
     breakpoint: (BreakPointMenu, bool),
     watchpoint: (WatchPointMenu, bool),
+    search: (SearchMenu, bool),
+    profiler: (ProfilerMenu, bool),
 
     /// Open flag for executor menu.
     executor_open: bool,
@@ -1081,6 +1735,8 @@ impl Default for DebugMenu {
             popups: Popups::new("debug"),
             breakpoint: (BreakPointMenu::default(), false),
             watchpoint: (WatchPointMenu::default(), false),
+            search: (SearchMenu::default(), false),
+            profiler: (ProfilerMenu::default(), false),
             executor_open: false,
             stateless_open: [false; 9],
             memory: Vec::default(),
@@ -1098,12 +1754,36 @@ impl DebugMenu {
         self.debugger.run(system, dt);
     }
 
+    /// Write all frozen values to memory.
+    pub fn apply_frozen(&self, system: &mut System) {
+        for frozen in &self.debugger.frozen {
+            let val = frozen.ty.encode(frozen.val);
+            let bus = system.bus_mut();
+            match frozen.ty.width() {
+                1 => bus.store(frozen.addr, val as u8),
+                2 => bus.store(frozen.addr, val as u16),
+                _ => bus.store(frozen.addr, val),
+            };
+        }
+    }
+
     pub fn show(&mut self, ctx: &egui::Context, system: &mut System, mode: &mut RunMode) {
         for br in self.debugger.breaks.drain(..) {
             self.popups
                 .add(format!("Hit {}", br.name), format!("Broke {}", br.kind));
         }
 
+        if let Some(capture) = system.gpu_mut().take_capture() {
+            if let Some(path) = self.debugger.gpu_capture.take() {
+                let result = File::create(&path)
+                    .map_err(CaptureError::from)
+                    .and_then(|file| capture.write(BufWriter::new(file)));
+                if let Err(err) = result {
+                    self.popups.add("Failed to save GPU capture", err.to_string());
+                }
+            }
+        }
+
         if let RunMode::Debug = mode {
             if let (menu, open @ true) = &mut self.breakpoint {
                 egui::Window::new("Breakpoints").open(open).show(
@@ -1123,10 +1803,30 @@ impl DebugMenu {
                 );
             }
 
+            if let (menu, open @ true) = &mut self.search {
+                egui::Window::new("Memory Search").open(open).show(
+                    ctx,
+                    |ui| {
+                        menu.show(system, &mut self.debugger, &mut self.popups, ui);
+                    },
+                );
+            }
+
+            if let (menu, open @ true) = &mut self.profiler {
+                egui::Window::new("Profiler").open(open).show(
+                    ctx,
+                    |ui| {
+                        menu.show(&mut self.debugger, &mut self.popups, ui);
+                    },
+                );
+            }
+
             if self.executor_open {
                 egui::Window::new("Executor")
                     .open(&mut self.executor_open)
-                    .show(ctx, |ui| show_executor(&mut self.debugger, ui));
+                    .show(ctx, |ui| {
+                        show_executor(system, &mut self.debugger, &mut self.popups, ui)
+                    });
             }
 
             for ((name, show), open) in STATELESS_MENUS
@@ -1147,7 +1847,9 @@ impl DebugMenu {
 
                 egui::Window::new(format!("Memory {i}"))
                     .open(&mut open)
-                    .show(ctx, |ui| memory.show(&mut self.popups, system, ui));
+                    .show(ctx, |ui| {
+                        memory.show(&mut self.popups, system, &mut self.debugger, ui)
+                    });
 
                 i += 1;
 
@@ -1190,6 +1892,8 @@ impl DebugMenu {
                             .show(ui, |ui| {
                                 ui.checkbox(&mut self.breakpoint.1, "Breakpoints");
                                 ui.checkbox(&mut self.watchpoint.1, "Watchpoints");
+                                ui.checkbox(&mut self.search.1, "Memory Search");
+                                ui.checkbox(&mut self.profiler.1, "Profiler");
     
                                 ui.checkbox(&mut self.executor_open, "Executor");
 
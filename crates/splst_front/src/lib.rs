@@ -11,14 +11,16 @@ mod config;
 mod debug;
 mod gui;
 mod keys;
+mod record;
 mod start_menu;
 
 use audio_stream::AudioStream;
 use config::Config;
 use debug::DebugMenu;
 use gui::GuiRenderer;
+use record::{RecordOutput, Recording};
 use start_menu::StartMenu;
-use splst_core::{io_port::pad, io_port::memcard, Disc, System};
+use splst_core::{io_port::pad, io_port::memcard, cheat::Cheats, Disc, System};
 use splst_render::{Renderer, SurfaceSize};
 
 use winit::event::{ElementState, Event, VirtualKeyCode, WindowEvent};
@@ -64,11 +66,23 @@ pub fn run() {
     let memcards = Rc::new(RefCell::new(memcard::MemCards::default()));
 
     let disc = Rc::new(RefCell::new(Disc::default()));
+    let cheats = Rc::new(RefCell::new(Cheats::default()));
 
     // TODO: Show and error in the settings menu, but still allow the emulator to run without audio.
     let audio_stream = AudioStream::new().unwrap();
     let audio_stream = Rc::new(RefCell::new(audio_stream));
 
+    // Both the video and audio sent by the system goes through the recording.
+    let recording = Recording::default();
+    let video_output = Rc::new(RefCell::new(RecordOutput {
+        output: renderer.clone(),
+        recording: recording.clone(),
+    }));
+    let audio_output = Rc::new(RefCell::new(RecordOutput {
+        output: audio_stream,
+        recording: recording.clone(),
+    }));
+
     let mut gui_renderer = GuiRenderer::new(window.scale_factor() as f32, &renderer.borrow());
     let mut stage = Stage::StartMenu(StartMenu::default());
 
@@ -118,7 +132,15 @@ pub fn run() {
                 ref event,
                 window_id,
             } if window_id == window.id() => match event {
-                WindowEvent::CloseRequested => *ctrl_flow = ControlFlow::Exit,
+                WindowEvent::CloseRequested => {
+                    // Make sure the recording is finished so the WAV file is valid.
+                    if let Some(mut recorder) = recording.borrow_mut().take() {
+                        if let Err(err) = recorder.finish() {
+                            error!("failed to finish recording: {err}");
+                        }
+                    }
+                    *ctrl_flow = ControlFlow::Exit;
+                }
                 WindowEvent::Resized(physical_size) => {
                     let size = SurfaceSize {
                         width: physical_size.width,
@@ -150,6 +172,7 @@ pub fn run() {
                     Stage::Running {
                         ref mut app_menu,
                         ref mut show_settings,
+                        ref system,
                         ..
                     } => match (key_event.virtual_keycode, key_event.state) {
                         (Some(VirtualKeyCode::Escape), ElementState::Pressed) => {
@@ -158,6 +181,20 @@ pub fn run() {
                         (Some(VirtualKeyCode::Tab), ElementState::Pressed) => {
                             *show_settings = !*show_settings;
                         }
+                        (Some(VirtualKeyCode::F12), ElementState::Pressed) => {
+                            debug::save_screenshot(
+                                system,
+                                config.video.filter(),
+                                &mut gui_renderer.popups,
+                            );
+                        }
+                        (Some(VirtualKeyCode::F9), ElementState::Pressed) => {
+                            record::toggle_recording(
+                                &recording,
+                                config.video.filter(),
+                                &mut gui_renderer.popups,
+                            );
+                        }
                         (Some(key), state) if *show_settings => {
                             if !config.handle_key_event(
                                 &mut key_map,
@@ -211,6 +248,12 @@ pub fn run() {
                     show_settings,
                     ..
                 } => {
+                    renderer.borrow_mut().set_deinterlace(config.video.deinterlace());
+                    renderer.borrow_mut().set_filter(config.video.filter());
+                    system.gpu_mut().set_resolution_scale(config.video.resolution_scale());
+                    system.gpu_mut().set_pgxp(config.video.pgxp());
+                    system.gpu_mut().set_raster_threads(config.video.raster_threads());
+                    config.video.sync_textures(system.gpu_mut(), &mut gui_renderer.popups);
                     renderer.borrow_mut().render(|encoder, view, renderer| {
                         let res =
                             gui_renderer.render(renderer, encoder, view, &window, |ctx, popups| {
@@ -222,6 +265,7 @@ pub fn run() {
                                         &mut gamepads.borrow_mut(),
                                         &mut memcards.borrow_mut(),
                                         &mut disc.borrow_mut(),
+                                        &mut cheats.borrow_mut(),
                                         popups,
                                         ctx,
                                     );
@@ -242,6 +286,7 @@ pub fn run() {
                                     &mut gamepads.borrow_mut(),
                                     &mut memcards.borrow_mut(),
                                     &mut disc.borrow_mut(),
+                                    &mut cheats.borrow_mut(),
                                     ctx,
                                 );
                             });
@@ -253,11 +298,12 @@ pub fn run() {
                     if let Some((bios, mode)) = out {
                         let mut system = System::new(
                             bios,
-                            renderer.clone(),
-                            audio_stream.clone(),
+                            video_output.clone(),
+                            audio_output.clone(),
                             disc.clone(),
                             gamepads.clone(),
                             memcards.clone(),
+                            cheats.clone(),
                         );
 
                         if let Some(exe) = config.exe.take_exe() {
@@ -298,6 +344,7 @@ pub fn run() {
                             }
                         }
                     }
+                    app_menu.apply_frozen(system);
                     *last_update = Instant::now();
                 }
                 Stage::StartMenu { .. } => (),
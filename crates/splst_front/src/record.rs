@@ -0,0 +1,77 @@
+//! Recording video and audio while running in the window.
+
+use crate::gui::Popups;
+use splst_core::record::Recorder;
+use splst_core::gpu::Filter;
+use splst_core::{AudioOutput, DisplayInfo, UpscaledVram, VideoOutput};
+
+use std::cell::RefCell;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::time::SystemTime;
+
+/// The size of recorded frames.
+const FRAME_SIZE: (u32, u32) = (640, 480);
+
+/// The current recording, shared between the video and audio output.
+pub type Recording = Rc<RefCell<Option<Recorder>>>;
+
+/// Forwards everything sent to `output` to the current recording as well.
+pub struct RecordOutput<T> {
+    pub output: Rc<RefCell<T>>,
+    pub recording: Recording,
+}
+
+impl<T: VideoOutput> VideoOutput for RecordOutput<T> {
+    fn send_frame(
+        &mut self,
+        display: &DisplayInfo,
+        vram_data: &[u16; 512 * 1024],
+        upscaled: Option<&UpscaledVram>,
+    ) {
+        if let Some(recorder) = self.recording.borrow_mut().as_mut() {
+            recorder.send_frame(display, vram_data, upscaled);
+        }
+        self.output.borrow_mut().send_frame(display, vram_data, upscaled);
+    }
+}
+
+impl<T: AudioOutput> AudioOutput for RecordOutput<T> {
+    fn send_audio(&mut self, samples: [i16; 2]) {
+        if let Some(recorder) = self.recording.borrow_mut().as_mut() {
+            recorder.send_audio(samples);
+        }
+        self.output.borrow_mut().send_audio(samples);
+    }
+}
+
+/// Stop the current recording, or start recording to files in the current directory named after
+/// the time the recording started. Frames are run through `filter`.
+pub fn toggle_recording(recording: &Recording, filter: Filter, popups: &mut Popups) {
+    let mut recording = recording.borrow_mut();
+
+    if let Some(mut recorder) = recording.take() {
+        match recorder.finish() {
+            Ok(()) => info!("stopped recording after {} frames", recorder.frames()),
+            Err(err) => popups.add("Failed to record", err.to_string()),
+        }
+        return;
+    }
+
+    let secs = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |time| time.as_secs());
+
+    let video = PathBuf::from(format!("recording-{secs}.y4m"));
+    let audio = video.with_extension("wav");
+
+    let (width, height) = FRAME_SIZE;
+    match Recorder::create(&video, &audio, width, height) {
+        Ok(mut recorder) => {
+            recorder.set_filter(filter);
+            info!("recording to {} and {}", video.display(), audio.display());
+            *recording = Some(recorder);
+        }
+        Err(err) => popups.add("Failed to start recording", err.to_string()),
+    }
+}
@@ -1,4 +1,5 @@
 use splst_core::{Bios, io_port::{memcard, pad}, Disc};
+use splst_core::cheat::Cheats;
 use crate::gui::Popups;
 use crate::RunMode;
 use super::config::Config;
@@ -20,6 +21,7 @@ impl StartMenu {
         gamepads: &mut pad::GamePads,
         memcards: &mut memcard::MemCards,
         disc: &mut Disc,
+        cheats: &mut Cheats,
         ctx: &egui::Context,
     ) -> Option<(Bios, RunMode)> {
         self.popups.show(ctx);
@@ -40,7 +42,15 @@ impl StartMenu {
                 .max_width(ui.available_width())
                 .show(ui, |ui| {
                     ui.group(|ui| {
-                        config.show_inside(None, gamepads, memcards, disc, &mut self.popups, ui);
+                        config.show_inside(
+                            None,
+                            gamepads,
+                            memcards,
+                            disc,
+                            cheats,
+                            &mut self.popups,
+                            ui,
+                        );
                         ui.horizontal(|ui| {
                             let mut take_bios = || {
                                 config.bios.take_bios(&mut self.popups).or_else(|| {
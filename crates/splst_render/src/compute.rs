@@ -1,9 +1,13 @@
 //! This module handles generating ['Canvas'] texture each frame from the Playstation VRAM using compute
-//! shaders. This means that 1 mb of data is uploaded to the GPU each frame, which could be quite expensive.
+//! shaders. This means that the lines of VRAM covered by the display area, up to 1 mb of data at native
+//! resolution, are uploaded to the GPU each frame, which could be quite expensive.
 //! However generating the texture on the CPU would take a lot of time, and the generated texture,
 //! which would be almost as big or bigger, still has to transfered to the GPU.
 
-use super::{Canvas, CANVAS_FORMAT};
+use splst_core::DisplayInfo;
+use splst_core::gpu::{ColorDepth, InterlaceField};
+
+use super::{Canvas, Deinterlace, CANVAS_FORMAT};
 
 /// Info used to compute ['Canvas'] from VRAM.
 #[repr(C)]
@@ -11,6 +15,35 @@ use super::{Canvas, CANVAS_FORMAT};
 pub struct DrawInfo {
     pub x_start: u32,
     pub y_start: u32,
+    /// The size of the display area in pixels. Everything outside is black.
+    pub width: u32,
+    pub height: u32,
+    /// 1 if the display area is in 24 bit color depth.
+    pub color_24bit: u32,
+    /// 0 if the display is disabled.
+    pub enabled: u32,
+    /// 1 if interlaced and using bob deinterlacing.
+    pub bob: u32,
+    /// 1 if the field just displayed is the odd lines.
+    pub odd_field: u32,
+    /// The scale of VRAM relative to native resolution.
+    pub scale: u32,
+}
+
+impl DrawInfo {
+    pub fn new(display: &DisplayInfo, deinterlace: Deinterlace, scale: u32) -> Self {
+        Self {
+            x_start: display.vram_x_start,
+            y_start: display.vram_y_start,
+            width: display.width() * scale,
+            height: display.height() * scale,
+            color_24bit: (display.color_depth == ColorDepth::B24) as u32,
+            enabled: display.enabled as u32,
+            bob: (display.interlaced && deinterlace == Deinterlace::Bob) as u32,
+            odd_field: (display.field == InterlaceField::Top) as u32,
+            scale,
+        }
+    }
 }
 
 unsafe impl bytemuck::Zeroable for DrawInfo {
@@ -18,6 +51,13 @@ unsafe impl bytemuck::Zeroable for DrawInfo {
         Self {
             x_start: 0,
             y_start: 0,
+            width: 0,
+            height: 0,
+            color_24bit: 0,
+            enabled: 0,
+            bob: 0,
+            odd_field: 0,
+            scale: 0,
         }
     }
 }
@@ -25,8 +65,8 @@ unsafe impl bytemuck::Zeroable for DrawInfo {
 /// Used to generate the ['Canvas'] from the playstation VRAM directly using compute shaders.
 /// This is called before every rendered frame.
 pub(super) struct ComputeStage {
-    /// The playstation VRAM is transfered to this buffer each frame. It's 1 mb big, so it's
-    /// probably gioing to be a bottleneck on some systems.
+    /// The playstation VRAM is transfered to this buffer each frame. It's 1 mb big at native
+    /// resolution, so it's probably gioing to be a bottleneck on some systems.
     input_buffer: wgpu::Buffer,
     /// The compute shader has two bindings.
     /// The first is 'input_buffer', the second is ['Canvas'].
@@ -36,7 +76,8 @@ pub(super) struct ComputeStage {
 }
 
 impl ComputeStage {
-    pub(super) fn new(device: &wgpu::Device, canvas: &Canvas) -> Self {
+    /// Create with room for VRAM scaled by `scale`.
+    pub(super) fn new(device: &wgpu::Device, canvas: &Canvas, scale: u32) -> Self {
         let shader = device.create_shader_module(&wgpu::include_spirv!("shader/comp.spv"));
         let input_buffer = device.create_buffer(&wgpu::BufferDescriptor {
             label: Some("Compute Storage Buffer"),
@@ -47,7 +88,7 @@ impl ComputeStage {
                 | wgpu::BufferUsages::COPY_DST
                 | wgpu::BufferUsages::MAP_WRITE,
             mapped_at_creation: false,
-            size: (VRAM_SIZE + std::mem::size_of::<DrawInfo>()) as u64,
+            size: (VRAM_SIZE * (scale * scale) as usize + std::mem::size_of::<DrawInfo>()) as u64,
         });
         let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
             label: Some("Compute Bind Group Layout"),
@@ -106,32 +147,44 @@ impl ComputeStage {
         }
     }
 
-    /// Generate ['Canvas'] from the playstations VRAM. First it transfers the entire VRAM
-    /// to the shdader, then it dispatches the compute shader for each pixel in ['Canvas'].
+    /// Generate ['Canvas'] from the playstations VRAM. First it transfers the lines of VRAM
+    /// covered by the display area to the shdader, then it dispatches the compute shader for
+    /// each pixel in ['Canvas']. `vram_data` is VRAM scaled by `draw_info.scale`.
     pub(super) fn compute_canvas(
         &self,
-        vram_data: &[u16; 512 * 1024],
+        vram_data: &[u16],
         draw_info: &DrawInfo,
         encoder: &mut wgpu::CommandEncoder,
         queue: &wgpu::Queue,
         canvas: &Canvas,
     ) {
-        let vram_data = unsafe {
-            let data = vram_data as *const u16 as *const u8;
-            std::slice::from_raw_parts(data, 1024 * 1024)
-        };
-        
-        // Transfer the entire ['Vram']. This could be done with a staging belt, which should be faster
-        // in theory. However in the testing i have done, that didn't seem to be the case, which
-        // means that either write_buffer does the same under the hood, or it just isn't a
-        // bottleneck. Perhaps it's faster on some systems, in which case it probably should be
-        // used, but since it made the code more complicated, i opted not to use i it for now.
+        // Transfer the lines of ['Vram'] shown. This could be done with a staging belt, which
+        // should be faster in theory. However in the testing i have done, that didn't seem to be
+        // the case, which means that either write_buffer does the same under the hood, or it just
+        // isn't a bottleneck. Perhaps it's faster on some systems, in which case it probably
+        // should be used, but since it made the code more complicated, i opted not to use i it
+        // for now.
         queue.write_buffer(&self.input_buffer, 0, bytemuck::bytes_of(draw_info));
-        queue.write_buffer(
-            &self.input_buffer,
-            std::mem::size_of::<DrawInfo>() as u64,
-            vram_data,
-        );
+
+        let line_size = 1024 * draw_info.scale as usize;
+        let line_count = vram_data.len() / line_size;
+
+        let first = (draw_info.y_start * draw_info.scale) as usize % line_count;
+        let end = first + (draw_info.height as usize).min(line_count);
+
+        // The display area may wrap around to the top of VRAM.
+        for lines in [first..end.min(line_count), 0..end.saturating_sub(line_count)] {
+            if lines.is_empty() {
+                continue;
+            }
+            let data = &vram_data[lines.start * line_size..lines.end * line_size];
+            queue.write_buffer(
+                &self.input_buffer,
+                (std::mem::size_of::<DrawInfo>() + lines.start * line_size * 2) as u64,
+                bytemuck::cast_slice(data),
+            );
+        }
+
         let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
             label: Some("Compute Pass"),
         });
@@ -24,6 +24,10 @@ pub struct DrawStage {
     bind_group: wgpu::BindGroup,
     pipeline: wgpu::RenderPipeline,
     scissor_rect: ScissorRect,
+    /// The size of the display area in pixels.
+    display_size: (u32, u32),
+    /// The aspect ratio the display area is shown in.
+    aspect_ratio: f32,
 }
 
 impl DrawStage {
@@ -69,9 +73,13 @@ impl DrawStage {
                 },
             ],
         };
+        let display_size = (canvas.extent.width, canvas.extent.height);
+        let aspect_ratio = 4.0 / 3.0;
         let (transform, scissor_rect) = gen_transform_matrix(
-            Vec2::new(canvas.extent.width as f32, canvas.extent.height as f32),
-            Vec2::new(surface_size.width as f32, surface_size.height as f32),
+            canvas,
+            display_size,
+            aspect_ratio,
+            surface_size,
         );
         let uniform_buffer = device
             .create_buffer_init(&wgpu::util::BufferInitDescriptor {
@@ -165,6 +173,8 @@ impl DrawStage {
             });
         Self {
             scissor_rect,
+            display_size,
+            aspect_ratio,
             bind_group,
             uniform_buffer,
             vertex_buffer,
@@ -204,34 +214,72 @@ impl DrawStage {
 
     pub fn resize(&mut self, queue: &wgpu::Queue, surface_size: SurfaceSize, canvas: &Canvas) {
         let (transform, scissor_rect) = gen_transform_matrix(
-            Vec2::new(canvas.extent.width as f32, canvas.extent.height as f32),
-            Vec2::new(surface_size.width as f32, surface_size.height as f32),
+            canvas,
+            self.display_size,
+            self.aspect_ratio,
+            surface_size,
         );
         self.scissor_rect = scissor_rect;
         queue.write_buffer(&self.uniform_buffer, 0, transform.as_byte_slice());
     }
+
+    /// Set the size of the display area in pixels and the aspect ratio to show it in. Only the
+    /// display area of the canvas gets drawn.
+    pub fn set_display(
+        &mut self,
+        queue: &wgpu::Queue,
+        surface_size: SurfaceSize,
+        canvas: &Canvas,
+        display_size: (u32, u32),
+        aspect_ratio: f32,
+    ) {
+        let display_size = (
+            display_size.0.clamp(1, canvas.extent.width),
+            display_size.1.clamp(1, canvas.extent.height),
+        );
+        if display_size != self.display_size || aspect_ratio != self.aspect_ratio {
+            self.display_size = display_size;
+            self.aspect_ratio = aspect_ratio;
+            self.resize(queue, surface_size, canvas);
+        }
+    }
 }
 
 /// Generates the transform matrix used by the fragment shader, and the scissor rectangle used by
-/// the render pipeline. It depends on size of the surface texture and the render texture, so it
-/// must be recaluculated each time on of these change.
-fn gen_transform_matrix(texture: Vec2, screen: Vec2) -> (Mat4, ScissorRect) {
+/// the render pipeline. It depends on size of the surface texture, the render texture and the
+/// display area, so it must be recaluculated each time on of these change.
+///
+/// The display area is in the top left corner of the canvas. The canvas is scaled so that the
+/// display area has the right aspect ratio and fills the screen, and the rest is clipped away.
+fn gen_transform_matrix(
+    canvas: &Canvas,
+    display_size: (u32, u32),
+    aspect_ratio: f32,
+    surface_size: SurfaceSize,
+) -> (Mat4, ScissorRect) {
+    let texture = Vec2::new(canvas.extent.width as f32, canvas.extent.height as f32);
+    let screen = Vec2::new(surface_size.width as f32, surface_size.height as f32);
+    let display = Vec2::new(display_size.0 as f32, display_size.1 as f32);
+
+    // The size of the display area shown with the right aspect ratio, before scaling.
+    let shown = Vec2::new(display.y * aspect_ratio, display.y);
+
     // The smallest scale ratio.
-    let scale = (screen.x / texture.x)
-        .min(screen.y / texture.y)
+    let scale = (screen.x / shown.x)
+        .min(screen.y / shown.y)
         .max(1.0)
         .floor();
 
-    // Scaled tetxure dimension.
-    let scaled = texture * scale;
+    // Scaled display area dimension.
+    let scaled = shown * scale;
 
-    // Scaling of the vertices.
-    let s = scaled / screen;
+    // Scaling of the vertices, so that the whole canvas is scaled the same as the display area.
+    let s = scaled * (texture / display) / screen;
 
-    // Translation of the vertices. The min/max just makes sure the texture doesn't go off screen.
+    // Translation of the vertices, so that the display area is centered.
     let t = Vec2::new(
-        (texture.x / screen.x - 1.0).max(0.0),
-        (1.0 - texture.y / screen.y).min(0.0),
+        s.x - scaled.x / screen.x,
+        scaled.y / screen.y - s.y,
     );
 
     // Transformation matrix. It flips the image vertically since the Playstations coordinates in VRAM
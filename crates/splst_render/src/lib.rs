@@ -1,24 +1,36 @@
 //! This module handles drawing to the screen. It uses wgpu for hardware accelaration for both
 //! rendering and computing a drawable image from the Playstations VRAM.
+//!
+//! If a [`Filter`] other than [`Filter::Nearest`] is used, the display area is instead decoded
+//! and filtered on the CPU and written directly to the ['Canvas'].
 
 pub mod compute;
 mod draw;
 
-use splst_core::VideoOutput;
+use splst_core::{VideoOutput, DisplayInfo, UpscaledVram};
+use splst_core::gpu::{self, ColorDepth, Filter, Image};
 use compute::ComputeStage;
 use draw::DrawStage;
 
 use winit::window::Window;
+use serde::{Serialize, Deserialize};
+
+use std::fmt;
 
 pub use compute::DrawInfo;
 
 /// This is the texture drawn to the screen each frame. This is generated by
 /// ['ComputePipeline'] and drawn by ['RenderPipeline'].
 pub struct Canvas {
+    texture: wgpu::Texture,
     view: wgpu::TextureView,
     extent: wgpu::Extent3d,
 }
 
+/// The size of ['Canvas'] when not scaled. It fits the largest display area, which is 640 pixels
+/// wide and 576 lines high for interlaced PAL.
+const CANVAS_SIZE: (u32, u32) = (640, 576);
+
 /// The format of ['Canvas'].
 pub const CANVAS_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
 
@@ -41,10 +53,89 @@ impl Canvas {
             // I'm a bit unsure which usage flags would be optimal. Maybe COPY_DST, but it's not
             // really copied to but written to pixel by pixel by the compute shader. But it doesn't
             // seem to change performance really, so perhaps it doesn't matter.
-            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::STORAGE_BINDING,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::STORAGE_BINDING
+                | wgpu::TextureUsages::COPY_DST,
         });
         let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
-        Self { view, extent }
+        Self { texture, view, extent }
+    }
+
+    /// Write `image` to the top left corner of the canvas. Anything outside the canvas is cut off.
+    fn write_image(&self, queue: &wgpu::Queue, image: &Image) {
+        let width = image.width.min(self.extent.width);
+        let height = image.height.min(self.extent.height);
+
+        if width == 0 || height == 0 {
+            return;
+        }
+
+        let halfs: Vec<u16> = (0..=255).map(unorm_to_f16).collect();
+        let mut data = Vec::with_capacity((width * height * 8) as usize);
+
+        for line in image.data.chunks_exact(image.width as usize * 3).take(height as usize) {
+            for rgb in line.chunks_exact(3).take(width as usize) {
+                for val in [rgb[0], rgb[1], rgb[2], 255] {
+                    data.extend(halfs[val as usize].to_le_bytes());
+                }
+            }
+        }
+
+        queue.write_texture(
+            wgpu::ImageCopyTexture {
+                texture: &self.texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            &data,
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: std::num::NonZeroU32::new(width * 8),
+                rows_per_image: std::num::NonZeroU32::new(height),
+            },
+            wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+        );
+    }
+}
+
+/// Convert an 8-bit color channel to a 16 bit float between 0 and 1, as used by ['CANVAS_FORMAT'].
+fn unorm_to_f16(val: u8) -> u16 {
+    if val == 0 {
+        return 0;
+    }
+    // It's always a normal number, so only the exponent has to be rebiased.
+    let bits = (val as f32 / 255.0).to_bits();
+    let exp = ((bits >> 23) & 0xff) + 15 - 127;
+    ((exp << 10) | ((bits >> 13) & 0x3ff)) as u16
+}
+
+/// How to show 480 line interlaced images.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Deinterlace {
+    /// Show both fields at once. This gives the full resolution, but moving objects get combing
+    /// artifacts.
+    #[default]
+    Weave,
+    /// Only show the field just displayed with each line doubled. This avoids combing, but
+    /// halves the vertical resolution and makes the image flicker slightly.
+    Bob,
+}
+
+impl Deinterlace {
+    pub const ALL: [Self; 2] = [Self::Weave, Self::Bob];
+}
+
+impl fmt::Display for Deinterlace {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Deinterlace::Weave => f.write_str("Weave"),
+            Deinterlace::Bob => f.write_str("Bob"),
+        }
     }
 }
 
@@ -72,6 +163,11 @@ pub struct Renderer {
     compute_stage: ComputeStage,
     /// If the renderer has been send a new frame which hasn't been shown yet.
     pending_frame: bool,
+    deinterlace: Deinterlace,
+    filter: Filter,
+    /// The scale of the canvas relative to [`CANVAS_SIZE`], and the scale of the VRAM the compute stage
+    /// has room for. The compute stage isn't used when filtering on the CPU, so it's 1 then.
+    scale: (u32, u32),
 }
 
 impl Renderer {
@@ -106,8 +202,8 @@ impl Renderer {
                 present_mode: wgpu::PresentMode::Mailbox,
             },
         );
-        let canvas = Canvas::new(&device, SurfaceSize::new(640, 480));
-        let compute_stage = ComputeStage::new(&device, &canvas);
+        let canvas = Canvas::new(&device, SurfaceSize::new(CANVAS_SIZE.0, CANVAS_SIZE.1));
+        let compute_stage = ComputeStage::new(&device, &canvas, 1);
         let draw_stage = DrawStage::new(&device, surface_size, surface_format, &canvas);
         Self {
             device,
@@ -119,6 +215,9 @@ impl Renderer {
             draw_stage,
             compute_stage,
             pending_frame: false,
+            deinterlace: Deinterlace::default(),
+            filter: Filter::default(),
+            scale: (1, 1),
         }
     }
 
@@ -187,20 +286,96 @@ impl Renderer {
     pub fn has_pending_frame(&self) -> bool {
         self.pending_frame
     }
+
+    pub fn set_deinterlace(&mut self, deinterlace: Deinterlace) {
+        self.deinterlace = deinterlace;
+    }
+
+    /// Set the filter used on the display area. Filtered frames are always made from native VRAM
+    /// and aren't deinterlaced with [`Deinterlace::Bob`].
+    pub fn set_filter(&mut self, filter: Filter) {
+        self.filter = filter;
+    }
+
+    /// Recreate the canvas and the stages using it to fit the display area scaled by
+    /// `canvas_scale`, with room for VRAM scaled by `vram_scale`.
+    fn set_scale(&mut self, (canvas_scale, vram_scale): (u32, u32)) {
+        self.scale = (canvas_scale, vram_scale);
+        self.canvas = Canvas::new(
+            &self.device,
+            SurfaceSize::new(CANVAS_SIZE.0 * canvas_scale, CANVAS_SIZE.1 * canvas_scale),
+        );
+        self.compute_stage = ComputeStage::new(&self.device, &self.canvas, vram_scale);
+        self.draw_stage = DrawStage::new(
+            &self.device,
+            self.surface_size,
+            self.surface_format,
+            &self.canvas,
+        );
+    }
+
+    /// Filter the display area on the CPU and write it to the canvas.
+    fn send_filtered(&mut self, display: &DisplayInfo, vram_data: &[u16; 512 * 1024]) {
+        let scale = (self.filter.scale(), 1);
+        if scale != self.scale {
+            self.set_scale(scale);
+        }
+
+        let image = self.filter.apply(&gpu::display_image(vram_data, display));
+
+        self.draw_stage.set_display(
+            &self.queue,
+            self.surface_size,
+            &self.canvas,
+            (image.width, image.height),
+            display.aspect_ratio(),
+        );
+
+        self.canvas.write_image(&self.queue, &image);
+        self.pending_frame = true;
+    }
 }
 
 impl VideoOutput for Renderer { 
-    fn send_frame(&mut self, vram_start: (u32, u32), vram_data: &[u16; 512 * 1024]) {
+    fn send_frame(
+        &mut self,
+        display: &DisplayInfo,
+        vram_data: &[u16; 512 * 1024],
+        upscaled: Option<&UpscaledVram>,
+    ) {
+        if self.filter != Filter::Nearest {
+            self.send_filtered(display, vram_data);
+            return;
+        }
+
+        let scale = upscaled.map_or(1, |upscaled| upscaled.scale());
+        if (scale, scale) != self.scale {
+            self.set_scale((scale, scale));
+        }
+
+        // The GPU can't draw in 24-bit color depth, so it's always shown from native VRAM.
+        let (vram_data, scale) = match upscaled {
+            Some(upscaled) if display.color_depth != ColorDepth::B24 => {
+                (upscaled.data(), upscaled.scale())
+            }
+            _ => (&vram_data[..], 1),
+        };
+
         let mut encoder = self
             .device
             .create_command_encoder(&wgpu::CommandEncoderDescriptor {
                 label: Some("Canvas Compute Encoder")
             });
 
-        let (x_start, y_start) = vram_start;
-        let draw_info = DrawInfo {
-            x_start, y_start,
-        };
+        let draw_info = DrawInfo::new(display, self.deinterlace, scale);
+
+        self.draw_stage.set_display(
+            &self.queue,
+            self.surface_size,
+            &self.canvas,
+            (display.width() * scale, display.height() * scale),
+            display.aspect_ratio(),
+        );
 
         self.compute_stage.compute_canvas(
             vram_data,